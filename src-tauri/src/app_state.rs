@@ -1,24 +1,54 @@
 use std::{path::Path, sync::Arc};
 
+use tokio::sync::{mpsc::UnboundedReceiver, Mutex, Notify, Semaphore};
+
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::PermissionsExt;
 
 use event::{Event, Forwarder};
+use jobs::JobProgress;
 use serde::{Deserialize, Serialize};
 
-use crate::{audio::{self, Audio, Playlist, PlaylistIOImpl, Source}, binaries, downloader::{self, FileDownloader, RequestFiles, Storage}, ytdlp::{self}};
+use crate::{audio::{self, Audio, Chapter, History, HistoryFile, PlayQueue, Playlist, PlaylistIOImpl, QueueFile, Segment, SmartPlaylist, SmartPlaylistsFile, Source}, binaries, decoder::{self, Analyzer}, formats, downloader::{self, FileDownloader, Phase, RequestFiles, Storage}, jobs::{JobQueue, JobsFile}, player::{AudioSink, CpalSink, EngineEvent, NullSink, Player}, settings::{self, DownloadPolicy, Settings, SettingsFile}, search::{Highlight, SearchIndex}, key::Key, tagging, ytdlp::{self}};
 
 pub struct AppState {
     ytdlp: ytdlp::YtDlp,
     playlist: audio::Playlist<PlaylistIOImpl>,
     downloader: Arc<FileDownloader>,
     forwarder: Forwarder,
+    settings: Mutex<Settings>,
+    settings_file: SettingsFile,
+    player: Player,
+    player_events: Mutex<Option<UnboundedReceiver<EngineEvent>>>,
+    queue: Mutex<PlayQueue>,
+    queue_file: QueueFile,
+    history: Mutex<History>,
+    history_file: HistoryFile,
+    smart_playlists: Mutex<Vec<SmartPlaylist>>,
+    smart_playlists_file: SmartPlaylistsFile,
+    evaluated_smart_playlists: Mutex<Vec<SmartPlaylistDTO>>,
+    search_index: Mutex<SearchIndex>,
+    jobs: Mutex<JobQueue>,
+    jobs_file: JobsFile,
+    /// One for every CPU, taken by running analyses.
+    job_slots: Arc<Semaphore>,
+    /// Taken by running jobs that wait on the network.
+    network_job_slots: Arc<Semaphore>,
+    jobs_changed: Notify,
+    /// Play counts changed since `playlist.json` was last written.
+    plays_changed: Notify,
 }
 
 #[derive(Debug, Clone)]
 pub enum AppError {
     Downloader(downloader::Error),
     YtDlp(ytdlp::FetchError),
+    Settings(settings::Error),
+    Decoder(decoder::Error),
+    Format(formats::Error),
+    Tagging(tagging::Error),
+    Backup(crate::backup::Error),
+    SmartPlaylist(audio::SmartPlaylistError),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,23 +68,94 @@ pub struct IndexedAudioDTO {
     id: u32,
     title: String,
     author: String,
+    album: Option<String>,
+    tags: Vec<String>,
     source: AudioSourceDTO,
+    pinned: bool,
+    last_played: Option<u64>,
+    play_count: u32,
+    download_policy: Option<DownloadPolicy>,
+    added_at: Option<u64>,
+    duration: Option<f64>,
+    has_file: bool,
+    skip_count: u32,
+    /// Total listening time in seconds.
+    listened: f64,
+    chapters: Vec<Chapter>,
+    segment: Option<Segment>,
+    bpm: Option<f64>,
+    key: Option<Key>,
+    /// Position of the key on the Camelot wheel like `8A`, for mixing.
+    camelot: Option<String>,
+    bpm_key_corrected: bool,
 }
 
-impl From<Audio> for IndexedAudioDTO {
-    fn from(value: Audio) -> Self {
-        Self {
-            id: value.id,
-            title: value.title,
-            author: value.author,
-            source: match value.source {
-                Source::YouTube(url) => AudioSourceDTO::YouTube(url),
-            }
-        }
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequeueFailureDTO {
+    audio: IndexedAudioDTO,
+    error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryReportDTO {
+    orphaned: Vec<String>,
+    missing_media: Vec<IndexedAudioDTO>,
+    truncated_media: Vec<IndexedAudioDTO>,
+    unknown_mime: Vec<IndexedAudioDTO>,
+    stale_partials: Vec<IndexedAudioDTO>,
+    requeued: Vec<IndexedAudioDTO>,
+    /// Broken tracks whose download couldn't be started again.
+    failed: Vec<RequeueFailureDTO>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteAudioDTO {
+    id: String,
+    url: String,
+    title: String,
+    channel: String,
+    duration: Option<f64>,
+    thumbnail: Option<String>,
+    /// Set when the audio is already in the playlist.
+    audio: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResultDTO {
+    audio: IndexedAudioDTO,
+    score: f64,
+    highlights: Vec<Highlight>,
 }
 
+mod backup;
+mod bpm_key;
+mod chapters;
+mod duplicates;
 pub mod event;
+mod export;
+mod history;
+mod jobs;
+mod loudness;
+mod lyrics;
+mod playback;
+mod silence;
+mod smart;
+mod storage;
+mod sync;
+mod waveform;
+
+pub use backup::{RestoreMode, RestoreReportDTO};
+pub use bpm_key::{BpmKey, PlaylistSort};
+pub use duplicates::DuplicatesDTO;
+pub use export::{ExportReportDTO, ImportReportDTO};
+pub use history::ListenDTO;
+pub use loudness::LoudnessDTO;
+pub use lyrics::LyricsDTO;
+pub use playback::PlaybackStateDTO;
+pub use silence::TrimDTO;
+pub use smart::SmartPlaylistDTO;
+pub use storage::DiskUsageDTO;
+pub use sync::SyncReportDTO;
 
 impl ToString for AppError {
     fn to_string(&self) -> String {
@@ -65,21 +166,60 @@ impl ToString for AppError {
                 downloader::Error::InQueue => "Audio is already in queue".to_string(),
                 downloader::Error::NotFound => "Audio not found".to_string(),
                 downloader::Error::Connection => "Connection error".to_string(),
+                downloader::Error::Busy => "Library is busy with downloads".to_string(),
+                downloader::Error::InvalidTarget => "Target directory must be empty".to_string(),
+                downloader::Error::Transcode => "Transcoding failed".to_string(),
             },
             AppError::YtDlp(err) => match err {
                 ytdlp::FetchError::Unknown => "Unknown error".to_string(),
                 ytdlp::FetchError::NotFound => "Video not found".to_string(),
                 ytdlp::FetchError::BadLink => "Bad link".to_string(),
             },
+            AppError::Settings(err) => match err {
+                settings::Error::Unknown => "Unknown error".to_string(),
+                settings::Error::UnsupportedVersion => "Settings were saved by a newer version".to_string(),
+                settings::Error::InvalidConcurrentDownloads => "Concurrent downloads must be between 1 and 16".to_string(),
+                settings::Error::InvalidDownloadAttempts => "Download attempts must be between 1 and 20".to_string(),
+                settings::Error::InvalidStorageQuota => "Storage quota must be greater than zero".to_string(),
+                settings::Error::InvalidSilenceThreshold => "Silence threshold must be between -96 and 0 dB".to_string(),
+                settings::Error::InvalidSilenceLength => "Minimum silence length must be between 0 and 60 seconds".to_string(),
+                settings::Error::InvalidBitrate => "Bitrate must be between 32 and 320 kbit/s".to_string(),
+            },
+            AppError::Decoder(err) => match err {
+                decoder::Error::Unknown => "Unknown error".to_string(),
+                decoder::Error::Unsupported => "Audio format is not supported".to_string(),
+                decoder::Error::TooShort => "Audio is too short to analyze".to_string(),
+            },
+            AppError::Format(err) => match err {
+                formats::Error::Unknown => "Unknown error".to_string(),
+                formats::Error::UnsupportedFormat => "Playlist was saved by a newer version".to_string(),
+                formats::Error::InvalidFile => "Not a valid playlist file".to_string(),
+                formats::Error::InvalidTemplate => "Invalid file name template".to_string(),
+            },
+            AppError::Tagging(err) => match err {
+                tagging::Error::Unknown => "Unknown error".to_string(),
+                tagging::Error::Unsupported => "Audio format can't be tagged".to_string(),
+                tagging::Error::Invalid => "Audio file is damaged".to_string(),
+                tagging::Error::CutUnsupported => "Chapters can't be cut from this audio format".to_string(),
+            },
+            AppError::Backup(err) => match err {
+                crate::backup::Error::Unknown => "Unknown error".to_string(),
+                crate::backup::Error::InvalidArchive => "Not a valid backup archive".to_string(),
+                crate::backup::Error::UnsupportedVersion => "Backup was made by a newer version".to_string(),
+            },
+            AppError::SmartPlaylist(err) => match err {
+                audio::SmartPlaylistError::NotFound => "Smart playlist not found".to_string(),
+            },
         }
     }
 }
 
 impl AppState {
     pub fn new(forwarder: Forwarder) -> Self {
-        let is_portable = std::env::var("PORTABLE").is_ok();
+        let exe_dir = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        let is_portable = settings::is_portable(&exe_dir);
         let app_dir = if is_portable {
-            std::env::current_exe().unwrap().parent().unwrap().to_str().unwrap().to_string()
+            exe_dir.to_str().unwrap().to_string()
         } else {
             dirs::config_dir().unwrap().join("FurPlayer").to_str().unwrap().to_string()
         };
@@ -98,23 +238,134 @@ impl AppState {
                 std::fs::set_permissions(ytdlp_path.clone(), metadata).unwrap();
             }
         }
+        Self::open(forwarder, &app_dir, ytdlp_path, || {
+            CpalSink::new()
+                .map(|sink| Box::new(sink) as Box<dyn AudioSink>)
+                .unwrap_or(Box::new(NullSink))
+        })
+    }
+
+    /// Loads the state kept in `app_dir`, playing through the sink `sink` creates.
+    pub fn open<F>(forwarder: Forwarder, app_dir: &str, ytdlp_path: String, sink: F) -> Self
+    where
+        F: FnOnce() -> Box<dyn AudioSink> + Send + 'static,
+    {
         let ytdlp = ytdlp::YtDlp::new(ytdlp_path);
+        let settings_file = SettingsFile(Path::new(&app_dir).join("settings.json").to_str().unwrap().to_string());
+        let settings = settings_file.load().unwrap_or_default();
         let playlist_path = Path::new(&app_dir).join("playlist.json");
-        let playlist = tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let library_dir = settings.library_dir.clone().unwrap_or(app_dir.to_string());
+        let (audio_dir, downloading_dir) = Self::library_dirs(&library_dir);
+        let downloader = Arc::new(FileDownloader::new(audio_dir, downloading_dir));
+        let (playlist, search_index) = tokio::runtime::Runtime::new().unwrap().block_on(async {
             let playlist = Playlist::new(audio::PlaylistIOImpl(playlist_path.to_str().unwrap().to_string()));
             playlist.load().await.unwrap();
-            playlist
+            Self::apply_settings(&ytdlp, &downloader, &settings).await;
+            let search_index = SearchIndex::new(&playlist.get_audios().await);
+            (playlist, search_index)
         });
-        let audio_dir = Path::new(&app_dir).join("audios").to_str().unwrap().to_string();
-        let downloading_dir = Path::new(&app_dir).join("downloading").to_str().unwrap().to_string();
+        let queue_file = QueueFile(Path::new(&app_dir).join("queue.json").to_str().unwrap().to_string());
+        let queue = queue_file.load().unwrap_or_default();
+        let history_file = HistoryFile(Path::new(&app_dir).join("history.jsonl").to_str().unwrap().to_string());
+        let history = history_file.load().unwrap_or_default();
+        let smart_playlists_file = SmartPlaylistsFile(Path::new(&app_dir).join("smart_playlists.json").to_str().unwrap().to_string());
+        let smart_playlists = smart_playlists_file.load().unwrap_or_default();
+        let jobs_file = JobsFile(Path::new(&app_dir).join("jobs.json").to_str().unwrap().to_string());
+        let mut jobs = jobs_file.load().unwrap_or_default();
+        jobs.resume();
+        let cpus = std::thread::available_parallelism().map(|cpus| cpus.get()).unwrap_or(1);
+        let (player, player_events) = Player::new(sink);
         Self {
-            downloader: Arc::new(FileDownloader::new(audio_dir, downloading_dir)),
+            downloader,
             ytdlp,
             playlist,
             forwarder,
+            settings: Mutex::new(settings),
+            settings_file,
+            player,
+            player_events: Mutex::new(Some(player_events)),
+            queue: Mutex::new(queue),
+            queue_file,
+            history: Mutex::new(history),
+            history_file,
+            smart_playlists: Mutex::new(smart_playlists),
+            smart_playlists_file,
+            evaluated_smart_playlists: Mutex::new(Vec::new()),
+            search_index: Mutex::new(search_index),
+            jobs: Mutex::new(jobs),
+            jobs_file,
+            job_slots: Arc::new(Semaphore::new(cpus)),
+            network_job_slots: Arc::new(Semaphore::new(jobs::NETWORK_JOBS)),
+            jobs_changed: Notify::new(),
+            plays_changed: Notify::new(),
         }
     }
 
+    fn library_dirs(library_dir: &str) -> (String, String) {
+        (
+            Path::new(library_dir).join("audios").to_str().unwrap().to_string(),
+            Path::new(library_dir).join("downloading").to_str().unwrap().to_string(),
+        )
+    }
+
+    async fn apply_settings(ytdlp: &ytdlp::YtDlp, downloader: &FileDownloader, settings: &Settings) {
+        downloader.configure(settings.max_concurrent_downloads, settings.download_attempts, settings.transcode);
+        ytdlp.set_quality(settings.audio_quality).await;
+    }
+
+    pub async fn get_settings(&self) -> Settings {
+        self.settings.lock().await.clone()
+    }
+
+    pub async fn update_settings(&self, mut settings: Settings) -> Result<Settings, AppError> {
+        settings.validate().map_err(AppError::Settings)?;
+        {
+            let mut current = self.settings.lock().await;
+            settings.library_dir = current.library_dir.clone();
+            self.settings_file.save(&settings).map_err(AppError::Settings)?;
+            *current = settings.clone();
+        }
+        Self::apply_settings(&self.ytdlp, &self.downloader, &settings).await;
+        self.forwarder.forward_event(Event::SettingsChanged { settings: settings.clone() });
+        self.enforce_quota(None).await;
+        Ok(settings)
+    }
+
+    /// The new directory is saved once everything moved, so settings never
+    /// point at a library that is only partly there. The files are moved back
+    /// when it can't be saved.
+    pub async fn move_library(&self, path: String) -> Result<(), AppError> {
+        let (audio_dir, downloading_dir) = Self::library_dirs(&path);
+        let forwarder = self.forwarder.clone();
+        let progress = |moved, total| {
+            let forwarder = forwarder.clone();
+            async move {
+                forwarder.forward_event(Event::MoveLibrary { moved, total });
+            }
+        };
+        let (old_audio_dir, old_downloading_dir) = self.downloader.get_dirs().await;
+        let mut result = self.downloader.move_library(audio_dir, downloading_dir, progress).await.map_err(AppError::Downloader);
+        if result.is_ok() {
+            let mut settings = self.settings.lock().await;
+            let mut updated = settings.clone();
+            updated.library_dir = Some(path.clone());
+            result = self.settings_file.save(&updated).map_err(AppError::Settings);
+            match result {
+                Ok(()) => *settings = updated,
+                Err(_) => {
+                    let _ = self.downloader.move_library(old_audio_dir, old_downloading_dir, |_, _| async {}).await;
+                },
+            }
+        }
+        if let Err(err) = result {
+            self.forwarder.forward_event(Event::ErrorMoveLibrary { error: err.clone() });
+            return Err(err);
+        }
+        self.forwarder.forward_event(Event::FinishedMoveLibrary { path });
+        self.forwarder.forward_event(Event::SettingsChanged { settings: self.settings.lock().await.clone() });
+        Ok(())
+    }
+
     fn install_binary(app_dir: String, filename: String, binary: &[u8]) -> String {
         let path = Path::new(&app_dir).join("bin");
         let executable_path = path.join(filename);
@@ -125,69 +376,276 @@ impl AppState {
         executable_path.to_str().unwrap().to_string()
     }
 
-    pub async fn add_new_audio(&self, url: String) -> Result<IndexedAudioDTO, AppError> {
+    pub async fn add_new_audio(self: &Arc<Self>, url: String) -> Result<IndexedAudioDTO, AppError> {
         let details = self.ytdlp.fetch(url.clone()).await.map_err(AppError::YtDlp)?;
-        let audio = audio::Audio::create(details.title, details.author, audio::Source::YouTube(details.url.clone()));
+        let mut audio = audio::Audio::create(details.title, details.author, audio::Source::YouTube(details.url.clone()));
+        audio.duration = details.duration;
+        audio.chapters = details.chapters;
+        audio.subtitles = details.subtitles;
         self.playlist.add_audio(audio.clone()).await;
         self.save_playlist().await;
-        self.download_audio(audio.clone(), details.thumbnail, details.media);
-        Ok(audio.into())
+        self.search_index.lock().await.upsert(&audio);
+        self.refresh_smart_playlists().await;
+        if self.should_download(&audio, false).await {
+            self.download_audio(audio.clone(), details.thumbnail, details.media);
+        }
+        Ok(self.indexed(audio).await)
+    }
+
+    /// Searches YouTube, the results can be passed to `add_new_audio` by url.
+    pub async fn search_remote(&self, query: String, limit: u32) -> Result<Vec<RemoteAudioDTO>, AppError> {
+        if query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let entries = self.ytdlp.search_remote(query, limit.clamp(1, 50)).await.map_err(AppError::YtDlp)?;
+        let audios = self.playlist.get_audios().await;
+        Ok(entries.into_iter().map(|entry| RemoteAudioDTO {
+            audio: audios.iter()
+                .find(|audio| matches!(&audio.source, Source::YouTube(url) if ytdlp::video_id(url) == Some(entry.id.as_str())))
+                .map(|audio| audio.id),
+            id: entry.id,
+            url: entry.url,
+            title: entry.title,
+            channel: entry.channel,
+            duration: entry.duration,
+            thumbnail: entry.thumbnail,
+        }).collect())
     }
 
     pub async fn save_playlist(&self) {
         let _ = self.playlist.save().await;
     }
 
+    /// Virtual tracks split from the audio are removed with it.
     pub async fn remove_audio(&self, id: u32) {
+        for child in self.chapter_tracks(id).await {
+            self.remove_from_player(child.id).await;
+            self.playlist.remove_audio(child.id).await;
+            self.search_index.lock().await.remove(child.id);
+            self.remove_jobs(child.id).await;
+        }
+        self.remove_from_player(id).await;
         self.playlist.remove_audio(id).await;
         self.save_playlist().await;
+        self.search_index.lock().await.remove(id);
+        self.remove_jobs(id).await;
         let _ = self.downloader.remove(id).await;
+        self.refresh_smart_playlists().await;
     }
 
-    pub fn download_audio(&self, audio: audio::Audio, thumbnail: String, media: String) {
-        let downloader = self.downloader.clone();
-        let forwarder = self.forwarder.clone();
+    /// Virtual tracks download the media of their parent.
+    pub fn download_audio(self: &Arc<Self>, audio: audio::Audio, thumbnail: String, media: String) {
+        let state = self.clone();
         tokio::spawn(async move {
-            if downloader.is_in_queue(audio.id).await {
-                return;
-            }
-            forwarder.forward_event(Event::StartDownload { audio: audio.clone().into() });
-            let result = downloader.save(&audio, |downloaded, total| {
-                let forwarder = forwarder.clone();
-                let audio = audio.clone();
-                async move {
-                    forwarder.forward_event(Event::Download { audio: audio.into(), downloaded, total, });
+            state.save_audio(audio, thumbnail, media).await;
+        });
+    }
+
+    /// Downloads the media of the audio, or of its parent for virtual tracks,
+    /// and waits for it. Failures are forwarded as events.
+    async fn save_audio(self: &Arc<Self>, audio: audio::Audio, thumbnail: String, media: String) {
+        let audio = match audio.segment {
+            Some(segment) => match self.playlist.get_audio(segment.parent).await {
+                Some(parent) => parent,
+                None => return,
+            },
+            None => audio,
+        };
+        if self.downloader.is_in_queue(audio.id).await {
+            return;
+        }
+        let forwarder = self.forwarder.clone();
+        let dto = self.indexed(audio.clone()).await;
+        forwarder.forward_event(Event::StartDownload { audio: dto.clone() });
+        let result = self.downloader.save(&audio, |phase, done, total| {
+            let forwarder = forwarder.clone();
+            let audio = dto.clone();
+            async move {
+                match phase {
+                    Phase::Download => forwarder.forward_event(Event::Download { audio, downloaded: done, total, }),
+                    Phase::Transcode => forwarder.forward_event(Event::Transcode { audio, transcoded: done, total, }),
                 }
-            }, RequestFiles::new(thumbnail, media)).await;
-            if result.is_ok() {
-                forwarder.forward_event(Event::FinishedDownload { audio: audio.into() });
-            } else if let Err(err) = result {
-                forwarder.forward_event(Event::ErrorDownload { audio: audio.into(), error: AppError::Downloader(err) });
             }
-        });
+        }, RequestFiles::new(thumbnail, media)).await;
+        match result {
+            Ok(()) => {
+                forwarder.forward_event(Event::FinishedDownload { audio: self.indexed(audio.clone()).await });
+                self.enforce_quota(Some(audio.id)).await;
+                self.refresh_smart_playlists().await;
+                self.queue_download_jobs(audio.id).await;
+            },
+            Err(err) => forwarder.forward_event(Event::ErrorDownload { audio: dto, error: AppError::Downloader(err) }),
+        }
     }
 
-    pub async fn get_all_audios(&self) -> Result<Vec<IndexedAudioDTO>, AppError> {
+    pub async fn verify_library(self: &Arc<Self>, repair: bool) -> Result<LibraryReportDTO, AppError> {
         let audios = self.playlist.get_audios().await;
+        let ids: Vec<u32> = audios.iter().map(|audio| audio.id).collect();
+        let report = self.downloader.verify(&ids).await;
+        let mut requeued = Vec::new();
+        let mut failed = Vec::new();
+        if repair {
+            self.downloader.repair(&report).await.map_err(AppError::Downloader)?;
+            for audio in audios.iter() {
+                let broken = report.broken().contains(&audio.id);
+                let partial = report.stale_partials.contains(&audio.id) && !self.downloader.has_file(audio).await;
+                if !broken && !partial {
+                    continue;
+                }
+                match &audio.source {
+                    Source::YouTube(url) => {
+                        match self.ytdlp.fetch(url.clone()).await {
+                            Ok(details) => self.download_audio(audio.clone(), details.thumbnail, details.media),
+                            Err(err) => {
+                                failed.push(RequeueFailureDTO { audio: self.indexed(audio.clone()).await, error: AppError::YtDlp(err).to_string() });
+                                continue;
+                            },
+                        }
+                    },
+                }
+                requeued.push(self.indexed(audio.clone()).await);
+            }
+        }
+        Ok(LibraryReportDTO {
+            orphaned: report.orphaned.clone(),
+            missing_media: self.indexed_of(&audios, &report.missing_media).await,
+            truncated_media: self.indexed_of(&audios, &report.truncated_media).await,
+            unknown_mime: self.indexed_of(&audios, &report.unknown_mime).await,
+            stale_partials: self.indexed_of(&audios, &report.stale_partials).await,
+            requeued,
+            failed,
+        })
+    }
+
+    async fn indexed_of(&self, audios: &[Audio], ids: &[u32]) -> Vec<IndexedAudioDTO> {
         let mut indexed_audios = Vec::new();
-        for audio in audios.iter()  {
-            indexed_audios.push(IndexedAudioDTO {
-                id: audio.id,
-                author: audio.author.clone(),
-                source: match &audio.source {
-                    Source::YouTube(youtube) => AudioSourceDTO::YouTube(youtube.clone()),
-                },
-                title: audio.title.clone(),
-            });
+        for audio in audios.iter().filter(|audio| ids.contains(&audio.id)) {
+            indexed_audios.push(self.indexed(audio.clone()).await);
+        }
+        indexed_audios
+    }
+
+    pub async fn get_all_audios(&self, sort: Option<PlaylistSort>) -> Result<Vec<IndexedAudioDTO>, AppError> {
+        let mut audios = self.playlist.get_audios().await;
+        if let Some(sort) = sort {
+            bpm_key::sort_audios(&mut audios, sort);
+        }
+        let mut indexed_audios = Vec::new();
+        for audio in audios {
+            indexed_audios.push(self.indexed(audio).await);
         }
         Ok(indexed_audios)
     }
 
-    pub async fn get_thumbnail(&self, id: u32) -> Result<ContentDTO, AppError> {
+    /// Decodes the downloaded media of `id`, or the part between two seconds
+    /// of it, into the analyzer on the blocking pool.
+    async fn run_analysis<A, F>(&self, id: u32, part: Option<(f64, f64)>, make: F, progress: JobProgress) -> Result<Option<A::Output>, AppError>
+    where
+        A: Analyzer,
+        A::Output: Send + 'static,
+        F: Fn(usize, u32) -> A + Send + 'static,
+    {
+        let files = self.downloader.local_files(id).await.map_err(AppError::Downloader)?;
+        let known = self.playlist.get_audio(id).await.and_then(|audio| audio.duration);
+        tokio::task::spawn_blocking(move || decoder::analyze_file(&files.media, part, make, |position, duration| {
+            progress.report(position, duration.or(known).unwrap_or(position));
+        })).await
+            .map_err(|_| AppError::Decoder(decoder::Error::Unknown))?
+            .map_err(AppError::Decoder)
+    }
+
+    pub async fn indexed(&self, audio: Audio) -> IndexedAudioDTO {
+        let has_file = self.downloader.has_file(&audio).await;
+        let stats = self.history.lock().await.stats(audio.id);
+        IndexedAudioDTO {
+            id: audio.id,
+            title: audio.title,
+            author: audio.author,
+            album: audio.album,
+            tags: audio.tags,
+            source: match audio.source {
+                Source::YouTube(url) => AudioSourceDTO::YouTube(url),
+            },
+            pinned: audio.pinned,
+            last_played: audio.last_played,
+            play_count: audio.play_count,
+            download_policy: audio.download_policy,
+            added_at: audio.added_at,
+            duration: audio.duration,
+            has_file,
+            skip_count: stats.skip_count,
+            listened: stats.listened,
+            chapters: audio.chapters,
+            segment: audio.segment,
+            bpm: audio.bpm,
+            key: audio.key,
+            camelot: audio.key.map(|key| {
+                let (number, letter) = key.camelot();
+                format!("{number}{letter}")
+            }),
+            bpm_key_corrected: audio.bpm_key_corrected,
+        }
+    }
+
+    pub async fn update_metadata(&self, id: u32, title: String, author: String, album: Option<String>, tags: Vec<String>) -> Result<IndexedAudioDTO, AppError> {
+        let audio = self.playlist.update_audio(id, |audio| {
+            audio.title = title;
+            audio.author = author;
+            audio.album = album.filter(|album| !album.trim().is_empty());
+            audio.tags = tags.into_iter().filter(|tag| !tag.trim().is_empty()).collect();
+        }).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        self.save_playlist().await;
+        self.search_index.lock().await.upsert(&audio);
+        self.refresh_smart_playlists().await;
+        Ok(self.indexed(audio).await)
+    }
+
+    pub async fn search(&self, query: String) -> Vec<SearchResultDTO> {
+        let results = self.search_index.lock().await.search(&query);
+        let mut found = Vec::new();
+        for result in results {
+            if let Some(audio) = self.playlist.get_audio(result.id).await {
+                found.push(SearchResultDTO { audio: self.indexed(audio).await, score: result.score, highlights: result.highlights });
+            }
+        }
+        found
+    }
+
+    /// `playing` counts the listen that is about to start.
+    pub(crate) async fn should_download(&self, audio: &Audio, playing: bool) -> bool {
+        let policy = match audio.download_policy {
+            Some(policy) => policy,
+            None => self.settings.lock().await.download_policy,
+        };
+        policy.should_download(audio.play_count + playing as u32)
+    }
+
+    pub async fn set_download_policy(self: &Arc<Self>, id: u32, policy: Option<DownloadPolicy>) -> Result<IndexedAudioDTO, AppError> {
+        let audio = self.playlist.update_audio(id, |audio| audio.download_policy = policy).await
+            .ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        self.save_playlist().await;
+        if self.should_download(&audio, false).await && !self.downloader.has_file(&audio).await {
+            match &audio.source {
+                Source::YouTube(url) => {
+                    let details = self.ytdlp.fetch(url.clone()).await.map_err(AppError::YtDlp)?;
+                    self.download_audio(audio.clone(), details.thumbnail, details.media);
+                },
+            }
+        }
+        Ok(self.indexed(audio).await)
+    }
+
+    /// Downloaded audios get the cover of the size closest to `size` pixels,
+    /// or the thumbnail as it was downloaded when it can't be decoded.
+    pub async fn get_thumbnail(&self, id: u32, size: u32) -> Result<ContentDTO, AppError> {
         let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
         if self.downloader.has_file(&audio).await {
-            let content = self.downloader.get_files(&audio).await.map_err(AppError::Downloader)?;
-            Ok(ContentDTO::Local { bytes: content.thumbnail, mime: content.thumbnail_mime })
+            if let Ok(bytes) = self.downloader.load_cover(audio.media_id(), size).await {
+                return Ok(ContentDTO::Local { bytes, mime: "image/jpeg".to_string() });
+            }
+            let files = self.downloader.local_files(audio.media_id()).await.map_err(AppError::Downloader)?;
+            let bytes = tokio::fs::read(&files.thumbnail).await.map_err(|_| AppError::Downloader(downloader::Error::NotFound))?;
+            Ok(ContentDTO::Local { bytes, mime: files.thumbnail_mime })
         } else {
             match &audio.source {
                 Source::YouTube(url) => {
@@ -198,7 +656,7 @@ impl AppState {
         }
     }
 
-    pub async fn get_media(&self, id: u32) -> Result<ContentDTO, AppError> {
+    pub async fn get_media(self: &Arc<Self>, id: u32) -> Result<ContentDTO, AppError> {
         let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
         if self.downloader.has_file(&audio).await {
             let content = self.downloader.get_files(&audio).await.map_err(AppError::Downloader)?;
@@ -207,7 +665,9 @@ impl AppState {
             match &audio.source {
                 Source::YouTube(url) => {
                     let details = self.ytdlp.fetch(url.clone()).await.map_err(AppError::YtDlp)?;
-                    self.download_audio(audio, details.thumbnail, details.media.clone());
+                    if self.should_download(&audio, true).await {
+                        self.download_audio(audio, details.thumbnail, details.media.clone());
+                    }
                     Ok(ContentDTO::Url(details.media))
                 },
             }
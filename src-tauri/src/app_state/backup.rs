@@ -0,0 +1,218 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::{self, Audio, History, Listen, SmartPlaylist}, backup::{self, Backup, Manifest}, downloader::{self, Storage}, search::SearchIndex, settings};
+
+use super::{event::Event, storage::now, AppError, AppState, IndexedAudioDTO};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RestoreMode {
+    /// Adds audios, listens and smart playlists the library doesn't have yet.
+    /// Audios whose id is taken by another track get a fresh one.
+    Merge,
+    /// Makes the library, history, smart playlists and settings match the backup.
+    Replace,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreReportDTO {
+    added: Vec<IndexedAudioDTO>,
+    removed: u32,
+    /// Audios whose downloaded files came from the archive.
+    media: u32,
+}
+
+/// Contents of a backup parsed and validated before anything is changed.
+struct Restored {
+    audios: Vec<Audio>,
+    settings: Option<settings::Settings>,
+    listens: Vec<Listen>,
+    smart_playlists: Vec<SmartPlaylist>,
+}
+
+fn parse(backup: &Backup) -> Result<Restored, AppError> {
+    let invalid = || AppError::Backup(backup::Error::InvalidArchive);
+    let audios = audio::audios_from_json(&backup.files[backup::PLAYLIST]).map_err(|_| invalid())?;
+    let settings = match backup.files.get(backup::SETTINGS) {
+        Some(serialized) => Some(settings::from_json(serialized).map_err(AppError::Settings)?),
+        None => None,
+    };
+    let listens = match backup.files.get(backup::HISTORY) {
+        Some(serialized) => serde_json::from_str(serialized).map_err(|_| invalid())?,
+        None => Vec::new(),
+    };
+    let smart_playlists = match backup.files.get(backup::SMART_PLAYLISTS) {
+        Some(serialized) => serde_json::from_str(serialized).map_err(|_| invalid())?,
+        None => Vec::new(),
+    };
+    Ok(Restored { audios, settings, listens, smart_playlists })
+}
+
+impl AppState {
+    /// Writes the playlist, settings, history and smart playlists into a zip
+    /// archive at `path`, with `include_media` also the downloaded files.
+    pub async fn backup(&self, path: String, include_media: bool) -> Result<(), AppError> {
+        let audios = self.playlist.get_audios().await;
+        let unknown = || AppError::Backup(backup::Error::Unknown);
+        let files = vec![
+            (backup::PLAYLIST, audio::audios_to_json(&audios).map_err(|_| unknown())?),
+            (backup::SETTINGS, settings::to_json(&*self.settings.lock().await).map_err(AppError::Settings)?),
+            (backup::HISTORY, serde_json::to_string(self.history.lock().await.listens()).map_err(|_| unknown())?),
+            (backup::SMART_PLAYLISTS, serde_json::to_string(&*self.smart_playlists.lock().await).map_err(|_| unknown())?),
+        ];
+        let manifest = Manifest { format: backup::FORMAT.to_string(), version: backup::VERSION, created_at: now(), include_media };
+        let ids: Vec<u32> = audios.iter().map(|audio| audio.id).collect();
+        let (audio_dir, _) = self.downloader.get_dirs().await;
+        tokio::task::spawn_blocking(move || {
+            let media = include_media.then_some((PathBuf::from(audio_dir), ids));
+            backup::write(PathBuf::from(path).as_path(), &manifest, &files, media.as_ref().map(|(dir, ids)| (dir.as_path(), ids.as_slice())))
+        }).await.map_err(|_| unknown())?.map_err(AppError::Backup)
+    }
+
+    /// Validates the archive, then merges it into the library or replaces the
+    /// library with it. Downloaded files in the archive are restored for the
+    /// audios taken from it, they are extracted before anything changes so a
+    /// broken archive leaves the library as it was. Refused while downloads
+    /// are running.
+    pub async fn restore(&self, path: String, mode: RestoreMode) -> Result<RestoreReportDTO, AppError> {
+        if self.downloader.is_busy().await {
+            return Err(AppError::Downloader(downloader::Error::Busy));
+        }
+        let archive = PathBuf::from(path);
+        let read = archive.clone();
+        let backup = tokio::task::spawn_blocking(move || backup::read(&read)).await
+            .map_err(|_| AppError::Backup(backup::Error::Unknown))?
+            .map_err(AppError::Backup)?;
+        let restored = parse(&backup)?;
+
+        let current = self.playlist.get_audios().await;
+        let mut report = RestoreReportDTO { added: Vec::new(), removed: 0, media: 0 };
+        // Archive ids of the audios taken from the backup to their library ids.
+        let taken: HashMap<u32, u32> = match mode {
+            RestoreMode::Merge => {
+                let ids = backup::merge_ids(&current, &restored.audios);
+                ids.into_iter().filter(|(_, id)| !current.iter().any(|existing| existing.id == *id)).collect()
+            },
+            RestoreMode::Replace => restored.audios.iter().map(|audio| (audio.id, audio.id)).collect(),
+        };
+        // Current audios whose id the backup gives to another track.
+        let replaced: Vec<u32> = match mode {
+            RestoreMode::Merge => Vec::new(),
+            RestoreMode::Replace => current.iter()
+                .filter(|audio| !restored.audios.iter().any(|restored| restored.id == audio.id && backup::same_track(restored, audio)))
+                .map(|audio| audio.id)
+                .collect(),
+        };
+
+        let (audio_dir, _) = self.downloader.get_dirs().await;
+        let staging = PathBuf::from(&audio_dir).with_file_name("restoring");
+        let extracted = match backup.manifest.include_media {
+            true => {
+                let (archive, staging, ids) = (archive.clone(), staging.clone(), taken.clone());
+                let extracted = tokio::task::spawn_blocking(move || {
+                    let _ = std::fs::remove_dir_all(&staging);
+                    let extracted = backup::extract_media(&archive, &staging, &ids);
+                    if extracted.is_err() {
+                        let _ = std::fs::remove_dir_all(&staging);
+                    }
+                    extracted
+                }).await.map_err(|_| AppError::Backup(backup::Error::Unknown))?;
+                extracted.map_err(AppError::Backup)?
+            },
+            false => Vec::new(),
+        };
+
+        let audios = match mode {
+            RestoreMode::Merge => {
+                let ids = backup::merge_ids(&current, &restored.audios);
+                let library_id = |id: u32| ids.get(&id).copied().unwrap_or(id);
+                let mut audios = current.clone();
+                for mut audio in restored.audios {
+                    let Some(&id) = taken.get(&audio.id) else {
+                        continue;
+                    };
+                    audio.id = id;
+                    if let Some(segment) = audio.segment.as_mut() {
+                        segment.parent = library_id(segment.parent);
+                    }
+                    audios.push(audio);
+                }
+                let mut history = self.history.lock().await;
+                let mut listens = history.listens().to_vec();
+                for mut listen in restored.listens {
+                    listen.audio_id = library_id(listen.audio_id);
+                    let exists = listens.iter().any(|existing| existing.audio_id == listen.audio_id && existing.started_at == listen.started_at);
+                    if !exists {
+                        listens.push(listen);
+                    }
+                }
+                listens.sort_by_key(|listen| listen.started_at);
+                *history = History::new(listens);
+                self.history_file.save(&history);
+                drop(history);
+                let mut smart_playlists = self.smart_playlists.lock().await;
+                for mut playlist in restored.smart_playlists {
+                    if smart_playlists.iter().any(|existing| existing.name == playlist.name && existing.rules == playlist.rules) {
+                        continue;
+                    }
+                    while smart_playlists.iter().any(|existing| existing.id == playlist.id) {
+                        playlist.id = rand::random();
+                    }
+                    smart_playlists.push(playlist);
+                }
+                self.smart_playlists_file.save(&smart_playlists);
+                audios
+            },
+            RestoreMode::Replace => {
+                for id in replaced.iter() {
+                    self.remove_from_player(*id).await;
+                    let _ = self.downloader.remove(*id).await;
+                    report.removed += 1;
+                }
+                {
+                    let mut history = self.history.lock().await;
+                    *history = History::new(restored.listens);
+                    self.history_file.save(&history);
+                }
+                {
+                    let mut smart_playlists = self.smart_playlists.lock().await;
+                    *smart_playlists = restored.smart_playlists;
+                    self.smart_playlists_file.save(&smart_playlists);
+                }
+                if let Some(mut settings) = restored.settings {
+                    let mut current = self.settings.lock().await;
+                    settings.library_dir = current.library_dir.clone();
+                    self.settings_file.save(&settings).map_err(AppError::Settings)?;
+                    *current = settings.clone();
+                    Self::apply_settings(&self.ytdlp, &self.downloader, &settings).await;
+                    self.forwarder.forward_event(Event::SettingsChanged { settings });
+                }
+                restored.audios
+            },
+        };
+
+        if backup.manifest.include_media {
+            let ids = extracted.clone();
+            tokio::task::spawn_blocking(move || backup::install_media(&staging, PathBuf::from(audio_dir).as_path(), &ids)).await
+                .map_err(|_| AppError::Backup(backup::Error::Unknown))?
+                .map_err(AppError::Backup)?;
+            report.media = extracted.len() as u32;
+        }
+
+        *self.search_index.lock().await = SearchIndex::new(&audios);
+        self.playlist.set_audios(audios.clone()).await;
+        self.save_playlist().await;
+        let kept = |audio: &Audio| current.iter().any(|existing| existing.id == audio.id && !replaced.contains(&audio.id));
+        for audio in audios.into_iter().filter(|audio| taken.values().any(|id| *id == audio.id) && !kept(audio)) {
+            report.added.push(self.indexed(audio).await);
+        }
+        let mut restored = Vec::new();
+        for audio in self.playlist.get_audios().await {
+            restored.push(self.indexed(audio).await);
+        }
+        self.forwarder.forward_event(Event::Restored { audios: restored });
+        self.refresh_smart_playlists().await;
+        Ok(report)
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Audio, downloader::{self, Storage}, key::Key, tempo::BpmKeyMeter};
+
+use super::{jobs::JobProgress, AppError, AppState, IndexedAudioDTO};
+
+/// Values the user corrects the detection with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BpmKey {
+    pub bpm: Option<f64>,
+    pub key: Option<Key>,
+}
+
+/// Orders the playlist can be listed in, audios without the value go last.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PlaylistSort {
+    Bpm,
+    /// Around the Camelot wheel, so keys that mix well are next to each other.
+    Key,
+}
+
+/// Stable, audios with the same value keep their order.
+pub(super) fn sort_audios(audios: &mut [Audio], sort: PlaylistSort) {
+    match sort {
+        PlaylistSort::Bpm => audios.sort_by(|a, b| match (a.bpm, b.bpm) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        }),
+        PlaylistSort::Key => audios.sort_by_key(|audio| (audio.key.is_none(), audio.key.map(|key| key.camelot()))),
+    }
+}
+
+impl AppState {
+    /// Decodes the downloaded media, or the segment of a virtual track, and
+    /// stores the detected tempo and key on the audio unless the user
+    /// corrected them.
+    pub(super) async fn analyze_bpm_key(&self, id: u32, progress: JobProgress) -> Result<(), AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        if audio.bpm_key_corrected {
+            return Ok(());
+        }
+        let part = audio.segment.map(|segment| (segment.start, segment.end));
+        let (bpm, key) = self.run_analysis(audio.media_id(), part, BpmKeyMeter::new, progress).await?.unwrap_or((None, None));
+        self.playlist.update_audio(id, |audio| {
+            if !audio.bpm_key_corrected {
+                audio.bpm = bpm;
+                audio.key = key;
+            }
+        }).await;
+        self.save_playlist().await;
+        self.refresh_smart_playlists().await;
+        Ok(())
+    }
+
+    /// Replaces the detected tempo and key, `None` detects them again.
+    pub async fn set_bpm_key(&self, id: u32, correction: Option<BpmKey>) -> Result<IndexedAudioDTO, AppError> {
+        let correction = correction.map(|correction| BpmKey {
+            bpm: correction.bpm.filter(|bpm| bpm.is_finite() && *bpm > 0.0),
+            key: correction.key.filter(|key| key.tonic < 12),
+        });
+        let audio = self.playlist.update_audio(id, |audio| {
+            audio.bpm_key_corrected = correction.is_some();
+            if let Some(correction) = correction {
+                audio.bpm = correction.bpm;
+                audio.key = correction.key;
+            }
+        }).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        self.save_playlist().await;
+        if correction.is_none() && self.downloader.has_file(&audio).await {
+            self.analyze_bpm_key(id, JobProgress::default()).await?;
+        }
+        self.refresh_smart_playlists().await;
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        Ok(self.indexed(audio).await)
+    }
+}
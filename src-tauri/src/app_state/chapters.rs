@@ -0,0 +1,64 @@
+use crate::{audio::{Audio, Source}, downloader};
+
+use super::{AppError, AppState, IndexedAudioDTO};
+
+impl AppState {
+    /// Virtual tracks split from the audio, in the order of its chapters.
+    pub(super) async fn chapter_tracks(&self, id: u32) -> Vec<Audio> {
+        let mut tracks: Vec<Audio> = self.playlist.get_audios().await.into_iter()
+            .filter(|audio| audio.segment.is_some_and(|segment| segment.parent == id))
+            .collect();
+        let start = |audio: &Audio| audio.segment.map(|segment| segment.start).unwrap_or(0.0);
+        tracks.sort_by(|a, b| start(a).total_cmp(&start(b)));
+        tracks
+    }
+
+    /// Adds a virtual track for every chapter of the audio, sharing its media.
+    /// Chapters are fetched for audios added before they were stored, chapters
+    /// that already have a track are skipped.
+    pub async fn split_chapters(&self, id: u32) -> Result<Vec<IndexedAudioDTO>, AppError> {
+        let mut audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        if audio.segment.is_some() {
+            return Ok(Vec::new());
+        }
+        if audio.chapters.is_empty() {
+            let url = match &audio.source {
+                Source::YouTube(url) => url.clone(),
+            };
+            let details = self.ytdlp.fetch(url).await.map_err(AppError::YtDlp)?;
+            audio = self.playlist.update_audio(id, |audio| {
+                audio.chapters = details.chapters;
+                audio.duration = audio.duration.or(details.duration);
+            }).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        }
+        let existing = self.chapter_tracks(id).await;
+        for chapter in audio.chapters.iter() {
+            if existing.iter().any(|track| track.segment.is_some_and(|segment| segment.start == chapter.start)) {
+                continue;
+            }
+            let track = Audio::from_chapter(&audio, chapter);
+            self.playlist.add_audio(track.clone()).await;
+            self.search_index.lock().await.upsert(&track);
+        }
+        self.save_playlist().await;
+        self.refresh_smart_playlists().await;
+        let mut tracks = Vec::new();
+        for track in self.chapter_tracks(id).await {
+            tracks.push(self.indexed(track).await);
+        }
+        Ok(tracks)
+    }
+
+    /// Removes the virtual tracks split from the audio, the audio itself stays.
+    pub async fn merge_chapters(&self, id: u32) -> Result<IndexedAudioDTO, AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        for track in self.chapter_tracks(id).await {
+            self.remove_from_player(track.id).await;
+            self.playlist.remove_audio(track.id).await;
+            self.search_index.lock().await.remove(track.id);
+        }
+        self.save_playlist().await;
+        self.refresh_smart_playlists().await;
+        Ok(self.indexed(audio).await)
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Audio, decoder, downloader::{self, Storage, LYRICS_FILE}, fingerprint::{self, Chromagram, Fingerprint}, jobs::JobKind};
+
+use super::{jobs::JobProgress, AppError, AppState, IndexedAudioDTO};
+
+/// Stored in the folder of the audio next to `index.json`.
+pub(super) const FINGERPRINT_FILE: &str = "fingerprint.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroupDTO {
+    audios: Vec<IndexedAudioDTO>,
+    /// Lowest similarity of the matches joining the group, from
+    /// [`fingerprint::THRESHOLD`] to 1.0.
+    similarity: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicatesDTO {
+    groups: Vec<DuplicateGroupDTO>,
+    /// Audios that aren't downloaded, couldn't be decoded, are too short or
+    /// are still being fingerprinted, so they weren't compared. Virtual
+    /// tracks are never compared.
+    unchecked: Vec<IndexedAudioDTO>,
+}
+
+fn root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+/// Groups of indexes of matching fingerprints with the lowest similarity
+/// that joined each group.
+fn group(fingerprints: &[Fingerprint]) -> Vec<(Vec<usize>, f64)> {
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+    let mut lowest = vec![1.0f64; fingerprints.len()];
+    for a in 0..fingerprints.len() {
+        for b in a + 1..fingerprints.len() {
+            let (a_duration, b_duration) = (fingerprints[a].duration(), fingerprints[b].duration());
+            // Recordings of very different length can't overlap enough.
+            if a_duration.min(b_duration) < a_duration.max(b_duration) / 2.0 {
+                continue;
+            }
+            let similarity = fingerprints[a].similarity(&fingerprints[b]);
+            if similarity < fingerprint::THRESHOLD {
+                continue;
+            }
+            let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+            let joined = lowest[root_a].min(lowest[root_b]).min(similarity);
+            parents[root_b] = root_a;
+            lowest[root_a] = joined;
+        }
+    }
+    let mut groups: BTreeMap<usize, (Vec<usize>, f64)> = BTreeMap::new();
+    for index in 0..fingerprints.len() {
+        let root = root(&mut parents, index);
+        groups.entry(root).or_insert_with(|| (Vec::new(), lowest[root])).0.push(index);
+    }
+    groups.into_values().filter(|(members, _)| members.len() > 1).collect()
+}
+
+impl AppState {
+    /// Decodes the downloaded media and stores its fingerprint. Audios too
+    /// short for one get none and stay unchecked.
+    pub(super) async fn analyze_fingerprint(&self, id: u32, progress: JobProgress) -> Result<Fingerprint, AppError> {
+        let measured = self.run_analysis(id, None, Chromagram::new, progress).await?
+            .filter(|measured| !measured.codes.is_empty())
+            .ok_or(AppError::Decoder(decoder::Error::TooShort))?;
+        self.downloader.save_analysis(id, FINGERPRINT_FILE, &measured).await.map_err(AppError::Downloader)?;
+        Ok(measured)
+    }
+
+    /// Groups downloaded audios that sound the same, whatever their source
+    /// and title. Downloaded audios without a fingerprint get a job for it
+    /// unless one failed, and count as unchecked.
+    pub async fn find_duplicates(&self) -> Result<DuplicatesDTO, AppError> {
+        let mut checked: Vec<Audio> = Vec::new();
+        let mut fingerprints = Vec::new();
+        let mut unchecked = Vec::new();
+        for audio in self.playlist.get_audios().await {
+            if audio.segment.is_some() {
+                continue;
+            }
+            match self.downloader.load_analysis::<Fingerprint>(audio.id, FINGERPRINT_FILE).await {
+                // Empty fingerprints were stored for short audios before.
+                Some(measured) if !measured.codes.is_empty() => {
+                    checked.push(audio);
+                    fingerprints.push(measured);
+                },
+                _ => {
+                    let failed = self.jobs.lock().await.has_failed(audio.id, JobKind::Fingerprint);
+                    if !failed && self.downloader.has_file(&audio).await {
+                        self.queue_job(audio.id, JobKind::Fingerprint).await;
+                    }
+                    unchecked.push(self.indexed(audio).await);
+                },
+            }
+        }
+        let groups = tokio::task::spawn_blocking(move || group(&fingerprints)).await
+            .map_err(|_| AppError::Decoder(decoder::Error::Unknown))?;
+        let mut duplicates = Vec::new();
+        for (members, similarity) in groups {
+            let mut audios = Vec::new();
+            for index in members {
+                audios.push(self.indexed(checked[index].clone()).await);
+            }
+            duplicates.push(DuplicateGroupDTO { audios, similarity });
+        }
+        Ok(DuplicatesDTO { groups: duplicates, unchecked })
+    }
+
+    /// Folds the other audios into the kept one and removes them. Listens,
+    /// tags and pins add up, lyrics move over when the kept audio has none
+    /// and so do the downloaded files with their analyses.
+    pub async fn merge_duplicates(&self, keep: u32, others: Vec<u32>) -> Result<IndexedAudioDTO, AppError> {
+        for id in others.into_iter().filter(|id| *id != keep) {
+            let kept = self.playlist.get_audio(keep).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+            let Some(other) = self.playlist.get_audio(id).await else {
+                continue;
+            };
+            // Virtual tracks play the media of their parent, there's nothing to move.
+            let movable = kept.segment.is_none() && other.segment.is_none()
+                && !self.downloader.has_file(&kept).await
+                && self.downloader.has_file(&other).await
+                && !self.downloader.is_in_queue(id).await
+                && !self.downloader.is_in_queue(keep).await;
+            if movable {
+                self.downloader.move_downloaded(id, keep).await.map_err(AppError::Downloader)?;
+            }
+            self.playlist.update_audio(keep, |audio| {
+                if movable && !audio.bpm_key_corrected {
+                    audio.bpm = other.bpm;
+                    audio.key = other.key;
+                    audio.bpm_key_corrected = other.bpm_key_corrected;
+                }
+                audio.added_at = match (audio.added_at, other.added_at) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                audio.pinned |= other.pinned;
+                audio.play_count += other.play_count;
+                audio.last_played = audio.last_played.max(other.last_played);
+                for tag in other.tags.iter() {
+                    if !audio.tags.contains(tag) {
+                        audio.tags.push(tag.clone());
+                    }
+                }
+                audio.album = audio.album.take().or(other.album.clone());
+                audio.download_policy = audio.download_policy.or(other.download_policy);
+            }).await;
+            {
+                let mut history = self.history.lock().await;
+                history.reassign(id, keep);
+                self.history_file.save(&history);
+            }
+            if self.downloader.load_user_file(keep, LYRICS_FILE).await.is_none() {
+                if let Some(lyrics) = self.downloader.load_user_file(id, LYRICS_FILE).await {
+                    self.downloader.save_user_file(keep, LYRICS_FILE, &lyrics).await.map_err(AppError::Downloader)?;
+                }
+            }
+            self.remove_audio(id).await;
+        }
+        let audio = self.playlist.get_audio(keep).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        self.search_index.lock().await.upsert(&audio);
+        self.save_playlist().await;
+        self.refresh_smart_playlists().await;
+        Ok(self.indexed(audio).await)
+    }
+}
@@ -3,7 +3,9 @@ use std::sync::Arc;
 use serde::Serialize;
 use tauri::{Emitter, Runtime, WebviewWindow};
 
-use super::{AppError, IndexedAudioDTO};
+use crate::{jobs::Job, settings::Settings};
+
+use super::{AppError, IndexedAudioDTO, PlaybackStateDTO, SmartPlaylistDTO};
 
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -21,7 +23,58 @@ pub enum Event {
         audio: IndexedAudioDTO,
         downloaded: u64,
         total: u64,
-    }
+    },
+    /// Milliseconds of the downloaded media converted to the configured codec.
+    Transcode {
+        audio: IndexedAudioDTO,
+        transcoded: u64,
+        total: u64,
+    },
+    SettingsChanged {
+        settings: Settings,
+    },
+    MoveLibrary {
+        moved: u64,
+        total: u64,
+    },
+    FinishedMoveLibrary {
+        path: String,
+    },
+    ErrorMoveLibrary {
+        error: AppError,
+    },
+    Evicted {
+        audio: IndexedAudioDTO,
+    },
+    Playback {
+        state: PlaybackStateDTO,
+    },
+    ErrorPlayback {
+        audio: IndexedAudioDTO,
+        error: AppError,
+    },
+    SmartPlaylistsChanged {
+        playlists: Vec<SmartPlaylistDTO>,
+    },
+    Sync {
+        synced: u64,
+        total: u64,
+    },
+    Restored {
+        audios: Vec<IndexedAudioDTO>,
+    },
+    /// A job was queued, started, finished or failed.
+    Job {
+        job: Job,
+        /// Jobs queued or running.
+        pending: usize,
+    },
+    /// Milliseconds of media a running job went through.
+    JobProgress {
+        job: u32,
+        done: u64,
+        total: u64,
+    },
 }
 
 pub trait ForwardEvents {
@@ -50,7 +103,54 @@ enum WebviewEvent {
         audio: IndexedAudioDTO,
         downloaded: u64,
         total: u64,
-    }
+    },
+    Transcode {
+        audio: IndexedAudioDTO,
+        transcoded: u64,
+        total: u64,
+    },
+    SettingsChanged {
+        settings: Settings,
+    },
+    MoveLibrary {
+        moved: u64,
+        total: u64,
+    },
+    FinishedMoveLibrary {
+        path: String,
+    },
+    ErrorMoveLibrary {
+        error: String,
+    },
+    Evicted {
+        audio: IndexedAudioDTO,
+    },
+    Playback {
+        state: PlaybackStateDTO,
+    },
+    ErrorPlayback {
+        audio: IndexedAudioDTO,
+        error: String,
+    },
+    SmartPlaylistsChanged {
+        playlists: Vec<SmartPlaylistDTO>,
+    },
+    Sync {
+        synced: u64,
+        total: u64,
+    },
+    Restored {
+        audios: Vec<IndexedAudioDTO>,
+    },
+    Job {
+        job: Job,
+        pending: usize,
+    },
+    JobProgress {
+        job: u32,
+        done: u64,
+        total: u64,
+    },
 }
 
 impl From<Event> for WebviewEvent {
@@ -60,6 +160,19 @@ impl From<Event> for WebviewEvent {
             Event::FinishedDownload { audio } => Self::FinishedDownload { audio },
             Event::ErrorDownload { audio, error } => Self::ErrorDownload { audio, error: error.to_string() },
             Event::Download { audio, downloaded, total } => Self::Download { audio, downloaded, total },
+            Event::Transcode { audio, transcoded, total } => Self::Transcode { audio, transcoded, total },
+            Event::SettingsChanged { settings } => Self::SettingsChanged { settings },
+            Event::MoveLibrary { moved, total } => Self::MoveLibrary { moved, total },
+            Event::FinishedMoveLibrary { path } => Self::FinishedMoveLibrary { path },
+            Event::ErrorMoveLibrary { error } => Self::ErrorMoveLibrary { error: error.to_string() },
+            Event::Evicted { audio } => Self::Evicted { audio },
+            Event::Playback { state } => Self::Playback { state },
+            Event::ErrorPlayback { audio, error } => Self::ErrorPlayback { audio, error: error.to_string() },
+            Event::SmartPlaylistsChanged { playlists } => Self::SmartPlaylistsChanged { playlists },
+            Event::Sync { synced, total } => Self::Sync { synced, total },
+            Event::Restored { audios } => Self::Restored { audios },
+            Event::Job { job, pending } => Self::Job { job, pending },
+            Event::JobProgress { job, done, total } => Self::JobProgress { job, done, total },
         }
     }
 }
@@ -79,6 +192,45 @@ impl<R: Runtime> ForwardEvents for WebviewForwarder<R> {
             Event::Download { audio: _, downloaded: _, total: _ } => {
                 self.webview.emit("download", WebviewEvent::from(event)).unwrap();
             },
+            Event::Transcode { audio: _, transcoded: _, total: _ } => {
+                self.webview.emit("download", WebviewEvent::from(event)).unwrap();
+            },
+            Event::SettingsChanged { settings: _ } => {
+                self.webview.emit("settings", WebviewEvent::from(event)).unwrap();
+            },
+            Event::MoveLibrary { moved: _, total: _ } => {
+                self.webview.emit("library", WebviewEvent::from(event)).unwrap();
+            },
+            Event::FinishedMoveLibrary { path: _ } => {
+                self.webview.emit("library", WebviewEvent::from(event)).unwrap();
+            },
+            Event::ErrorMoveLibrary { error: _ } => {
+                self.webview.emit("library", WebviewEvent::from(event)).unwrap();
+            },
+            Event::Evicted { audio: _ } => {
+                self.webview.emit("storage", WebviewEvent::from(event)).unwrap();
+            },
+            Event::Playback { state: _ } => {
+                self.webview.emit("playback", WebviewEvent::from(event)).unwrap();
+            },
+            Event::ErrorPlayback { audio: _, error: _ } => {
+                self.webview.emit("playback", WebviewEvent::from(event)).unwrap();
+            },
+            Event::SmartPlaylistsChanged { playlists: _ } => {
+                self.webview.emit("library", WebviewEvent::from(event)).unwrap();
+            },
+            Event::Sync { synced: _, total: _ } => {
+                self.webview.emit("sync", WebviewEvent::from(event)).unwrap();
+            },
+            Event::Restored { audios: _ } => {
+                self.webview.emit("library", WebviewEvent::from(event)).unwrap();
+            },
+            Event::Job { job: _, pending: _ } => {
+                self.webview.emit("jobs", WebviewEvent::from(event)).unwrap();
+            },
+            Event::JobProgress { job: _, done: _, total: _ } => {
+                self.webview.emit("jobs", WebviewEvent::from(event)).unwrap();
+            },
         }
     }
 }
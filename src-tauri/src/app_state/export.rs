@@ -0,0 +1,212 @@
+use std::{collections::HashSet, path::{Path, PathBuf}, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::{Audio, Source}, downloader, formats::{self, PlaylistEntry, PlaylistFormat, TrackName}, settings::{Codec, Transcode}, tagging::{self, Cover, Tags}, transcode, ytdlp};
+
+use super::{AppError, AppState, IndexedAudioDTO};
+
+/// Encoding of chapters cut from their audio when downloads aren't transcoded.
+const CHAPTER_TRANSCODE: Transcode = Transcode { codec: Codec::Aac, bitrate: 192 };
+/// Size in pixels of the cover embedded in exported tracks.
+const EXPORT_COVER: u32 = 512;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportFailureDTO {
+    location: String,
+    error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReportDTO {
+    added: Vec<IndexedAudioDTO>,
+    /// Entries that were already in the playlist.
+    existing: Vec<IndexedAudioDTO>,
+    failed: Vec<ImportFailureDTO>,
+    /// Locations that aren't urls, like local files, which can't be added.
+    skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedTrackDTO {
+    audio: IndexedAudioDTO,
+    path: String,
+    /// False when the format can't be tagged and the file was copied as is.
+    tagged: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportFailureDTO {
+    audio: IndexedAudioDTO,
+    error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportReportDTO {
+    exported: Vec<ExportedTrackDTO>,
+    failed: Vec<ExportFailureDTO>,
+}
+
+/// Adds a number to the file name when another exported audio already took the path.
+pub(super) fn unique_path(path: PathBuf, used: &mut HashSet<PathBuf>) -> PathBuf {
+    let mut unique = path.clone();
+    let mut number = 2;
+    while used.contains(&unique) {
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+        let name = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => format!("{} ({}).{}", stem, number, extension),
+            None => format!("{} ({})", stem, number),
+        };
+        unique = path.with_file_name(name);
+        number += 1;
+    }
+    used.insert(unique.clone());
+    unique
+}
+
+pub(super) fn source_url(audio: &Audio) -> String {
+    match &audio.source {
+        Source::YouTube(url) => url.clone(),
+    }
+}
+
+impl AppState {
+    /// Audios with the given ids in that order, or the whole playlist.
+    pub(super) async fn select_audios(&self, ids: Option<Vec<u32>>) -> Vec<Audio> {
+        let audios = self.playlist.get_audios().await;
+        match ids {
+            Some(ids) => ids.iter().filter_map(|id| audios.iter().find(|audio| audio.id == *id).cloned()).collect(),
+            None => audios,
+        }
+    }
+
+    /// Writes a playlist file. With `local` the entries point at downloaded files
+    /// where there are any, otherwise at the source urls. Chapters always point
+    /// at the source, the file would play the whole audio.
+    pub async fn export_playlist(&self, path: String, format: PlaylistFormat, ids: Option<Vec<u32>>, local: bool) -> Result<(), AppError> {
+        let mut entries = Vec::new();
+        for audio in self.select_audios(ids).await {
+            let source = source_url(&audio);
+            let location = match local && audio.segment.is_none() {
+                true => self.downloader.local_files(audio.id).await.ok()
+                    .and_then(|files| files.media.to_str().map(|path| path.to_string())),
+                false => None,
+            };
+            entries.push(PlaylistEntry {
+                title: audio.title,
+                author: audio.author,
+                album: audio.album,
+                tags: audio.tags,
+                duration: audio.duration,
+                location: location.unwrap_or(source.clone()),
+                source,
+            });
+        }
+        let content = format.export(&entries).map_err(AppError::Format)?;
+        tokio::fs::write(&path, content).await.map_err(|_| AppError::Format(formats::Error::Unknown))?;
+        Ok(())
+    }
+
+    /// Adds every url of the playlist file, entries that fail don't stop the import.
+    /// Other locations are skipped. Links to a video already in the library
+    /// are found in any of their forms.
+    pub async fn import_playlist(self: &Arc<Self>, path: String) -> Result<ImportReportDTO, AppError> {
+        let content = tokio::fs::read_to_string(&path).await.map_err(|_| AppError::Format(formats::Error::InvalidFile))?;
+        let format = PlaylistFormat::from_path(Path::new(&path)).unwrap_or(PlaylistFormat::detect(&content));
+        let locations = format.import(&content).map_err(AppError::Format)?;
+        let mut report = ImportReportDTO { added: Vec::new(), existing: Vec::new(), failed: Vec::new(), skipped: Vec::new() };
+        for location in locations {
+            if !formats::is_remote(&location) {
+                report.skipped.push(location);
+                continue;
+            }
+            let id = ytdlp::video_id(&location);
+            let existing = self.playlist.get_audios().await.into_iter().find(|audio| match id {
+                Some(id) => ytdlp::video_id(&source_url(audio)) == Some(id),
+                None => source_url(audio) == location,
+            });
+            if let Some(audio) = existing {
+                report.existing.push(self.indexed(audio).await);
+                continue;
+            }
+            match self.add_new_audio(location.clone()).await {
+                Ok(audio) => report.added.push(audio),
+                Err(error) => report.failed.push(ImportFailureDTO { location, error: error.to_string() }),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Writes a copy of the audio with embedded tags under `dir`, returns the
+    /// path relative to `dir` and whether the tags could be written. Chapters
+    /// are cut from the media of their audio.
+    pub(super) async fn export_track(&self, audio: &Audio, dir: &Path, template: &str, used: &mut HashSet<PathBuf>) -> Result<(PathBuf, bool), AppError> {
+        let files = self.downloader.local_files(audio.media_id()).await.map_err(AppError::Downloader)?;
+        // Thumbnails are often WebP, which MP4 can't embed and players don't show.
+        let cover = match self.downloader.load_cover(audio.media_id(), EXPORT_COVER).await {
+            Ok(bytes) => Some(Cover { bytes, mime: "image/jpeg".to_string() }),
+            Err(_) => tokio::fs::read(&files.thumbnail).await.ok().map(|bytes| Cover { bytes, mime: files.thumbnail_mime.clone() }),
+        };
+        let tags = Tags {
+            title: audio.title.clone(),
+            artist: audio.author.clone(),
+            album: audio.album.clone(),
+            cover,
+        };
+        let (media, mime) = (files.media.clone(), files.media_mime.clone());
+        let segment = audio.segment;
+        let target = self.settings.lock().await.transcode.unwrap_or(CHAPTER_TRANSCODE);
+        let tagged = tokio::task::spawn_blocking(move || match segment {
+            Some(segment) => match tagging::tag_part(&media, &mime, &tags, segment.start, segment.end) {
+                // Chapters that can't be cut losslessly are re-encoded.
+                Err(tagging::Error::CutUnsupported) => {
+                    let bytes = std::fs::read(&media).map_err(|_| AppError::Downloader(downloader::Error::NotFound))?;
+                    let cut = transcode::cut(bytes, &mime, segment.start, segment.end, target).map_err(|err| match err {
+                        transcode::Error::Decoder(err) => AppError::Decoder(err),
+                        _ => AppError::Downloader(downloader::Error::Transcode),
+                    })?;
+                    tagging::tag_bytes(&cut, transcode::mime(target.codec), &tags).map_err(AppError::Tagging)
+                },
+                tagged => tagged.map_err(AppError::Tagging),
+            },
+            None => tagging::tag(&media, &mime, &tags).map_err(AppError::Tagging),
+        }).await.map_err(|_| AppError::Tagging(tagging::Error::Unknown))?;
+        let (bytes, extension, tagged) = match tagged {
+            Ok(tagged) => (tagged.bytes, tagged.extension.to_string(), true),
+            Err(AppError::Tagging(tagging::Error::Unsupported)) => {
+                let bytes = tokio::fs::read(&files.media).await.map_err(|_| AppError::Downloader(downloader::Error::NotFound))?;
+                let extension = files.media.extension().and_then(|extension| extension.to_str()).unwrap_or("bin").to_string();
+                (bytes, extension, false)
+            },
+            Err(err) => return Err(err),
+        };
+        let name = TrackName { id: audio.id, title: &audio.title, author: &audio.author, album: audio.album.as_deref(), ext: &extension };
+        let relative = unique_path(formats::render(template, &name).map_err(AppError::Format)?, used);
+        let path = dir.join(&relative);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|_| AppError::Format(formats::Error::Unknown))?;
+        }
+        tokio::fs::write(&path, bytes).await.map_err(|_| AppError::Format(formats::Error::Unknown))?;
+        Ok((relative, tagged))
+    }
+
+    /// Copies downloaded audios into `dir` named by the template, with title,
+    /// artist, album and cover embedded. Audios without files are reported as failed.
+    pub async fn export_tracks(&self, ids: Vec<u32>, dir: String, template: String) -> Result<ExportReportDTO, AppError> {
+        let example = TrackName { id: 0, title: "", author: "", album: None, ext: "" };
+        formats::render(&template, &example).map_err(AppError::Format)?;
+        let mut report = ExportReportDTO { exported: Vec::new(), failed: Vec::new() };
+        let mut used = HashSet::new();
+        for audio in self.select_audios(Some(ids)).await {
+            match self.export_track(&audio, Path::new(&dir), &template, &mut used).await {
+                Ok((path, tagged)) => report.exported.push(ExportedTrackDTO {
+                    audio: self.indexed(audio).await,
+                    path: Path::new(&dir).join(path).to_str().unwrap_or("").to_string(),
+                    tagged,
+                }),
+                Err(error) => report.failed.push(ExportFailureDTO { audio: self.indexed(audio).await, error: error.to_string() }),
+            }
+        }
+        Ok(report)
+    }
+}
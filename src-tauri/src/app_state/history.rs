@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Listen;
+
+use super::{storage::now, AppState, IndexedAudioDTO};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenDTO {
+    audio_id: u32,
+    /// Missing when the audio was removed from the playlist since.
+    audio: Option<IndexedAudioDTO>,
+    started_at: u64,
+    listened: f64,
+    completed: bool,
+}
+
+impl AppState {
+    /// Listens started between `from` and `to` (unix seconds, `to` exclusive), newest first.
+    pub async fn get_history(&self, from: Option<u64>, to: Option<u64>) -> Vec<ListenDTO> {
+        let listens = self.history.lock().await.range(from, to);
+        let mut history = Vec::new();
+        for listen in listens {
+            let audio = match self.playlist.get_audio(listen.audio_id).await {
+                Some(audio) => Some(self.indexed(audio).await),
+                None => None,
+            };
+            history.push(ListenDTO {
+                audio_id: listen.audio_id,
+                audio,
+                started_at: listen.started_at,
+                listened: listen.listened,
+                completed: listen.completed,
+            });
+        }
+        history
+    }
+
+    /// Records a listen played outside of the native player, e.g. by the webview.
+    pub async fn report_listen(&self, id: u32, started_at: u64, listened: f64, completed: bool) {
+        let listen = Listen { audio_id: id, started_at, listened: listened.max(0.0), completed };
+        self.history_file.append(&listen);
+        self.history.lock().await.record(listen.clone());
+        self.count_listen(&listen).await;
+    }
+
+    pub(super) async fn begin_listen(&self, id: u32, position: f64) {
+        self.finish_listen(false).await;
+        self.history.lock().await.begin(id, now(), position);
+    }
+
+    pub(super) async fn progress_listen(&self, id: u32, position: f64) {
+        self.history.lock().await.progress(id, position);
+    }
+
+    pub(super) async fn finish_listen(&self, completed: bool) {
+        let listen = self.history.lock().await.finish(completed);
+        if let Some(listen) = listen {
+            self.history_file.append(&listen);
+            self.count_listen(&listen).await;
+        }
+    }
+
+    /// Adds a recorded listen to the play count and last played time of its audio.
+    async fn count_listen(&self, listen: &Listen) {
+        let updated = self.playlist.update_audio(listen.audio_id, |audio| {
+            audio.play_count += listen.is_play() as u32;
+            audio.last_played = audio.last_played.max(Some(listen.started_at));
+        }).await;
+        if updated.is_some() {
+            self.plays_changed.notify_one();
+        }
+        self.refresh_smart_playlists().await;
+    }
+}
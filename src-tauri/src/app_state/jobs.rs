@@ -0,0 +1,184 @@
+use std::{sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::{downloader::{self, Storage, LYRICS_FILE}, jobs::{Job, JobKind}};
+
+use super::{event::{Event, Forwarder}, duplicates::FINGERPRINT_FILE, loudness::LOUDNESS_FILE, silence::SILENCE_FILE, storage::now, waveform::WAVEFORM_FILE, AppError, AppState};
+
+/// Lyrics and duration jobs running at once.
+pub(super) const NETWORK_JOBS: usize = 4;
+
+/// Reports how far a running job got, does nothing for work outside of jobs.
+#[derive(Clone, Default)]
+pub(super) struct JobProgress(Option<(u32, Forwarder)>);
+
+impl JobProgress {
+    /// Seconds of media done and in total.
+    pub(super) fn report(&self, done: f64, total: f64) {
+        if let Some((job, forwarder)) = &self.0 {
+            forwarder.forward_event(Event::JobProgress { job: *job, done: (done * 1000.0) as u64, total: (total * 1000.0) as u64 });
+        }
+    }
+}
+
+impl AppState {
+    /// Runs queued jobs as slots free up or failed ones may run again,
+    /// including the ones left from the last session. Must be called once
+    /// after the state is created.
+    pub(super) fn run_jobs(self: &Arc<Self>) {
+        let state = self.clone();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                let (job, slot) = loop {
+                    if let Some(started) = state.start_next_job().await {
+                        break started;
+                    }
+                    let next_retry = state.jobs.lock().await.next_retry(now());
+                    match next_retry {
+                        Some(retry_at) => {
+                            let wait = (UNIX_EPOCH + Duration::from_secs(retry_at)).duration_since(SystemTime::now()).unwrap_or_default();
+                            tokio::select! {
+                                _ = state.jobs_changed.notified() => {},
+                                _ = tokio::time::sleep(wait) => {},
+                            }
+                        },
+                        None => state.jobs_changed.notified().await,
+                    }
+                };
+                let state = state.clone();
+                tauri::async_runtime::spawn(async move {
+                    let result = state.run_job(&job).await;
+                    let (job, pending) = {
+                        let mut jobs = state.jobs.lock().await;
+                        let job = match result {
+                            Ok(()) => jobs.finish(job.id),
+                            Err(err) => jobs.fail(job.id, err.to_string(), now()),
+                        };
+                        state.jobs_file.save(&jobs);
+                        (job, jobs.pending())
+                    };
+                    if let Some(job) = job {
+                        state.forwarder.forward_event(Event::Job { job, pending });
+                    }
+                    drop(slot);
+                    state.jobs_changed.notify_one();
+                });
+            }
+        });
+    }
+
+    /// Starts the oldest job with a free slot, network jobs have their own.
+    async fn start_next_job(&self) -> Option<(Job, OwnedSemaphorePermit)> {
+        let cpu = self.job_slots.clone().try_acquire_owned().ok();
+        let network = self.network_job_slots.clone().try_acquire_owned().ok();
+        let mut jobs = self.jobs.lock().await;
+        let job = jobs.start_next(now(), |kind| match kind.is_network() {
+            true => network.is_some(),
+            false => cpu.is_some(),
+        })?;
+        let slot = match job.kind.is_network() {
+            true => network,
+            false => cpu,
+        }?;
+        self.jobs_file.save(&jobs);
+        self.forwarder.forward_event(Event::Job { job: job.clone(), pending: jobs.pending() });
+        Some((job, slot))
+    }
+
+    /// Audios that are gone or no longer downloaded have nothing left to do.
+    async fn run_job(&self, job: &Job) -> Result<(), AppError> {
+        let Some(audio) = self.playlist.get_audio(job.audio).await else {
+            return Ok(());
+        };
+        if !matches!(job.kind, JobKind::Lyrics | JobKind::Duration) && !self.downloader.has_file(&audio).await {
+            return Ok(());
+        }
+        let progress = JobProgress(Some((job.id, self.forwarder.clone())));
+        match job.kind {
+            JobKind::Loudness => self.analyze_loudness(audio.id, progress).await.map(|_| ()),
+            JobKind::Waveform => self.analyze_waveform(audio.id, progress).await.map(|_| ()),
+            JobKind::Silence => self.analyze_silence(audio.id, progress).await.map(|_| ()),
+            JobKind::Fingerprint => self.analyze_fingerprint(audio.id, progress).await.map(|_| ()),
+            JobKind::BpmKey => self.analyze_bpm_key(audio.id, progress).await,
+            JobKind::Lyrics => self.fetch_missing_lyrics(audio.id).await,
+            JobKind::Duration => self.fetch_duration(audio.id).await,
+        }
+    }
+
+    pub(super) async fn queue_job(&self, audio: u32, kind: JobKind) {
+        let mut jobs = self.jobs.lock().await;
+        if let Some(job) = jobs.push(audio, kind) {
+            self.jobs_file.save(&jobs);
+            self.forwarder.forward_event(Event::Job { job, pending: jobs.pending() });
+            self.jobs_changed.notify_one();
+        }
+    }
+
+    /// Analyses of a finished download, virtual tracks split from it detect
+    /// the tempo and key of their part.
+    pub(super) async fn queue_download_jobs(&self, id: u32) {
+        for kind in JobKind::AFTER_DOWNLOAD {
+            self.queue_job(id, kind).await;
+        }
+        for track in self.chapter_tracks(id).await {
+            self.queue_job(track.id, JobKind::BpmKey).await;
+        }
+    }
+
+    /// Queues the analyses missing for audios downloaded before there were
+    /// jobs, once. Virtual tracks only detect their tempo and key.
+    pub(super) async fn backfill_jobs(&self) {
+        if self.jobs.lock().await.is_backfilled() {
+            return;
+        }
+        for audio in self.playlist.get_audios().await {
+            if !self.downloader.has_file(&audio).await {
+                continue;
+            }
+            let kinds: &[JobKind] = match audio.segment {
+                Some(_) => &[JobKind::BpmKey],
+                None => &JobKind::AFTER_DOWNLOAD,
+            };
+            for kind in kinds.iter().copied() {
+                let done = match kind {
+                    JobKind::Loudness => self.downloader.load_analysis_file(audio.id, LOUDNESS_FILE).await.is_some(),
+                    JobKind::Waveform => self.downloader.load_analysis_file(audio.id, WAVEFORM_FILE).await.is_some(),
+                    JobKind::Silence => self.downloader.load_analysis_file(audio.id, SILENCE_FILE).await.is_some(),
+                    JobKind::Fingerprint => self.downloader.load_analysis_file(audio.id, FINGERPRINT_FILE).await.is_some(),
+                    JobKind::BpmKey => audio.bpm.is_some() || audio.key.is_some() || audio.bpm_key_corrected,
+                    JobKind::Lyrics => self.downloader.load_user_file(audio.id, LYRICS_FILE).await.is_some(),
+                    JobKind::Duration => audio.duration.is_some(),
+                };
+                // Jobs resumed from the last session may not have stored their result yet.
+                if !done && !self.jobs.lock().await.is_pending(audio.id, kind) {
+                    self.queue_job(audio.id, kind).await;
+                }
+            }
+        }
+        let mut jobs = self.jobs.lock().await;
+        jobs.set_backfilled();
+        self.jobs_file.save(&jobs);
+    }
+
+    pub(super) async fn remove_jobs(&self, audio: u32) {
+        let mut jobs = self.jobs.lock().await;
+        jobs.remove_audio(audio);
+        self.jobs_file.save(&jobs);
+    }
+
+    /// Queued, running and failed jobs, oldest first.
+    pub async fn get_jobs(&self) -> Vec<Job> {
+        self.jobs.lock().await.jobs().to_vec()
+    }
+
+    /// Queues a failed job again.
+    pub async fn retry_job(&self, id: u32) -> Result<Vec<Job>, AppError> {
+        let mut jobs = self.jobs.lock().await;
+        let job = jobs.retry(id).ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        self.jobs_file.save(&jobs);
+        self.forwarder.forward_event(Event::Job { job, pending: jobs.pending() });
+        self.jobs_changed.notify_one();
+        Ok(jobs.jobs().to_vec())
+    }
+}
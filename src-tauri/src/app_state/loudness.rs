@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Audio, downloader, loudness::{Loudness, Meter}, settings::Normalization};
+
+use super::{jobs::JobProgress, AppError, AppState};
+
+/// Stored in the folder of the audio next to `index.json`.
+pub(super) const LOUDNESS_FILE: &str = "loudness.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoudnessDTO {
+    /// LUFS.
+    integrated: Option<f64>,
+    /// dBTP.
+    true_peak: Option<f64>,
+    /// dB to add for the loudness to match the other tracks.
+    track_gain: f64,
+    /// dB for the audios sharing the album, `None` without an album.
+    album_gain: Option<f64>,
+}
+
+impl AppState {
+    /// Decodes the downloaded media and stores its loudness.
+    pub(super) async fn analyze_loudness(&self, id: u32, progress: JobProgress) -> Result<Loudness, AppError> {
+        let measured = self.run_analysis(id, None, Meter::new, progress).await?.unwrap_or_default();
+        self.downloader.save_analysis(id, LOUDNESS_FILE, &measured).await.map_err(AppError::Downloader)?;
+        Ok(measured)
+    }
+
+    /// Stored loudness of a downloaded audio, streamed audios aren't measured.
+    async fn loudness(&self, id: u32) -> Option<Loudness> {
+        self.downloader.load_analysis(id, LOUDNESS_FILE).await
+    }
+
+    /// Loudness of every measured audio with the same album. Virtual tracks
+    /// count with the media they share.
+    async fn album_loudness(&self, audio: &Audio) -> Option<Loudness> {
+        let album = audio.album.as_ref()?;
+        let mut media = Vec::new();
+        for other in self.playlist.get_audios().await.iter().filter(|other| other.album.as_ref() == Some(album)) {
+            if !media.contains(&other.media_id()) {
+                media.push(other.media_id());
+            }
+        }
+        let mut tracks = Vec::new();
+        for id in media {
+            if let Some(loudness) = self.loudness(id).await {
+                tracks.push(loudness);
+            }
+        }
+        (!tracks.is_empty()).then(|| Loudness::combine(&tracks))
+    }
+
+    /// Measures the audio first when it is downloaded but wasn't measured yet.
+    /// Virtual tracks report the loudness of the media they share.
+    pub async fn get_loudness(&self, id: u32) -> Result<LoudnessDTO, AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        let track = match self.loudness(audio.media_id()).await {
+            Some(track) => track,
+            None => self.analyze_loudness(audio.media_id(), JobProgress::default()).await?,
+        };
+        Ok(LoudnessDTO {
+            integrated: track.integrated,
+            true_peak: (track.true_peak > 0.0).then(|| 20.0 * track.true_peak.log10()),
+            track_gain: track.gain(),
+            album_gain: self.album_loudness(&audio).await.map(|album| album.gain()),
+        })
+    }
+
+    /// Linear gain for the player by the normalization setting, 1.0 when the
+    /// audio wasn't measured.
+    pub(super) async fn playback_gain(&self, audio: &Audio) -> f32 {
+        let normalization = self.settings.lock().await.normalization;
+        let Some(track) = self.loudness(audio.media_id()).await else {
+            return 1.0;
+        };
+        let gain = match normalization {
+            Normalization::Off => return 1.0,
+            Normalization::Track => track.gain(),
+            Normalization::Album => self.album_loudness(audio).await.unwrap_or(track).gain(),
+        };
+        10f64.powf(gain / 20.0) as f32
+    }
+}
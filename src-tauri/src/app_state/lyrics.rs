@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::{Source, Subtitle}, downloader::{self, ContentRetriever, DefaultContentRetriever, LYRICS_FILE}, formats, lyrics::{Line, Lyrics}};
+
+use super::{AppError, AppState};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricsDTO {
+    /// Every line has a time.
+    synced: bool,
+    lines: Vec<Line>,
+}
+
+impl From<Lyrics> for LyricsDTO {
+    fn from(lyrics: Lyrics) -> Self {
+        Self {
+            synced: lyrics.is_synced(),
+            lines: lyrics.lines,
+        }
+    }
+}
+
+impl AppState {
+    async fn stored_lyrics(&self, id: u32) -> Option<Lyrics> {
+        let bytes = self.downloader.load_user_file(id, LYRICS_FILE).await?;
+        let lyrics = Lyrics::parse_lrc(&String::from_utf8_lossy(&bytes));
+        (!lyrics.lines.is_empty()).then_some(lyrics)
+    }
+
+    async fn store_lyrics(&self, id: u32, lyrics: &Lyrics) -> Result<(), AppError> {
+        match lyrics.lines.is_empty() {
+            true => self.downloader.remove_user_file(id, LYRICS_FILE).await,
+            false => self.downloader.save_user_file(id, LYRICS_FILE, lyrics.to_lrc().as_bytes()).await,
+        }.map_err(AppError::Downloader)
+    }
+
+    /// Stored lyrics of the audio. Virtual tracks without their own get the
+    /// synced lyrics of their part of the parent.
+    pub async fn get_lyrics(&self, id: u32) -> Result<Option<LyricsDTO>, AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        if let Some(lyrics) = self.stored_lyrics(id).await {
+            return Ok(Some(lyrics.into()));
+        }
+        let Some(segment) = audio.segment else {
+            return Ok(None);
+        };
+        Ok(self.stored_lyrics(segment.parent).await
+            .filter(|lyrics| lyrics.is_synced())
+            .map(|lyrics| lyrics.slice(segment.start, segment.end))
+            .filter(|lyrics| !lyrics.lines.is_empty())
+            .map(LyricsDTO::from))
+    }
+
+    /// Replaces the lyrics with the captions of the video, `None` when it has
+    /// none. Virtual tracks fetch the captions of their parent and store their
+    /// part of them, lyrics edited for the parent stay. The captions are looked
+    /// up again when the stored link expired.
+    pub async fn fetch_lyrics(&self, id: u32) -> Result<Option<LyricsDTO>, AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        let segment = audio.segment;
+        let parent = match segment {
+            Some(segment) => self.playlist.get_audio(segment.parent).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?,
+            None => audio,
+        };
+        let Some(subtitle) = Subtitle::preferred(&parent.subtitles) else {
+            return Ok(None);
+        };
+        let bytes = match Self::download_subtitle(subtitle).await {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                let url = match &parent.source {
+                    Source::YouTube(url) => url.clone(),
+                };
+                let details = self.ytdlp.fetch(url).await.map_err(AppError::YtDlp)?;
+                self.playlist.update_audio(parent.id, |audio| audio.subtitles = details.subtitles.clone()).await;
+                self.save_playlist().await;
+                let Some(subtitle) = Subtitle::preferred(&details.subtitles) else {
+                    return Ok(None);
+                };
+                Self::download_subtitle(subtitle).await?
+            },
+        };
+        let mut lyrics = Lyrics::parse_vtt(&String::from_utf8_lossy(&bytes));
+        if let Some(segment) = segment {
+            lyrics = lyrics.slice(segment.start, segment.end);
+        }
+        if lyrics.lines.is_empty() {
+            return Ok(None);
+        }
+        self.store_lyrics(id, &lyrics).await?;
+        Ok(Some(lyrics.into()))
+    }
+
+    async fn download_subtitle(subtitle: &Subtitle) -> Result<Vec<u8>, AppError> {
+        let mut bytes = Vec::new();
+        DefaultContentRetriever.download(subtitle.url.clone(), &mut bytes, |_, _| async { true }, 0).await
+            .map_err(AppError::Downloader)?;
+        Ok(bytes)
+    }
+
+    /// Captions for audios that have no lyrics yet, run after downloads.
+    pub(super) async fn fetch_missing_lyrics(&self, id: u32) -> Result<(), AppError> {
+        if self.stored_lyrics(id).await.is_none() {
+            self.fetch_lyrics(id).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads an `.lrc` file, plain text files import as plain lyrics.
+    pub async fn import_lyrics(&self, id: u32, path: String) -> Result<Option<LyricsDTO>, AppError> {
+        let content = tokio::fs::read_to_string(&path).await.map_err(|_| AppError::Format(formats::Error::InvalidFile))?;
+        self.set_lyrics(id, content).await
+    }
+
+    /// Stores edited lyrics in LRC, which may leave out the timestamps.
+    /// Empty text removes the lyrics.
+    pub async fn set_lyrics(&self, id: u32, text: String) -> Result<Option<LyricsDTO>, AppError> {
+        self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        let lyrics = Lyrics::parse_lrc(&text);
+        self.store_lyrics(id, &lyrics).await?;
+        Ok((!lyrics.lines.is_empty()).then(|| lyrics.into()))
+    }
+}
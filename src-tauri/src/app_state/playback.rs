@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::{Audio, RepeatMode, Source}, downloader::{self, ContentRetriever, DefaultContentRetriever, Storage}, player::{Bounds, EngineEvent, Status}, ytdlp::Details};
+
+use super::{event::Event, AppError, AppState, IndexedAudioDTO};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackStateDTO {
+    status: Status,
+    audio: Option<IndexedAudioDTO>,
+    position: f64,
+    duration: Option<f64>,
+    queue: Vec<u32>,
+    index: Option<usize>,
+    repeat: RepeatMode,
+    shuffle_seed: Option<u64>,
+    history: Vec<u32>,
+}
+
+/// Going to the previous audio restarts the current one when it has played longer than this.
+const RESTART_THRESHOLD: f64 = 3.0;
+
+/// How often the playing position is written to `queue.json`, in seconds.
+const SAVE_POSITION_INTERVAL: f64 = 10.0;
+
+impl AppState {
+    /// Starts listening to the playback engine, must be called once after the state is created.
+    pub fn start(self: &Arc<Self>) {
+        self.run_jobs();
+        self.save_plays();
+        let state = self.clone();
+        tauri::async_runtime::spawn(async move {
+            state.refresh_smart_playlists().await;
+            state.backfill_jobs().await;
+            state.backfill_durations().await;
+        });
+        let state = self.clone();
+        tauri::async_runtime::spawn(async move {
+            let Some(mut events) = state.player_events.lock().await.take() else {
+                return;
+            };
+            while let Some(event) = events.recv().await {
+                state.handle_engine_event(event).await;
+            }
+        });
+    }
+
+    async fn handle_engine_event(self: &Arc<Self>, event: EngineEvent) {
+        match event {
+            EngineEvent::Position { id, position, duration } => {
+                if self.player.current().await == Some(id) {
+                    self.player.update_position(position, duration).await;
+                    self.progress_listen(id, position).await;
+                    let mut queue = self.queue.lock().await;
+                    if (position - queue.position).abs() >= SAVE_POSITION_INTERVAL {
+                        queue.position = position;
+                        self.queue_file.save(&queue);
+                    }
+                    drop(queue);
+                    self.forward_playback().await;
+                }
+            },
+            EngineEvent::Finished { id } => {
+                if self.player.current().await == Some(id) {
+                    self.finish_listen(true).await;
+                    let next = self.queue.lock().await.next(true);
+                    self.save_queue().await;
+                    if let (Some(next), Err(error)) = (next, self.play_audio(next, 0.0).await) {
+                        self.stop_with_error(next, error).await;
+                    }
+                }
+            },
+            EngineEvent::Error { id, error } => self.stop_with_error(id, AppError::Decoder(error)).await,
+        }
+    }
+
+    async fn stop_with_error(&self, id: u32, error: AppError) {
+        if let Some(audio) = self.playlist.get_audio(id).await {
+            self.forwarder.forward_event(Event::ErrorPlayback { audio: self.indexed(audio).await, error });
+        }
+        self.finish_listen(false).await;
+        self.player.stop().await;
+        self.forward_playback().await;
+    }
+
+    pub async fn get_playback_state(&self) -> PlaybackStateDTO {
+        let player = self.player.get_state().await;
+        let queue = self.queue.lock().await.clone();
+        let current = player.current.or(queue.current());
+        let audio = match current {
+            Some(id) => match self.playlist.get_audio(id).await {
+                Some(audio) => Some(self.indexed(audio).await),
+                None => None,
+            },
+            None => None,
+        };
+        PlaybackStateDTO {
+            status: player.status,
+            audio,
+            position: if player.status == Status::Stopped { queue.position } else { player.position },
+            duration: player.duration,
+            queue: queue.items().to_vec(),
+            index: queue.index(),
+            repeat: queue.repeat(),
+            shuffle_seed: queue.shuffle_seed(),
+            history: queue.history(),
+        }
+    }
+
+    async fn forward_playback(&self) {
+        let state = self.get_playback_state().await;
+        self.forwarder.forward_event(Event::Playback { state });
+    }
+
+    async fn save_queue(&self) {
+        let mut queue = self.queue.lock().await;
+        let player = self.player.get_state().await;
+        if player.status != Status::Stopped {
+            queue.position = player.position;
+        }
+        self.queue_file.save(&queue);
+    }
+
+    /// Downloaded media of the audio. Audios the policy downloads are
+    /// downloaded first, others are fetched without being stored.
+    async fn load_media(self: &Arc<Self>, id: u32) -> Result<(Vec<u8>, String), AppError> {
+        let audio: Audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        if !self.downloader.has_file(&audio).await {
+            self.downloader.wait_for(audio.media_id()).await;
+        }
+        let mut details = None;
+        if !self.downloader.has_file(&audio).await && self.should_download(&audio, true).await {
+            let fetched = self.fetch_details(&audio).await?;
+            self.save_audio(audio.clone(), fetched.thumbnail.clone(), fetched.media.clone()).await;
+            details = Some(fetched);
+        }
+        if self.downloader.has_file(&audio).await {
+            let content = self.downloader.get_files(&audio).await.map_err(AppError::Downloader)?;
+            return Ok((content.media, content.media_mime));
+        }
+        let details = match details {
+            Some(details) => details,
+            None => self.fetch_details(&audio).await?,
+        };
+        let mut bytes = Vec::new();
+        let content = DefaultContentRetriever.download(details.media, &mut bytes, |_, _| async { true }, 0).await
+            .map_err(AppError::Downloader)?;
+        Ok((bytes, content.mime))
+    }
+
+    async fn fetch_details(&self, audio: &Audio) -> Result<Details, AppError> {
+        match &audio.source {
+            Source::YouTube(url) => self.ytdlp.fetch(url.clone()).await.map_err(AppError::YtDlp),
+        }
+    }
+
+    async fn play_audio(self: &Arc<Self>, id: Option<u32>, start: f64) -> Result<PlaybackStateDTO, AppError> {
+        match id {
+            Some(id) => {
+                let (bytes, mime) = self.load_media(id).await?;
+                let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+                let (start, bounds) = match audio.segment {
+                    Some(segment) => (start, Bounds { offset: segment.start, end: Some(segment.end), duration: Some(segment.end - segment.start) }),
+                    None => {
+                        let trim = self.playback_trim(&audio).await;
+                        let start = start.max(trim.map(|trim| trim.start).unwrap_or(0.0));
+                        (start, Bounds { end: trim.and_then(|trim| trim.end), ..Default::default() })
+                    },
+                };
+                self.begin_listen(id, start).await;
+                let gain = self.playback_gain(&audio).await;
+                self.player.load(id, bytes, mime, start, gain, bounds).await;
+            },
+            None => {
+                self.finish_listen(false).await;
+                self.player.stop().await;
+            },
+        }
+        self.forward_playback().await;
+        Ok(self.get_playback_state().await)
+    }
+
+    async fn update_queue<F: FnOnce(&mut crate::audio::PlayQueue)>(&self, update: F) -> PlaybackStateDTO {
+        update(&mut *self.queue.lock().await);
+        self.save_queue().await;
+        self.forward_playback().await;
+        self.get_playback_state().await
+    }
+
+    pub async fn play_queue(self: &Arc<Self>, ids: Vec<u32>, index: usize) -> Result<PlaybackStateDTO, AppError> {
+        let current = {
+            let mut queue = self.queue.lock().await;
+            queue.set(ids, index);
+            self.queue_file.save(&queue);
+            queue.current()
+        };
+        self.play_audio(current, 0.0).await
+    }
+
+    pub async fn jump(self: &Arc<Self>, index: usize) -> Result<PlaybackStateDTO, AppError> {
+        let current = self.queue.lock().await.jump(index);
+        self.save_queue().await;
+        self.play_audio(current, 0.0).await
+    }
+
+    /// Resumes the paused audio, or after a restart the audio the last session stopped at.
+    pub async fn resume(self: &Arc<Self>) -> Result<PlaybackStateDTO, AppError> {
+        if self.player.get_state().await.status == Status::Stopped {
+            let (current, position) = {
+                let queue = self.queue.lock().await;
+                (queue.current(), queue.position)
+            };
+            return self.play_audio(current, position).await;
+        }
+        self.player.play().await;
+        self.forward_playback().await;
+        Ok(self.get_playback_state().await)
+    }
+
+    pub async fn pause(&self) -> PlaybackStateDTO {
+        self.player.pause().await;
+        self.save_queue().await;
+        self.forward_playback().await;
+        self.get_playback_state().await
+    }
+
+    pub async fn seek(&self, position: f64) -> PlaybackStateDTO {
+        self.player.seek(position).await;
+        self.save_queue().await;
+        self.forward_playback().await;
+        self.get_playback_state().await
+    }
+
+    pub async fn next(self: &Arc<Self>) -> Result<PlaybackStateDTO, AppError> {
+        let next = self.queue.lock().await.next(false);
+        self.save_queue().await;
+        self.play_audio(next, 0.0).await
+    }
+
+    pub async fn previous(self: &Arc<Self>) -> Result<PlaybackStateDTO, AppError> {
+        let restart = self.player.get_state().await.position > RESTART_THRESHOLD;
+        let previous = {
+            let mut queue = self.queue.lock().await;
+            if restart { queue.current() } else { queue.previous() }
+        };
+        self.save_queue().await;
+        self.play_audio(previous, 0.0).await
+    }
+
+    pub async fn queue_play_next(&self, id: u32) -> PlaybackStateDTO {
+        self.update_queue(|queue| queue.play_next(id)).await
+    }
+
+    pub async fn queue_add(&self, id: u32) -> PlaybackStateDTO {
+        self.update_queue(|queue| queue.add(id)).await
+    }
+
+    pub async fn queue_move(&self, from: usize, to: usize) -> PlaybackStateDTO {
+        self.update_queue(|queue| queue.move_item(from, to)).await
+    }
+
+    /// Removing the playing entry goes on with the one that followed it.
+    pub async fn queue_remove(self: &Arc<Self>, index: usize) -> PlaybackStateDTO {
+        let playing = self.player.current().await;
+        let (playing, following) = {
+            let queue = self.queue.lock().await;
+            (queue.index() == Some(index) && playing.is_some() && playing == queue.current(), index + 1 < queue.items().len())
+        };
+        if playing {
+            self.finish_listen(false).await;
+            self.player.stop().await;
+        }
+        self.update_queue(|queue| queue.remove_at(index)).await;
+        if playing && following {
+            let next = self.queue.lock().await.current();
+            if let (Some(next), Err(error)) = (next, self.play_audio(next, 0.0).await) {
+                self.stop_with_error(next, error).await;
+            }
+        }
+        self.get_playback_state().await
+    }
+
+    pub async fn set_shuffle(&self, enabled: bool, seed: Option<u64>) -> PlaybackStateDTO {
+        self.update_queue(|queue| queue.set_shuffle(enabled, seed)).await
+    }
+
+    pub async fn set_repeat(&self, repeat: RepeatMode) -> PlaybackStateDTO {
+        self.update_queue(|queue| queue.set_repeat(repeat)).await
+    }
+
+    pub(super) async fn remove_from_player(&self, id: u32) {
+        if self.player.current().await == Some(id) {
+            self.finish_listen(false).await;
+            self.player.stop().await;
+        }
+        self.update_queue(|queue| queue.remove(id)).await;
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::{Audio, Trim}, downloader::{self, Storage}, jobs::JobKind, silence::{Silence, SilenceDetector}};
+
+use super::{jobs::JobProgress, AppError, AppState};
+
+/// Stored in the folder of the audio next to `index.json`.
+pub(super) const SILENCE_FILE: &str = "silence.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrimDTO {
+    /// Silence found with the current settings, `None` for audios that aren't
+    /// downloaded and for virtual tracks, which play their segment.
+    detected: Option<Trim>,
+    user: Option<Trim>,
+    /// What playback skips to.
+    effective: Option<Trim>,
+}
+
+impl AppState {
+    /// Decodes the downloaded media and stores the silence at its ends.
+    pub(super) async fn analyze_silence(&self, id: u32, progress: JobProgress) -> Result<Silence, AppError> {
+        let (threshold, min_length) = {
+            let settings = self.settings.lock().await;
+            (settings.silence_threshold, settings.silence_min_length)
+        };
+        let detector = move |channels, sample_rate| SilenceDetector::new(channels, sample_rate, threshold, min_length);
+        let detected = self.run_analysis(id, None, detector, progress).await?
+            .unwrap_or(Silence { threshold, min_length, start: 0.0, end: None });
+        self.downloader.save_analysis(id, SILENCE_FILE, &detected).await.map_err(AppError::Downloader)?;
+        Ok(detected)
+    }
+
+    /// Stored silence, unless it was detected with other settings.
+    async fn detected_silence(&self, id: u32) -> Option<Silence> {
+        let silence: Silence = self.downloader.load_analysis(id, SILENCE_FILE).await?;
+        let settings = self.settings.lock().await;
+        silence.detected_with(settings.silence_threshold, settings.silence_min_length).then_some(silence)
+    }
+
+    async fn effective_trim(&self, audio: &Audio, detected: Option<Trim>) -> Option<Trim> {
+        if audio.trim.is_some() {
+            return audio.trim;
+        }
+        match self.settings.lock().await.trim_silence {
+            true => detected,
+            false => None,
+        }
+    }
+
+    /// Trim for the player. Downloaded audios that weren't analyzed with the
+    /// current settings play whole and get analyzed for the next time.
+    pub(super) async fn playback_trim(&self, audio: &Audio) -> Option<Trim> {
+        let detected = self.detected_silence(audio.id).await;
+        if detected.is_none() && audio.trim.is_none() && self.downloader.has_file(audio).await {
+            self.queue_job(audio.id, JobKind::Silence).await;
+        }
+        self.effective_trim(audio, detected.map(|silence| silence.trim())).await
+    }
+
+    pub async fn get_trim(&self, id: u32) -> Result<TrimDTO, AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        let mut detected = self.detected_silence(id).await.map(|silence| silence.trim());
+        if detected.is_none() && audio.segment.is_none() && self.downloader.has_file(&audio).await {
+            detected = Some(self.analyze_silence(id, JobProgress::default()).await?.trim());
+        }
+        Ok(TrimDTO {
+            detected,
+            user: audio.trim,
+            effective: self.effective_trim(&audio, detected).await,
+        })
+    }
+
+    /// Overrides the detected silence, `None` goes back to it. An end before
+    /// the start plays to the end.
+    pub async fn set_trim(&self, id: u32, trim: Option<Trim>) -> Result<TrimDTO, AppError> {
+        let trim = trim.map(|trim| {
+            let start = trim.start.max(0.0);
+            Trim { start, end: trim.end.filter(|end| *end > start) }
+        });
+        self.playlist.update_audio(id, |audio| audio.trim = trim).await
+            .ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        self.save_playlist().await;
+        self.get_trim(id).await
+    }
+}
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::{Rule, RuleContext, RuleMatch, SmartPlaylist, SmartPlaylistError, Source}, decoder::Decoder, downloader::{self, Storage}, jobs::JobKind, ytdlp::FetchError};
+
+use super::{event::Event, storage::now, AppError, AppState};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylistDTO {
+    pub(super) id: u32,
+    pub(super) name: String,
+    rules: Vec<Rule>,
+    rule_match: RuleMatch,
+    pub(super) audios: Vec<u32>,
+}
+
+impl AppState {
+    pub(super) async fn evaluate_smart_playlists(&self) -> Vec<SmartPlaylistDTO> {
+        let now = now();
+        let mut audios = Vec::new();
+        for audio in self.playlist.get_audios().await {
+            let downloaded = self.downloader.has_file(&audio).await;
+            audios.push((audio, RuleContext { now, downloaded }));
+        }
+        self.smart_playlists.lock().await.iter().map(|playlist| SmartPlaylistDTO {
+            id: playlist.id,
+            name: playlist.name.clone(),
+            rules: playlist.rules.clone(),
+            rule_match: playlist.rule_match,
+            audios: audios.iter()
+                .filter(|(audio, context)| playlist.matches(audio, *context))
+                .map(|(audio, _)| audio.id)
+                .collect(),
+        }).collect()
+    }
+
+    pub async fn get_smart_playlists(&self) -> Vec<SmartPlaylistDTO> {
+        let playlists = self.evaluate_smart_playlists().await;
+        *self.evaluated_smart_playlists.lock().await = playlists.clone();
+        playlists
+    }
+
+    /// Evaluates the rules again and notifies the webview when any playlist changed.
+    pub(super) async fn refresh_smart_playlists(&self) {
+        let playlists = self.evaluate_smart_playlists().await;
+        let mut evaluated = self.evaluated_smart_playlists.lock().await;
+        if *evaluated != playlists {
+            *evaluated = playlists.clone();
+            self.forwarder.forward_event(Event::SmartPlaylistsChanged { playlists });
+        }
+    }
+
+    /// Duration rules never match audios without a duration, audios added before
+    /// it was stored get a job for it, once.
+    pub(super) async fn backfill_durations(&self) {
+        if self.jobs.lock().await.is_durations_backfilled() {
+            return;
+        }
+        for audio in self.playlist.get_audios().await {
+            if audio.duration.is_none() && audio.segment.is_none() {
+                self.queue_job(audio.id, JobKind::Duration).await;
+            }
+        }
+        let mut jobs = self.jobs.lock().await;
+        jobs.set_durations_backfilled();
+        self.jobs_file.save(&jobs);
+    }
+
+    /// Stores the duration of the media file, or the one yt-dlp reports.
+    pub(super) async fn fetch_duration(&self, id: u32) -> Result<(), AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        if audio.duration.is_some() {
+            return Ok(());
+        }
+        let duration = match self.downloader.local_files(audio.id).await {
+            Ok(files) => tokio::task::spawn_blocking(move || Decoder::open_file(&files.media).ok()?.duration())
+                .await.ok().flatten(),
+            Err(_) => None,
+        };
+        let duration = match (duration, &audio.source) {
+            (Some(duration), _) => duration,
+            (None, Source::YouTube(url)) => self.ytdlp.fetch(url.clone()).await.map_err(AppError::YtDlp)?
+                .duration.ok_or(AppError::YtDlp(FetchError::NotFound))?,
+        };
+        self.playlist.update_audio(audio.id, |audio| audio.duration = Some(duration)).await;
+        self.save_playlist().await;
+        self.refresh_smart_playlists().await;
+        Ok(())
+    }
+
+    async fn save_smart_playlists(&self) {
+        let playlists = self.smart_playlists.lock().await;
+        self.smart_playlists_file.save(&playlists);
+    }
+
+    pub async fn create_smart_playlist(&self, name: String, rules: Vec<Rule>, rule_match: RuleMatch) -> Vec<SmartPlaylistDTO> {
+        self.smart_playlists.lock().await.push(SmartPlaylist::create(name, rules, rule_match));
+        self.save_smart_playlists().await;
+        self.refresh_smart_playlists().await;
+        self.evaluated_smart_playlists.lock().await.clone()
+    }
+
+    pub async fn update_smart_playlist(&self, id: u32, name: String, rules: Vec<Rule>, rule_match: RuleMatch) -> Result<Vec<SmartPlaylistDTO>, AppError> {
+        {
+            let mut playlists = self.smart_playlists.lock().await;
+            let playlist = playlists.iter_mut().find(|playlist| playlist.id == id)
+                .ok_or(AppError::SmartPlaylist(SmartPlaylistError::NotFound))?;
+            playlist.name = name;
+            playlist.rules = rules;
+            playlist.rule_match = rule_match;
+        }
+        self.save_smart_playlists().await;
+        self.refresh_smart_playlists().await;
+        Ok(self.evaluated_smart_playlists.lock().await.clone())
+    }
+
+    pub async fn remove_smart_playlist(&self, id: u32) -> Vec<SmartPlaylistDTO> {
+        self.smart_playlists.lock().await.retain(|playlist| playlist.id != id);
+        self.save_smart_playlists().await;
+        self.refresh_smart_playlists().await;
+        self.evaluated_smart_playlists.lock().await.clone()
+    }
+}
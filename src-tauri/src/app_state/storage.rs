@@ -0,0 +1,118 @@
+use std::{collections::HashSet, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Audio, downloader::Storage};
+
+use super::{event::Event, AppError, AppState, IndexedAudioDTO};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioUsageDTO {
+    audio: IndexedAudioDTO,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskUsageDTO {
+    audios: Vec<AudioUsageDTO>,
+    total: u64,
+    quota: Option<u64>,
+}
+
+/// Listens ending within this long of each other are written to
+/// `playlist.json` together.
+const SAVE_PLAYS_DELAY: Duration = Duration::from_secs(10);
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+impl AppState {
+    /// Writes changed play counts after [`SAVE_PLAYS_DELAY`]. Must be called
+    /// once after the state is created.
+    pub(super) fn save_plays(self: &Arc<Self>) {
+        let state = self.clone();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                state.plays_changed.notified().await;
+                tokio::time::sleep(SAVE_PLAYS_DELAY).await;
+                state.save_playlist().await;
+            }
+        });
+    }
+
+    pub async fn get_disk_usage(&self) -> Result<DiskUsageDTO, AppError> {
+        let mut audios = Vec::new();
+        for audio in self.playlist.get_audios().await {
+            let size = self.downloader.size(audio.id).await;
+            audios.push(AudioUsageDTO { audio: self.indexed(audio).await, size });
+        }
+        Ok(DiskUsageDTO {
+            total: audios.iter().map(|usage| usage.size).sum(),
+            audios,
+            quota: self.settings.lock().await.storage_quota,
+        })
+    }
+
+    pub async fn set_pinned(&self, id: u32, pinned: bool) -> Result<IndexedAudioDTO, AppError> {
+        let audio = self.playlist.update_audio(id, |audio| audio.pinned = pinned).await
+            .ok_or(AppError::Downloader(crate::downloader::Error::NotFound))?;
+        self.save_playlist().await;
+        if !pinned {
+            self.enforce_quota(None).await;
+        }
+        Ok(self.indexed(audio).await)
+    }
+
+    /// Removes local files of the least recently played audios until the library
+    /// fits into the storage quota. Library entries stay, so evicted audios are
+    /// downloaded again by `get_media`.
+    pub(crate) async fn enforce_quota(&self, keep: Option<u32>) {
+        let quota = match self.settings.lock().await.storage_quota {
+            Some(quota) => quota,
+            None => return,
+        };
+        let audios = self.playlist.get_audios().await;
+        // The playing and queued audios are needed soon, their media stays.
+        let mut needed = self.queue.lock().await.items().to_vec();
+        needed.extend(self.player.current().await);
+        let needed: HashSet<u32> = audios.iter()
+            .filter(|audio| needed.contains(&audio.id))
+            .map(|audio| audio.media_id())
+            .collect();
+        let mut candidates = Vec::new();
+        let mut total = 0;
+        for audio in audios.iter() {
+            let size = self.downloader.size(audio.id).await;
+            total += size;
+            // Chapters play the media of their audio, pinning or playing one counts for it.
+            let chapters: Vec<&Audio> = audios.iter().filter(|chapter| chapter.segment.is_some_and(|segment| segment.parent == audio.id)).collect();
+            let pinned = audio.pinned || chapters.iter().any(|chapter| chapter.pinned);
+            let last_played = chapters.iter().map(|chapter| chapter.last_played).fold(audio.last_played, |latest, played| latest.max(played));
+            let evictable = !pinned
+                && Some(audio.id) != keep
+                && !needed.contains(&audio.id)
+                && size > 0
+                && !self.downloader.is_in_queue(audio.id).await;
+            if evictable {
+                // Never played audios count as used when they were added.
+                candidates.push((audio, size, last_played.or(audio.added_at)));
+            }
+        }
+        candidates.sort_by_key(|(_, _, last_played)| last_played.unwrap_or(0));
+        let mut evicted = false;
+        for (audio, size, _) in candidates {
+            if total <= quota {
+                break;
+            }
+            if self.downloader.evict(audio.id).await.is_ok() {
+                total -= size;
+                evicted = true;
+                self.forwarder.forward_event(Event::Evicted { audio: self.indexed(audio.clone()).await });
+            }
+        }
+        if evicted {
+            self.refresh_smart_playlists().await;
+        }
+    }
+}
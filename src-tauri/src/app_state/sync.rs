@@ -0,0 +1,194 @@
+use std::{collections::{HashMap, HashSet}, path::{Component, Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{downloader, formats::{self, PlaylistEntry, PlaylistFormat, TrackName}};
+
+use super::{event::Event, export::unique_path, AppError, AppState, IndexedAudioDTO};
+
+/// Written to the root of the target so the next sync only copies changes.
+const MANIFEST: &str = ".furplayer-sync.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SyncedAudio {
+    path: PathBuf,
+    title: String,
+    author: String,
+    album: Option<String>,
+    media_size: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    template: String,
+    audios: HashMap<u32, SyncedAudio>,
+    playlists: Vec<PathBuf>,
+}
+
+/// Only paths down from the root of the target are touched, anything else
+/// in an edited manifest could remove files outside of it.
+fn is_inside(path: &Path) -> bool {
+    path.components().next().is_some() && path.components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+impl Manifest {
+    async fn load(dir: &Path) -> Self {
+        let mut manifest: Self = tokio::fs::read_to_string(dir.join(MANIFEST)).await.ok()
+            .and_then(|serialized| serde_json::from_str(&serialized).ok())
+            .unwrap_or_default();
+        manifest.audios.retain(|_, synced| is_inside(&synced.path));
+        manifest.playlists.retain(|path| is_inside(path));
+        manifest
+    }
+
+    async fn save(&self, dir: &Path) -> Result<(), AppError> {
+        let serialized = serde_json::to_string(self).map_err(|_| AppError::Format(formats::Error::Unknown))?;
+        tokio::fs::write(dir.join(MANIFEST), serialized).await.map_err(|_| AppError::Format(formats::Error::Unknown))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncFailureDTO {
+    audio: IndexedAudioDTO,
+    error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncReportDTO {
+    copied: u32,
+    removed: u32,
+    unchanged: u32,
+    failed: Vec<SyncFailureDTO>,
+}
+
+/// Removes the file and the directories it leaves empty, up to `root`.
+async fn remove_file(root: &Path, relative: &Path) {
+    let _ = tokio::fs::remove_file(root.join(relative)).await;
+    let mut parent = relative.parent();
+    while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty()) {
+        if tokio::fs::remove_dir(root.join(dir)).await.is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+impl AppState {
+    /// One-way sync of the audios of the smart playlists into `dir`, with an
+    /// M3U playlist for each. Only files written by earlier syncs are removed.
+    pub async fn sync_device(&self, dir: String, playlists: Vec<u32>, template: String) -> Result<SyncReportDTO, AppError> {
+        let example = TrackName { id: 0, title: "", author: "", album: None, ext: "" };
+        formats::render(&template, &example).map_err(AppError::Format)?;
+        let dir = PathBuf::from(dir);
+        tokio::fs::create_dir_all(&dir).await.map_err(|_| AppError::Format(formats::Error::Unknown))?;
+
+        let evaluated = self.evaluate_smart_playlists().await;
+        let mut selected = Vec::new();
+        for id in playlists.iter() {
+            let playlist = evaluated.iter().find(|playlist| playlist.id == *id)
+                .ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+            for audio in playlist.audios.iter() {
+                if !selected.contains(audio) {
+                    selected.push(*audio);
+                }
+            }
+        }
+
+        let mut manifest = Manifest::load(&dir).await;
+        let mut report = SyncReportDTO { copied: 0, removed: 0, unchanged: 0, failed: Vec::new() };
+        let removed: Vec<u32> = manifest.audios.keys().filter(|id| !selected.contains(id)).cloned().collect();
+        for id in removed {
+            if let Some(synced) = manifest.audios.remove(&id) {
+                remove_file(&dir, &synced.path).await;
+                report.removed += 1;
+            }
+        }
+
+        let mut used: HashSet<PathBuf> = manifest.audios.values().map(|synced| synced.path.clone()).collect();
+        let total = selected.len() as u64;
+        for (done, audio) in self.select_audios(Some(selected)).await.into_iter().enumerate() {
+            self.forwarder.forward_event(Event::Sync { synced: done as u64, total });
+            let media_size = match self.downloader.local_files(audio.media_id()).await {
+                Ok(files) => tokio::fs::metadata(&files.media).await.map(|metadata| metadata.len()).unwrap_or(0),
+                Err(err) => {
+                    report.failed.push(SyncFailureDTO { audio: self.indexed(audio).await, error: AppError::Downloader(err).to_string() });
+                    continue;
+                },
+            };
+            let previous = manifest.audios.get(&audio.id).cloned();
+            let unchanged = match previous.as_ref() {
+                Some(synced) => manifest.template == template
+                    && synced.title == audio.title
+                    && synced.author == audio.author
+                    && synced.album == audio.album
+                    && synced.media_size == media_size
+                    && tokio::fs::try_exists(dir.join(&synced.path)).await.unwrap_or(false),
+                None => false,
+            };
+            if unchanged {
+                report.unchanged += 1;
+                continue;
+            }
+            // The previous file may be overwritten by the new one, but stays
+            // until the export succeeded.
+            if let Some(previous) = previous.as_ref() {
+                used.remove(&previous.path);
+            }
+            match self.export_track(&audio, &dir, &template, &mut used).await {
+                Ok((path, _)) => {
+                    if let Some(previous) = previous.filter(|previous| previous.path != path) {
+                        remove_file(&dir, &previous.path).await;
+                    }
+                    manifest.audios.insert(audio.id, SyncedAudio {
+                        path,
+                        title: audio.title.clone(),
+                        author: audio.author.clone(),
+                        album: audio.album.clone(),
+                        media_size,
+                    });
+                    report.copied += 1;
+                },
+                Err(err) => {
+                    if let Some(previous) = previous {
+                        used.insert(previous.path);
+                    }
+                    report.failed.push(SyncFailureDTO { audio: self.indexed(audio).await, error: err.to_string() });
+                },
+            }
+        }
+        manifest.template = template;
+        // The copied tracks are recorded even if writing a playlist fails.
+        manifest.save(&dir).await?;
+
+        let mut written = Vec::new();
+        for playlist in evaluated.iter().filter(|playlist| playlists.contains(&playlist.id)) {
+            let mut entries = Vec::new();
+            for audio in self.select_audios(Some(playlist.audios.clone())).await {
+                let Some(synced) = manifest.audios.get(&audio.id) else {
+                    continue;
+                };
+                let location = synced.path.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/");
+                entries.push(PlaylistEntry {
+                    title: audio.title,
+                    author: audio.author,
+                    album: audio.album,
+                    tags: audio.tags,
+                    duration: audio.duration,
+                    source: location.clone(),
+                    location,
+                });
+            }
+            let path = unique_path(PathBuf::from(format!("{}.m3u8", formats::sanitize(&playlist.name))), &mut used);
+            let content = PlaylistFormat::M3u8.export(&entries).map_err(AppError::Format)?;
+            tokio::fs::write(dir.join(&path), content).await.map_err(|_| AppError::Format(formats::Error::Unknown))?;
+            written.push(path);
+        }
+        for path in manifest.playlists.iter().filter(|path| !written.contains(path)) {
+            remove_file(&dir, path).await;
+        }
+        manifest.playlists = written;
+        manifest.save(&dir).await?;
+        self.forwarder.forward_event(Event::Sync { synced: total, total });
+        Ok(report)
+    }
+}
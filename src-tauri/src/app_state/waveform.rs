@@ -0,0 +1,38 @@
+use crate::{downloader, waveform::{PeakMeter, Waveform}};
+
+use super::{jobs::JobProgress, AppError, AppState};
+
+/// Stored in the folder of the audio next to `index.json`.
+pub(super) const WAVEFORM_FILE: &str = "waveform.bin";
+
+impl AppState {
+    /// Decodes the downloaded media and stores its peaks.
+    pub(super) async fn analyze_waveform(&self, id: u32, progress: JobProgress) -> Result<Waveform, AppError> {
+        let measured = self.run_analysis(id, None, PeakMeter::new, progress).await?.unwrap_or_default();
+        self.downloader.save_analysis_file(id, WAVEFORM_FILE, &measured.to_bytes()).await.map_err(AppError::Downloader)?;
+        Ok(measured)
+    }
+
+    /// Min and max peaks of the downloaded audio in `buckets` stretches,
+    /// measured first when missing. Virtual tracks get their part of the
+    /// peaks of the shared media.
+    pub async fn get_waveform(&self, id: u32, buckets: usize) -> Result<Vec<(f32, f32)>, AppError> {
+        let audio = self.playlist.get_audio(id).await.ok_or(AppError::Downloader(downloader::Error::NotFound))?;
+        let media_id = audio.media_id();
+        let stored = self.downloader.load_analysis_file(media_id, WAVEFORM_FILE).await
+            .and_then(|bytes| Waveform::from_bytes(&bytes));
+        let waveform = match stored {
+            Some(waveform) => waveform,
+            None => self.analyze_waveform(media_id, JobProgress::default()).await?,
+        };
+        let duration = match audio.segment {
+            Some(segment) => self.playlist.get_audio(segment.parent).await.and_then(|parent| parent.duration),
+            None => None,
+        };
+        let waveform = match (audio.segment, duration) {
+            (Some(segment), Some(duration)) if duration > 0.0 => waveform.slice(segment.start / duration, segment.end / duration),
+            _ => waveform,
+        };
+        Ok(waveform.downsample(buckets))
+    }
+}
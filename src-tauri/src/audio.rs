@@ -1,4 +1,10 @@
-#[derive(Debug, Clone)]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{key::Key, settings::DownloadPolicy};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Source {
     YouTube(String),
 }
@@ -16,13 +22,92 @@ pub struct Audio {
     pub id: u32,
     pub title: String,
     pub author: String,
+    pub album: Option<String>,
+    pub tags: Vec<String>,
     pub source: Source,
+    /// Pinned audios are never evicted to satisfy the storage quota.
+    pub pinned: bool,
+    /// Unix timestamp in seconds of the latest listen.
+    pub last_played: Option<u64>,
+    /// Listens that count as plays, see [`Listen::is_play`].
+    pub play_count: u32,
+    /// Overrides the global download policy when set.
+    pub download_policy: Option<DownloadPolicy>,
+    /// Unix timestamp in seconds, unknown for audios added by older versions.
+    pub added_at: Option<u64>,
+    /// Seconds.
+    pub duration: Option<f64>,
+    /// Set by the user, replaces the detected silence.
+    pub trim: Option<Trim>,
+    pub chapters: Vec<Chapter>,
+    /// WebVTT captions of the video, the best match first.
+    pub subtitles: Vec<Subtitle>,
+    /// Set for virtual tracks split from the chapters of another audio.
+    pub segment: Option<Segment>,
+    /// Beats per minute, detected after downloading or corrected by the user.
+    pub bpm: Option<f64>,
+    /// Detected after downloading or corrected by the user.
+    pub key: Option<Key>,
+    /// The user corrected `bpm` or `key`, detection leaves them alone.
+    pub bpm_key_corrected: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    /// Seconds.
+    pub start: f64,
+    /// Seconds.
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subtitle {
+    pub language: String,
+    pub url: String,
+    /// Generated by speech recognition.
+    pub automatic: bool,
+    /// In the language of the video.
+    #[serde(default)]
+    pub original: bool,
+}
+
+impl Subtitle {
+    /// Captions in the language of the video first, uploaded ones before
+    /// automatic ones, otherwise the order of the list.
+    pub fn preferred(subtitles: &[Subtitle]) -> Option<&Subtitle> {
+        subtitles.iter().min_by_key(|subtitle| (!subtitle.original, subtitle.automatic))
+    }
+}
+
+/// Part of the media of the parent audio that a virtual track plays.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub parent: u32,
+    /// Seconds.
+    pub start: f64,
+    /// Seconds.
+    pub end: f64,
+}
+
+/// Part of the audio that is played, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Trim {
+    pub start: f64,
+    /// Plays to the end when unset.
+    pub end: Option<f64>,
 }
 
+mod history;
 mod playlist;
+mod queue;
+mod smart;
 
+pub use history::{History, HistoryFile, Listen};
 pub use playlist::Playlist;
-pub use playlist::PlaylistIOImpl;
+pub use playlist::{audios_from_json, audios_to_json, PlaylistIOImpl};
+pub use queue::{PlayQueue, QueueFile, RepeatMode};
+pub use smart::{Error as SmartPlaylistError, Rule, RuleContext, RuleMatch, SmartPlaylist, SmartPlaylistsFile};
 
 impl Audio {
     pub fn create(title: String, author: String, source: Source) -> Self {
@@ -30,7 +115,37 @@ impl Audio {
             id: rand::random(),
             title,
             author,
+            album: None,
+            tags: Vec::new(),
             source,
+            pinned: false,
+            last_played: None,
+            play_count: 0,
+            download_policy: None,
+            added_at: Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()),
+            duration: None,
+            trim: None,
+            chapters: Vec::new(),
+            subtitles: Vec::new(),
+            segment: None,
+            bpm: None,
+            key: None,
+            bpm_key_corrected: false,
         }
     }
+
+    /// Virtual track playing one chapter of `parent`.
+    pub fn from_chapter(parent: &Audio, chapter: &Chapter) -> Self {
+        let mut audio = Self::create(chapter.title.clone(), parent.author.clone(), parent.source.clone());
+        audio.album = Some(parent.album.clone().unwrap_or(parent.title.clone()));
+        audio.tags = parent.tags.clone();
+        audio.duration = Some(chapter.end - chapter.start);
+        audio.segment = Some(Segment { parent: parent.id, start: chapter.start, end: chapter.end });
+        audio
+    }
+
+    /// Id the downloaded files are stored under, virtual tracks share the files of their parent.
+    pub fn media_id(&self) -> u32 {
+        self.segment.map(|segment| segment.parent).unwrap_or(self.id)
+    }
 }
@@ -0,0 +1,152 @@
+use std::{collections::HashMap, io::Write, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listen {
+    pub audio_id: u32,
+    /// Unix timestamp in seconds.
+    pub started_at: u64,
+    /// Seconds actually listened, seeking doesn't count.
+    pub listened: f64,
+    pub completed: bool,
+}
+
+impl Listen {
+    /// Completed listens and listens of at least [`MIN_PLAY`] seconds, skips
+    /// right after starting don't count.
+    pub fn is_play(&self) -> bool {
+        self.completed || self.listened >= MIN_PLAY
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListenStats {
+    pub skip_count: u32,
+    pub listened: f64,
+}
+
+/// Seconds a listen that wasn't completed takes to count as a play.
+pub const MIN_PLAY: f64 = 30.0;
+
+/// Seconds between two reported positions above which the jump is treated as a seek.
+const MAX_PROGRESS: f64 = 1.0;
+
+#[derive(Debug, Default)]
+pub struct History {
+    listens: Vec<Listen>,
+    stats: HashMap<u32, ListenStats>,
+    /// The listen in progress with the last reported position.
+    current: Option<(Listen, f64)>,
+}
+
+impl History {
+    pub fn new(listens: Vec<Listen>) -> Self {
+        let mut history = Self::default();
+        for listen in listens {
+            history.record(listen);
+        }
+        history
+    }
+
+    pub fn record(&mut self, listen: Listen) {
+        let stats = self.stats.entry(listen.audio_id).or_default();
+        if !listen.completed {
+            stats.skip_count += 1;
+        }
+        stats.listened += listen.listened;
+        self.listens.push(listen);
+    }
+
+    pub fn begin(&mut self, audio_id: u32, started_at: u64, position: f64) {
+        let listen = Listen { audio_id, started_at, listened: 0.0, completed: false };
+        self.current = Some((listen, position));
+    }
+
+    /// Counts the time played since the last position, seeks aren't counted.
+    pub fn progress(&mut self, audio_id: u32, position: f64) {
+        if let Some((listen, last)) = self.current.as_mut().filter(|(listen, _)| listen.audio_id == audio_id) {
+            let delta = position - *last;
+            if delta > 0.0 && delta <= MAX_PROGRESS {
+                listen.listened += delta;
+            }
+            *last = position;
+        }
+    }
+
+    /// Ends the listen in progress and records it, returns it if there was one.
+    pub fn finish(&mut self, completed: bool) -> Option<Listen> {
+        let (mut listen, _) = self.current.take()?;
+        listen.completed = completed;
+        self.record(listen.clone());
+        Some(listen)
+    }
+
+    /// Moves the listens of one audio to another, for merged duplicates.
+    pub fn reassign(&mut self, from: u32, to: u32) {
+        let listens = std::mem::take(&mut self.listens).into_iter()
+            .map(|listen| Listen { audio_id: if listen.audio_id == from { to } else { listen.audio_id }, ..listen })
+            .collect();
+        let current = self.current.take();
+        *self = Self::new(listens);
+        self.current = current.map(|(listen, position)| {
+            (Listen { audio_id: if listen.audio_id == from { to } else { listen.audio_id }, ..listen }, position)
+        });
+    }
+
+    pub fn stats(&self, audio_id: u32) -> ListenStats {
+        self.stats.get(&audio_id).cloned().unwrap_or_default()
+    }
+
+    pub fn listens(&self) -> &[Listen] {
+        &self.listens
+    }
+
+    /// Listens started within `from..to`, newest first.
+    pub fn range(&self, from: Option<u64>, to: Option<u64>) -> Vec<Listen> {
+        let mut listens: Vec<Listen> = self.listens.iter()
+            .filter(|listen| from.map(|from| listen.started_at >= from).unwrap_or(true))
+            .filter(|listen| to.map(|to| listen.started_at < to).unwrap_or(true))
+            .cloned()
+            .collect();
+        listens.reverse();
+        listens
+    }
+}
+
+/// Listens as JSON lines, new ones are appended instead of rewriting the file.
+#[derive(Debug)]
+pub struct HistoryFile(pub String);
+
+impl HistoryFile {
+    pub fn load(&self) -> Option<History> {
+        let serialized = std::fs::read_to_string(&self.0).ok()?;
+        let listens = serialized.lines()
+            .filter_map(|line| serde_json::from_str::<Listen>(line).ok())
+            .collect();
+        Some(History::new(listens))
+    }
+
+    pub fn save(&self, history: &History) {
+        let serialized: String = history.listens().iter()
+            .filter_map(|listen| serde_json::to_string(listen).ok())
+            .map(|line| line + "\n")
+            .collect();
+        if let Some(parent) = Path::new(&self.0).parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = std::fs::write(&self.0, serialized);
+    }
+
+    pub fn append(&self, listen: &Listen) {
+        if let Ok(serialized) = serde_json::to_string(listen) {
+            if let Some(parent) = Path::new(&self.0).parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(&self.0);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{}", serialized);
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use super::{Audio, Source};
+use crate::{key::Key, settings::DownloadPolicy};
+
+use super::{Audio, Chapter, Segment, Source, Subtitle, Trim};
 
 #[derive(Debug)]
 pub struct Playlist<T: PlaylistIO<T>> {
@@ -52,6 +54,13 @@ impl<T: PlaylistIO<T>> Playlist<T> {
         audios.retain(|audio| audio.id != id);
     }
 
+    pub async fn update_audio<F: FnOnce(&mut Audio)>(&self, id: u32, update: F) -> Option<Audio> {
+        let mut audios = self.audios.lock().await;
+        let audio = audios.iter_mut().find(|audio| audio.id == id)?;
+        update(audio);
+        Some(audio.clone())
+    }
+
     pub async fn get_audios(&self) -> Vec<Audio> {
         self.audios.lock().await.clone()
     }
@@ -75,7 +84,37 @@ struct AudioDTO {
     id: u32,
     title: String,
     author: String,
+    #[serde(default)]
+    album: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
     source: LocalSource,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    last_played: Option<u64>,
+    #[serde(default)]
+    play_count: u32,
+    #[serde(default)]
+    download_policy: Option<DownloadPolicy>,
+    #[serde(default)]
+    added_at: Option<u64>,
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    trim: Option<Trim>,
+    #[serde(default)]
+    chapters: Vec<Chapter>,
+    #[serde(default)]
+    subtitles: Vec<Subtitle>,
+    #[serde(default)]
+    segment: Option<Segment>,
+    #[serde(default)]
+    bpm: Option<f64>,
+    #[serde(default)]
+    key: Option<Key>,
+    #[serde(default)]
+    bpm_key_corrected: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -83,35 +122,72 @@ struct PlaylistDTO {
     audios: Vec<AudioDTO>,
 }
 
-impl PlaylistIO<PlaylistIOImpl> for PlaylistIOImpl {
-    async fn load(&self, playlist: &Playlist<PlaylistIOImpl>) -> Result<(), LoadError> {
-        let serialized = std::fs::read_to_string(self.0.clone()).map_err(|_| LoadError::NotFound)?;
-        let playlist_dto: PlaylistDTO = serde_json::from_str(&serialized).map_err(|_| LoadError::Unknown)?;
-        let audios = playlist_dto.audios.iter().map(|audio| Audio {
+/// Serializes audios in the format of `playlist.json`.
+pub fn audios_to_json(audios: &[Audio]) -> Result<String, LoadError> {
+    let playlist_dto = PlaylistDTO {
+        audios: audios.iter().map(|audio| AudioDTO {
             id: audio.id,
             title: audio.title.clone(),
+            author: audio.author.clone(),
+            album: audio.album.clone(),
+            tags: audio.tags.clone(),
             source: match &audio.source {
-                LocalSource::YouTube(url) => Source::YouTube(url.clone()),
+                Source::YouTube(url) => LocalSource::YouTube(url.clone()),
             },
-            author: audio.author.clone(),
-        }).collect();
-        playlist.set_audios(audios).await;
+            pinned: audio.pinned,
+            last_played: audio.last_played,
+            play_count: audio.play_count,
+            download_policy: audio.download_policy,
+            added_at: audio.added_at,
+            duration: audio.duration,
+            trim: audio.trim,
+            chapters: audio.chapters.clone(),
+            subtitles: audio.subtitles.clone(),
+            segment: audio.segment,
+            bpm: audio.bpm,
+            key: audio.key,
+            bpm_key_corrected: audio.bpm_key_corrected,
+        }).collect(),
+    };
+    serde_json::to_string(&playlist_dto).map_err(|_| LoadError::Unknown)
+}
+
+pub fn audios_from_json(serialized: &str) -> Result<Vec<Audio>, LoadError> {
+    let playlist_dto: PlaylistDTO = serde_json::from_str(serialized).map_err(|_| LoadError::Unknown)?;
+    Ok(playlist_dto.audios.iter().map(|audio| Audio {
+        id: audio.id,
+        title: audio.title.clone(),
+        source: match &audio.source {
+            LocalSource::YouTube(url) => Source::YouTube(url.clone()),
+        },
+        author: audio.author.clone(),
+        album: audio.album.clone(),
+        tags: audio.tags.clone(),
+        pinned: audio.pinned,
+        last_played: audio.last_played,
+        play_count: audio.play_count,
+        download_policy: audio.download_policy,
+        added_at: audio.added_at,
+        duration: audio.duration,
+        trim: audio.trim,
+        chapters: audio.chapters.clone(),
+        subtitles: audio.subtitles.clone(),
+        segment: audio.segment,
+        bpm: audio.bpm,
+        key: audio.key,
+        bpm_key_corrected: audio.bpm_key_corrected,
+    }).collect())
+}
+
+impl PlaylistIO<PlaylistIOImpl> for PlaylistIOImpl {
+    async fn load(&self, playlist: &Playlist<PlaylistIOImpl>) -> Result<(), LoadError> {
+        let serialized = std::fs::read_to_string(self.0.clone()).map_err(|_| LoadError::NotFound)?;
+        playlist.set_audios(audios_from_json(&serialized)?).await;
         Ok(())
     }
 
     async fn save(&self, playlist: &Playlist<PlaylistIOImpl>) -> Result<(), LoadError> {
-        let audios = playlist.get_audios().await;
-        let playlist_dto = PlaylistDTO {
-            audios: audios.iter().map(|audio| AudioDTO {
-                id: audio.id,
-                title: audio.title.clone(),
-                author: audio.author.clone(),
-                source: match &audio.source {
-                    Source::YouTube(url) => LocalSource::YouTube(url.clone()),
-                },
-            }).collect(),
-        };
-        let serialized = serde_json::to_string(&playlist_dto).map_err(|_| LoadError::Unknown)?;
+        let serialized = audios_to_json(&playlist.get_audios().await)?;
         std::fs::write(self.0.clone(), serialized).map_err(|_| LoadError::Unknown)?;
         Ok(())
     }
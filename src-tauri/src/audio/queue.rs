@@ -0,0 +1,283 @@
+use std::{cmp::Ordering, path::Path};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+/// Shuffled order of the entries not played yet in the current round, so
+/// nothing repeats until every entry of the queue has been played.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shuffle {
+    pub seed: u64,
+    round: u64,
+    /// Indices into the items.
+    remaining: Vec<usize>,
+}
+
+/// Entries are addressed by their index, so an audio can be queued more than once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayQueue {
+    items: Vec<u32>,
+    current: Option<usize>,
+    shuffle: Option<Shuffle>,
+    repeat: RepeatMode,
+    /// Indices of the entries played before the current one.
+    history: Vec<usize>,
+    /// Position in the current audio, used to resume after restart.
+    pub position: f64,
+}
+
+const HISTORY_LIMIT: usize = 100;
+
+impl Default for PlayQueue {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            current: None,
+            shuffle: None,
+            repeat: RepeatMode::Off,
+            history: Vec::new(),
+            position: 0.0,
+        }
+    }
+}
+
+impl PlayQueue {
+    pub fn items(&self) -> &[u32] {
+        &self.items
+    }
+
+    pub fn index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<u32> {
+        self.current.and_then(|index| self.items.get(index).copied())
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn shuffle_seed(&self) -> Option<u64> {
+        self.shuffle.as_ref().map(|shuffle| shuffle.seed)
+    }
+
+    /// Audios played before the current one, oldest first.
+    pub fn history(&self) -> Vec<u32> {
+        self.history.iter().map(|index| self.items[*index]).collect()
+    }
+
+    pub fn set(&mut self, items: Vec<u32>, index: usize) {
+        self.current = if index < items.len() { Some(index) } else { None };
+        self.items = items;
+        self.history.clear();
+        self.position = 0.0;
+        if let Some(shuffle) = self.shuffle.as_ref() {
+            let seed = shuffle.seed;
+            self.start_round(seed, 0);
+        }
+    }
+
+    pub fn jump(&mut self, index: usize) -> Option<u32> {
+        if index >= self.items.len() {
+            return None;
+        }
+        self.push_history();
+        self.current = Some(index);
+        self.position = 0.0;
+        if let Some(shuffle) = self.shuffle.as_mut() {
+            shuffle.remaining.retain(|remaining| *remaining != index);
+        }
+        Some(self.items[index])
+    }
+
+    /// Inserts the audio right after the current one, it's played next even with shuffle on.
+    pub fn play_next(&mut self, id: u32) {
+        let index = self.current.map(|index| index + 1).unwrap_or(0);
+        self.items.insert(index, id);
+        self.reindex(|i| Some(if i >= index { i + 1 } else { i }));
+        if let Some(shuffle) = self.shuffle.as_mut() {
+            shuffle.remaining.insert(0, index);
+        }
+    }
+
+    pub fn add(&mut self, id: u32) {
+        self.items.push(id);
+        if let Some(shuffle) = self.shuffle.as_mut() {
+            let mut rng = StdRng::seed_from_u64(shuffle.seed ^ id as u64);
+            let index = rng.gen_range(0..=shuffle.remaining.len());
+            shuffle.remaining.insert(index, self.items.len() - 1);
+        }
+    }
+
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from >= self.items.len() || to >= self.items.len() {
+            return;
+        }
+        let id = self.items.remove(from);
+        self.items.insert(to, id);
+        self.reindex(|i| Some(match i {
+            i if i == from => to,
+            i if from < i && i <= to => i - 1,
+            i if to <= i && i < from => i + 1,
+            i => i,
+        }));
+    }
+
+    /// Removes every entry of the audio.
+    pub fn remove(&mut self, id: u32) {
+        while let Some(index) = self.items.iter().rposition(|item| *item == id) {
+            self.remove_at(index);
+        }
+    }
+
+    /// Removing the current entry keeps its index, the entry that followed it
+    /// becomes the current one, or the last when it was at the end.
+    pub fn remove_at(&mut self, index: usize) {
+        if index >= self.items.len() {
+            return;
+        }
+        let removed_current = self.current == Some(index);
+        self.items.remove(index);
+        self.reindex(|i| match i.cmp(&index) {
+            Ordering::Less => Some(i),
+            Ordering::Equal => None,
+            Ordering::Greater => Some(i - 1),
+        });
+        if !removed_current {
+            return;
+        }
+        self.position = 0.0;
+        self.current = self.items.len().checked_sub(1).map(|last| index.min(last));
+        if let (Some(current), Some(shuffle)) = (self.current, self.shuffle.as_mut()) {
+            shuffle.remaining.retain(|item| *item != current);
+        }
+    }
+
+    /// Maps the stored indices after the items changed, `None` drops an index.
+    fn reindex(&mut self, map: impl Fn(usize) -> Option<usize>) {
+        self.current = self.current.and_then(&map);
+        self.history = self.history.iter().filter_map(|index| map(*index)).collect();
+        if let Some(shuffle) = self.shuffle.as_mut() {
+            shuffle.remaining = shuffle.remaining.iter().filter_map(|index| map(*index)).collect();
+        }
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    /// Turns shuffle on with the given seed, or a random one, or turns it off.
+    pub fn set_shuffle(&mut self, enabled: bool, seed: Option<u64>) {
+        if enabled {
+            self.start_round(seed.unwrap_or_else(rand::random), 0);
+        } else {
+            self.shuffle = None;
+        }
+    }
+
+    fn start_round(&mut self, seed: u64, round: u64) {
+        let mut remaining: Vec<usize> = (0..self.items.len()).filter(|index| Some(*index) != self.current).collect();
+        remaining.shuffle(&mut StdRng::seed_from_u64(seed.wrapping_add(round)));
+        self.shuffle = Some(Shuffle { seed, round, remaining });
+    }
+
+    fn push_history(&mut self) {
+        if let Some(index) = self.current {
+            self.history.push(index);
+            if self.history.len() > HISTORY_LIMIT {
+                self.history.remove(0);
+            }
+        }
+    }
+
+    /// Moves to the next audio. `auto` is set when the current audio has ended,
+    /// only then repeat-one plays the same audio again.
+    pub fn next(&mut self, auto: bool) -> Option<u32> {
+        if self.items.is_empty() {
+            return None;
+        }
+        self.position = 0.0;
+        if auto && self.repeat == RepeatMode::One && self.current.is_some() {
+            return self.current();
+        }
+        self.push_history();
+        let next = match self.shuffle.clone() {
+            Some(mut shuffle) => {
+                if shuffle.remaining.is_empty() && self.repeat == RepeatMode::All {
+                    self.current = None;
+                    self.start_round(shuffle.seed, shuffle.round + 1);
+                    shuffle = self.shuffle.clone().unwrap();
+                }
+                if shuffle.remaining.is_empty() {
+                    None
+                } else {
+                    let index = shuffle.remaining.remove(0);
+                    self.shuffle = Some(shuffle);
+                    Some(index)
+                }
+            },
+            None => {
+                let next = self.current.map(|index| index + 1).unwrap_or(0);
+                if next < self.items.len() {
+                    Some(next)
+                } else if self.repeat == RepeatMode::All {
+                    Some(0)
+                } else {
+                    None
+                }
+            },
+        };
+        self.current = next;
+        self.current()
+    }
+
+    /// Goes back through the history of played entries.
+    pub fn previous(&mut self) -> Option<u32> {
+        self.position = 0.0;
+        if let Some(index) = self.history.pop() {
+            if let (Some(current), Some(shuffle)) = (self.current, self.shuffle.as_mut()) {
+                shuffle.remaining.insert(0, current);
+            }
+            self.current = Some(index);
+        }
+        self.current()
+    }
+}
+
+#[derive(Debug)]
+pub struct QueueFile(pub String);
+
+impl QueueFile {
+    pub fn load(&self) -> Option<PlayQueue> {
+        let serialized = std::fs::read_to_string(&self.0).ok()?;
+        let mut queue: PlayQueue = serde_json::from_str(&serialized).ok()?;
+        // The file may have been edited by hand, drop what points outside the items.
+        if queue.current.is_some_and(|index| index >= queue.items.len()) {
+            queue.current = None;
+            queue.position = 0.0;
+        }
+        let len = queue.items.len();
+        queue.history.retain(|index| *index < len);
+        if let Some(shuffle) = queue.shuffle.as_mut() {
+            shuffle.remaining.retain(|index| *index < len);
+        }
+        Some(queue)
+    }
+
+    pub fn save(&self, queue: &PlayQueue) {
+        if let Ok(serialized) = serde_json::to_string(queue) {
+            if let Some(parent) = Path::new(&self.0).parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let _ = std::fs::write(&self.0, serialized);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::key::Key;
+
+use super::Audio;
+
+const DAY: u64 = 60 * 60 * 24;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    NotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Rule {
+    TitleContains(String),
+    AuthorContains(String),
+    AddedWithinDays(u32),
+    PlayCountGreater(u32),
+    PlayCountLess(u32),
+    /// Seconds.
+    DurationLess(f64),
+    /// Seconds.
+    DurationGreater(f64),
+    Downloaded(bool),
+    Pinned(bool),
+    BpmLess(f64),
+    BpmGreater(f64),
+    KeyIs(Key),
+    /// The key or one that mixes well with it, see [`Key::is_compatible`].
+    KeyCompatible(Key),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RuleMatch {
+    All,
+    Any,
+}
+
+/// State of an audio that isn't stored on `Audio` itself.
+#[derive(Debug, Clone, Copy)]
+pub struct RuleContext {
+    pub now: u64,
+    pub downloaded: bool,
+}
+
+impl Rule {
+    /// Audios without the added date, duration, BPM or key never match rules
+    /// on them.
+    pub fn matches(&self, audio: &Audio, context: RuleContext) -> bool {
+        let contains = |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+        match self {
+            Rule::TitleContains(text) => contains(&audio.title, text),
+            Rule::AuthorContains(text) => contains(&audio.author, text),
+            Rule::AddedWithinDays(days) => audio.added_at
+                .map(|added_at| context.now.saturating_sub(added_at) <= *days as u64 * DAY)
+                .unwrap_or(false),
+            Rule::PlayCountGreater(count) => audio.play_count > *count,
+            Rule::PlayCountLess(count) => audio.play_count < *count,
+            Rule::DurationLess(duration) => audio.duration.map(|value| value < *duration).unwrap_or(false),
+            Rule::DurationGreater(duration) => audio.duration.map(|value| value > *duration).unwrap_or(false),
+            Rule::Downloaded(downloaded) => context.downloaded == *downloaded,
+            Rule::Pinned(pinned) => audio.pinned == *pinned,
+            Rule::BpmLess(bpm) => audio.bpm.map(|value| value < *bpm).unwrap_or(false),
+            Rule::BpmGreater(bpm) => audio.bpm.map(|value| value > *bpm).unwrap_or(false),
+            Rule::KeyIs(key) => audio.key == Some(*key),
+            Rule::KeyCompatible(key) => audio.key.map(|value| value.is_compatible(key)).unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub id: u32,
+    pub name: String,
+    pub rules: Vec<Rule>,
+    pub rule_match: RuleMatch,
+}
+
+impl SmartPlaylist {
+    pub fn create(name: String, rules: Vec<Rule>, rule_match: RuleMatch) -> Self {
+        Self {
+            id: rand::random(),
+            name,
+            rules,
+            rule_match,
+        }
+    }
+
+    /// A playlist without rules matches every audio.
+    pub fn matches(&self, audio: &Audio, context: RuleContext) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        match self.rule_match {
+            RuleMatch::All => self.rules.iter().all(|rule| rule.matches(audio, context)),
+            RuleMatch::Any => self.rules.iter().any(|rule| rule.matches(audio, context)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SmartPlaylistsFile(pub String);
+
+impl SmartPlaylistsFile {
+    pub fn load(&self) -> Option<Vec<SmartPlaylist>> {
+        let serialized = std::fs::read_to_string(&self.0).ok()?;
+        serde_json::from_str(&serialized).ok()
+    }
+
+    pub fn save(&self, playlists: &[SmartPlaylist]) {
+        if let Ok(serialized) = serde_json::to_string(playlists) {
+            if let Some(parent) = Path::new(&self.0).parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let _ = std::fs::write(&self.0, serialized);
+        }
+    }
+}
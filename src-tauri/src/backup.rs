@@ -0,0 +1,211 @@
+use std::{collections::{HashMap, HashSet}, fs::File, io::{Read, Write}, path::{Component, Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::audio::Audio;
+
+pub const FORMAT: &str = "furplayer-backup";
+pub const VERSION: u32 = 1;
+
+pub const MANIFEST: &str = "manifest.json";
+pub const PLAYLIST: &str = "playlist.json";
+pub const SETTINGS: &str = "settings.json";
+pub const HISTORY: &str = "history.json";
+pub const SMART_PLAYLISTS: &str = "smart_playlists.json";
+/// Prefix of the track folders, laid out like the `audios/` library directory.
+pub const AUDIOS: &str = "audios/";
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Unknown,
+    InvalidArchive,
+    UnsupportedVersion,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    pub created_at: u64,
+    pub include_media: bool,
+}
+
+/// The manifest and the json files of an archive, media stays in the archive
+/// until [`extract_media`].
+#[derive(Debug)]
+pub struct Backup {
+    pub manifest: Manifest,
+    pub files: HashMap<String, String>,
+}
+
+/// Writes the archive next to `path` first and moves it in place when
+/// complete, so a failed backup never leaves a truncated file behind. With
+/// `media` the track folders of the given ids are added from the audio directory.
+pub fn write(path: &Path, manifest: &Manifest, files: &[(&str, String)], media: Option<(&Path, &[u32])>) -> Result<(), Error> {
+    let partial = path.with_extension("partial");
+    let result = write_archive(&partial, manifest, files, media);
+    match result {
+        Ok(()) => std::fs::rename(&partial, path).map_err(|_| Error::Unknown),
+        Err(err) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(err)
+        },
+    }
+}
+
+fn write_archive(path: &Path, manifest: &Manifest, files: &[(&str, String)], media: Option<(&Path, &[u32])>) -> Result<(), Error> {
+    let file = File::create(path).map_err(|_| Error::Unknown)?;
+    let mut zip = ZipWriter::new(file);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    // Media is already compressed, storing it keeps backups fast.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored).large_file(true);
+
+    let serialized = serde_json::to_string(manifest).map_err(|_| Error::Unknown)?;
+    zip.start_file(MANIFEST, deflated).map_err(|_| Error::Unknown)?;
+    zip.write_all(serialized.as_bytes()).map_err(|_| Error::Unknown)?;
+    for (name, content) in files {
+        zip.start_file(*name, deflated).map_err(|_| Error::Unknown)?;
+        zip.write_all(content.as_bytes()).map_err(|_| Error::Unknown)?;
+    }
+    if let Some((audio_dir, ids)) = media {
+        for id in ids {
+            let dir = audio_dir.join(id.to_string());
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if !entry.file_type().map(|kind| kind.is_file()).unwrap_or(false) {
+                    continue;
+                }
+                let name = format!("{}{}/{}", AUDIOS, id, entry.file_name().to_string_lossy());
+                let mut source = File::open(entry.path()).map_err(|_| Error::Unknown)?;
+                zip.start_file(name, stored).map_err(|_| Error::Unknown)?;
+                std::io::copy(&mut source, &mut zip).map_err(|_| Error::Unknown)?;
+            }
+        }
+    }
+    zip.finish().map_err(|_| Error::Unknown)?;
+    Ok(())
+}
+
+/// Opens the archive and checks the manifest, returns every json file at its root.
+pub fn read(path: &Path) -> Result<Backup, Error> {
+    let file = File::open(path).map_err(|_| Error::InvalidArchive)?;
+    let mut zip = ZipArchive::new(file).map_err(|_| Error::InvalidArchive)?;
+    let manifest: Manifest = {
+        let mut entry = zip.by_name(MANIFEST).map_err(|_| Error::InvalidArchive)?;
+        let mut serialized = String::new();
+        entry.read_to_string(&mut serialized).map_err(|_| Error::InvalidArchive)?;
+        serde_json::from_str(&serialized).map_err(|_| Error::InvalidArchive)?
+    };
+    if manifest.format != FORMAT {
+        return Err(Error::InvalidArchive);
+    }
+    if manifest.version > VERSION {
+        return Err(Error::UnsupportedVersion);
+    }
+    let mut files = HashMap::new();
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(|_| Error::InvalidArchive)?;
+        let name = entry.name().to_string();
+        if name == MANIFEST || name.contains('/') || !name.ends_with(".json") {
+            continue;
+        }
+        let mut content = String::new();
+        entry.read_to_string(&mut content).map_err(|_| Error::InvalidArchive)?;
+        files.insert(name, content);
+    }
+    if !files.contains_key(PLAYLIST) {
+        return Err(Error::InvalidArchive);
+    }
+    Ok(Backup { manifest, files })
+}
+
+/// The same track, or the same chapter of it for virtual tracks.
+pub fn same_track(a: &Audio, b: &Audio) -> bool {
+    let range = |audio: &Audio| audio.segment.map(|segment| (segment.start, segment.end));
+    a.source == b.source && range(a) == range(b)
+}
+
+/// Library ids of the audios of a backup merged into `library`. Tracks the
+/// library already has map to its id, the others keep theirs unless another
+/// track took it, then they get a fresh one.
+pub fn merge_ids(library: &[Audio], restored: &[Audio]) -> HashMap<u32, u32> {
+    let mut used: HashSet<u32> = library.iter().map(|audio| audio.id).collect();
+    let mut ids = HashMap::new();
+    for audio in restored {
+        let id = match library.iter().find(|existing| same_track(existing, audio)) {
+            Some(existing) => existing.id,
+            None => {
+                let mut id = audio.id;
+                while used.contains(&id) {
+                    id = rand::random();
+                }
+                used.insert(id);
+                id
+            },
+        };
+        ids.insert(audio.id, id);
+    }
+    ids
+}
+
+/// Track folder and file name of an `audios/<id>/<file>` entry. Anything
+/// else, including names that would escape the folder, is ignored.
+fn media_entry(name: &str) -> Option<(u32, PathBuf)> {
+    let relative = Path::new(name.strip_prefix(AUDIOS)?);
+    let mut components = relative.components();
+    let id = match components.next()? {
+        Component::Normal(id) => id.to_str()?.parse().ok()?,
+        _ => return None,
+    };
+    let file = match (components.next()?, components.next()) {
+        (Component::Normal(file), None) => PathBuf::from(file),
+        _ => return None,
+    };
+    Some((id, file))
+}
+
+/// Replaces the track folders in the audio directory with the ones in the
+/// archive, `ids` maps the archive ids to extract to library ids. Returns the
+/// library ids that had files in the archive.
+pub fn extract_media(path: &Path, audio_dir: &Path, ids: &HashMap<u32, u32>) -> Result<Vec<u32>, Error> {
+    let file = File::open(path).map_err(|_| Error::InvalidArchive)?;
+    let mut zip = ZipArchive::new(file).map_err(|_| Error::InvalidArchive)?;
+    let mut extracted = Vec::new();
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(|_| Error::InvalidArchive)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let Some((id, name)) = media_entry(entry.name()) else {
+            continue;
+        };
+        let Some(&id) = ids.get(&id) else {
+            continue;
+        };
+        let dir = audio_dir.join(id.to_string());
+        if !extracted.contains(&id) {
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).map_err(|_| Error::Unknown)?;
+            extracted.push(id);
+        }
+        let mut target = File::create(dir.join(name)).map_err(|_| Error::Unknown)?;
+        std::io::copy(&mut entry, &mut target).map_err(|_| Error::Unknown)?;
+    }
+    Ok(extracted)
+}
+
+/// Moves the track folders extracted into `staging` into the audio directory,
+/// replacing the folders of the same ids, and removes `staging`.
+pub fn install_media(staging: &Path, audio_dir: &Path, ids: &[u32]) -> Result<(), Error> {
+    std::fs::create_dir_all(audio_dir).map_err(|_| Error::Unknown)?;
+    for id in ids {
+        let target = audio_dir.join(id.to_string());
+        let _ = std::fs::remove_dir_all(&target);
+        std::fs::rename(staging.join(id.to_string()), &target).map_err(|_| Error::Unknown)?;
+    }
+    let _ = std::fs::remove_dir_all(staging);
+    Ok(())
+}
@@ -0,0 +1,235 @@
+mod opus;
+
+use std::{io::Cursor, path::Path};
+
+use mime2ext::mime2ext;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint,
+    units::{Time, TimeBase},
+};
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Unknown,
+    Unsupported,
+    /// Too little audio was decoded for the analysis.
+    TooShort,
+}
+
+/// Decoder of the track, Symphonia's or our own for the codecs it lacks.
+enum Codec {
+    Symphonia(Box<dyn symphonia::core::codecs::Decoder>),
+    Opus(Box<opus::Decoder>),
+}
+
+/// Decodes a media file into interleaved `f32` samples.
+pub struct Decoder {
+    format: Box<dyn FormatReader>,
+    codec: Codec,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    duration: Option<f64>,
+    channels: usize,
+    sample_rate: u32,
+    position: f64,
+    /// Frames still to drop after seeking, the format seeks to a packet
+    /// boundary before the requested time.
+    skip: u64,
+}
+
+impl Decoder {
+    pub fn open(bytes: Vec<u8>, mime: &str) -> Result<Self, Error> {
+        let mut hint = Hint::new();
+        hint.mime_type(mime);
+        if let Some(extension) = mime2ext(mime) {
+            hint.with_extension(extension);
+        }
+        Self::open_source(Box::new(Cursor::new(bytes)), hint)
+    }
+
+    pub fn open_file(path: &Path) -> Result<Self, Error> {
+        let file = std::fs::File::open(path).map_err(|_| Error::Unknown)?;
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+        Self::open_source(Box::new(file), hint)
+    }
+
+    fn open_source(source: Box<dyn MediaSource>, hint: Hint) -> Result<Self, Error> {
+        let stream = MediaSourceStream::new(source, Default::default());
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|_| Error::Unsupported)?;
+        let format = probed.format;
+        let track = format.tracks().iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(Error::Unsupported)?;
+        let (codec, channels, sample_rate) = if track.codec_params.codec == CODEC_TYPE_OPUS {
+            let head = track.codec_params.extra_data.as_deref().ok_or(Error::Unsupported)?;
+            let decoder = opus::Decoder::new(head)?;
+            let channels = decoder.channels();
+            (Codec::Opus(Box::new(decoder)), channels, 48000)
+        } else {
+            let decoder = symphonia::default::get_codecs()
+                .make(&track.codec_params, &DecoderOptions::default())
+                .map_err(|_| Error::Unsupported)?;
+            let channels = track.codec_params.channels.map(|channels| channels.count()).unwrap_or(2);
+            (Codec::Symphonia(decoder), channels, track.codec_params.sample_rate.unwrap_or(44100))
+        };
+        let time_base = track.codec_params.time_base;
+        let duration = match (time_base, track.codec_params.n_frames) {
+            (Some(time_base), Some(frames)) => Some(Self::seconds(time_base.calc_time(frames))),
+            _ => None,
+        };
+        Ok(Self {
+            track_id: track.id,
+            channels,
+            sample_rate,
+            format,
+            codec,
+            time_base,
+            duration,
+            position: 0.0,
+            skip: 0,
+        })
+    }
+
+    fn seconds(time: Time) -> f64 {
+        time.seconds as f64 + time.frac
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    /// Position of the last decoded chunk in seconds.
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    /// Returns the next chunk of interleaved samples or `None` at the end of the stream.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<f32>>, Error> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(_) => return Err(Error::Unknown),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let samples = match &mut self.codec {
+                Codec::Symphonia(decoder) => match decoder.decode(&packet) {
+                    Ok(decoded) => {
+                        let spec = *decoded.spec();
+                        self.channels = spec.channels.count();
+                        self.sample_rate = spec.rate;
+                        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                        buffer.copy_interleaved_ref(decoded);
+                        buffer.samples().to_vec()
+                    },
+                    Err(SymphoniaError::DecodeError(_)) => continue,
+                    Err(_) => return Err(Error::Unknown),
+                },
+                Codec::Opus(decoder) => match decoder.decode(&packet.data) {
+                    Ok(samples) => samples,
+                    Err(_) => continue,
+                },
+            };
+            let frames = (samples.len() / self.channels) as u64;
+            let skipped = self.skip.min(frames);
+            self.skip -= skipped;
+            if let Some(time_base) = self.time_base {
+                self.position = Self::seconds(time_base.calc_time(packet.ts())) + skipped as f64 / self.sample_rate as f64;
+            }
+            if skipped == frames {
+                continue;
+            }
+            return Ok(Some(samples[skipped as usize * self.channels..].to_vec()));
+        }
+    }
+
+    pub fn seek(&mut self, position: f64) -> Result<(), Error> {
+        let position = position.max(0.0);
+        let time = Time::new(position.trunc() as u64, position.fract());
+        let seeked = self.format.seek(SeekMode::Accurate, SeekTo::Time { time, track_id: Some(self.track_id) })
+            .map_err(|_| Error::Unknown)?;
+        match &mut self.codec {
+            Codec::Symphonia(decoder) => decoder.reset(),
+            Codec::Opus(decoder) => decoder.reset(),
+        }
+        // Timestamps are in the units of the format, Matroska counts milliseconds.
+        let ahead = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        self.skip = match self.time_base {
+            Some(time_base) => (Self::seconds(time_base.calc_time(ahead)) * self.sample_rate as f64).round() as u64,
+            None => ahead,
+        };
+        self.position = position;
+        Ok(())
+    }
+}
+
+/// Measures something over the decoded samples of a track.
+pub trait Analyzer {
+    type Output;
+
+    fn push(&mut self, samples: &[f32]);
+
+    fn finish(self) -> Self::Output;
+}
+
+/// Decodes the file, or only the part between two seconds of it, into the
+/// analyzer `make` creates for its channels and sample rate. `progress` gets
+/// the decoded seconds and the duration about every second of audio. `None`
+/// when nothing could be decoded.
+pub fn analyze_file<A, P>(path: &Path, part: Option<(f64, f64)>, make: impl Fn(usize, u32) -> A, mut progress: P) -> Result<Option<A::Output>, Error>
+where
+    A: Analyzer,
+    P: FnMut(f64, Option<f64>),
+{
+    let mut decoder = Decoder::open_file(path)?;
+    if let Some((start, _)) = part {
+        decoder.seek(start)?;
+    }
+    let (start, duration) = match part {
+        Some((start, end)) => (start, Some((end - start).max(0.0))),
+        None => (0.0, decoder.duration()),
+    };
+    let mut analyzer: Option<A> = None;
+    let mut remaining = part.map(|(start, end)| ((end - start).max(0.0) * decoder.sample_rate() as f64) as usize * decoder.channels());
+    let mut reported = 0.0;
+    while let Some(samples) = decoder.next_chunk()? {
+        let samples = match remaining {
+            Some(remaining) => &samples[..samples.len().min(remaining)],
+            None => &samples[..],
+        };
+        analyzer.get_or_insert_with(|| make(decoder.channels(), decoder.sample_rate())).push(samples);
+        if let Some(remaining) = remaining.as_mut() {
+            *remaining -= samples.len();
+            if *remaining == 0 {
+                break;
+            }
+        }
+        let position = decoder.position() - start;
+        if position - reported >= 1.0 {
+            reported = position;
+            progress(position, duration);
+        }
+    }
+    Ok(analyzer.map(Analyzer::finish))
+}
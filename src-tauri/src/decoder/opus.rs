@@ -0,0 +1,172 @@
+mod bands;
+mod celt;
+mod range;
+mod tables;
+
+use celt::Celt;
+
+use super::Error;
+
+/// Largest frame in bytes.
+const MAX_FRAME: usize = 1275;
+/// Longest packet in samples per channel, 120 ms.
+const MAX_PACKET: usize = 5760;
+
+/// Splits a packet into its TOC byte and frames, RFC 6716 section 3.2.
+fn parse_packet(packet: &[u8]) -> Result<(u8, Vec<&[u8]>), Error> {
+    fn size(data: &[u8]) -> Result<(usize, usize), Error> {
+        match data {
+            [first, ..] if *first < 252 => Ok((*first as usize, 1)),
+            [first, second, ..] => Ok((4 * *second as usize + *first as usize, 2)),
+            _ => Err(Error::Unknown),
+        }
+    }
+    let (&toc, mut data) = packet.split_first().ok_or(Error::Unknown)?;
+    let mut sizes = Vec::new();
+    match toc & 3 {
+        0 => {},
+        1 => {
+            if data.len() % 2 != 0 {
+                return Err(Error::Unknown);
+            }
+            sizes.push(data.len() / 2);
+        },
+        2 => {
+            let (first, bytes) = size(data)?;
+            data = &data[bytes..];
+            if first > data.len() {
+                return Err(Error::Unknown);
+            }
+            sizes.push(first);
+        },
+        _ => {
+            let (&count, rest) = data.split_first().ok_or(Error::Unknown)?;
+            data = rest;
+            let frames = (count & 0x3f) as usize;
+            if frames == 0 || frames * frame_size(toc) > MAX_PACKET {
+                return Err(Error::Unknown);
+            }
+            if count & 0x40 != 0 {
+                // Padding at the end, its length in bytes of up to 254 each
+                let mut padding = 0;
+                loop {
+                    let (&byte, rest) = data.split_first().ok_or(Error::Unknown)?;
+                    data = rest;
+                    padding += if byte == 255 { 254 } else { byte as usize };
+                    if byte != 255 {
+                        break;
+                    }
+                }
+                data = &data[..data.len().checked_sub(padding).ok_or(Error::Unknown)?];
+            }
+            if count & 0x80 != 0 {
+                for _ in 0..frames - 1 {
+                    let (frame, bytes) = size(data)?;
+                    data = &data[bytes..];
+                    sizes.push(frame);
+                }
+                if sizes.iter().sum::<usize>() > data.len() {
+                    return Err(Error::Unknown);
+                }
+            } else {
+                if data.len() % frames != 0 {
+                    return Err(Error::Unknown);
+                }
+                sizes.resize(frames - 1, data.len() / frames);
+            }
+        },
+    }
+    let last = data.len() - sizes.iter().sum::<usize>();
+    sizes.push(last);
+    if sizes.iter().any(|size| *size > MAX_FRAME) {
+        return Err(Error::Unknown);
+    }
+    let frames = sizes.iter().map(|size| {
+        let (frame, rest) = data.split_at(*size);
+        data = rest;
+        frame
+    }).collect();
+    Ok((toc, frames))
+}
+
+/// Samples per channel of each frame of a packet at 48 kHz.
+fn frame_size(toc: u8) -> usize {
+    let config = toc >> 3;
+    match config {
+        0..=11 => [480, 960, 1920, 2880][config as usize & 3],
+        12..=15 => [480, 960][config as usize & 1],
+        _ => 120 << (config & 3),
+    }
+}
+
+/// Opus decoder for the CELT mode music is coded in. SILK and hybrid frames,
+/// for speech, play as silence.
+pub struct Decoder {
+    celt: Celt,
+    channels: usize,
+    /// Linear output gain of the header.
+    gain: f32,
+    /// Samples per channel still to drop from the start of the stream.
+    skip: usize,
+}
+
+impl Decoder {
+    /// A decoder for the stream described by the `OpusHead` identification
+    /// header, which Matroska, MP4 and Ogg all carry.
+    pub fn new(head: &[u8]) -> Result<Self, Error> {
+        if head.len() < 19 || &head[..8] != b"OpusHead" {
+            return Err(Error::Unsupported);
+        }
+        let channels = head[9] as usize;
+        // Mapping family 0 is mono or stereo, the others are multistream.
+        if head[18] != 0 || !(1..=2).contains(&channels) {
+            return Err(Error::Unsupported);
+        }
+        let gain = i16::from_le_bytes([head[16], head[17]]);
+        Ok(Self {
+            celt: Celt::new(channels),
+            channels,
+            gain: (std::f64::consts::LN_2 * 6.488_140_8e-4 * gain as f64).exp() as f32,
+            skip: u16::from_le_bytes([head[10], head[11]]) as usize,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Decodes a packet into interleaved samples at 48 kHz.
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>, Error> {
+        let (toc, frames) = parse_packet(packet)?;
+        let size = frame_size(toc);
+        let mut samples = vec![0.0; frames.len() * size * self.channels];
+        // SILK and hybrid frames keep their length so the track plays on,
+        // the CELT frames after them start over like after a seek.
+        if toc >> 3 < 16 {
+            self.celt.reset();
+            return Ok(self.skip(samples, frames.len() * size));
+        }
+        // The coded bandwidth, from narrowband to fullband
+        let end = [13, 17, 19, 21][(toc >> 5 & 3) as usize];
+        let stream_channels = if toc & 4 != 0 { 2 } else { 1 };
+        for (frame, pcm) in frames.iter().zip(samples.chunks_mut(size * self.channels)) {
+            self.celt.decode(frame, stream_channels, end, size, pcm);
+        }
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|sample| *sample *= self.gain);
+        }
+        Ok(self.skip(samples, frames.len() * size))
+    }
+
+    /// Drops what is left of the pre-skip from the start of `samples`.
+    fn skip(&mut self, mut samples: Vec<f32>, frames: usize) -> Vec<f32> {
+        let skipped = self.skip.min(frames);
+        self.skip -= skipped;
+        samples.split_off(skipped * self.channels)
+    }
+
+    /// Forgets the decoder state, for seeking.
+    pub fn reset(&mut self) {
+        self.celt.reset();
+    }
+}
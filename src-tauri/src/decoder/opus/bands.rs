@@ -0,0 +1,718 @@
+//! Decoding of the normalized band shapes, the PVQ codebook with the band
+//! splitting, folding and stereo coding of RFC 6716 section 4.3.4.
+
+use super::{
+    celt::{Allocation, Header, Layout},
+    range::{RangeDecoder, BITRES},
+    tables::{CACHE_BITS, CACHE_INDEX, EBANDS, LOG_N, ORDERY},
+};
+
+const SPREAD_NONE: usize = 0;
+pub const SPREAD_NORMAL: usize = 2;
+const SPREAD_AGGRESSIVE: usize = 3;
+const SPREAD_FACTOR: [i32; 3] = [15, 10, 5];
+const QTHETA_OFFSET: i32 = 4;
+const QTHETA_OFFSET_TWOPHASE: i32 = 16;
+const LOG_MAX_PSEUDO: usize = 6;
+const BIT_INTERLEAVE: [u32; 16] = [0, 1, 1, 1, 2, 3, 3, 3, 2, 3, 3, 3, 2, 3, 3, 3];
+const BIT_DEINTERLEAVE: [u32; 16] = [
+    0x00, 0x03, 0x0c, 0x0f, 0x30, 0x33, 0x3c, 0x3f, 0xc0, 0xc3, 0xcc, 0xcf, 0xf0, 0xf3, 0xfc, 0xff,
+];
+
+pub fn lcg_rand(seed: u32) -> u32 {
+    seed.wrapping_mul(1664525).wrapping_add(1013904223)
+}
+
+/// Scales `x` back to unit norm times `gain`.
+pub fn renormalise(x: &mut [f32], gain: f32) {
+    let energy = 1e-15 + x.iter().fold(0.0, |sum, value| sum + value * value);
+    let gain = 1.0 / energy.sqrt() * gain;
+    x.iter_mut().for_each(|value| *value *= gain);
+}
+
+fn frac_mul16(a: i32, b: i32) -> i32 {
+    (16384 + (a as i16 as i32) * (b as i16 as i32)) >> 15
+}
+
+/// The cosine approximation the bit allocation relies on being exact.
+fn bitexact_cos(x: i32) -> i32 {
+    let x2 = (4096 + x * x) >> 13;
+    let x2 = (32767 - x2) + frac_mul16(x2, -7651 + frac_mul16(x2, 8277 + frac_mul16(-626, x2)));
+    1 + x2
+}
+
+fn bitexact_log2tan(sin: i32, cos: i32) -> i32 {
+    let lc = 32 - cos.leading_zeros() as i32;
+    let ls = 32 - sin.leading_zeros() as i32;
+    let cos = cos << (15 - lc);
+    let sin = sin << (15 - ls);
+    (ls - lc) * (1 << 11) + frac_mul16(sin, frac_mul16(sin, -2597) + 7932) - frac_mul16(cos, frac_mul16(cos, -2597) + 7932)
+}
+
+fn isqrt(mut value: u32) -> u32 {
+    let mut root = 0;
+    let mut shift = (32 - value.leading_zeros() as i32 - 1) >> 1;
+    let mut bit = 1 << shift;
+    while shift >= 0 {
+        let trial = ((root << 1) + bit) << shift;
+        if trial <= value {
+            root += bit;
+            value -= trial;
+        }
+        bit >>= 1;
+        shift -= 1;
+    }
+    root
+}
+
+/// Pulses of a pseudo-pulse count, which grow exponentially past 8.
+fn get_pulses(i: i32) -> i32 {
+    if i < 8 { i } else { (8 + (i & 7)) << ((i >> 3) - 1) }
+}
+
+fn pulse_cache(band: usize, lm: i32) -> &'static [u8] {
+    &CACHE_BITS[CACHE_INDEX[(lm + 1) as usize * 21 + band] as usize..]
+}
+
+fn bits2pulses(band: usize, lm: i32, bits: i32) -> i32 {
+    let cache = pulse_cache(band, lm);
+    let bits = bits - 1;
+    let (mut low, mut high) = (0, cache[0] as usize);
+    for _ in 0..LOG_MAX_PSEUDO {
+        let mid = (low + high + 1) >> 1;
+        if cache[mid] as i32 >= bits {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    let below = if low == 0 { -1 } else { cache[low] as i32 };
+    if bits - below <= cache[high] as i32 - bits { low as i32 } else { high as i32 }
+}
+
+fn pulses2bits(band: usize, lm: i32, pulses: i32) -> i32 {
+    if pulses == 0 { 0 } else { pulse_cache(band, lm)[pulses as usize] as i32 + 1 }
+}
+
+/// Row `n` of the PVQ codebook sizes U(n, k) for k up to `k + 1`, returns V(n, k).
+fn pvq_row(n: usize, k: usize, row: &mut [u32]) -> u32 {
+    row[0] = 0;
+    row[1] = 1;
+    for (i, value) in row.iter_mut().enumerate().take(k + 2).skip(2) {
+        *value = ((i as u32) << 1) - 1;
+    }
+    for _ in 2..n {
+        let mut previous = 1;
+        for j in 2..k + 2 {
+            let next = row[j].wrapping_add(row[j - 1]).wrapping_add(previous);
+            row[j - 1] = previous;
+            previous = next;
+        }
+        row[k + 1] = previous;
+    }
+    row[k].wrapping_add(row[k + 1])
+}
+
+/// Decodes the pulse vector of `index` into `pulses`, returns its energy.
+fn decode_pulses(pulses: &mut [i32], mut k: usize, mut index: u32, row: &mut [u32]) -> f32 {
+    let mut energy = 0.0;
+    for pulse in pulses.iter_mut() {
+        let total = row[k + 1];
+        let negative = index >= total;
+        if negative {
+            index -= total;
+        }
+        let first = k;
+        let mut value = row[k];
+        while value > index {
+            k -= 1;
+            value = row[k];
+        }
+        index -= value;
+        let magnitude = (first - k) as i32;
+        *pulse = if negative { -magnitude } else { magnitude };
+        energy += (*pulse * *pulse) as f32;
+        let mut previous = 0;
+        for j in 1..k + 2 {
+            let next = row[j].wrapping_sub(row[j - 1]).wrapping_sub(previous);
+            row[j - 1] = previous;
+            previous = next;
+        }
+        row[k + 1] = previous;
+    }
+    energy
+}
+
+fn exp_rotation1(x: &mut [f32], stride: usize, c: f32, s: f32) {
+    let len = x.len();
+    for i in 0..len.saturating_sub(stride) {
+        let (x1, x2) = (x[i], x[i + stride]);
+        x[i + stride] = c * x2 + s * x1;
+        x[i] = c * x1 - s * x2;
+    }
+    if len > 2 * stride {
+        for i in (0..len - 2 * stride).rev() {
+            let (x1, x2) = (x[i], x[i + stride]);
+            x[i + stride] = c * x2 + s * x1;
+            x[i] = c * x1 - s * x2;
+        }
+    }
+}
+
+/// Undoes the spreading rotation the encoder applies before quantizing.
+fn exp_rotation(x: &mut [f32], stride: usize, k: usize, spread: usize) {
+    let len = x.len();
+    if 2 * k >= len || spread == SPREAD_NONE {
+        return;
+    }
+    let factor = SPREAD_FACTOR[spread - 1];
+    let gain = len as f32 / (len as i32 + factor * k as i32) as f32;
+    let theta = 0.5 * (gain * gain);
+    let cos_norm = |x: f32| (((0.5 * std::f32::consts::PI) * x) as f64).cos() as f32;
+    let (c, s) = (cos_norm(theta), cos_norm(1.0 - theta));
+    let mut stride2 = 0;
+    if len >= 8 * stride {
+        stride2 = 1;
+        while (stride2 * stride2 + stride2) * stride + (stride >> 2) < len {
+            stride2 += 1;
+        }
+    }
+    for block in x.chunks_mut(len / stride) {
+        if stride2 != 0 {
+            exp_rotation1(block, stride2, s, c);
+        }
+        exp_rotation1(block, 1, c, s);
+    }
+}
+
+fn haar1(x: &mut [f32], n0: usize, stride: usize) {
+    for i in 0..stride {
+        for j in 0..n0 >> 1 {
+            let a = std::f32::consts::FRAC_1_SQRT_2 * x[stride * 2 * j + i];
+            let b = std::f32::consts::FRAC_1_SQRT_2 * x[stride * (2 * j + 1) + i];
+            x[stride * 2 * j + i] = a + b;
+            x[stride * (2 * j + 1) + i] = a - b;
+        }
+    }
+}
+
+fn deinterleave_hadamard(x: &mut [f32], n0: usize, stride: usize, hadamard: bool) {
+    let mut reordered = vec![0.0; n0 * stride];
+    for i in 0..stride {
+        let row = if hadamard { ORDERY[stride - 2 + i] } else { i };
+        for j in 0..n0 {
+            reordered[row * n0 + j] = x[j * stride + i];
+        }
+    }
+    x[..n0 * stride].copy_from_slice(&reordered);
+}
+
+fn interleave_hadamard(x: &mut [f32], n0: usize, stride: usize, hadamard: bool) {
+    let mut reordered = vec![0.0; n0 * stride];
+    for i in 0..stride {
+        let row = if hadamard { ORDERY[stride - 2 + i] } else { i };
+        for j in 0..n0 {
+            reordered[j * stride + i] = x[row * n0 + j];
+        }
+    }
+    x[..n0 * stride].copy_from_slice(&reordered);
+}
+
+fn stereo_merge(x: &mut [f32], y: &mut [f32], mid: f32) {
+    let (cross, side) = x.iter().zip(y.iter()).fold((0.0, 0.0), |(cross, side), (x, y)| (cross + y * x, side + y * y));
+    let cross = mid * cross;
+    let left = mid * mid + side - 2.0 * cross;
+    let right = mid * mid + side + 2.0 * cross;
+    if right < 6e-4 || left < 6e-4 {
+        y.copy_from_slice(x);
+        return;
+    }
+    let (left, right) = (1.0 / left.sqrt(), 1.0 / right.sqrt());
+    for (x, y) in x.iter_mut().zip(y.iter_mut()) {
+        let l = mid * *x;
+        let r = *y;
+        *x = left * (l - r);
+        *y = right * (l + r);
+    }
+}
+
+fn compute_qn(n: usize, b: i32, offset: i32, pulse_cap: i32, stereo: bool) -> i32 {
+    const EXP2_TABLE8: [i32; 8] = [16384, 17866, 19483, 21247, 23170, 25267, 27554, 30048];
+    let mut n2 = 2 * n as i32 - 1;
+    if stereo && n == 2 {
+        n2 -= 1;
+    }
+    let qb = ((b + n2 * offset) / n2).min(b - pulse_cap - (4 << BITRES)).min(8 << BITRES);
+    if qb < (1 << BITRES >> 1) {
+        1
+    } else {
+        let qn = EXP2_TABLE8[(qb & 7) as usize] >> (14 - (qb >> BITRES));
+        (qn + 1) >> 1 << 1
+    }
+}
+
+/// The split of a band into two halves or a stereo pair.
+struct Split {
+    inv: bool,
+    imid: i32,
+    iside: i32,
+    delta: i32,
+    itheta: i32,
+    qalloc: i32,
+}
+
+impl Split {
+    /// Drops the fill of the half that gets no energy.
+    fn fill(&self, fill: u32, blocks: usize) -> u32 {
+        match self.itheta {
+            0 => fill & ((1 << blocks) - 1),
+            16384 => fill & (((1 << blocks) - 1) << blocks),
+            _ => fill,
+        }
+    }
+}
+
+/// Bits, in 1/8 bits, short blocks and the mask of blocks that may be filled
+/// with folded content, of a band or a part of one.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub bits: i32,
+    pub blocks: usize,
+    pub fill: u32,
+}
+
+/// State carried across the bands of a frame.
+pub struct Bands<'a, 'b> {
+    pub decoder: &'a mut RangeDecoder<'b>,
+    pub remaining_bits: i32,
+    pub seed: u32,
+    pub spread: usize,
+    pub tf_change: i32,
+    pub intensity: usize,
+    pub band: usize,
+}
+
+impl Bands<'_, '_> {
+    fn theta(&mut self, n: usize, b: &mut i32, b0: usize, lm: i32, stereo: bool) -> Split {
+        let pulse_cap = LOG_N[self.band] + lm * (1 << BITRES);
+        let offset = (pulse_cap >> 1) - if stereo && n == 2 { QTHETA_OFFSET_TWOPHASE } else { QTHETA_OFFSET };
+        let mut qn = compute_qn(n, *b, offset, pulse_cap, stereo);
+        if stereo && self.band >= self.intensity {
+            qn = 1;
+        }
+        let tell = self.decoder.tell_frac();
+        let mut itheta = 0;
+        let mut inv = false;
+        if qn != 1 {
+            if stereo && n > 2 {
+                // A step distribution, three times as likely up to the middle.
+                let (p0, x0) = (3, qn as u32 / 2);
+                let total = p0 * (x0 + 1) + x0;
+                let fs = self.decoder.decode(total);
+                let x = if fs < (x0 + 1) * p0 { fs / p0 } else { x0 + 1 + (fs - (x0 + 1) * p0) };
+                let (low, high) = if x <= x0 {
+                    (p0 * x, p0 * (x + 1))
+                } else {
+                    ((x - 1 - x0) + (x0 + 1) * p0, (x - x0) + (x0 + 1) * p0)
+                };
+                self.decoder.update(low, high, total);
+                itheta = x as i32;
+            } else if b0 > 1 || stereo {
+                itheta = self.decoder.uint(qn as u32 + 1) as i32;
+            } else {
+                // A triangular distribution peaking in the middle.
+                let half = qn >> 1;
+                let total = ((half + 1) * (half + 1)) as u32;
+                let fm = self.decoder.decode(total) as i32;
+                let (fs, fl);
+                if fm < (half * (half + 1)) >> 1 {
+                    itheta = (isqrt(8 * fm as u32 + 1) as i32 - 1) >> 1;
+                    fs = itheta + 1;
+                    fl = (itheta * (itheta + 1)) >> 1;
+                } else {
+                    itheta = (2 * (qn + 1) - isqrt(8 * (total as i32 - fm - 1) as u32 + 1) as i32) >> 1;
+                    fs = qn + 1 - itheta;
+                    fl = total as i32 - (((qn + 1 - itheta) * (qn + 2 - itheta)) >> 1);
+                }
+                self.decoder.update(fl as u32, (fl + fs) as u32, total);
+            }
+            itheta = itheta * 16384 / qn;
+        } else if stereo {
+            if *b > 2 << BITRES && self.remaining_bits > 2 << BITRES {
+                inv = self.decoder.bit_logp(2);
+            }
+            itheta = 0;
+        }
+        let qalloc = self.decoder.tell_frac() - tell;
+        *b -= qalloc;
+        let (imid, iside, delta) = match itheta {
+            0 => (32767, 0, -16384),
+            16384 => (0, 32767, 16384),
+            _ => {
+                let imid = bitexact_cos(itheta);
+                let iside = bitexact_cos(16384 - itheta);
+                (imid, iside, frac_mul16(((n as i32) - 1) << 7, bitexact_log2tan(iside, imid)))
+            },
+        };
+        Split { inv, imid, iside, delta, itheta, qalloc }
+    }
+
+    /// A band of a single sample per channel, only signs are coded.
+    fn band_n1(&mut self, x: &mut [f32], y: Option<&mut [f32]>, lowband_out: Option<&mut [f32]>) -> u32 {
+        let sign = |remaining_bits: &mut i32, decoder: &mut RangeDecoder| {
+            if *remaining_bits >= 1 << BITRES {
+                *remaining_bits -= 1 << BITRES;
+                if decoder.bits(1) != 0 {
+                    return -1.0;
+                }
+            }
+            1.0
+        };
+        x[0] = sign(&mut self.remaining_bits, self.decoder);
+        if let Some(y) = y {
+            y[0] = sign(&mut self.remaining_bits, self.decoder);
+        }
+        if let Some(lowband_out) = lowband_out {
+            lowband_out[0] = x[0];
+        }
+        1
+    }
+
+    /// A mono partition, split in halves recursively while it has more bits
+    /// than the codebook takes.
+    fn partition(&mut self, x: &mut [f32], budget: Budget, lowband: Option<&[f32]>, lm: i32, gain: f32) -> u32 {
+        let Budget { bits: mut b, mut blocks, mut fill } = budget;
+        let n = x.len();
+        let b0 = blocks;
+        let split = lm != -1 && n > 2 && {
+            let cache = pulse_cache(self.band, lm);
+            b > cache[cache[0] as usize] as i32 + 12
+        };
+        if split {
+            let n = n >> 1;
+            let lm = lm - 1;
+            if blocks == 1 {
+                fill = (fill & 1) | (fill << 1);
+            }
+            blocks = (blocks + 1) >> 1;
+            let split = self.theta(n, &mut b, b0, lm, false);
+            fill = split.fill(fill, blocks);
+            let mid = (1.0 / 32768.0) * split.imid as f32;
+            let side = (1.0 / 32768.0) * split.iside as f32;
+            let mut delta = split.delta;
+            if b0 > 1 && split.itheta & 0x3fff != 0 {
+                if split.itheta > 8192 {
+                    delta -= delta >> (4 - lm);
+                } else {
+                    delta = (delta + ((n as i32) << BITRES >> (5 - lm))).min(0);
+                }
+            }
+            let mut mbits = b.min((b - delta) / 2).max(0);
+            let mut sbits = b - mbits;
+            self.remaining_bits -= split.qalloc;
+            let (x, y) = x.split_at_mut(n);
+            let (lowband, next_lowband) = match lowband {
+                Some(lowband) => (Some(&lowband[..n]), Some(&lowband[n..])),
+                None => (None, None),
+            };
+            let rebalance = self.remaining_bits;
+            if mbits >= sbits {
+                let mut mask = self.partition(x, Budget { bits: mbits, blocks, fill }, lowband, lm, gain * mid);
+                let rebalance = mbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 0 {
+                    sbits += rebalance - (3 << BITRES);
+                }
+                mask |= self.partition(y, Budget { bits: sbits, blocks, fill: fill >> blocks }, next_lowband, lm, gain * side) << (b0 >> 1);
+                mask
+            } else {
+                let mut mask = self.partition(y, Budget { bits: sbits, blocks, fill: fill >> blocks }, next_lowband, lm, gain * side) << (b0 >> 1);
+                let rebalance = sbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 16384 {
+                    mbits += rebalance - (3 << BITRES);
+                }
+                mask |= self.partition(x, Budget { bits: mbits, blocks, fill }, lowband, lm, gain * mid);
+                mask
+            }
+        } else {
+            let mut q = bits2pulses(self.band, lm, b);
+            let mut bits = pulses2bits(self.band, lm, q);
+            self.remaining_bits -= bits;
+            while self.remaining_bits < 0 && q > 0 {
+                self.remaining_bits += bits;
+                q -= 1;
+                bits = pulses2bits(self.band, lm, q);
+                self.remaining_bits -= bits;
+            }
+            if q != 0 {
+                return self.unquant(x, get_pulses(q) as usize, blocks, gain);
+            }
+            // No pulses, the band is filled with noise or folded from lower bands.
+            let mask = (1u32 << blocks) - 1;
+            fill &= mask;
+            if fill == 0 {
+                x.fill(0.0);
+                return 0;
+            }
+            let mask = match lowband {
+                None => {
+                    for value in x.iter_mut() {
+                        self.seed = lcg_rand(self.seed);
+                        *value = (self.seed as i32 >> 20) as f32;
+                    }
+                    mask
+                },
+                Some(lowband) => {
+                    for (value, low) in x.iter_mut().zip(lowband) {
+                        self.seed = lcg_rand(self.seed);
+                        // About 48 dB below the folded level
+                        *value = low + if self.seed & 0x8000 != 0 { 1.0 / 256.0 } else { -1.0 / 256.0 };
+                    }
+                    fill
+                },
+            };
+            renormalise(x, gain);
+            mask
+        }
+    }
+
+    /// Decodes `k` pulses and returns the mask of blocks that got any.
+    fn unquant(&mut self, x: &mut [f32], k: usize, blocks: usize, gain: f32) -> u32 {
+        let n = x.len();
+        let mut row = vec![0; k + 2];
+        let total = pvq_row(n, k, &mut row);
+        let index = self.decoder.uint(total);
+        let mut pulses = vec![0; n];
+        let energy = decode_pulses(&mut pulses, k, index, &mut row);
+        let gain = 1.0 / energy.sqrt() * gain;
+        for (value, pulse) in x.iter_mut().zip(&pulses) {
+            *value = gain * *pulse as f32;
+        }
+        exp_rotation(x, blocks, k, self.spread);
+        if blocks <= 1 {
+            return 1;
+        }
+        pulses.chunks(n / blocks).enumerate()
+            .fold(0, |mask, (i, block)| mask | (u32::from(block.iter().any(|pulse| *pulse != 0)) << i))
+    }
+
+    /// A mono band, with the time-frequency resolution changes around the
+    /// partition.
+    pub fn band(&mut self, x: &mut [f32], budget: Budget, lowband: Option<&[f32]>, lm: i32, lowband_out: Option<&mut [f32]>,
+        gain: f32) -> u32 {
+        let Budget { bits: b, mut blocks, mut fill } = budget;
+        let n0 = x.len();
+        if n0 == 1 {
+            return self.band_n1(x, None, lowband_out);
+        }
+        let long_blocks = blocks == 1;
+        let mut n_b = n0 / blocks;
+        let mut tf_change = self.tf_change;
+        let recombine = tf_change.max(0) as usize;
+        let mut lowband = lowband.map(|lowband| lowband.to_vec());
+        for k in 0..recombine {
+            if let Some(lowband) = &mut lowband {
+                haar1(lowband, n0 >> k, 1 << k);
+            }
+            fill = BIT_INTERLEAVE[(fill & 0xf) as usize] | BIT_INTERLEAVE[(fill >> 4) as usize] << 2;
+        }
+        blocks >>= recombine;
+        n_b <<= recombine;
+        let mut time_divide = 0;
+        while n_b & 1 == 0 && tf_change < 0 {
+            if let Some(lowband) = &mut lowband {
+                haar1(lowband, n_b, blocks);
+            }
+            fill |= fill << blocks;
+            blocks <<= 1;
+            n_b >>= 1;
+            time_divide += 1;
+            tf_change += 1;
+        }
+        let (b0, n_b0) = (blocks, n_b);
+        if b0 > 1 {
+            if let Some(lowband) = &mut lowband {
+                deinterleave_hadamard(lowband, n_b >> recombine, b0 << recombine, long_blocks);
+            }
+        }
+        let mut mask = self.partition(x, Budget { bits: b, blocks, fill }, lowband.as_deref(), lm, gain);
+        if b0 > 1 {
+            interleave_hadamard(x, n_b >> recombine, b0 << recombine, long_blocks);
+        }
+        let (mut n_b, mut blocks) = (n_b0, b0);
+        for _ in 0..time_divide {
+            blocks >>= 1;
+            n_b <<= 1;
+            mask |= mask >> blocks;
+            haar1(x, n_b, blocks);
+        }
+        for k in 0..recombine {
+            mask = BIT_DEINTERLEAVE[mask as usize];
+            haar1(x, n0 >> k, 1 << k);
+        }
+        blocks <<= recombine;
+        if let Some(lowband_out) = lowband_out {
+            // Scaled for folding into later bands
+            let scale = (n0 as f32).sqrt();
+            for (out, value) in lowband_out.iter_mut().zip(x.iter()) {
+                *out = scale * value;
+            }
+        }
+        mask & ((1 << blocks) - 1)
+    }
+
+    /// A stereo band coded as mid and side.
+    pub fn band_stereo(&mut self, x: &mut [f32], y: &mut [f32], budget: Budget, lowband: Option<&[f32]>, lm: i32,
+        lowband_out: Option<&mut [f32]>) -> u32 {
+        let Budget { bits: mut b, blocks, mut fill } = budget;
+        let n = x.len();
+        if n == 1 {
+            return self.band_n1(x, Some(y), lowband_out);
+        }
+        let original_fill = fill;
+        let split = self.theta(n, &mut b, blocks, lm, true);
+        fill = split.fill(fill, blocks);
+        let mid = (1.0 / 32768.0) * split.imid as f32;
+        let side = (1.0 / 32768.0) * split.iside as f32;
+        let mask;
+        if n == 2 {
+            // Mid and side are orthogonal, the side only needs a sign.
+            let sbits = if split.itheta != 0 && split.itheta != 16384 { 1 << BITRES } else { 0 };
+            let mbits = b - sbits;
+            self.remaining_bits -= split.qalloc + sbits;
+            let negative = sbits != 0 && self.decoder.bits(1) != 0;
+            let sign = if negative { -1.0 } else { 1.0 };
+            let (x2, y2) = if split.itheta > 8192 { (&mut *y, &mut *x) } else { (&mut *x, &mut *y) };
+            mask = self.band(x2, Budget { bits: mbits, blocks, fill: original_fill }, lowband, lm, lowband_out, 1.0);
+            y2[0] = -sign * x2[1];
+            y2[1] = sign * x2[0];
+            for i in 0..2 {
+                let (l, r) = (mid * x[i], side * y[i]);
+                x[i] = l - r;
+                y[i] = l + r;
+            }
+        } else {
+            let mut mbits = b.min((b - split.delta) / 2).max(0);
+            let mut sbits = b - mbits;
+            self.remaining_bits -= split.qalloc;
+            let rebalance = self.remaining_bits;
+            // The mid isn't scaled, later bands fold from it.
+            if mbits >= sbits {
+                let mut both = self.band(x, Budget { bits: mbits, blocks, fill }, lowband, lm, lowband_out, 1.0);
+                let rebalance = mbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 0 {
+                    sbits += rebalance - (3 << BITRES);
+                }
+                both |= self.band(y, Budget { bits: sbits, blocks, fill: fill >> blocks }, None, lm, None, side);
+                mask = both;
+            } else {
+                let mut both = self.band(y, Budget { bits: sbits, blocks, fill: fill >> blocks }, None, lm, None, side);
+                let rebalance = sbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 16384 {
+                    mbits += rebalance - (3 << BITRES);
+                }
+                both |= self.band(x, Budget { bits: mbits, blocks, fill }, lowband, lm, lowband_out, 1.0);
+                mask = both;
+            }
+            stereo_merge(x, y, mid);
+        }
+        if split.inv {
+            y.iter_mut().for_each(|value| *value = -*value);
+        }
+        mask
+    }
+}
+
+/// Decodes the shapes of every band into `x`, both channels one after the
+/// other, and returns the collapse masks of the bands.
+pub fn decode_all(decoder: &mut RangeDecoder, x: &mut [f32], layout: Layout, header: &Header, allocation: &Allocation,
+    total_bits: i32, seed: &mut u32) -> Vec<u8> {
+    let Layout { channels, end, .. } = layout;
+    let Allocation { coded_bands, intensity, mut dual_stereo, mut balance, ref pulses, .. } = *allocation;
+    let (spread, tf_res) = (header.spread, &header.tf_res);
+    let lm = layout.lm as i32;
+    let m = 1 << lm;
+    let blocks = if header.transient { m } else { 1 };
+    let norm_size = m * EBANDS[20];
+    let mut norm = vec![0.0; channels * norm_size];
+    let (xs, ys) = x.split_at_mut(x.len() / channels);
+    let mut masks = vec![0u8; channels * 21];
+    let mut lowband_offset = 0;
+    let mut update_lowband = true;
+    let mut bands = Bands { decoder, remaining_bits: 0, seed: *seed, spread, tf_change: 0, intensity, band: 0 };
+    for i in 0..end {
+        bands.band = i;
+        let last = i == end - 1;
+        let start = m * EBANDS[i];
+        let width = m * EBANDS[i + 1] - start;
+        let tell = bands.decoder.tell_frac();
+        if i != 0 {
+            balance -= tell;
+        }
+        let remaining_bits = total_bits - tell - 1;
+        bands.remaining_bits = remaining_bits;
+        let b = if i < coded_bands {
+            let balance = balance / 3.min(coded_bands as i32 - i as i32);
+            (remaining_bits + 1).min(pulses[i] + balance).clamp(0, 16383)
+        } else {
+            0
+        };
+        if (start >= width || i == 1) && (update_lowband || lowband_offset == 0) {
+            lowband_offset = i;
+        }
+        bands.tf_change = tf_res[i];
+        let mut effective_lowband = None;
+        let (mut x_mask, mut y_mask) = ((1u32 << blocks) - 1, (1u32 << blocks) - 1);
+        if lowband_offset != 0 && (spread != SPREAD_AGGRESSIVE || blocks > 1 || bands.tf_change < 0) {
+            // Never repeats spectral content within a band
+            let lowband = (m * EBANDS[lowband_offset]).saturating_sub(width);
+            let mut fold_start = lowband_offset - 1;
+            while m * EBANDS[fold_start] > lowband {
+                fold_start -= 1;
+            }
+            let mut fold_end = lowband_offset;
+            while fold_end < i && m * EBANDS[fold_end] < lowband + width {
+                fold_end += 1;
+            }
+            (x_mask, y_mask) = (0, 0);
+            for fold in fold_start..fold_end.max(fold_start + 1) {
+                x_mask |= masks[fold * channels] as u32;
+                y_mask |= masks[fold * channels + channels - 1] as u32;
+            }
+            effective_lowband = Some(lowband);
+        }
+        if dual_stereo && i == intensity {
+            // Intensity stereo from here on, folding uses the average of both channels.
+            dual_stereo = false;
+            let (norm, norm2) = norm.split_at_mut(norm_size);
+            for (value, other) in norm[..start].iter_mut().zip(norm2.iter()) {
+                *value = 0.5 * (*value + other);
+            }
+        }
+        let lowband = effective_lowband.map(|lowband| norm[lowband..lowband + width].to_vec());
+        let x = &mut xs[start..start + width];
+        if dual_stereo {
+            let lowband2 = effective_lowband.map(|lowband| norm[norm_size + lowband..norm_size + lowband + width].to_vec());
+            let (norm, norm2) = norm.split_at_mut(norm_size);
+            x_mask = bands.band(x, Budget { bits: b / 2, blocks, fill: x_mask }, lowband.as_deref(), lm,
+                (!last).then(|| &mut norm[start..start + width]), 1.0);
+            y_mask = bands.band(&mut ys[start..start + width], Budget { bits: b / 2, blocks, fill: y_mask }, lowband2.as_deref(), lm,
+                (!last).then(|| &mut norm2[start..start + width]), 1.0);
+        } else {
+            let lowband_out = (!last).then(|| &mut norm[start..start + width]);
+            let budget = Budget { bits: b, blocks, fill: x_mask | y_mask };
+            x_mask = if channels == 2 {
+                bands.band_stereo(x, &mut ys[start..start + width], budget, lowband.as_deref(), lm, lowband_out)
+            } else {
+                bands.band(x, budget, lowband.as_deref(), lm, lowband_out, 1.0)
+            };
+            y_mask = x_mask;
+        }
+        masks[i * channels] = x_mask as u8;
+        masks[i * channels + channels - 1] = y_mask as u8;
+        balance += pulses[i] + tell;
+        // The folding position moves up only while the bands have a bit per sample.
+        update_lowband = b > (width << BITRES) as i32;
+    }
+    *seed = bands.seed;
+    masks
+}
@@ -0,0 +1,793 @@
+//! The CELT layer of RFC 6716 section 4.3 at 48 kHz, a port of the float
+//! build of the reference decoder.
+
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use super::{
+    bands::{self, lcg_rand, renormalise, SPREAD_NORMAL},
+    range::{RangeDecoder, BITRES},
+    tables::*,
+};
+
+const BANDS: usize = 21;
+/// Samples of the shortest MDCT.
+const SHORT_MDCT: usize = 120;
+/// Samples the MDCTs of consecutive frames overlap by.
+const OVERLAP: usize = 120;
+/// Past output kept for the postfilter.
+const DECODE_BUFFER: usize = 2048;
+const MAX_FINE_BITS: i32 = 8;
+const FINE_OFFSET: i32 = 21;
+const ALLOC_STEPS: i32 = 6;
+const COMBFILTER_MINPERIOD: usize = 15;
+const PREEMPHASIS: f32 = 0.850_006_1;
+/// Taps of the postfilter by tapset.
+const COMB_GAINS: [[f32; 3]; 3] = [
+    [0.306_640_63, 0.217_041_02, 0.129_638_67],
+    [0.463_867_2, 0.268_066_4, 0.0],
+    [0.799_804_7, 0.100_097_66, 0.0],
+];
+
+fn exp2(x: f32) -> f32 {
+    (std::f64::consts::LN_2 * x as f64).exp() as f32
+}
+
+/// Inverse MDCT of one size, computed with an FFT of a quarter of it.
+struct Mdct {
+    fft: Arc<dyn Fft<f32>>,
+    trig: Vec<f32>,
+}
+
+impl Mdct {
+    fn new(planner: &mut FftPlanner<f32>, n: usize) -> Self {
+        Self {
+            fft: planner.plan_fft_forward(n / 4),
+            trig: (0..n / 2)
+                .map(|i| (2.0 * std::f32::consts::PI as f64 * (i as f64 + 0.125) / n as f64).cos() as f32)
+                .collect(),
+        }
+    }
+
+    /// Transforms every `stride`th coefficient of `input` into `out`, which
+    /// holds the end of the previous block in its first `OVERLAP` samples.
+    fn backward(&self, input: &[f32], stride: usize, out: &mut [f32], window: &[f32]) {
+        let trig = &self.trig;
+        let n2 = trig.len();
+        let n4 = n2 / 2;
+        let mut buffer: Vec<Complex<f32>> = (0..n4).map(|i| {
+            let (x1, x2) = (input[2 * i * stride], input[stride * (n2 - 1 - 2 * i)]);
+            let yr = x2 * trig[i] + x1 * trig[n4 + i];
+            let yi = x1 * trig[i] - x2 * trig[n4 + i];
+            Complex::new(yi, yr)
+        }).collect();
+        self.fft.process(&mut buffer);
+        let y = &mut out[OVERLAP / 2..OVERLAP / 2 + n2];
+        for (pair, value) in y.chunks_exact_mut(2).zip(&buffer) {
+            pair[0] = value.re;
+            pair[1] = value.im;
+        }
+        // Post-rotation from both ends at once, the real and imaginary
+        // parts are swapped because of the forward FFT.
+        for i in 0..(n4 + 1) >> 1 {
+            let (front, back) = (2 * i, n2 - 2 - 2 * i);
+            let (re, im) = (y[front + 1], y[front]);
+            let (t0, t1) = (trig[i], trig[n4 + i]);
+            let yr = re * t0 + im * t1;
+            let yi = re * t1 - im * t0;
+            let (re, im) = (y[back + 1], y[back]);
+            y[front] = yr;
+            y[back + 1] = yi;
+            let (t0, t1) = (trig[n4 - i - 1], trig[n2 - i - 1]);
+            let yr = re * t0 + im * t1;
+            let yi = re * t1 - im * t0;
+            y[back] = yr;
+            y[front + 1] = yi;
+        }
+        // Mirror on both sides for the time-domain aliasing cancellation.
+        for i in 0..OVERLAP / 2 {
+            let (x1, x2) = (out[OVERLAP - 1 - i], out[i]);
+            out[i] = window[OVERLAP - 1 - i] * x2 - window[i] * x1;
+            out[OVERLAP - 1 - i] = window[i] * x2 + window[OVERLAP - 1 - i] * x1;
+        }
+    }
+}
+
+/// Pitch period, gain and tapset of the postfilter.
+#[derive(Clone, Copy, Default)]
+struct PostFilter {
+    period: usize,
+    gain: f32,
+    tapset: usize,
+}
+
+/// Comb filters `length` samples of `buffer` from `offset` in place,
+/// crossfading from the `from` filter to the `to` one over the overlap.
+fn comb_filter(buffer: &mut [f32], offset: usize, length: usize, from: PostFilter, to: PostFilter, window: &[f32]) {
+    if from.gain == 0.0 && to.gain == 0.0 {
+        return;
+    }
+    let (t0, t1) = (from.period.max(COMBFILTER_MINPERIOD), to.period.max(COMBFILTER_MINPERIOD));
+    let g0 = COMB_GAINS[from.tapset].map(|gain| from.gain * gain);
+    let g1 = COMB_GAINS[to.tapset].map(|gain| to.gain * gain);
+    let x = buffer;
+    let mut x1 = x[offset + 1 - t1];
+    let mut x2 = x[offset - t1];
+    let mut x3 = x[offset - t1 - 1];
+    let mut x4 = x[offset - t1 - 2];
+    let overlap = if from.gain == to.gain && t0 == t1 && from.tapset == to.tapset { 0 } else { OVERLAP };
+    for i in offset..offset + overlap {
+        let x0 = x[i - t1 + 2];
+        let f = window[i - offset] * window[i - offset];
+        x[i] = x[i]
+            + ((1.0 - f) * g0[0]) * x[i - t0]
+            + ((1.0 - f) * g0[1]) * (x[i - t0 + 1] + x[i - t0 - 1])
+            + ((1.0 - f) * g0[2]) * (x[i - t0 + 2] + x[i - t0 - 2])
+            + (f * g1[0]) * x2
+            + (f * g1[1]) * (x1 + x3)
+            + (f * g1[2]) * (x0 + x4);
+        (x4, x3, x2, x1) = (x3, x2, x1, x0);
+    }
+    if to.gain == 0.0 {
+        return;
+    }
+    let start = offset + overlap;
+    let mut x4 = x[start - t1 - 2];
+    let mut x3 = x[start - t1 - 1];
+    let mut x2 = x[start - t1];
+    let mut x1 = x[start - t1 + 1];
+    for i in start..offset + length {
+        let x0 = x[i - t1 + 2];
+        x[i] = x[i] + g1[0] * x2 + g1[1] * (x1 + x3) + g1[2] * (x0 + x4);
+        (x4, x3, x2, x1) = (x3, x2, x1, x0);
+    }
+}
+
+fn tf_decode(decoder: &mut RangeDecoder, end: usize, transient: bool, lm: usize) -> Vec<i32> {
+    let mut budget = decoder.storage as u32 * 8;
+    let mut tell = decoder.tell() as u32;
+    let mut logp = if transient { 2 } else { 4 };
+    let select_reserved = lm > 0 && tell + logp < budget;
+    budget -= select_reserved as u32;
+    let (mut changed, mut current) = (0, 0);
+    let mut resolution = [0; BANDS];
+    for value in resolution.iter_mut().take(end) {
+        if tell + logp <= budget {
+            current ^= decoder.bit_logp(logp) as usize;
+            tell = decoder.tell() as u32;
+            changed |= current;
+        }
+        *value = current;
+        logp = if transient { 4 } else { 5 };
+    }
+    let row = &TF_SELECT[lm];
+    let transient = 4 * transient as usize;
+    let select = select_reserved && row[transient + changed] != row[transient + 2 + changed] && decoder.bit_logp(1);
+    resolution.iter().map(|value| row[transient + 2 * select as usize + value]).collect()
+}
+
+/// Bands, channels and block size of a frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// Coded channels.
+    pub channels: usize,
+    /// Bands coded, from 13 for narrowband to 21 for fullband.
+    pub end: usize,
+    /// Log2 of the short MDCTs in the frame.
+    pub lm: usize,
+}
+
+impl Layout {
+    /// Samples per channel.
+    fn size(&self) -> usize {
+        SHORT_MDCT << self.lm
+    }
+}
+
+/// Side information of a frame coded before the allocation.
+pub struct Header {
+    /// Coded with short blocks.
+    pub transient: bool,
+    pub spread: usize,
+    /// Time-frequency resolution change of every band.
+    pub tf_res: Vec<i32>,
+}
+
+/// Bits of every band for PVQ and fine energy, RFC 6716 section 4.3.3.
+pub struct Allocation {
+    pub coded_bands: usize,
+    pub intensity: usize,
+    pub dual_stereo: bool,
+    pub balance: i32,
+    pub pulses: Vec<i32>,
+    fine_bits: Vec<i32>,
+    fine_priority: Vec<bool>,
+}
+
+/// Bits set aside for the side information coded along with the allocation.
+struct Reserved {
+    skip: i32,
+    intensity: i32,
+    dual_stereo: i32,
+}
+
+/// The two allocation vectors that get interpolated, with the threshold
+/// under which a band gets no PVQ bits and the last band with a boost.
+struct Curves {
+    low: [i32; BANDS],
+    high: [i32; BANDS],
+    thresh: [i32; BANDS],
+    skip_start: usize,
+}
+
+fn compute_allocation(decoder: &mut RangeDecoder, layout: Layout, offsets: &[i32], cap: &[i32], trim: i32, total: i32) -> Allocation {
+    let Layout { channels, end, lm } = layout;
+    let c = channels as i32;
+    let mut total = total.max(0);
+    let skip_reserved = if total >= 1 << BITRES { 1 << BITRES } else { 0 };
+    total -= skip_reserved;
+    let (mut intensity_reserved, mut dual_stereo_reserved) = (0, 0);
+    if channels == 2 {
+        intensity_reserved = LOG2_FRAC[end];
+        if intensity_reserved > total {
+            intensity_reserved = 0;
+        } else {
+            total -= intensity_reserved;
+            dual_stereo_reserved = if total >= 1 << BITRES { 1 << BITRES } else { 0 };
+            total -= dual_stereo_reserved;
+        }
+    }
+    let width = |band: usize| (EBANDS[band + 1] - EBANDS[band]) as i32;
+    let mut thresh = [0; BANDS];
+    let mut trim_offset = [0; BANDS];
+    for j in 0..end {
+        // Below this there are surely no PVQ bits
+        thresh[j] = (c << BITRES).max((((3 * width(j)) << lm) << BITRES) >> 4);
+        // Tilt of the allocation curve
+        trim_offset[j] = (c * width(j) * (trim - 5 - lm as i32) * (end - j - 1) as i32 * (1 << (lm as u32 + BITRES))) >> 6;
+        // Single coefficient bands gain more from coarse energy
+        if width(j) << lm == 1 {
+            trim_offset[j] -= c << BITRES;
+        }
+    }
+    let vector = |quality: usize, j: usize| ((c * width(j) * BAND_ALLOCATION[quality][j] as i32) << lm) >> 2;
+    let (mut low, mut high) = (1, BAND_ALLOCATION.len() as i32 - 1);
+    while low <= high {
+        let mid = (low + high) >> 1;
+        let (mut done, mut sum) = (false, 0);
+        for j in (0..end).rev() {
+            let mut bits = vector(mid as usize, j);
+            if bits > 0 {
+                bits = (bits + trim_offset[j]).max(0);
+            }
+            bits += offsets[j];
+            if bits >= thresh[j] || done {
+                done = true;
+                sum += bits.min(cap[j]);
+            } else if bits >= c << BITRES {
+                sum += c << BITRES;
+            }
+        }
+        if sum > total {
+            high = mid - 1;
+        } else {
+            low = mid + 1;
+        }
+    }
+    let (low, high) = ((low - 1) as usize, low as usize);
+    let mut skip_start = 0;
+    let mut bits1 = [0; BANDS];
+    let mut bits2 = [0; BANDS];
+    for j in 0..end {
+        let mut low_bits = vector(low, j);
+        let mut high_bits = if high >= BAND_ALLOCATION.len() { cap[j] } else { vector(high, j) };
+        if low_bits > 0 {
+            low_bits = (low_bits + trim_offset[j]).max(0);
+        }
+        if high_bits > 0 {
+            high_bits = (high_bits + trim_offset[j]).max(0);
+        }
+        if low > 0 {
+            low_bits += offsets[j];
+        }
+        high_bits += offsets[j];
+        if offsets[j] > 0 {
+            skip_start = j;
+        }
+        bits1[j] = low_bits;
+        bits2[j] = (high_bits - low_bits).max(0);
+    }
+    let curves = Curves { low: bits1, high: bits2, thresh, skip_start };
+    let reserved = Reserved { skip: skip_reserved, intensity: intensity_reserved, dual_stereo: dual_stereo_reserved };
+    interpolate_allocation(decoder, layout, &curves, cap, total, reserved)
+}
+
+fn interpolate_allocation(decoder: &mut RangeDecoder, layout: Layout, curves: &Curves, cap: &[i32], mut total: i32,
+    reserved: Reserved) -> Allocation {
+    let Layout { channels, end, lm } = layout;
+    let Curves { low: ref bits1, high: ref bits2, ref thresh, skip_start } = *curves;
+    let Reserved { skip: skip_reserved, intensity: mut intensity_reserved, dual_stereo: mut dual_stereo_reserved } = reserved;
+    let c = channels as i32;
+    let alloc_floor = c << BITRES;
+    let stereo = (channels > 1) as i32;
+    let log_m = (lm as i32) << BITRES;
+    let (mut low, mut high) = (0, 1 << ALLOC_STEPS);
+    for _ in 0..ALLOC_STEPS {
+        let mid = (low + high) >> 1;
+        let (mut sum, mut done) = (0, false);
+        for j in (0..end).rev() {
+            let bits = bits1[j] + ((mid * bits2[j]) >> ALLOC_STEPS);
+            if bits >= thresh[j] || done {
+                done = true;
+                sum += bits.min(cap[j]);
+            } else if bits >= alloc_floor {
+                sum += alloc_floor;
+            }
+        }
+        if sum > total {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    let mut sum = 0;
+    let mut done = false;
+    let mut bits = vec![0; BANDS];
+    for j in (0..end).rev() {
+        let mut value = bits1[j] + ((low * bits2[j]) >> ALLOC_STEPS);
+        if value < thresh[j] && !done {
+            value = if value >= alloc_floor { alloc_floor } else { 0 };
+        } else {
+            done = true;
+        }
+        value = value.min(cap[j]);
+        bits[j] = value;
+        sum += value;
+    }
+    // Skips bands from the end, giving their bits back to the others.
+    let mut coded_bands = end;
+    loop {
+        let j = coded_bands - 1;
+        if j <= skip_start {
+            total += skip_reserved;
+            break;
+        }
+        let mut left = total - sum;
+        let per_coefficient = left / EBANDS[coded_bands] as i32;
+        left -= EBANDS[coded_bands] as i32 * per_coefficient;
+        let rem = (left - EBANDS[j] as i32).max(0);
+        let band_width = (EBANDS[coded_bands] - EBANDS[j]) as i32;
+        let mut band_bits = bits[j] + per_coefficient * band_width + rem;
+        // Only bands above the threshold code the skip decision, the others are skipped.
+        if band_bits >= thresh[j].max(alloc_floor + (1 << BITRES)) {
+            if decoder.bit_logp(1) {
+                break;
+            }
+            sum += 1 << BITRES;
+            band_bits -= 1 << BITRES;
+        }
+        sum -= bits[j] + intensity_reserved;
+        if intensity_reserved > 0 {
+            intensity_reserved = LOG2_FRAC[j];
+        }
+        sum += intensity_reserved;
+        if band_bits >= alloc_floor {
+            sum += alloc_floor;
+            bits[j] = alloc_floor;
+        } else {
+            bits[j] = 0;
+        }
+        coded_bands -= 1;
+    }
+    let intensity = if intensity_reserved > 0 { decoder.uint(coded_bands as u32 + 1) as usize } else { 0 };
+    if intensity == 0 {
+        total += dual_stereo_reserved;
+        dual_stereo_reserved = 0;
+    }
+    let dual_stereo = dual_stereo_reserved > 0 && decoder.bit_logp(1);
+    let mut left = total - sum;
+    let per_coefficient = left / EBANDS[coded_bands] as i32;
+    left -= EBANDS[coded_bands] as i32 * per_coefficient;
+    for j in 0..coded_bands {
+        bits[j] += per_coefficient * (EBANDS[j + 1] - EBANDS[j]) as i32;
+    }
+    for j in 0..coded_bands {
+        let extra = left.min((EBANDS[j + 1] - EBANDS[j]) as i32);
+        bits[j] += extra;
+        left -= extra;
+    }
+    let mut fine_bits = vec![0; BANDS];
+    let mut fine_priority = vec![false; BANDS];
+    let mut balance = 0;
+    for j in 0..coded_bands {
+        let n = ((EBANDS[j + 1] - EBANDS[j]) << lm) as i32;
+        let bit = bits[j] + balance;
+        let mut excess;
+        if n > 1 {
+            excess = (bit - cap[j]).max(0);
+            bits[j] = bit - excess;
+            // The extra degree of freedom in stereo
+            let den = c * n + i32::from(channels == 2 && n > 2 && !dual_stereo && j < intensity);
+            let nc_log_n = den * (LOG_N[j] + log_m);
+            // Fine bits are offset by log2(N)/2 + FINE_OFFSET from their fair share.
+            let mut offset = (nc_log_n >> 1) - den * FINE_OFFSET;
+            if n == 2 {
+                offset += den << BITRES >> 2;
+            }
+            if bits[j] + offset < (den * 2) << BITRES {
+                offset += nc_log_n >> 2;
+            } else if bits[j] + offset < (den * 3) << BITRES {
+                offset += nc_log_n >> 3;
+            }
+            fine_bits[j] = ((bits[j] + offset + (den << (BITRES - 1))).max(0) / den) >> BITRES;
+            if c * fine_bits[j] > bits[j] >> BITRES {
+                fine_bits[j] = bits[j] >> stereo >> BITRES;
+            }
+            fine_bits[j] = fine_bits[j].min(MAX_FINE_BITS);
+            fine_priority[j] = fine_bits[j] * (den << BITRES) >= bits[j] + offset;
+            bits[j] -= (c * fine_bits[j]) << BITRES;
+        } else {
+            // A single coefficient only needs a sign, the rest goes to fine energy.
+            excess = (bit - (c << BITRES)).max(0);
+            bits[j] = bit - excess;
+            fine_bits[j] = 0;
+            fine_priority[j] = true;
+        }
+        if excess > 0 {
+            let extra_fine = (excess >> (stereo + BITRES as i32)).min(MAX_FINE_BITS - fine_bits[j]);
+            fine_bits[j] += extra_fine;
+            let extra_bits = (extra_fine * c) << BITRES;
+            fine_priority[j] = extra_bits >= excess - balance;
+            excess -= extra_bits;
+        }
+        balance = excess;
+    }
+    // Skipped bands use their bits for fine energy.
+    for j in coded_bands..end {
+        fine_bits[j] = bits[j] >> stereo >> BITRES;
+        bits[j] = 0;
+        fine_priority[j] = fine_bits[j] < 1;
+    }
+    Allocation { coded_bands, intensity, dual_stereo, balance, pulses: bits, fine_bits, fine_priority }
+}
+
+/// Decoder of CELT frames, keeps the overlap, energy and postfilter state
+/// between them.
+pub struct Celt {
+    channels: usize,
+    /// Past output of every channel, the frame being decoded at the end.
+    history: Vec<Vec<f32>>,
+    old_band_energy: [f32; 2 * BANDS],
+    old_log_energy: [f32; 2 * BANDS],
+    old_log_energy2: [f32; 2 * BANDS],
+    postfilter: PostFilter,
+    postfilter_old: PostFilter,
+    preemphasis: [f32; 2],
+    rng: u32,
+    window: Vec<f32>,
+    /// Inverse MDCTs by shift from the longest.
+    mdcts: Vec<Mdct>,
+}
+
+impl Celt {
+    pub fn new(channels: usize) -> Self {
+        let mut planner = FftPlanner::new();
+        let half_pi = std::f64::consts::FRAC_PI_2;
+        let mut celt = Self {
+            channels,
+            history: vec![vec![0.0; DECODE_BUFFER + OVERLAP]; channels],
+            old_band_energy: [0.0; 2 * BANDS],
+            old_log_energy: [0.0; 2 * BANDS],
+            old_log_energy2: [0.0; 2 * BANDS],
+            postfilter: PostFilter::default(),
+            postfilter_old: PostFilter::default(),
+            preemphasis: [0.0; 2],
+            rng: 0,
+            window: (0..OVERLAP).map(|i| {
+                let x = (half_pi * (i as f64 + 0.5) / OVERLAP as f64).sin();
+                (half_pi * x * x).sin() as f32
+            }).collect(),
+            mdcts: (0..4).map(|shift| Mdct::new(&mut planner, 1920 >> shift)).collect(),
+        };
+        celt.reset();
+        celt
+    }
+
+    pub fn reset(&mut self) {
+        self.history.iter_mut().for_each(|history| history.fill(0.0));
+        self.old_band_energy = [0.0; 2 * BANDS];
+        self.old_log_energy = [-28.0; 2 * BANDS];
+        self.old_log_energy2 = [-28.0; 2 * BANDS];
+        self.postfilter = PostFilter::default();
+        self.postfilter_old = PostFilter::default();
+        self.preemphasis = [0.0; 2];
+        self.rng = 0;
+    }
+
+    fn coarse_energy(&mut self, decoder: &mut RangeDecoder, end: usize, intra: bool, channels: usize, lm: usize) {
+        let model = &E_PROB_MODEL[lm][intra as usize];
+        let (coef, beta) = if intra { (0.0, BETA_INTRA) } else { (PRED_COEF[lm], BETA_COEF[lm]) };
+        let mut prediction = [0.0f32; 2];
+        let budget = decoder.storage as i32 * 8;
+        for i in 0..end {
+            for (c, prediction) in prediction.iter_mut().enumerate().take(channels) {
+                let left = budget - decoder.tell();
+                let qi = if left >= 15 {
+                    let pi = 2 * i.min(20);
+                    decoder.laplace((model[pi] as u32) << 7, (model[pi + 1] as u32) << 6)
+                } else if left >= 2 {
+                    let qi = decoder.icdf(&SMALL_ENERGY_ICDF, 2) as i32;
+                    (qi >> 1) ^ -(qi & 1)
+                } else if left >= 1 {
+                    -(decoder.bit_logp(1) as i32)
+                } else {
+                    -1
+                };
+                let q = qi as f32;
+                let energy = &mut self.old_band_energy[i + c * BANDS];
+                *energy = energy.max(-9.0);
+                *energy = coef * *energy + *prediction + q;
+                *prediction = *prediction + q - beta * q;
+            }
+        }
+    }
+
+    fn fine_energy(&mut self, decoder: &mut RangeDecoder, end: usize, fine_bits: &[i32], channels: usize) {
+        for (i, &bits) in fine_bits.iter().enumerate().take(end) {
+            if bits <= 0 {
+                continue;
+            }
+            for c in 0..channels {
+                let q = decoder.bits(bits as u32);
+                let offset = (q as f32 + 0.5) * (1 << (14 - bits)) as f32 * (1.0 / 16384.0) - 0.5;
+                self.old_band_energy[i + c * BANDS] += offset;
+            }
+        }
+    }
+
+    fn finalise_energy(&mut self, decoder: &mut RangeDecoder, end: usize, allocation: &Allocation, mut bits_left: i32, channels: usize) {
+        for priority in [false, true] {
+            for i in 0..end {
+                if bits_left < channels as i32 {
+                    break;
+                }
+                if allocation.fine_bits[i] >= MAX_FINE_BITS || allocation.fine_priority[i] != priority {
+                    continue;
+                }
+                for c in 0..channels {
+                    let q = decoder.bits(1);
+                    let offset = (q as f32 - 0.5) * (1 << (14 - allocation.fine_bits[i] - 1)) as f32 * (1.0 / 16384.0);
+                    self.old_band_energy[i + c * BANDS] += offset;
+                    bits_left -= 1;
+                }
+            }
+        }
+    }
+
+    /// Fills the blocks of transients that got no pulses with noise, so
+    /// the energy doesn't collapse.
+    fn anti_collapse(&self, x: &mut [f32], masks: &[u8], layout: Layout, pulses: &[i32], mut seed: u32) {
+        let Layout { channels, end, lm } = layout;
+        let size = layout.size();
+        for i in 0..end {
+            let n0 = EBANDS[i + 1] - EBANDS[i];
+            // Depth in 1/8 bits
+            let depth = ((1 + pulses[i]) as u32 / n0 as u32) >> lm;
+            let thresh = 0.5 * exp2(-0.125 * depth as f32);
+            let sqrt_1 = 1.0 / ((n0 << lm) as f32).sqrt();
+            for c in 0..channels {
+                let mut prev1 = self.old_log_energy[c * BANDS + i];
+                let mut prev2 = self.old_log_energy2[c * BANDS + i];
+                if channels == 1 {
+                    prev1 = prev1.max(self.old_log_energy[BANDS + i]);
+                    prev2 = prev2.max(self.old_log_energy2[BANDS + i]);
+                }
+                let difference = (self.old_band_energy[c * BANDS + i] - prev1.min(prev2)).max(0.0);
+                // Short blocks don't have the energy of long ones
+                let mut r = 2.0 * exp2(-difference);
+                if lm == 3 {
+                    r *= std::f32::consts::SQRT_2;
+                }
+                let r = r.min(thresh) * sqrt_1;
+                let band = &mut x[c * size + (EBANDS[i] << lm)..c * size + (EBANDS[i + 1] << lm)];
+                let mut renormalize = false;
+                for k in 0..1 << lm {
+                    if masks[i * channels + c] & 1 << k == 0 {
+                        for j in 0..n0 {
+                            seed = lcg_rand(seed);
+                            band[(j << lm) + k] = if seed & 0x8000 != 0 { r } else { -r };
+                        }
+                        renormalize = true;
+                    }
+                }
+                if renormalize {
+                    renormalise(band, 1.0);
+                }
+            }
+        }
+    }
+
+    /// Scales the normalized bands of a channel by their energy.
+    fn denormalise(x: &[f32], energy: &[f32], end: usize, m: usize, silence: bool) -> Vec<f32> {
+        let mut frequencies = vec![0.0; m * SHORT_MDCT];
+        if silence {
+            return frequencies;
+        }
+        for i in 0..end {
+            let gain = exp2((energy[i] + E_MEANS[i]).min(32.0));
+            for j in m * EBANDS[i]..m * EBANDS[i + 1] {
+                frequencies[j] = x[j] * gain;
+            }
+        }
+        frequencies
+    }
+
+    /// Decodes a frame of `frame_size` samples per channel coded with
+    /// `stream_channels` and the bands up to `end`, into interleaved `pcm`.
+    /// Without data the frame is silent and only the overlap fades out.
+    pub fn decode(&mut self, data: &[u8], stream_channels: usize, end: usize, frame_size: usize, pcm: &mut [f32]) {
+        let lm = (0..4).find(|lm| SHORT_MDCT << lm == frame_size).expect("CELT frame size");
+        let m = 1 << lm;
+        let n = m * SHORT_MDCT;
+        let c = stream_channels;
+        let mut decoder = RangeDecoder::new(data);
+        if c == 1 {
+            for i in 0..BANDS {
+                self.old_band_energy[i] = self.old_band_energy[i].max(self.old_band_energy[BANDS + i]);
+            }
+        }
+        let total_bits = data.len() as i32 * 8;
+        let mut tell = decoder.tell();
+        let silence = if data.len() <= 1 || tell >= total_bits {
+            true
+        } else {
+            tell == 1 && decoder.bit_logp(15)
+        };
+        if silence {
+            // Pretend every bit was read
+            decoder.skip_to_end();
+            tell = total_bits;
+        }
+        let mut postfilter = PostFilter::default();
+        if tell + 16 <= total_bits {
+            if decoder.bit_logp(1) {
+                let octave = decoder.uint(6);
+                postfilter.period = ((16 << octave) + decoder.bits(4 + octave) - 1) as usize;
+                let gain = decoder.bits(3);
+                if decoder.tell() + 2 <= total_bits {
+                    postfilter.tapset = decoder.icdf(&TAPSET_ICDF, 2);
+                }
+                postfilter.gain = 0.09375 * (gain + 1) as f32;
+            }
+            tell = decoder.tell();
+        }
+        let transient = lm > 0 && tell + 3 <= total_bits && decoder.bit_logp(3);
+        let tell = decoder.tell();
+        let intra = tell + 3 <= total_bits && decoder.bit_logp(3);
+        self.coarse_energy(&mut decoder, end, intra, c, lm);
+        let tf_res = tf_decode(&mut decoder, end, transient, lm);
+        let spread = if decoder.tell() + 4 <= total_bits { decoder.icdf(&SPREAD_ICDF, 5) } else { SPREAD_NORMAL };
+        let cap: Vec<i32> = (0..BANDS).map(|i| {
+            let n = ((EBANDS[i + 1] - EBANDS[i]) << lm) as i32;
+            ((CACHE_CAPS[BANDS * (2 * lm + c - 1) + i] as i32 + 64) * c as i32 * n) >> 2
+        }).collect();
+        let mut offsets = vec![0; BANDS];
+        let mut dynalloc_logp = 6;
+        let mut total_bits = total_bits << BITRES;
+        let mut tell = decoder.tell_frac();
+        for i in 0..end {
+            let width = ((c * (EBANDS[i + 1] - EBANDS[i])) << lm) as i32;
+            // Six bits, but at most one per sample and at least 1/8
+            let quanta = (width << BITRES).min((6 << BITRES).max(width));
+            let mut loop_logp = dynalloc_logp;
+            let mut boost = 0;
+            while tell + ((loop_logp as i32) << BITRES) < total_bits && boost < cap[i] {
+                let flag = decoder.bit_logp(loop_logp);
+                tell = decoder.tell_frac();
+                if !flag {
+                    break;
+                }
+                boost += quanta;
+                total_bits -= quanta;
+                loop_logp = 1;
+            }
+            offsets[i] = boost;
+            if boost > 0 {
+                dynalloc_logp = 2.max(dynalloc_logp - 1);
+            }
+        }
+        let trim = if tell + (6 << BITRES) <= total_bits { decoder.icdf(&TRIM_ICDF, 7) as i32 } else { 5 };
+        let mut bits = ((data.len() as i32 * 8) << BITRES) - decoder.tell_frac() - 1;
+        let anti_collapse_reserved = if transient && lm >= 2 && bits >= (lm as i32 + 2) << BITRES { 1 << BITRES } else { 0 };
+        bits -= anti_collapse_reserved;
+        let layout = Layout { channels: c, end, lm };
+        let allocation = compute_allocation(&mut decoder, layout, &offsets, &cap, trim, bits);
+        self.fine_energy(&mut decoder, end, &allocation.fine_bits, c);
+        for history in &mut self.history {
+            history.copy_within(n..DECODE_BUFFER + OVERLAP / 2, 0);
+        }
+        let mut x = vec![0.0; c * n];
+        let mut seed = self.rng;
+        let header = Header { transient, spread, tf_res };
+        let masks = bands::decode_all(&mut decoder, &mut x, layout, &header, &allocation,
+            data.len() as i32 * (8 << BITRES) - anti_collapse_reserved, &mut seed);
+        self.rng = seed;
+        let anti_collapse = anti_collapse_reserved > 0 && decoder.bits(1) != 0;
+        let bits_left = data.len() as i32 * 8 - decoder.tell();
+        self.finalise_energy(&mut decoder, end, &allocation, bits_left, c);
+        if anti_collapse {
+            self.anti_collapse(&mut x, &masks, layout, &allocation.pulses, self.rng);
+        }
+        if silence {
+            self.old_band_energy[..c * BANDS].fill(-28.0);
+        }
+        self.synthesis(&x, c, end, transient, lm, silence);
+        let start = DECODE_BUFFER - n;
+        for history in &mut self.history {
+            comb_filter(history, start, SHORT_MDCT, self.postfilter_old, self.postfilter, &self.window);
+            if lm != 0 {
+                comb_filter(history, start + SHORT_MDCT, n - SHORT_MDCT, self.postfilter, postfilter, &self.window);
+            }
+        }
+        self.postfilter_old = if lm != 0 { postfilter } else { self.postfilter };
+        self.postfilter = postfilter;
+        if c == 1 {
+            self.old_band_energy.copy_within(..BANDS, BANDS);
+        }
+        if transient {
+            for (log, band) in self.old_log_energy.iter_mut().zip(&self.old_band_energy) {
+                *log = log.min(*band);
+            }
+        } else {
+            self.old_log_energy2 = self.old_log_energy;
+            self.old_log_energy = self.old_band_energy;
+        }
+        for channel in 0..2 {
+            for i in end..BANDS {
+                self.old_band_energy[channel * BANDS + i] = 0.0;
+                self.old_log_energy[channel * BANDS + i] = -28.0;
+                self.old_log_energy2[channel * BANDS + i] = -28.0;
+            }
+        }
+        self.rng = decoder.range;
+        self.deemphasis(n, pcm);
+    }
+
+    fn synthesis(&mut self, x: &[f32], stream_channels: usize, end: usize, transient: bool, lm: usize, silence: bool) {
+        let m = 1 << lm;
+        let n = m * SHORT_MDCT;
+        let (blocks, block_size, shift) = if transient { (m, SHORT_MDCT, 3) } else { (1, n, 3 - lm) };
+        let mut frequencies: Vec<Vec<f32>> = (0..stream_channels)
+            .map(|c| Self::denormalise(&x[c * n..], &self.old_band_energy[c * BANDS..], end, m, silence))
+            .collect();
+        if self.channels == 1 && stream_channels == 2 {
+            let right = frequencies.pop().unwrap_or_default();
+            for (left, right) in frequencies[0].iter_mut().zip(right) {
+                *left = 0.5 * *left + 0.5 * right;
+            }
+        } else if self.channels == 2 && stream_channels == 1 {
+            frequencies.push(frequencies[0].clone());
+        }
+        let mdct = &self.mdcts[shift];
+        for (history, frequencies) in self.history.iter_mut().zip(&frequencies) {
+            for b in 0..blocks {
+                let out = &mut history[DECODE_BUFFER - n + block_size * b..];
+                mdct.backward(&frequencies[b..], blocks, out, &self.window);
+            }
+        }
+    }
+
+    fn deemphasis(&mut self, n: usize, pcm: &mut [f32]) {
+        let channels = self.channels;
+        for (c, history) in self.history.iter().enumerate() {
+            let mut memory = self.preemphasis[c];
+            for (j, sample) in history[DECODE_BUFFER - n..DECODE_BUFFER].iter().enumerate() {
+                let value = sample + 1e-30 + memory;
+                memory = PREEMPHASIS * value;
+                pcm[j * channels + c] = value * (1.0 / 32768.0);
+            }
+            self.preemphasis[c] = memory;
+        }
+    }
+}
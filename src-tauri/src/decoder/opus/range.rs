@@ -0,0 +1,239 @@
+/// Bits of resolution of [`RangeDecoder::tell_frac`].
+pub const BITRES: u32 = 3;
+
+const SYM_BITS: u32 = 8;
+const CODE_BITS: u32 = 32;
+const SYM_MAX: u32 = (1 << SYM_BITS) - 1;
+const CODE_TOP: u32 = 1 << (CODE_BITS - 1);
+const CODE_BOT: u32 = CODE_TOP >> SYM_BITS;
+const CODE_EXTRA: u32 = (CODE_BITS - 2) % SYM_BITS + 1;
+const UINT_BITS: u32 = 8;
+const WINDOW_SIZE: u32 = 32;
+const LAPLACE_MINP: u32 = 1;
+const LAPLACE_NMIN: u32 = 16;
+
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+/// The range decoder of RFC 6716 section 4.1, symbols are read from the
+/// front of the frame and raw bits from its back.
+pub struct RangeDecoder<'a> {
+    buffer: &'a [u8],
+    /// Bytes of `buffer` the frame may use.
+    pub storage: usize,
+    offset: usize,
+    end_offset: usize,
+    end_window: u32,
+    end_bits: u32,
+    bits_total: i32,
+    pub range: u32,
+    value: u32,
+    /// Scale of the last symbol decoded with [`Self::decode`].
+    extra: u32,
+    remainder: u32,
+    /// Set when a uniform value was out of range, the frame is corrupted.
+    pub error: bool,
+}
+
+impl<'a> RangeDecoder<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        let mut decoder = Self {
+            buffer,
+            storage: buffer.len(),
+            offset: 0,
+            end_offset: 0,
+            end_window: 0,
+            end_bits: 0,
+            bits_total: (CODE_BITS + 1 - ((CODE_BITS - CODE_EXTRA) / SYM_BITS) * SYM_BITS) as i32,
+            range: 1 << CODE_EXTRA,
+            value: 0,
+            extra: 0,
+            remainder: 0,
+            error: false,
+        };
+        decoder.remainder = decoder.read_byte();
+        decoder.value = decoder.range - 1 - (decoder.remainder >> (SYM_BITS - CODE_EXTRA));
+        decoder.normalize();
+        decoder
+    }
+
+    fn read_byte(&mut self) -> u32 {
+        if self.offset < self.storage {
+            self.offset += 1;
+            self.buffer[self.offset - 1] as u32
+        } else {
+            0
+        }
+    }
+
+    fn read_byte_from_end(&mut self) -> u32 {
+        if self.end_offset < self.storage {
+            self.end_offset += 1;
+            self.buffer[self.storage - self.end_offset] as u32
+        } else {
+            0
+        }
+    }
+
+    fn normalize(&mut self) {
+        while self.range <= CODE_BOT {
+            self.bits_total += SYM_BITS as i32;
+            self.range <<= SYM_BITS;
+            let symbol = self.remainder;
+            self.remainder = self.read_byte();
+            let symbol = ((symbol << SYM_BITS) | self.remainder) >> (SYM_BITS - CODE_EXTRA);
+            self.value = ((self.value << SYM_BITS).wrapping_add(SYM_MAX & !symbol)) & (CODE_TOP - 1);
+        }
+    }
+
+    /// The cumulative frequency of the next symbol out of `total`, which has
+    /// to be followed by [`Self::update`].
+    pub fn decode(&mut self, total: u32) -> u32 {
+        self.extra = self.range / total;
+        let symbol = self.value / self.extra;
+        total - (symbol + 1).min(total)
+    }
+
+    pub fn decode_bin(&mut self, bits: u32) -> u32 {
+        self.extra = self.range >> bits;
+        let symbol = self.value / self.extra;
+        (1 << bits) - (symbol + 1).min(1 << bits)
+    }
+
+    pub fn update(&mut self, low: u32, high: u32, total: u32) {
+        let scaled = self.extra * (total - high);
+        self.value -= scaled;
+        self.range = if low > 0 { self.extra * (high - low) } else { self.range - scaled };
+        self.normalize();
+    }
+
+    /// A bit that is set with a probability of `1 / 2^logp`.
+    pub fn bit_logp(&mut self, logp: u32) -> bool {
+        let threshold = self.range >> logp;
+        let set = self.value < threshold;
+        if set {
+            self.range = threshold;
+        } else {
+            self.value -= threshold;
+            self.range -= threshold;
+        }
+        self.normalize();
+        set
+    }
+
+    /// A symbol with the inverse cumulative distribution `icdf` in `bits` of
+    /// precision.
+    pub fn icdf(&mut self, icdf: &[u8], bits: u32) -> usize {
+        let mut scaled = self.range;
+        let step = scaled >> bits;
+        let mut symbol = 0;
+        let mut previous;
+        loop {
+            previous = scaled;
+            scaled = step * icdf[symbol] as u32;
+            if self.value >= scaled {
+                break;
+            }
+            symbol += 1;
+        }
+        self.value -= scaled;
+        self.range = previous - scaled;
+        self.normalize();
+        symbol
+    }
+
+    /// A uniformly distributed value below `total`.
+    pub fn uint(&mut self, total: u32) -> u32 {
+        let top = total - 1;
+        let bits = ilog(top);
+        if bits > UINT_BITS {
+            let bits = bits - UINT_BITS;
+            let total = (top >> bits) + 1;
+            let symbol = self.decode(total);
+            self.update(symbol, symbol + 1, total);
+            let value = (symbol << bits) | self.bits(bits);
+            if value <= top {
+                return value;
+            }
+            self.error = true;
+            top
+        } else {
+            let symbol = self.decode(total);
+            self.update(symbol, symbol + 1, total);
+            symbol
+        }
+    }
+
+    /// Raw bits from the end of the frame.
+    pub fn bits(&mut self, bits: u32) -> u32 {
+        let mut window = self.end_window;
+        let mut available = self.end_bits;
+        if available < bits {
+            loop {
+                window |= self.read_byte_from_end() << available;
+                available += SYM_BITS;
+                if available > WINDOW_SIZE - SYM_BITS {
+                    break;
+                }
+            }
+        }
+        let value = window & ((1u64 << bits) - 1) as u32;
+        self.end_window = window.checked_shr(bits).unwrap_or(0);
+        self.end_bits = available - bits;
+        self.bits_total += bits as i32;
+        value
+    }
+
+    /// A value of the Laplace-like distribution of the coarse energy, with
+    /// the probability of zero and the decay in Q15.
+    pub fn laplace(&mut self, mut frequency: u32, decay: u32) -> i32 {
+        let mut value = 0;
+        let position = self.decode_bin(15);
+        let mut low = 0;
+        if position >= frequency {
+            value += 1;
+            low = frequency;
+            frequency = (((32768 - LAPLACE_MINP * 2 * LAPLACE_NMIN - frequency) * (16384 - decay)) >> 15) + LAPLACE_MINP;
+            while frequency > LAPLACE_MINP && position >= low + 2 * frequency {
+                frequency *= 2;
+                low += frequency;
+                frequency = (((frequency - 2 * LAPLACE_MINP) * decay) >> 15) + LAPLACE_MINP;
+                value += 1;
+            }
+            if frequency <= LAPLACE_MINP {
+                let step = (position - low) >> 1;
+                value += step as i32;
+                low += 2 * step * LAPLACE_MINP;
+            }
+            if position < low + frequency {
+                value = -value;
+            } else {
+                low += frequency;
+            }
+        }
+        self.update(low, (low + frequency).min(32768), 32768);
+        value
+    }
+
+    /// Bits used so far, rounded up.
+    pub fn tell(&self) -> i32 {
+        self.bits_total - ilog(self.range) as i32
+    }
+
+    /// Bits used so far in 1/8 bits.
+    pub fn tell_frac(&self) -> i32 {
+        const CORRECTION: [u32; 8] = [35733, 38967, 42495, 46340, 50535, 55109, 60097, 65535];
+        let bits = self.bits_total << BITRES;
+        let log = ilog(self.range);
+        let range = self.range >> (log - 16);
+        let mut fraction = (range >> 12) - 8;
+        fraction += (range > CORRECTION[fraction as usize]) as u32;
+        bits - ((log << 3) + fraction) as i32
+    }
+
+    /// Pretends every bit of the frame was read.
+    pub fn skip_to_end(&mut self) {
+        self.bits_total += (self.storage * 8) as i32 - self.tell();
+    }
+}
@@ -1,15 +1,28 @@
-use std::{future::Future, io::Write, path::Path};
+use std::{future::Future, io::Write, path::{Path, PathBuf}, sync::atomic::{AtomicBool, AtomicU32, Ordering}};
 
 use mime2ext::mime2ext;
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
-use tokio::{io::AsyncWriteExt, sync::{broadcast, Mutex}};
+use tokio::{io::AsyncWriteExt, sync::{broadcast, Mutex, Notify}};
 
-use crate::audio::Audio;
+use crate::{audio::Audio, settings::Transcode, transcode};
+
+mod analysis;
+mod covers;
+mod integrity;
+mod library;
+mod transcoding;
+mod user_files;
+
+pub const LYRICS_FILE: &str = "lyrics.lrc";
+/// Files the user made for an audio, they survive eviction and repair.
+const USER_FILES: [&str; 1] = [LYRICS_FILE];
 
 #[derive(Debug)]
 pub struct Content {
     pub mime: String,
+    /// Size of the whole file the server announced.
+    pub size: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +32,9 @@ pub enum Error {
     Canceled,
     InQueue,
     NotFound,
+    Busy,
+    InvalidTarget,
+    Transcode,
 }
 
 pub trait ContentRetriever {
@@ -44,7 +60,8 @@ impl ContentRetriever for DefaultContentRetriever {
         let mut response = request.send()
             .await
             .map_err(|_| Error::Connection)?;
-        let size = response.content_length().unwrap_or(0);
+        let announced = response.content_length();
+        let size = announced.unwrap_or(0);
         let mime = response.headers().get("Content-Type").map(|x| x.to_str().unwrap_or("")).unwrap_or("").to_string();
         let mut length = 0;
         while let Some(chunk) = response.chunk().await.map_err(|_| Error::Connection)? {
@@ -54,7 +71,7 @@ impl ContentRetriever for DefaultContentRetriever {
                 return Err(Error::Canceled);
             }
         }
-        Ok(Content { mime })
+        Ok(Content { mime, size: announced.map(|size| size + skip as u64) })
     }
 }
 
@@ -66,22 +83,38 @@ pub struct RequestFiles {
 
 #[derive(Debug)]
 pub struct ResponseFiles {
-    pub thumbnail: Vec<u8>,
-    pub thumbnail_mime: String,
     pub media: Vec<u8>,
     pub media_mime: String,
 }
 
+/// Paths of the downloaded files of an audio.
+#[derive(Debug, Clone)]
+pub struct LocalFiles {
+    pub thumbnail: PathBuf,
+    pub thumbnail_mime: String,
+    pub media: PathBuf,
+    pub media_mime: String,
+}
+
 impl RequestFiles {
     pub fn new(thumbnail: String, media: String) -> Self {
         Self { thumbnail, media }
     }
 }
 
+/// Stage of [`Storage::save`] that its callback reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// Bytes of the thumbnail and then of the media.
+    Download,
+    /// Milliseconds of the media converted to the configured codec.
+    Transcode,
+}
+
 pub trait Storage {
     async fn save<C, Fut>(&self, audio: &Audio, callback: C, downloads: RequestFiles) -> Result<(), Error>
     where
-        C: Fn(u64, u64) -> Fut,
+        C: Fn(Phase, u64, u64) -> Fut,
         Fut: Future<Output = ()>;
 
     async fn has_file(&self, audio: &Audio) -> bool;
@@ -95,10 +128,19 @@ pub trait Storage {
 }
 
 pub struct FileDownloader {
-    audio_dir: String,
-    downloading_dir: String,
+    audio_dir: Mutex<String>,
+    downloading_dir: Mutex<String>,
     content_retriever: DefaultContentRetriever,
     queue: Mutex<Vec<u32>>,
+    active: Mutex<u32>,
+    slot_released: Notify,
+    /// Notified whenever an audio leaves the queue.
+    finished: Notify,
+    max_concurrent: AtomicU32,
+    /// Set while the library moves, downloads are refused meanwhile.
+    moving: AtomicBool,
+    attempts: AtomicU32,
+    transcode: std::sync::Mutex<Option<Transcode>>,
 
     cancel_broadcast: broadcast::Sender<u32>,
 }
@@ -106,57 +148,146 @@ pub struct FileDownloader {
 impl FileDownloader {
     pub fn new(audio_dir: String, downloading_dir: String) -> Self {
         Self {
-            audio_dir,
-            downloading_dir,
+            audio_dir: Mutex::new(audio_dir),
+            downloading_dir: Mutex::new(downloading_dir),
             content_retriever: DefaultContentRetriever,
             queue: Mutex::new(Vec::new()),
+            active: Mutex::new(0),
+            slot_released: Notify::new(),
+            finished: Notify::new(),
+            max_concurrent: AtomicU32::new(3),
+            moving: AtomicBool::new(false),
+            attempts: AtomicU32::new(5),
+            transcode: std::sync::Mutex::new(None),
 
             cancel_broadcast: broadcast::channel(10).0,
         }
     }
 
+    pub fn configure(&self, max_concurrent: u32, attempts: u32, transcode: Option<Transcode>) {
+        self.max_concurrent.store(max_concurrent, Ordering::SeqCst);
+        self.attempts.store(attempts, Ordering::SeqCst);
+        *self.transcode.lock().unwrap() = transcode;
+        self.slot_released.notify_waiters();
+    }
+
+    async fn acquire_slot(&self, id: u32) -> Result<(), Error> {
+        loop {
+            let released = self.slot_released.notified();
+            if !self.is_in_queue(id).await {
+                self.cancel_broadcast.send(id).map_err(|_| Error::Unknown)?;
+                return Err(Error::Canceled);
+            }
+            {
+                let mut active = self.active.lock().await;
+                if *active < self.max_concurrent.load(Ordering::SeqCst) {
+                    *active += 1;
+                    return Ok(());
+                }
+            }
+            released.await;
+        }
+    }
+
+    async fn release_slot(&self) {
+        *self.active.lock().await -= 1;
+        self.slot_released.notify_waiters();
+    }
+
+    async fn audio_path(&self, id: u32) -> PathBuf {
+        Path::new(&*self.audio_dir.lock().await).join(id.to_string())
+    }
+
+    async fn downloading_path(&self, id: u32) -> PathBuf {
+        Path::new(&*self.downloading_dir.lock().await).join(id.to_string())
+    }
+
+    async fn read_index(&self, id: u32) -> Result<Index, Error> {
+        let index = tokio::fs::read(self.audio_path(id).await.join("index.json")).await.map_err(|_| Error::NotFound)?;
+        serde_json::from_slice::<Index>(&index).map_err(|_| Error::Unknown)
+    }
+
+    async fn write_index(&self, id: u32, index: &Index) -> Result<(), Error> {
+        let index = serde_json::to_string(index).map_err(|_| Error::Unknown)?;
+        tokio::fs::write(self.audio_path(id).await.join("index.json"), index).await.map_err(|_| Error::Unknown)
+    }
+
+    pub async fn local_files(&self, id: u32) -> Result<LocalFiles, Error> {
+        let audio_dir = self.audio_path(id).await;
+        let index = self.read_index(id).await?;
+        Ok(LocalFiles {
+            thumbnail: audio_dir.join(format!("thumbnail.{}", mime2ext(index.thumbnail_mime.clone()).unwrap_or("bin"))),
+            thumbnail_mime: index.thumbnail_mime,
+            media: audio_dir.join(format!("media.{}", mime2ext(index.media_mime.clone()).unwrap_or("bin"))),
+            media_mime: index.media_mime,
+        })
+    }
+
     async fn download_files<C, Fut>(&self, audio: &Audio, callback: C, downloads: RequestFiles) -> Result<(), Error>
     where
-        C: Fn(u64, u64) -> Fut,
+        C: Fn(Phase, u64, u64) -> Fut,
         Fut: Future<Output = ()>,
     {
-        let audio_dir = Path::new(&self.audio_dir).join(audio.id.to_string());
-        let downloading_dir = Path::new(&self.downloading_dir).join(audio.id.to_string());
+        let audio_dir = self.audio_path(audio.id).await;
+        let downloading_dir = self.downloading_path(audio.id).await;
         tokio::fs::create_dir_all(&audio_dir).await.map_err(|_| Error::Unknown)?;
         tokio::fs::create_dir_all(&downloading_dir).await.map_err(|_| Error::Unknown)?;
         let thumbnail_content = self.donwload_file(audio, &callback, downloads.thumbnail, "thumbnail.bin".to_string()).await?;
         let audio_content = self.donwload_file(audio, &callback, downloads.media, "media.bin".to_string()).await?;
+        let target = *self.transcode.lock().unwrap();
+        // The size the server announced catches files cut short, a transcoded
+        // file only has the size it was written with.
+        let (media_mime, media_size) = match target {
+            Some(target) if !transcode::is_target(&audio_content.mime, target.codec) => {
+                (self.transcode_media(audio, &callback, target, &audio_content.mime).await?, None)
+            },
+            _ => (audio_content.mime, audio_content.size),
+        };
         tokio::fs::rename(downloading_dir.join("thumbnail.bin"), audio_dir.join(format!("thumbnail.{}", mime2ext(thumbnail_content.mime.clone()).unwrap_or("bin")))).await.map_err(|_| Error::Unknown)?;
-        tokio::fs::rename(downloading_dir.join("media.bin"), audio_dir.join(format!("media.{}", mime2ext(audio_content.mime.clone()).unwrap_or("bin")))).await.map_err(|_| Error::Unknown)?;
+        let media_path = audio_dir.join(format!("media.{}", mime2ext(media_mime.clone()).unwrap_or("bin")));
+        tokio::fs::rename(downloading_dir.join("media.bin"), &media_path).await.map_err(|_| Error::Unknown)?;
+        let media_size = match media_size {
+            Some(size) => size,
+            None => tokio::fs::metadata(&media_path).await.map_err(|_| Error::Unknown)?.len(),
+        };
         let index = Index {
-            media_mime: audio_content.mime,
+            media_mime,
             thumbnail_mime: thumbnail_content.mime,
+            media_size: Some(media_size),
+            no_covers: false,
         };
         let index = serde_json::to_string(&index).map_err(|_| Error::Unknown)?;
         tokio::fs::write(audio_dir.join("index.json"), index).await.map_err(|_| Error::Unknown)?;
+        let _ = tokio::fs::remove_dir(&downloading_dir).await;
+        // A thumbnail that can't be decoded is still served as it is.
+        let _ = self.derive_covers(audio.id).await;
         Ok(())
     }
 
     async fn donwload_file<C, Fut>(&self, audio: &Audio, callback: C, url: String, filename: String) -> Result<Content, Error>
     where
-        C: Fn(u64, u64) -> Fut,
+        C: Fn(Phase, u64, u64) -> Fut,
         Fut: Future<Output = ()>,
     {
-        let downloading_dir = Path::new(&self.downloading_dir).join(audio.id.to_string());
+        let downloading_dir = self.downloading_path(audio.id).await;
         let mut file = tokio::fs::File::create(downloading_dir.join(filename)).await.map_err(|_| Error::Unknown)?;
         let mut bytes = Vec::new();
         let mut result = Err(Error::Unknown);
-        for _attempt in 0..5 {
+        for _attempt in 0..self.attempts.load(Ordering::SeqCst) {
             let len = bytes.len();
             result = self.content_retriever.download(url.clone(), &mut bytes, |downloaded, total| {
                 let callback = &callback;
                 async move {
-                    callback(downloaded, total).await;
+                    callback(Phase::Download, downloaded, total).await;
                     self.is_in_queue(audio.id).await
                 }
             }, len).await;
             match &result {
                 Err(Error::Canceled) => { break; },
+                // The connection closed early, the next attempt resumes.
+                Ok(content) if content.size.is_some_and(|size| (bytes.len() as u64) < size) => {
+                    result = Err(Error::Connection);
+                },
                 Ok(_) => { break; }
                 _ => {},
             }
@@ -179,12 +310,27 @@ impl FileDownloader {
         if queue.contains(&id) {
             return Err(Error::InQueue);
         }
+        if self.moving.load(Ordering::SeqCst) {
+            return Err(Error::Busy);
+        }
         queue.push(id);
         Ok(())
     }
 
     async fn anyway_pop_queue(&self, id: u32) {
         self.queue.lock().await.retain(|x| *x != id);
+        self.finished.notify_waiters();
+    }
+
+    /// Waits until the audio is no longer queued or downloading.
+    pub async fn wait_for(&self, id: u32) {
+        loop {
+            let finished = self.finished.notified();
+            if !self.is_in_queue(id).await {
+                return;
+            }
+            finished.await;
+        }
     }
 }
 
@@ -192,22 +338,32 @@ impl FileDownloader {
 struct Index {
     media_mime: String,
     thumbnail_mime: String,
+    #[serde(default)]
+    media_size: Option<u64>,
+    /// Covers couldn't be derived from the thumbnail, they aren't tried again.
+    #[serde(default)]
+    no_covers: bool,
 }
 
 impl Storage for FileDownloader {
     async fn save<C, Fut>(&self, audio: &Audio, callback: C, downloads: RequestFiles) -> Result<(), Error>
     where
-        C: Fn(u64, u64) -> Fut,
+        C: Fn(Phase, u64, u64) -> Fut,
         Fut: Future<Output = ()>
     {        
         self.push_queue(audio.id).await?;
+        if let Err(err) = self.acquire_slot(audio.id).await {
+            self.anyway_pop_queue(audio.id).await;
+            return Err(err);
+        }
         let result = self.download_files(audio, callback, downloads).await;
+        self.release_slot().await;
         self.anyway_pop_queue(audio.id).await;
         result
     }
     
     async fn has_file(&self, audio: &Audio) -> bool {
-        let audio_dir = Path::new(&self.audio_dir).join(audio.id.to_string());
+        let audio_dir = self.audio_path(audio.media_id()).await;
         audio_dir.join("index.json").exists()
     }
     
@@ -216,16 +372,11 @@ impl Storage for FileDownloader {
     }
     
     async fn get_files(&self, audio: &Audio) -> Result<ResponseFiles, Error> {
-        let audio_dir = Path::new(&self.audio_dir).join(audio.id.to_string());
-        let index = tokio::fs::read(audio_dir.join("index.json")).await.map_err(|_| Error::NotFound)?;
-        let index = serde_json::from_slice::<Index>(&index).map_err(|_| Error::Unknown)?;
-        let thumbnail = tokio::fs::read(audio_dir.join(format!("thumbnail.{}", mime2ext(index.thumbnail_mime.clone()).unwrap_or("bin")))).await.map_err(|_| Error::NotFound)?;
-        let audio = tokio::fs::read(audio_dir.join(format!("media.{}", mime2ext(index.media_mime.clone()).unwrap_or("bin")))).await.map_err(|_| Error::NotFound)?;
+        let files = self.local_files(audio.media_id()).await?;
+        let audio = tokio::fs::read(&files.media).await.map_err(|_| Error::NotFound)?;
         Ok(ResponseFiles {
-            thumbnail,
-            thumbnail_mime: index.thumbnail_mime,
             media: audio,
-            media_mime: index.media_mime,
+            media_mime: files.media_mime,
         })
     }
     
@@ -233,6 +384,7 @@ impl Storage for FileDownloader {
         if self.queue.lock().await.contains(&id) {
             let mut rx = self.cancel_broadcast.subscribe();
             self.anyway_pop_queue(id).await;
+            self.slot_released.notify_waiters();
             loop {
                 let id = rx.recv().await.map_err(|_| Error::Unknown)?;
                 if id == id {
@@ -240,10 +392,14 @@ impl Storage for FileDownloader {
                 }
             }
         }
-        let audio_dir = Path::new(&self.audio_dir).join(id.to_string());
-        let downloading_dir = Path::new(&self.downloading_dir).join(id.to_string());
-        tokio::fs::remove_dir_all(audio_dir).await.map_err(|_| Error::Unknown)?;
-        tokio::fs::remove_dir_all(downloading_dir).await.map_err(|_| Error::Unknown)?;
+        let audio_dir = self.audio_path(id).await;
+        let downloading_dir = self.downloading_path(id).await;
+        if audio_dir.exists() {
+            tokio::fs::remove_dir_all(audio_dir).await.map_err(|_| Error::Unknown)?;
+        }
+        if downloading_dir.exists() {
+            tokio::fs::remove_dir_all(downloading_dir).await.map_err(|_| Error::Unknown)?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,29 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Error, FileDownloader};
+
+impl FileDownloader {
+    /// Reads a file that analysis stored next to `index.json`.
+    pub async fn load_analysis_file(&self, id: u32, name: &str) -> Option<Vec<u8>> {
+        tokio::fs::read(self.audio_path(id).await.join(name)).await.ok()
+    }
+
+    /// Stores analysis results in the folder of a downloaded audio, so they
+    /// move and get evicted together with its files.
+    pub async fn save_analysis_file(&self, id: u32, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let audio_dir = self.audio_path(id).await;
+        if !audio_dir.join("index.json").exists() {
+            return Err(Error::NotFound);
+        }
+        tokio::fs::write(audio_dir.join(name), bytes).await.map_err(|_| Error::Unknown)
+    }
+
+    pub async fn load_analysis<T: DeserializeOwned>(&self, id: u32, name: &str) -> Option<T> {
+        serde_json::from_slice(&self.load_analysis_file(id, name).await?).ok()
+    }
+
+    pub async fn save_analysis<T: Serialize>(&self, id: u32, name: &str, value: &T) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(value).map_err(|_| Error::Unknown)?;
+        self.save_analysis_file(id, name, &serialized).await
+    }
+}
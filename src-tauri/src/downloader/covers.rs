@@ -0,0 +1,44 @@
+use crate::thumbnail::{self, cover_file};
+
+use super::{Error, FileDownloader};
+
+impl FileDownloader {
+    /// Derives square covers from the downloaded thumbnail and stores them
+    /// next to it, returns the sizes stored. A thumbnail that can't be decoded
+    /// is remembered in the index.
+    pub async fn derive_covers(&self, id: u32) -> Result<Vec<u32>, Error> {
+        let files = self.local_files(id).await?;
+        let thumbnail = tokio::fs::read(&files.thumbnail).await.map_err(|_| Error::NotFound)?;
+        let covers = tokio::task::spawn_blocking(move || thumbnail::covers(&thumbnail)).await
+            .map_err(|_| Error::Unknown)?;
+        let Ok(covers) = covers else {
+            let mut index = self.read_index(id).await?;
+            index.no_covers = true;
+            self.write_index(id, &index).await?;
+            return Err(Error::Unknown);
+        };
+        let mut sizes = Vec::new();
+        for (size, bytes) in covers {
+            self.save_analysis_file(id, &cover_file(size), &bytes).await?;
+            sizes.push(size);
+        }
+        Ok(sizes)
+    }
+
+    /// JPEG cover of the size closest to `size`, derived first for audios
+    /// downloaded before covers existed unless that failed before.
+    pub async fn load_cover(&self, id: u32, size: u32) -> Result<Vec<u8>, Error> {
+        let audio_dir = self.audio_path(id).await;
+        let mut sizes: Vec<u32> = thumbnail::SIZES.into_iter()
+            .filter(|size| audio_dir.join(cover_file(*size)).exists())
+            .collect();
+        if sizes.is_empty() {
+            if self.read_index(id).await?.no_covers {
+                return Err(Error::NotFound);
+            }
+            sizes = self.derive_covers(id).await?;
+        }
+        let size = thumbnail::closest(&sizes, size).ok_or(Error::NotFound)?;
+        self.load_analysis_file(id, &cover_file(size)).await.ok_or(Error::NotFound)
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use mime2ext::mime2ext;
+
+use super::{user_files::remove_downloaded, Error, FileDownloader, Index, USER_FILES};
+
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// Folders under `audios/` or `downloading/` that belong to no track.
+    pub orphaned: Vec<String>,
+    pub missing_media: Vec<u32>,
+    pub truncated_media: Vec<u32>,
+    pub unknown_mime: Vec<u32>,
+    /// Tracks with leftovers in `downloading/` while nothing is downloading them.
+    pub stale_partials: Vec<u32>,
+}
+
+impl IntegrityReport {
+    /// Tracks whose local files can't be trusted and have to be downloaded again.
+    pub fn broken(&self) -> Vec<u32> {
+        let mut broken: Vec<u32> = self.missing_media.iter()
+            .chain(self.truncated_media.iter())
+            .chain(self.unknown_mime.iter())
+            .cloned()
+            .collect();
+        broken.sort();
+        broken.dedup();
+        broken
+    }
+}
+
+async fn list_dirs(path: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(path).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            paths.push(entry.path());
+        }
+    }
+    paths
+}
+
+/// Folders of audios that were never downloaded but have user files.
+async fn has_only_user_files(path: &Path) -> bool {
+    let names: Vec<_> = list_dirs(path).await.iter()
+        .filter_map(|entry| entry.file_name()?.to_str().map(|name| name.to_string()))
+        .collect();
+    !names.is_empty() && names.iter().all(|name| USER_FILES.contains(&name.as_str()))
+}
+
+fn track_id(path: &Path) -> Option<u32> {
+    path.file_name()?.to_str()?.parse().ok()
+}
+
+impl FileDownloader {
+    /// Checks the files of `ids`, tracks that are downloading are skipped.
+    pub async fn verify(&self, ids: &[u32]) -> IntegrityReport {
+        let (audio_dir, downloading_dir) = self.get_dirs().await;
+        let queue = self.queue.lock().await.clone();
+        let mut report = IntegrityReport::default();
+        for path in list_dirs(Path::new(&audio_dir)).await {
+            match track_id(&path) {
+                Some(id) if queue.contains(&id) => {},
+                Some(id) if ids.contains(&id) => self.verify_track(id, &path, &mut report).await,
+                _ => report.orphaned.push(path.to_str().unwrap().to_string()),
+            }
+        }
+        for path in list_dirs(Path::new(&downloading_dir)).await {
+            match track_id(&path) {
+                Some(id) if queue.contains(&id) => {},
+                Some(id) if ids.contains(&id) => report.stale_partials.push(id),
+                _ => report.orphaned.push(path.to_str().unwrap().to_string()),
+            }
+        }
+        report
+    }
+
+    async fn verify_track(&self, id: u32, path: &Path, report: &mut IntegrityReport) {
+        let index = tokio::fs::read(path.join("index.json")).await.ok()
+            .and_then(|index| serde_json::from_slice::<Index>(&index).ok());
+        let index = match index {
+            Some(index) => index,
+            None => {
+                if !has_only_user_files(path).await {
+                    report.missing_media.push(id);
+                }
+                return;
+            },
+        };
+        let extension = match mime2ext(index.media_mime.clone()) {
+            Some(extension) => extension,
+            None => {
+                report.unknown_mime.push(id);
+                "bin"
+            },
+        };
+        match tokio::fs::metadata(path.join(format!("media.{}", extension))).await {
+            Ok(metadata) => {
+                let truncated = metadata.len() == 0 || index.media_size.map(|size| metadata.len() < size).unwrap_or(false);
+                if truncated {
+                    report.truncated_media.push(id);
+                }
+            },
+            Err(_) => report.missing_media.push(id),
+        }
+    }
+
+    /// Deletes orphaned folders, stale partials and the files of broken tracks.
+    /// Library entries are kept so the broken tracks can be downloaded again.
+    pub async fn repair(&self, report: &IntegrityReport) -> Result<(), Error> {
+        for path in report.orphaned.iter() {
+            if Path::new(path).is_dir() {
+                tokio::fs::remove_dir_all(path).await.map_err(|_| Error::Unknown)?;
+            } else {
+                tokio::fs::remove_file(path).await.map_err(|_| Error::Unknown)?;
+            }
+        }
+        for id in report.stale_partials.iter() {
+            let path = self.downloading_path(*id).await;
+            tokio::fs::remove_dir_all(path).await.map_err(|_| Error::Unknown)?;
+        }
+        for id in report.broken() {
+            remove_downloaded(&self.audio_path(id).await).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use std::{future::Future, path::{Path, PathBuf}, sync::atomic::Ordering};
+
+use super::{Error, FileDownloader, USER_FILES};
+
+enum Moved {
+    Renamed { from: PathBuf, to: PathBuf },
+    Copied { from: PathBuf, to: PathBuf },
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> u64 {
+    std::fs::read_dir(path)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| {
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            }
+        }).sum())
+        .unwrap_or(0)
+}
+
+fn is_empty_or_missing(path: &Path) -> bool {
+    match std::fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => !path.exists(),
+    }
+}
+
+fn list_entries(path: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(path)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+        .unwrap_or_default()
+}
+
+/// Whether entries can be renamed from one directory into the other, which
+/// only works within a file system.
+fn can_rename(from: &Path, to: &Path) -> bool {
+    let (probe, target) = (from.join(".move"), to.join(".move"));
+    let renamed = std::fs::write(&probe, []).is_ok() && std::fs::rename(&probe, &target).is_ok();
+    let _ = std::fs::remove_file(&probe);
+    let _ = std::fs::remove_file(&target);
+    renamed
+}
+
+fn rename_entry(from: &Path, to: &Path) -> Result<Moved, Error> {
+    std::fs::rename(from, to).map_err(|_| Error::Unknown)?;
+    Ok(Moved::Renamed { from: from.to_path_buf(), to: to.to_path_buf() })
+}
+
+fn copy_entry(from: &Path, to: &Path) -> Result<Moved, Error> {
+    let copied = if from.is_dir() {
+        copy_dir(from, to)
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    };
+    match copied {
+        Ok(_) => Ok(Moved::Copied { from: from.to_path_buf(), to: to.to_path_buf() }),
+        Err(_) => {
+            let _ = std::fs::remove_dir_all(to);
+            let _ = std::fs::remove_file(to);
+            Err(Error::Unknown)
+        }
+    }
+}
+
+fn rollback(moved: Vec<Moved>) {
+    for entry in moved.into_iter().rev() {
+        match entry {
+            Moved::Renamed { from, to } => {
+                let _ = std::fs::rename(to, from);
+            },
+            Moved::Copied { from: _, to } => {
+                let _ = std::fs::remove_dir_all(&to);
+                let _ = std::fs::remove_file(&to);
+            },
+        }
+    }
+}
+
+/// Keeps the moved entry, or puts back everything moved so far.
+async fn track_move(moved: &mut Vec<Moved>, result: Result<Moved, Error>) -> Result<(), Error> {
+    match result {
+        Ok(entry) => {
+            moved.push(entry);
+            Ok(())
+        },
+        Err(err) => {
+            let moved = std::mem::take(moved);
+            let _ = tokio::task::spawn_blocking(move || rollback(moved)).await;
+            Err(err)
+        },
+    }
+}
+
+/// Deletes what was copied instead of renamed and the emptied old directories.
+fn remove_sources(moved: Vec<Moved>, dirs: Vec<PathBuf>) {
+    for entry in moved {
+        if let Moved::Copied { from, to: _ } = entry {
+            let _ = std::fs::remove_dir_all(&from);
+            let _ = std::fs::remove_file(&from);
+        }
+    }
+    for dir in dirs {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+impl FileDownloader {
+    pub async fn get_dirs(&self) -> (String, String) {
+        (self.audio_dir.lock().await.clone(), self.downloading_dir.lock().await.clone())
+    }
+
+    /// True while downloads are queued or running.
+    pub async fn is_busy(&self) -> bool {
+        !self.queue.lock().await.is_empty()
+    }
+
+    /// Bytes taken by the downloaded and partially downloaded files of the
+    /// audio, user files don't count.
+    pub async fn size(&self, id: u32) -> u64 {
+        let audio_dir = self.audio_path(id).await;
+        let user_files: u64 = USER_FILES.iter()
+            .filter_map(|name| std::fs::metadata(audio_dir.join(name)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        dir_size(&audio_dir).saturating_sub(user_files) + dir_size(&self.downloading_path(id).await)
+    }
+
+    /// Moves every track folder of `audios/` and `downloading/` into the new
+    /// directories. If anything fails, already moved folders are put back and
+    /// the downloader keeps using the old directories. No downloads start
+    /// while moving.
+    pub async fn move_library<C, Fut>(&self, new_audio_dir: String, new_downloading_dir: String, callback: C) -> Result<(), Error>
+    where
+        C: Fn(u64, u64) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        {
+            let queue = self.queue.lock().await;
+            if !queue.is_empty() || self.moving.swap(true, Ordering::SeqCst) {
+                return Err(Error::Busy);
+            }
+        }
+        let result = self.move_dirs(new_audio_dir, new_downloading_dir, callback).await;
+        self.moving.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn move_dirs<C, Fut>(&self, new_audio_dir: String, new_downloading_dir: String, callback: C) -> Result<(), Error>
+    where
+        C: Fn(u64, u64) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let (old_audio_dir, old_downloading_dir) = self.get_dirs().await;
+        let targets = [
+            (PathBuf::from(old_audio_dir), PathBuf::from(&new_audio_dir)),
+            (PathBuf::from(old_downloading_dir), PathBuf::from(&new_downloading_dir)),
+        ];
+        for (from, to) in targets.iter() {
+            if to.starts_with(from) || !is_empty_or_missing(to) {
+                return Err(Error::InvalidTarget);
+            }
+        }
+        let pairs = targets.clone();
+        let renamable = tokio::task::spawn_blocking(move || {
+            pairs.iter().map(|(from, to)| std::fs::create_dir_all(to).map(|_| can_rename(from, to))).collect::<Result<Vec<bool>, _>>()
+        }).await.map_err(|_| Error::Unknown)?.map_err(|_| Error::Unknown)?;
+        let (renames, copies): (Vec<(PathBuf, PathBuf, bool)>, Vec<_>) = targets.iter().zip(renamable)
+            .flat_map(|((from, to), rename)| list_entries(from).into_iter().map(move |entry| {
+                let target = to.join(entry.file_name().unwrap());
+                (entry, target, rename)
+            }))
+            .partition(|(_, _, rename)| *rename);
+        let total = (renames.len() + copies.len()) as u64;
+        let mut moved = Vec::new();
+        // Copies between file systems take long, the library keeps being read
+        // from the old directories meanwhile.
+        for (from, to, _) in copies {
+            let result = tokio::task::spawn_blocking(move || copy_entry(&from, &to)).await
+                .unwrap_or(Err(Error::Unknown));
+            track_move(&mut moved, result).await?;
+            callback(moved.len() as u64, total).await;
+        }
+        // Renames are quick, they happen under the locks together with the switch.
+        let mut audio_dir = self.audio_dir.lock().await;
+        let mut downloading_dir = self.downloading_dir.lock().await;
+        for (from, to, _) in renames {
+            let result = tokio::task::spawn_blocking(move || rename_entry(&from, &to)).await
+                .unwrap_or(Err(Error::Unknown));
+            track_move(&mut moved, result).await?;
+            callback(moved.len() as u64, total).await;
+        }
+        *audio_dir = new_audio_dir;
+        *downloading_dir = new_downloading_dir;
+        drop(audio_dir);
+        drop(downloading_dir);
+        let sources: Vec<PathBuf> = targets.iter().map(|(from, _)| from.clone()).collect();
+        let _ = tokio::task::spawn_blocking(move || remove_sources(moved, sources)).await;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,46 @@
+use std::{future::Future, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+use crate::{audio::Audio, settings::Transcode, transcode};
+
+use super::{Error, FileDownloader, Phase, Storage};
+
+impl FileDownloader {
+    /// Converts the downloaded `media.bin` in place, reporting decoded and
+    /// total milliseconds. Returns the MIME type of the converted media.
+    pub(super) async fn transcode_media<C, Fut>(&self, audio: &Audio, callback: C, target: Transcode, mime: &str) -> Result<String, Error>
+    where
+        C: Fn(Phase, u64, u64) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let downloading_dir = self.downloading_path(audio.id).await;
+        let bytes = tokio::fs::read(downloading_dir.join("media.bin")).await.map_err(|_| Error::Unknown)?;
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let canceled = Arc::new(AtomicBool::new(false));
+        let task = {
+            let canceled = canceled.clone();
+            let mime = mime.to_string();
+            tokio::task::spawn_blocking(move || transcode::transcode(bytes, &mime, target, |position, duration| {
+                let _ = sender.send((position, duration));
+                !canceled.load(Ordering::SeqCst)
+            }))
+        };
+        while let Some((position, duration)) = receiver.recv().await {
+            let total = duration.or(audio.duration).unwrap_or(position);
+            callback(Phase::Transcode, (position * 1000.0) as u64, (total * 1000.0) as u64).await;
+            if !self.is_in_queue(audio.id).await {
+                canceled.store(true, Ordering::SeqCst);
+            }
+        }
+        let encoded = match task.await.map_err(|_| Error::Unknown)? {
+            Ok(encoded) => encoded,
+            Err(transcode::Error::Canceled) => {
+                self.cancel_broadcast.send(audio.id).map_err(|_| Error::Unknown)?;
+                return Err(Error::Canceled);
+            },
+            Err(_) => return Err(Error::Transcode),
+        };
+        tokio::fs::write(downloading_dir.join("media.transcoded"), encoded).await.map_err(|_| Error::Unknown)?;
+        tokio::fs::rename(downloading_dir.join("media.transcoded"), downloading_dir.join("media.bin")).await.map_err(|_| Error::Unknown)?;
+        Ok(transcode::mime(target.codec).to_string())
+    }
+}
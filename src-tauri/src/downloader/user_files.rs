@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use super::{Error, FileDownloader, USER_FILES};
+
+/// Deletes everything in the folder of an audio but the user files, and the
+/// folder when nothing is left.
+pub(super) async fn remove_downloaded(path: &Path) -> Result<(), Error> {
+    let mut entries = match tokio::fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    let mut kept = false;
+    while let Some(entry) = entries.next_entry().await.map_err(|_| Error::Unknown)? {
+        if entry.file_name().to_str().is_some_and(|name| USER_FILES.contains(&name)) {
+            kept = true;
+        } else if entry.file_type().await.map_err(|_| Error::Unknown)?.is_dir() {
+            tokio::fs::remove_dir_all(entry.path()).await.map_err(|_| Error::Unknown)?;
+        } else {
+            tokio::fs::remove_file(entry.path()).await.map_err(|_| Error::Unknown)?;
+        }
+    }
+    if !kept {
+        tokio::fs::remove_dir(path).await.map_err(|_| Error::Unknown)?;
+    }
+    Ok(())
+}
+
+impl FileDownloader {
+    pub async fn load_user_file(&self, id: u32, name: &str) -> Option<Vec<u8>> {
+        tokio::fs::read(self.audio_path(id).await.join(name)).await.ok()
+    }
+
+    /// Stores a file in the folder of an audio, downloaded or not.
+    pub async fn save_user_file(&self, id: u32, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let audio_dir = self.audio_path(id).await;
+        tokio::fs::create_dir_all(&audio_dir).await.map_err(|_| Error::Unknown)?;
+        tokio::fs::write(audio_dir.join(name), bytes).await.map_err(|_| Error::Unknown)
+    }
+
+    pub async fn remove_user_file(&self, id: u32, name: &str) -> Result<(), Error> {
+        let audio_dir = self.audio_path(id).await;
+        if audio_dir.join(name).exists() {
+            tokio::fs::remove_file(audio_dir.join(name)).await.map_err(|_| Error::Unknown)?;
+        }
+        let _ = tokio::fs::remove_dir(&audio_dir).await;
+        Ok(())
+    }
+
+    /// Moves the downloaded files and analyses of an audio to another one
+    /// that has none, user files stay with their audio. `index.json` goes
+    /// last so the target never looks downloaded with files missing.
+    pub async fn move_downloaded(&self, from: u32, to: u32) -> Result<(), Error> {
+        let (source, target) = (self.audio_path(from).await, self.audio_path(to).await);
+        if target.join("index.json").exists() {
+            return Err(Error::InvalidTarget);
+        }
+        let mut entries = tokio::fs::read_dir(&source).await.map_err(|_| Error::NotFound)?;
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(|_| Error::Unknown)? {
+            let name = entry.file_name();
+            if !name.to_str().is_some_and(|name| USER_FILES.contains(&name)) {
+                names.push(name);
+            }
+        }
+        names.sort_by_key(|name| name == "index.json");
+        tokio::fs::create_dir_all(&target).await.map_err(|_| Error::Unknown)?;
+        for name in names {
+            tokio::fs::rename(source.join(&name), target.join(&name)).await.map_err(|_| Error::Unknown)?;
+        }
+        let _ = tokio::fs::remove_dir(&source).await;
+        Ok(())
+    }
+
+    /// Removes the downloaded files of an audio, what the user made for it
+    /// stays. Unlike [`super::Storage::remove`] the audio isn't canceled.
+    pub async fn evict(&self, id: u32) -> Result<(), Error> {
+        remove_downloaded(&self.audio_path(id).await).await?;
+        let downloading_dir = self.downloading_path(id).await;
+        if downloading_dir.exists() {
+            tokio::fs::remove_dir_all(downloading_dir).await.map_err(|_| Error::Unknown)?;
+        }
+        Ok(())
+    }
+}
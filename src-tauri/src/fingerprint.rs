@@ -0,0 +1,217 @@
+use std::{collections::HashMap, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+use crate::decoder::Analyzer;
+
+/// Samples of each analyzed window, about 90 ms at common rates.
+const WINDOW: usize = 4096;
+/// Codes per second of audio, each from two windows.
+pub const CODES_PER_SECOND: f64 = 4.0;
+const MIN_FREQUENCY: f64 = 55.0;
+const MAX_FREQUENCY: f64 = 4000.0;
+/// RMS below which a window counts as silence, about -60 dBFS.
+const SILENCE: f32 = 0.001;
+/// Set on codes of silence, which never match.
+const SILENT: u32 = 1 << 31;
+/// Bits compared in codes that aren't silent.
+const BITS: u32 = 24;
+/// Fraction of equal bits from which two fingerprints are the same recording.
+/// Unrelated music stays around 0.6.
+pub const THRESHOLD: f64 = 0.8;
+/// Alignments tried, the ones where most codes are exactly equal.
+const CANDIDATES: usize = 8;
+/// Seconds one recording may start later than the other, for intros.
+const MAX_OFFSET: f64 = 60.0;
+/// Seconds of sound that have to overlap at least.
+const MIN_OVERLAP: f64 = 10.0;
+
+/// Chroma of stretches of a quarter second packed into codes. The lower 12
+/// bits tell which pitch classes are above the mean, the next 12 which are
+/// above the following one, which stays the same across encodings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub codes: Vec<u32>,
+}
+
+fn code(chroma: &[f64; 12]) -> u32 {
+    let mean = chroma.iter().sum::<f64>() / 12.0;
+    let mut code = 0;
+    for class in 0..12 {
+        if chroma[class] > mean {
+            code |= 1 << class;
+        }
+        if chroma[class] > chroma[(class + 1) % 12] {
+            code |= 1 << (12 + class);
+        }
+    }
+    code
+}
+
+impl Fingerprint {
+    /// Seconds.
+    pub fn duration(&self) -> f64 {
+        self.codes.len() as f64 / CODES_PER_SECOND
+    }
+
+    /// Fraction of equal bits with the recordings aligned as well as they get
+    /// within [`MAX_OFFSET`]. Alignments are only tried where some codes are
+    /// exactly equal. Silence isn't compared and 0.0 means the sound overlaps
+    /// less than [`MIN_OVERLAP`] or half the shorter recording.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let max_offset = (MAX_OFFSET * CODES_PER_SECOND) as isize;
+        let mut positions: HashMap<u32, Vec<isize>> = HashMap::new();
+        for (position, code) in other.codes.iter().enumerate().filter(|(_, code)| *code & SILENT == 0) {
+            positions.entry(*code).or_default().push(position as isize);
+        }
+        let mut votes: HashMap<isize, u32> = HashMap::new();
+        for (index, code) in self.codes.iter().enumerate() {
+            for position in positions.get(code).into_iter().flatten() {
+                let offset = position - index as isize;
+                if offset.abs() <= max_offset {
+                    *votes.entry(offset).or_default() += 1;
+                }
+            }
+        }
+        let mut votes: Vec<(isize, u32)> = votes.into_iter().collect();
+        votes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let sound = |fingerprint: &Fingerprint| fingerprint.codes.iter().filter(|code| *code & SILENT == 0).count();
+        let min_overlap = ((MIN_OVERLAP * CODES_PER_SECOND) as usize).max(sound(self).min(sound(other)) / 2).max(1);
+        votes.iter().take(CANDIDATES)
+            .flat_map(|(offset, _)| offset - 1..=offset + 1)
+            .filter_map(|offset| self.aligned(other, offset, min_overlap))
+            .fold(0.0, f64::max)
+    }
+
+    /// Fraction of equal bits with the other recording starting `offset`
+    /// codes later.
+    fn aligned(&self, other: &Fingerprint, offset: isize, min_overlap: usize) -> Option<f64> {
+        let (mut overlap, mut equal) = (0, 0);
+        for (index, code) in self.codes.iter().enumerate() {
+            let position = index as isize + offset;
+            let Some(other) = (position >= 0).then(|| other.codes.get(position as usize)).flatten() else {
+                continue;
+            };
+            if (code | other) & SILENT != 0 {
+                continue;
+            }
+            overlap += 1;
+            equal += BITS - (code ^ other).count_ones();
+        }
+        (overlap >= min_overlap).then(|| equal as f64 / (overlap as f64 * BITS as f64))
+    }
+}
+
+/// Collects the chroma of interleaved samples fed in chunks of any size.
+pub struct Chromagram {
+    channels: usize,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Pitch class of every bin of the spectrum, `None` outside the range.
+    classes: Vec<Option<usize>>,
+    /// Samples between the starts of two windows.
+    hop: usize,
+    /// Mono samples not yet analyzed.
+    buffer: Vec<f32>,
+    /// Chroma of every window and whether it was silent.
+    frames: Vec<([f64; 12], bool)>,
+}
+
+impl Chromagram {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let classes = (0..WINDOW / 2).map(|bin| {
+            let frequency = bin as f64 * sample_rate as f64 / WINDOW as f64;
+            if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                return None;
+            }
+            Some(((12.0 * (frequency / 440.0).log2()).round() as i64).rem_euclid(12) as usize)
+        }).collect();
+        let window = (0..WINDOW)
+            .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / WINDOW as f32).cos())
+            .collect();
+        Self {
+            channels: channels.max(1),
+            fft: FftPlanner::new().plan_fft_forward(WINDOW),
+            window,
+            classes,
+            hop: ((sample_rate as f64 / CODES_PER_SECOND / 2.0).round() as usize).max(1),
+            buffer: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    fn analyze(&self, start: usize) -> ([f64; 12], bool) {
+        let samples = &self.buffer[start..start + WINDOW];
+        let rms = (samples.iter().map(|sample| sample * sample).sum::<f32>() / WINDOW as f32).sqrt();
+        let mut spectrum: Vec<Complex<f32>> = samples.iter().zip(self.window.iter())
+            .map(|(sample, weight)| Complex::new(sample * weight, 0.0))
+            .collect();
+        self.fft.process(&mut spectrum);
+        let mut chroma = [0.0; 12];
+        for (bin, class) in self.classes.iter().enumerate() {
+            if let Some(class) = class {
+                chroma[*class] += spectrum[bin].norm_sqr() as f64;
+            }
+        }
+        (chroma, rms < SILENCE)
+    }
+
+    /// How much each pitch class sounds over the windows that aren't silent,
+    /// each window weighing the same. Pitch class 0 is A.
+    pub fn profile(&self) -> [f64; 12] {
+        let mut profile = [0.0; 12];
+        for (frame, _) in self.frames.iter().filter(|(_, silent)| !silent) {
+            let total = frame.iter().sum::<f64>();
+            if total > 0.0 {
+                for class in 0..12 {
+                    profile[class] += frame[class] / total;
+                }
+            }
+        }
+        profile
+    }
+}
+
+impl Analyzer for Chromagram {
+    type Output = Fingerprint;
+
+    fn push(&mut self, samples: &[f32]) {
+        self.buffer.extend(samples.chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32));
+        let mut start = 0;
+        while start + WINDOW.max(self.hop) <= self.buffer.len() {
+            let frame = self.analyze(start);
+            self.frames.push(frame);
+            start += self.hop;
+        }
+        self.buffer.drain(..start);
+    }
+
+    /// Codes from pairs of windows, smoothed with the neighbouring codes so
+    /// a slightly different alignment gives the same codes.
+    fn finish(self) -> Fingerprint {
+        let stretches: Vec<Option<[f64; 12]>> = self.frames.chunks(2).map(|frames| {
+            let mut chroma = [0.0; 12];
+            for (frame, _) in frames.iter().filter(|(_, silent)| !silent) {
+                for class in 0..12 {
+                    chroma[class] += frame[class];
+                }
+            }
+            frames.iter().any(|(_, silent)| !silent).then_some(chroma)
+        }).collect();
+        let codes = (0..stretches.len()).map(|index| {
+            if stretches[index].is_none() {
+                return SILENT;
+            }
+            let mut chroma = [0.0; 12];
+            for stretch in stretches[index.saturating_sub(1)..(index + 2).min(stretches.len())].iter().flatten() {
+                for class in 0..12 {
+                    chroma[class] += stretch[class];
+                }
+            }
+            code(&chroma)
+        }).collect();
+        Fingerprint { codes }
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+mod json;
+mod m3u;
+mod template;
+mod xspf;
+
+pub use template::{render, sanitize, TrackName, DEFAULT_TEMPLATE};
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Unknown,
+    UnsupportedFormat,
+    InvalidFile,
+    InvalidTemplate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+    Json,
+}
+
+/// An audio as written to an exported playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub title: String,
+    pub author: String,
+    pub album: Option<String>,
+    pub tags: Vec<String>,
+    /// Seconds.
+    pub duration: Option<f64>,
+    /// Url the audio was added from.
+    pub source: String,
+    /// Local file path or the source url.
+    pub location: String,
+}
+
+pub fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u8),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            "json" => Some(PlaylistFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a file without a known extension.
+    pub fn detect(content: &str) -> Self {
+        let content = content.trim_start_matches('\u{feff}').trim_start();
+        if content.starts_with('{') {
+            PlaylistFormat::Json
+        } else if content.starts_with('<') {
+            PlaylistFormat::Xspf
+        } else {
+            PlaylistFormat::M3u8
+        }
+    }
+
+    pub fn export(&self, entries: &[PlaylistEntry]) -> Result<String, Error> {
+        match self {
+            PlaylistFormat::M3u8 => Ok(m3u::export(entries)),
+            PlaylistFormat::Xspf => Ok(xspf::export(entries)),
+            PlaylistFormat::Json => json::export(entries),
+        }
+    }
+
+    /// Returns the locations of the playlist entries in order, entries that
+    /// point at local files give their source url where the format keeps it.
+    pub fn import(&self, content: &str) -> Result<Vec<String>, Error> {
+        let content = content.trim_start_matches('\u{feff}');
+        match self {
+            PlaylistFormat::M3u8 => Ok(m3u::import(content)),
+            PlaylistFormat::Xspf => xspf::import(content),
+            PlaylistFormat::Json => json::import(content),
+        }
+    }
+}
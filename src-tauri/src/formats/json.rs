@@ -0,0 +1,80 @@
+//! FurPlayer playlist format:
+//!
+//! ```json
+//! {
+//!   "format": "furplayer-playlist",
+//!   "version": 1,
+//!   "audios": [
+//!     {
+//!       "title": "Never Gonna Give You Up",
+//!       "author": "Rick Astley",
+//!       "album": null,
+//!       "tags": ["pop"],
+//!       "duration": 213.0,
+//!       "source": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+//!       "location": "/home/user/FurPlayer/audios/1234/media.m4a"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `duration` is in seconds and may be `null`. `location` is the local file
+//! when it was exported with local files, otherwise the same as `source`.
+//! Importing only reads `source`.
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, PlaylistEntry};
+
+const FORMAT: &str = "furplayer-playlist";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AudioDTO {
+    title: String,
+    author: String,
+    #[serde(default)]
+    album: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    duration: Option<f64>,
+    source: String,
+    #[serde(default)]
+    location: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PlaylistDTO {
+    format: String,
+    version: u32,
+    audios: Vec<AudioDTO>,
+}
+
+pub fn export(entries: &[PlaylistEntry]) -> Result<String, Error> {
+    let playlist = PlaylistDTO {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        audios: entries.iter().map(|entry| AudioDTO {
+            title: entry.title.clone(),
+            author: entry.author.clone(),
+            album: entry.album.clone(),
+            tags: entry.tags.clone(),
+            duration: entry.duration,
+            source: entry.source.clone(),
+            location: Some(entry.location.clone()),
+        }).collect(),
+    };
+    serde_json::to_string_pretty(&playlist).map_err(|_| Error::Unknown)
+}
+
+pub fn import(content: &str) -> Result<Vec<String>, Error> {
+    let playlist: PlaylistDTO = serde_json::from_str(content).map_err(|_| Error::InvalidFile)?;
+    if playlist.format != FORMAT {
+        return Err(Error::InvalidFile);
+    }
+    if playlist.version > VERSION {
+        return Err(Error::UnsupportedFormat);
+    }
+    Ok(playlist.audios.into_iter().map(|audio| audio.source).collect())
+}
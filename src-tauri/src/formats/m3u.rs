@@ -0,0 +1,37 @@
+use super::{is_remote, PlaylistEntry};
+
+/// Source url of an entry that points at a local file, players skip unknown directives.
+const SOURCE: &str = "#FURPLAYER-SOURCE:";
+
+/// Extended M3U, always written as UTF-8.
+pub fn export(entries: &[PlaylistEntry]) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for entry in entries {
+        let duration = entry.duration.map(|duration| duration.round() as i64).unwrap_or(-1);
+        // Line breaks would end the entry early.
+        let name = format!("{} - {}", entry.author, entry.title).replace(['\r', '\n'], " ");
+        playlist.push_str(&format!("#EXTINF:{},{}\n", duration, name));
+        if entry.location != entry.source {
+            playlist.push_str(&format!("{}{}\n", SOURCE, entry.source));
+        }
+        playlist.push_str(&format!("{}\n", entry.location));
+    }
+    playlist
+}
+
+/// Local files are replaced by the source url written next to them.
+pub fn import(content: &str) -> Vec<String> {
+    let mut locations = Vec::new();
+    let mut source = None;
+    for line in content.lines().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+        if let Some(url) = line.strip_prefix(SOURCE) {
+            source = Some(url.trim().to_string());
+        } else if !line.starts_with('#') {
+            locations.push(match source.take() {
+                Some(source) if !is_remote(line) => source,
+                _ => line.to_string(),
+            });
+        }
+    }
+    locations
+}
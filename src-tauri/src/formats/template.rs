@@ -0,0 +1,57 @@
+use std::path::{Component, PathBuf};
+
+use super::Error;
+
+pub const DEFAULT_TEMPLATE: &str = "{author}/{title}.{ext}";
+
+/// Values of the template placeholders.
+#[derive(Debug, Clone)]
+pub struct TrackName<'a> {
+    pub id: u32,
+    pub title: &'a str,
+    pub author: &'a str,
+    pub album: Option<&'a str>,
+    pub ext: &'a str,
+}
+
+/// Makes a value safe to use as a single path component on every platform.
+pub fn sanitize(value: &str) -> String {
+    let value: String = value.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    let value = value.trim().trim_end_matches('.').trim();
+    if value.is_empty() {
+        "Unknown".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders a relative path like `{author}/{title}.{ext}`. Placeholders are
+/// `{id}`, `{title}`, `{author}`, `{album}` and `{ext}`, slashes in the
+/// template separate directories.
+pub fn render(template: &str, name: &TrackName) -> Result<PathBuf, Error> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or(Error::InvalidTemplate)? + start;
+        let value = match &rest[start + 1..end] {
+            "id" => name.id.to_string(),
+            "title" => sanitize(name.title),
+            "author" => sanitize(name.author),
+            "album" => sanitize(name.album.unwrap_or("")),
+            "ext" => sanitize(name.ext),
+            _ => return Err(Error::InvalidTemplate),
+        };
+        rendered.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    let path = PathBuf::from(rendered.replace('\\', "/"));
+    let is_relative = path.components().all(|component| matches!(component, Component::Normal(_)));
+    if !is_relative || path.file_name().is_none() {
+        return Err(Error::InvalidTemplate);
+    }
+    Ok(path)
+}
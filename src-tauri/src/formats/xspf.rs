@@ -0,0 +1,145 @@
+use super::{is_remote, Error, PlaylistEntry};
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Decodes the predefined and numeric character references, unknown ones are
+/// kept as written.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let decoded = reference.and_then(|(name, _)| match name {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "amp" => Some('&'),
+            _ => match name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => name.strip_prefix('#').and_then(|decimal| decimal.parse().ok()),
+            }.and_then(char::from_u32),
+        });
+        match (decoded, reference) {
+            (Some(decoded), Some((_, end))) => {
+                unescaped.push(decoded);
+                rest = &rest[end + 1..];
+            },
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Character data of an element, CDATA sections are taken as written.
+fn text(content: &str) -> String {
+    let mut text = String::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<![CDATA[") {
+        text.push_str(&unescape(&rest[..start]));
+        rest = &rest[start + "<![CDATA[".len()..];
+        let end = rest.find("]]>").unwrap_or(rest.len());
+        text.push_str(&rest[..end]);
+        rest = &rest[(end + "]]>".len()).min(rest.len())..];
+    }
+    text.push_str(&unescape(rest));
+    text
+}
+
+/// XSPF locations are URIs, local paths become `file://` URIs.
+fn uri(location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let path = location.replace('\\', "/");
+    let mut uri = String::from(if path.starts_with('/') { "file://" } else { "file:///" });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn element(name: &str, value: &str) -> String {
+    format!("      <{}>{}</{}>\n", name, escape(value), name)
+}
+
+pub fn export(entries: &[PlaylistEntry]) -> String {
+    let mut playlist = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n");
+    for entry in entries {
+        playlist.push_str("    <track>\n");
+        playlist.push_str(&element("location", &uri(&entry.location)));
+        playlist.push_str(&element("title", &entry.title));
+        playlist.push_str(&element("creator", &entry.author));
+        if let Some(album) = &entry.album {
+            playlist.push_str(&element("album", album));
+        }
+        if let Some(duration) = entry.duration {
+            playlist.push_str(&element("duration", &((duration * 1000.0).round() as u64).to_string()));
+        }
+        playlist.push_str(&element("info", &entry.source));
+        playlist.push_str("    </track>\n");
+    }
+    playlist.push_str("  </trackList>\n</playlist>\n");
+    playlist
+}
+
+/// Contents of every `name` element, attributes are skipped and empty
+/// elements left out.
+fn elements<'a>(content: &'a str, name: &str) -> Result<Vec<&'a str>, Error> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut found = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // `<track` also starts `<trackList>`.
+        if !rest.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let tag_end = rest.find('>').ok_or(Error::InvalidFile)?;
+        let self_closing = rest[..tag_end].ends_with('/');
+        rest = &rest[tag_end + 1..];
+        if self_closing {
+            continue;
+        }
+        let end = rest.find(&close).ok_or(Error::InvalidFile)?;
+        found.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    Ok(found)
+}
+
+/// Reads the first location of every track, tracks exported with local files
+/// fall back to the source url in `info`. The rest of the document is ignored.
+pub fn import(content: &str) -> Result<Vec<String>, Error> {
+    if !content.contains("<playlist") {
+        return Err(Error::InvalidFile);
+    }
+    let mut locations = Vec::new();
+    for track in elements(content, "track")? {
+        let first = |name| -> Result<Option<String>, Error> {
+            Ok(elements(track, name)?.first().map(|value| text(value.trim())))
+        };
+        let location = match (first("location")?, first("info")?) {
+            (Some(location), Some(info)) if !is_remote(&location) && is_remote(&info) => Some(info),
+            (location, info) => location.or(info),
+        };
+        locations.extend(location);
+    }
+    Ok(locations)
+}
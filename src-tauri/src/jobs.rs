@@ -0,0 +1,214 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Runs of a job before it stays failed.
+pub const MAX_ATTEMPTS: u32 = 3;
+/// Seconds a failed job waits before it runs again, doubled for every
+/// further failure so an offline network job doesn't use up its attempts.
+pub const RETRY_DELAY: u64 = 30;
+
+/// Work on the files of a downloaded audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobKind {
+    Loudness,
+    Waveform,
+    Silence,
+    Fingerprint,
+    BpmKey,
+    Lyrics,
+    /// Duration of an audio added before it was stored.
+    Duration,
+}
+
+impl JobKind {
+    /// Jobs queued once a download finishes, in the order they run.
+    pub const AFTER_DOWNLOAD: [JobKind; 6] = [
+        JobKind::Loudness,
+        JobKind::Waveform,
+        JobKind::Silence,
+        JobKind::Fingerprint,
+        JobKind::BpmKey,
+        JobKind::Lyrics,
+    ];
+
+    /// Jobs that wait on the network rather than the CPU.
+    pub fn is_network(&self) -> bool {
+        matches!(self, JobKind::Lyrics | JobKind::Duration)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Failed [`MAX_ATTEMPTS`] times, stays until retried or the audio is removed.
+    Failed,
+    /// Only reported, finished jobs leave the queue.
+    Finished,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u32,
+    pub audio: u32,
+    pub kind: JobKind,
+    pub state: JobState,
+    /// Runs that failed so far.
+    pub attempts: u32,
+    /// Why the last run failed.
+    pub error: Option<String>,
+    /// Unix timestamp in seconds the job waits for after failing.
+    #[serde(default)]
+    pub retry_at: Option<u64>,
+}
+
+/// Jobs waiting, running or failed, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobQueue {
+    jobs: Vec<Job>,
+    #[serde(default)]
+    next_id: u32,
+    /// Set once jobs were queued for audios downloaded before there were jobs.
+    #[serde(default)]
+    backfilled: bool,
+    /// Set once duration jobs were queued for audios added before durations were stored.
+    #[serde(default)]
+    durations_backfilled: bool,
+}
+
+impl JobQueue {
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// Jobs that didn't finish yet and didn't fail for good.
+    pub fn pending(&self) -> usize {
+        self.jobs.iter().filter(|job| matches!(job.state, JobState::Queued | JobState::Running)).count()
+    }
+
+    /// Queues a job unless the same one is already waiting. A failed one is
+    /// queued again.
+    pub fn push(&mut self, audio: u32, kind: JobKind) -> Option<Job> {
+        self.jobs.retain(|job| !(job.audio == audio && job.kind == kind && job.state == JobState::Failed));
+        if self.jobs.iter().any(|job| job.audio == audio && job.kind == kind && job.state == JobState::Queued) {
+            return None;
+        }
+        let job = Job { id: self.next_id(), audio, kind, state: JobState::Queued, attempts: 0, error: None, retry_at: None };
+        self.jobs.push(job.clone());
+        Some(job)
+    }
+
+    /// Marks the oldest waiting job of a kind that can run as running,
+    /// skipping failed jobs until they may run again.
+    pub fn start_next(&mut self, now: u64, runnable: impl Fn(JobKind) -> bool) -> Option<Job> {
+        let job = self.jobs.iter_mut().find(|job| {
+            job.state == JobState::Queued && job.retry_at.is_none_or(|retry_at| retry_at <= now) && runnable(job.kind)
+        })?;
+        job.state = JobState::Running;
+        Some(job.clone())
+    }
+
+    /// The earliest time after `now` a failed job may run again.
+    pub fn next_retry(&self, now: u64) -> Option<u64> {
+        self.jobs.iter()
+            .filter(|job| job.state == JobState::Queued)
+            .filter_map(|job| job.retry_at)
+            .filter(|retry_at| *retry_at > now)
+            .min()
+    }
+
+    /// Removes the job, returns it as finished.
+    pub fn finish(&mut self, id: u32) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        let mut job = self.jobs.remove(index);
+        job.state = JobState::Finished;
+        job.error = None;
+        Some(job)
+    }
+
+    /// Queues the job again at the end, after [`RETRY_DELAY`], until it
+    /// failed [`MAX_ATTEMPTS`] times.
+    pub fn fail(&mut self, id: u32, error: String, now: u64) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        let mut job = self.jobs.remove(index);
+        job.attempts += 1;
+        job.error = Some(error);
+        (job.state, job.retry_at) = match job.attempts < MAX_ATTEMPTS {
+            true => (JobState::Queued, Some(now + (RETRY_DELAY << (job.attempts - 1)))),
+            false => (JobState::Failed, None),
+        };
+        self.jobs.push(job.clone());
+        Some(job)
+    }
+
+    /// Queues a failed job again with all its attempts.
+    pub fn retry(&mut self, id: u32) -> Option<Job> {
+        let job = self.jobs.iter_mut().find(|job| job.id == id && job.state == JobState::Failed)?;
+        job.state = JobState::Queued;
+        job.attempts = 0;
+        job.retry_at = None;
+        Some(job.clone())
+    }
+
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    pub fn is_backfilled(&self) -> bool {
+        self.backfilled
+    }
+
+    pub fn set_backfilled(&mut self) {
+        self.backfilled = true;
+    }
+
+    pub fn is_durations_backfilled(&self) -> bool {
+        self.durations_backfilled
+    }
+
+    pub fn set_durations_backfilled(&mut self) {
+        self.durations_backfilled = true;
+    }
+
+    /// Whether the job is queued or running.
+    pub fn is_pending(&self, audio: u32, kind: JobKind) -> bool {
+        self.jobs.iter().any(|job| job.audio == audio && job.kind == kind && matches!(job.state, JobState::Queued | JobState::Running))
+    }
+
+    pub fn has_failed(&self, audio: u32, kind: JobKind) -> bool {
+        self.jobs.iter().any(|job| job.audio == audio && job.kind == kind && job.state == JobState::Failed)
+    }
+
+    pub fn remove_audio(&mut self, audio: u32) {
+        self.jobs.retain(|job| job.audio != audio);
+    }
+
+    /// Jobs that were running when the app quit start over.
+    pub fn resume(&mut self) {
+        for job in self.jobs.iter_mut().filter(|job| job.state == JobState::Running) {
+            job.state = JobState::Queued;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JobsFile(pub String);
+
+impl JobsFile {
+    pub fn load(&self) -> Option<JobQueue> {
+        let serialized = std::fs::read_to_string(&self.0).ok()?;
+        serde_json::from_str(&serialized).ok()
+    }
+
+    pub fn save(&self, queue: &JobQueue) {
+        if let Ok(serialized) = serde_json::to_string(queue) {
+            if let Some(parent) = Path::new(&self.0).parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let _ = std::fs::write(&self.0, serialized);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+/// Krumhansl-Kessler ratings of how well each pitch class fits a key,
+/// starting at the tonic.
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Key {
+    /// Pitch class of the tonic, 0 is C.
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    /// Position on the Camelot wheel, 1 to 12 with `A` for minor and `B`
+    /// for major keys. Neighbours differ by a fifth.
+    pub fn camelot(&self) -> (u8, char) {
+        match self.mode {
+            Mode::Major => (((self.tonic as u32 % 12 * 7 + 7) % 12 + 1) as u8, 'B'),
+            // Same number as the relative major.
+            Mode::Minor => (Key { tonic: (self.tonic + 3) % 12, mode: Mode::Major }.camelot().0, 'A'),
+        }
+    }
+
+    /// Whether tracks in the keys mix without clashing: the same key, the
+    /// relative major or minor, or a fifth up or down.
+    pub fn is_compatible(&self, other: &Key) -> bool {
+        let ((number, letter), (other_number, other_letter)) = (self.camelot(), other.camelot());
+        match letter == other_letter {
+            true => matches!((number + 12 - other_number) % 12, 0 | 1 | 11),
+            false => number == other_number,
+        }
+    }
+}
+
+fn correlation(a: &[f64; 12], b: &[f64; 12]) -> f64 {
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / 12.0, b.iter().sum::<f64>() / 12.0);
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for class in 0..12 {
+        covariance += (a[class] - mean_a) * (b[class] - mean_b);
+        variance_a += (a[class] - mean_a).powi(2);
+        variance_b += (b[class] - mean_b).powi(2);
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+/// Key whose profile correlates best with how much each pitch class sounds,
+/// `chroma[0]` being C. `None` when no pitch class stands out.
+pub fn detect(chroma: &[f64; 12]) -> Option<Key> {
+    let mut best: Option<(f64, Key)> = None;
+    for tonic in 0..12 {
+        for (mode, profile) in [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)] {
+            let rotated: [f64; 12] = std::array::from_fn(|class| profile[(class + 12 - tonic) % 12]);
+            let score = correlation(chroma, &rotated);
+            if score.is_finite() && best.is_none_or(|(best, _)| score > best) {
+                best = Some((score, Key { tonic: tonic as u8, mode }));
+            }
+        }
+    }
+    best.map(|(_, key)| key)
+}
@@ -1,7 +1,11 @@
 
 use std::sync::Arc;
 
-use app_state::{event::WebviewForwarder, AppState, ContentDTO, IndexedAudioDTO};
+use app_state::{event::WebviewForwarder, AppState, BpmKey, ContentDTO, DiskUsageDTO, DuplicatesDTO, ExportReportDTO, ImportReportDTO, IndexedAudioDTO, LibraryReportDTO, ListenDTO, LoudnessDTO, LyricsDTO, PlaybackStateDTO, PlaylistSort, RemoteAudioDTO, RestoreMode, RestoreReportDTO, SearchResultDTO, SmartPlaylistDTO, SyncReportDTO, TrimDTO};
+use audio::{RepeatMode, Rule, RuleMatch, Trim};
+use formats::PlaylistFormat;
+use jobs::Job;
+use settings::{DownloadPolicy, Settings};
 use tauri::{Manager, State};
 
 mod app_state;
@@ -9,6 +13,23 @@ mod audio;
 mod ytdlp;
 mod downloader;
 mod binaries;
+mod settings;
+mod decoder;
+mod player;
+mod search;
+mod formats;
+mod tagging;
+mod backup;
+mod loudness;
+mod waveform;
+mod silence;
+mod transcode;
+mod lyrics;
+mod fingerprint;
+mod key;
+mod tempo;
+mod jobs;
+mod thumbnail;
 
 
 #[tauri::command]
@@ -21,8 +42,8 @@ async fn add_new_audio(state: State<'_, Arc<AppState>>, url: String) -> Result<I
 }
 
 #[tauri::command]
-async fn get_playlist(state: State<'_, Arc<AppState>>) -> Result<Vec<IndexedAudioDTO>, String> {
-    state.get_all_audios().await.map_err(|e| e.to_string())
+async fn get_playlist(state: State<'_, Arc<AppState>>, sort: Option<PlaylistSort>) -> Result<Vec<IndexedAudioDTO>, String> {
+    state.get_all_audios(sort).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -35,8 +56,8 @@ async fn remove_audio(state: State<'_, Arc<AppState>>, id: u32) -> Result<(), ()
 }
 
 #[tauri::command]
-async fn get_thumbnail(state: State<'_, Arc<AppState>>, id: u32) -> Result<ContentDTO, String> {
-    state.get_thumbnail(id).await.map_err(|e| e.to_string())
+async fn get_thumbnail(state: State<'_, Arc<AppState>>, id: u32, size: u32) -> Result<ContentDTO, String> {
+    state.get_thumbnail(id, size).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -44,16 +65,283 @@ async fn get_media(state: State<'_, Arc<AppState>>, id: u32) -> Result<ContentDT
     state.get_media(id).await.map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn get_settings(state: State<'_, Arc<AppState>>) -> Result<Settings, String> {
+    Ok(state.get_settings().await)
+}
+
+#[tauri::command]
+async fn update_settings(state: State<'_, Arc<AppState>>, settings: Settings) -> Result<Settings, String> {
+    state.update_settings(settings).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn move_library(state: State<'_, Arc<AppState>>, path: String) -> Result<(), String> {
+    state.move_library(path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_library(state: State<'_, Arc<AppState>>, repair: bool) -> Result<LibraryReportDTO, String> {
+    state.verify_library(repair).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_disk_usage(state: State<'_, Arc<AppState>>) -> Result<DiskUsageDTO, String> {
+    state.get_disk_usage().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_pinned(state: State<'_, Arc<AppState>>, id: u32, pinned: bool) -> Result<IndexedAudioDTO, String> {
+    state.set_pinned(id, pinned).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_download_policy(state: State<'_, Arc<AppState>>, id: u32, policy: Option<DownloadPolicy>) -> Result<IndexedAudioDTO, String> {
+    state.set_download_policy(id, policy).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_playback_state(state: State<'_, Arc<AppState>>) -> Result<PlaybackStateDTO, String> {
+    Ok(state.get_playback_state().await)
+}
+
+#[tauri::command]
+async fn play_queue(state: State<'_, Arc<AppState>>, ids: Vec<u32>, index: usize) -> Result<PlaybackStateDTO, String> {
+    state.play_queue(ids, index).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn jump(state: State<'_, Arc<AppState>>, index: usize) -> Result<PlaybackStateDTO, String> {
+    state.jump(index).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn resume(state: State<'_, Arc<AppState>>) -> Result<PlaybackStateDTO, String> {
+    state.resume().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pause(state: State<'_, Arc<AppState>>) -> Result<PlaybackStateDTO, String> {
+    Ok(state.pause().await)
+}
+
+#[tauri::command]
+async fn seek(state: State<'_, Arc<AppState>>, position: f64) -> Result<PlaybackStateDTO, String> {
+    Ok(state.seek(position).await)
+}
+
+#[tauri::command]
+async fn next(state: State<'_, Arc<AppState>>) -> Result<PlaybackStateDTO, String> {
+    state.next().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn previous(state: State<'_, Arc<AppState>>) -> Result<PlaybackStateDTO, String> {
+    state.previous().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn queue_play_next(state: State<'_, Arc<AppState>>, id: u32) -> Result<PlaybackStateDTO, String> {
+    Ok(state.queue_play_next(id).await)
+}
+
+#[tauri::command]
+async fn queue_add(state: State<'_, Arc<AppState>>, id: u32) -> Result<PlaybackStateDTO, String> {
+    Ok(state.queue_add(id).await)
+}
+
+#[tauri::command]
+async fn queue_move(state: State<'_, Arc<AppState>>, from: usize, to: usize) -> Result<PlaybackStateDTO, String> {
+    Ok(state.queue_move(from, to).await)
+}
+
+#[tauri::command]
+async fn queue_remove(state: State<'_, Arc<AppState>>, index: usize) -> Result<PlaybackStateDTO, String> {
+    Ok(state.queue_remove(index).await)
+}
+
+#[tauri::command]
+async fn set_shuffle(state: State<'_, Arc<AppState>>, enabled: bool, seed: Option<u64>) -> Result<PlaybackStateDTO, String> {
+    Ok(state.set_shuffle(enabled, seed).await)
+}
+
+#[tauri::command]
+async fn set_repeat(state: State<'_, Arc<AppState>>, repeat: RepeatMode) -> Result<PlaybackStateDTO, String> {
+    Ok(state.set_repeat(repeat).await)
+}
+
+#[tauri::command]
+async fn get_history(state: State<'_, Arc<AppState>>, from: Option<u64>, to: Option<u64>) -> Result<Vec<ListenDTO>, String> {
+    Ok(state.get_history(from, to).await)
+}
+
+#[tauri::command]
+async fn report_listen(state: State<'_, Arc<AppState>>, id: u32, started_at: u64, listened: f64, completed: bool) -> Result<(), String> {
+    state.report_listen(id, started_at, listened, completed).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn update_metadata(state: State<'_, Arc<AppState>>, id: u32, title: String, author: String, album: Option<String>, tags: Vec<String>) -> Result<IndexedAudioDTO, String> {
+    state.update_metadata(id, title, author, album, tags).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn search(state: State<'_, Arc<AppState>>, query: String) -> Result<Vec<SearchResultDTO>, String> {
+    Ok(state.search(query).await)
+}
+
+#[tauri::command]
+async fn search_remote(state: State<'_, Arc<AppState>>, query: String, limit: u32) -> Result<Vec<RemoteAudioDTO>, String> {
+    state.search_remote(query, limit).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_playlist(state: State<'_, Arc<AppState>>, path: String, format: PlaylistFormat, ids: Option<Vec<u32>>, local: bool) -> Result<(), String> {
+    state.export_playlist(path, format, ids, local).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_playlist(state: State<'_, Arc<AppState>>, path: String) -> Result<ImportReportDTO, String> {
+    state.import_playlist(path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_tracks(state: State<'_, Arc<AppState>>, ids: Vec<u32>, dir: String, template: Option<String>) -> Result<ExportReportDTO, String> {
+    state.export_tracks(ids, dir, template.unwrap_or(formats::DEFAULT_TEMPLATE.to_string())).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn sync_device(state: State<'_, Arc<AppState>>, dir: String, playlists: Vec<u32>, template: Option<String>) -> Result<SyncReportDTO, String> {
+    state.sync_device(dir, playlists, template.unwrap_or(formats::DEFAULT_TEMPLATE.to_string())).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_loudness(state: State<'_, Arc<AppState>>, id: u32) -> Result<LoudnessDTO, String> {
+    state.get_loudness(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_waveform(state: State<'_, Arc<AppState>>, id: u32, buckets: usize) -> Result<Vec<(f32, f32)>, String> {
+    state.get_waveform(id, buckets).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_trim(state: State<'_, Arc<AppState>>, id: u32) -> Result<TrimDTO, String> {
+    state.get_trim(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_trim(state: State<'_, Arc<AppState>>, id: u32, trim: Option<Trim>) -> Result<TrimDTO, String> {
+    state.set_trim(id, trim).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn split_chapters(state: State<'_, Arc<AppState>>, id: u32) -> Result<Vec<IndexedAudioDTO>, String> {
+    state.split_chapters(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn merge_chapters(state: State<'_, Arc<AppState>>, id: u32) -> Result<IndexedAudioDTO, String> {
+    state.merge_chapters(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_lyrics(state: State<'_, Arc<AppState>>, id: u32) -> Result<Option<LyricsDTO>, String> {
+    state.get_lyrics(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn fetch_lyrics(state: State<'_, Arc<AppState>>, id: u32) -> Result<Option<LyricsDTO>, String> {
+    state.fetch_lyrics(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_lyrics(state: State<'_, Arc<AppState>>, id: u32, path: String) -> Result<Option<LyricsDTO>, String> {
+    state.import_lyrics(id, path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_lyrics(state: State<'_, Arc<AppState>>, id: u32, text: String) -> Result<Option<LyricsDTO>, String> {
+    state.set_lyrics(id, text).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn find_duplicates(state: State<'_, Arc<AppState>>) -> Result<DuplicatesDTO, String> {
+    state.find_duplicates().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn merge_duplicates(state: State<'_, Arc<AppState>>, keep: u32, others: Vec<u32>) -> Result<IndexedAudioDTO, String> {
+    state.merge_duplicates(keep, others).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_bpm_key(state: State<'_, Arc<AppState>>, id: u32, correction: Option<BpmKey>) -> Result<IndexedAudioDTO, String> {
+    state.set_bpm_key(id, correction).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_jobs(state: State<'_, Arc<AppState>>) -> Result<Vec<Job>, String> {
+    Ok(state.get_jobs().await)
+}
+
+#[tauri::command]
+async fn retry_job(state: State<'_, Arc<AppState>>, id: u32) -> Result<Vec<Job>, String> {
+    state.retry_job(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn backup(state: State<'_, Arc<AppState>>, path: String, include_media: bool) -> Result<(), String> {
+    state.backup(path, include_media).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore(state: State<'_, Arc<AppState>>, path: String, mode: RestoreMode) -> Result<RestoreReportDTO, String> {
+    state.restore(path, mode).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_smart_playlists(state: State<'_, Arc<AppState>>) -> Result<Vec<SmartPlaylistDTO>, String> {
+    Ok(state.get_smart_playlists().await)
+}
+
+#[tauri::command]
+async fn create_smart_playlist(state: State<'_, Arc<AppState>>, name: String, rules: Vec<Rule>, rule_match: RuleMatch) -> Result<Vec<SmartPlaylistDTO>, String> {
+    Ok(state.create_smart_playlist(name, rules, rule_match).await)
+}
+
+#[tauri::command]
+async fn update_smart_playlist(state: State<'_, Arc<AppState>>, id: u32, name: String, rules: Vec<Rule>, rule_match: RuleMatch) -> Result<Vec<SmartPlaylistDTO>, String> {
+    state.update_smart_playlist(id, name, rules, rule_match).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_smart_playlist(state: State<'_, Arc<AppState>>, id: u32) -> Result<Vec<SmartPlaylistDTO>, String> {
+    Ok(state.remove_smart_playlist(id).await)
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .setup(|app| {
             let webview_forwarder = WebviewForwarder::new(app.get_webview_window("main").unwrap());
-            app.manage(Arc::new(AppState::new(Arc::new(webview_forwarder))));
+            let state = Arc::new(AppState::new(Arc::new(webview_forwarder)));
+            state.start();
+            app.manage(state);
             Ok(())
         })
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![add_new_audio, get_playlist, remove_audio, get_media, get_thumbnail])
+        .invoke_handler(tauri::generate_handler![add_new_audio, get_playlist, remove_audio, get_media, get_thumbnail, get_settings, update_settings, move_library, verify_library, get_disk_usage, set_pinned, set_download_policy,
+            get_playback_state, play_queue, jump, resume, pause, seek, next, previous,
+            queue_play_next, queue_add, queue_move, queue_remove, set_shuffle, set_repeat,
+            get_history, report_listen,
+            get_smart_playlists, create_smart_playlist, update_smart_playlist, remove_smart_playlist,
+            update_metadata, search, search_remote, export_playlist, import_playlist,
+            export_tracks, sync_device, backup, restore,
+            get_loudness, get_waveform, get_trim, set_trim, split_chapters, merge_chapters,
+            get_lyrics, fetch_lyrics, import_lyrics, set_lyrics, find_duplicates, merge_duplicates, set_bpm_key,
+            get_jobs, retry_job])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
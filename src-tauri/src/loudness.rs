@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::decoder::Analyzer;
+
+/// Loudness the gains aim for, in LUFS. Same as ReplayGain 2.0.
+pub const REFERENCE: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Width of the histogram bins in LU, album loudness is measured from them.
+const HISTOGRAM_STEP: f64 = 0.1;
+/// Taps of each phase of the true peak interpolation filter.
+const PEAK_TAPS: usize = 12;
+
+/// Result of measuring one track as in ITU-R BS.1770-4 and EBU R128.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, `None` when everything is below the absolute gate.
+    pub integrated: Option<f64>,
+    /// Highest absolute sample value after 4x oversampling, 1.0 is full scale.
+    pub true_peak: f64,
+    /// Gated 400 ms blocks counted by loudness in 0.1 LU steps from the
+    /// absolute gate, enough to measure several tracks together.
+    pub histogram: Vec<(u16, u32)>,
+}
+
+fn to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn to_energy(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+/// Two-pass gating over block energies already above the absolute gate.
+fn gated(blocks: impl Iterator<Item = (f64, u64)> + Clone) -> Option<f64> {
+    let (sum, count) = blocks.clone().fold((0.0, 0), |(sum, count), (energy, n)| (sum + energy * n as f64, count + n));
+    if count == 0 {
+        return None;
+    }
+    let threshold = to_energy(to_loudness(sum / count as f64) + RELATIVE_GATE);
+    let (sum, count) = blocks
+        .filter(|(energy, _)| *energy > threshold)
+        .fold((0.0, 0), |(sum, count), (energy, n)| (sum + energy * n as f64, count + n));
+    (count > 0).then(|| to_loudness(sum / count as f64))
+}
+
+impl Loudness {
+    /// Gain in dB that brings the loudness to [`REFERENCE`], lowered so the
+    /// true peak stays below full scale. No gain without a measurement.
+    pub fn gain(&self) -> f64 {
+        let Some(integrated) = self.integrated else {
+            return 0.0;
+        };
+        let gain = REFERENCE - integrated;
+        if self.true_peak > 0.0 {
+            gain.min(-20.0 * self.true_peak.log10())
+        } else {
+            gain
+        }
+    }
+
+    /// Measures tracks as one, like the tracks of an album played in a row.
+    pub fn combine(tracks: &[Loudness]) -> Loudness {
+        let mut histogram = BTreeMap::new();
+        for track in tracks {
+            for (bin, count) in track.histogram.iter() {
+                *histogram.entry(*bin).or_insert(0) += count;
+            }
+        }
+        let blocks = histogram.iter()
+            .map(|(bin, count)| (to_energy(ABSOLUTE_GATE + (*bin as f64 + 0.5) * HISTOGRAM_STEP), *count as u64));
+        Loudness {
+            integrated: gated(blocks),
+            true_peak: tracks.iter().map(|track| track.true_peak).fold(0.0, f64::max),
+            histogram: histogram.into_iter().collect(),
+        }
+    }
+}
+
+/// Biquad in transposed direct form II.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self { b0, b1, b2, a1, a2, z1: 0.0, z2: 0.0 }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// The K-weighting pre-filter: a high shelf for the head followed by a high
+/// pass. BS.1770 only lists 48 kHz coefficients, these are derived from the
+/// analog prototype so any sample rate gets the same response.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    );
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let pass = Biquad::new(1.0, -2.0, 1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0);
+    [shelf, pass]
+}
+
+/// Peak of the signal reconstructed between samples, found by oversampling
+/// with a windowed sinc interpolation filter.
+struct TruePeak {
+    factor: usize,
+    taps: Vec<f64>,
+    history: Vec<VecDeque<f64>>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let factor = match sample_rate {
+            0..96000 => 4,
+            96000..192000 => 2,
+            _ => 1,
+        };
+        let length = factor * PEAK_TAPS;
+        let center = (length - 1) as f64 / 2.0;
+        let taps = (0..length).map(|n| {
+            let x = (n as f64 - center) / factor as f64;
+            let sinc = if x == 0.0 { 1.0 } else { (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x) };
+            let window = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * (n + 1) as f64 / (length + 1) as f64).cos();
+            sinc * window
+        }).collect();
+        Self {
+            factor,
+            taps,
+            history: vec![VecDeque::from(vec![0.0; PEAK_TAPS]); channels],
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, channel: usize, sample: f64) {
+        self.peak = self.peak.max(sample.abs());
+        if self.factor == 1 {
+            return;
+        }
+        let history = &mut self.history[channel];
+        history.pop_back();
+        history.push_front(sample);
+        for phase in 0..self.factor {
+            let value: f64 = history.iter().enumerate()
+                .map(|(k, x)| self.taps[phase + k * self.factor] * x)
+                .sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+}
+
+/// Measures interleaved samples fed in chunks of any size.
+pub struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    /// Frames in 100 ms, blocks are 400 ms long and start every 100 ms.
+    step: usize,
+    frames: usize,
+    energy: f64,
+    steps: VecDeque<f64>,
+    /// Energies of the blocks above the absolute gate.
+    blocks: Vec<f64>,
+    peak: TruePeak,
+}
+
+impl Meter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        // Surround channels of 5.1 count more and the LFE channel not at all.
+        let weights = match channels {
+            6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+            _ => vec![1.0; channels],
+        };
+        Self {
+            channels,
+            weights,
+            filters: vec![k_weighting(sample_rate as f64); channels],
+            step: (sample_rate as usize / 10).max(1),
+            frames: 0,
+            energy: 0.0,
+            steps: VecDeque::new(),
+            blocks: Vec::new(),
+            peak: TruePeak::new(channels, sample_rate),
+        }
+    }
+}
+
+impl Analyzer for Meter {
+    type Output = Loudness;
+
+    fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = *sample as f64;
+                self.peak.push(channel, sample);
+                let [shelf, pass] = &mut self.filters[channel];
+                let weighted = pass.process(shelf.process(sample));
+                self.energy += self.weights[channel] * weighted * weighted;
+            }
+            self.frames += 1;
+            if self.frames < self.step {
+                continue;
+            }
+            self.steps.push_back(self.energy / self.step as f64);
+            self.frames = 0;
+            self.energy = 0.0;
+            if self.steps.len() > 4 {
+                self.steps.pop_front();
+            }
+            if self.steps.len() == 4 {
+                let block = self.steps.iter().sum::<f64>() / 4.0;
+                if to_loudness(block) > ABSOLUTE_GATE {
+                    self.blocks.push(block);
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Loudness {
+        let mut histogram = BTreeMap::new();
+        for block in self.blocks.iter() {
+            let bin = ((to_loudness(*block) - ABSOLUTE_GATE) / HISTOGRAM_STEP) as u16;
+            *histogram.entry(bin).or_insert(0) += 1;
+        }
+        Loudness {
+            integrated: gated(self.blocks.iter().map(|block| (*block, 1))),
+            true_peak: self.peak.peak,
+            histogram: histogram.into_iter().collect(),
+        }
+    }
+}
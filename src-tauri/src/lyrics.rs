@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+/// Keys of the LRC ID tags, other bracketed text is part of the lyrics.
+const ID_TAGS: [&str; 9] = ["ar", "ti", "al", "au", "by", "re", "ve", "length", "offset"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Line {
+    /// Seconds, `None` for plain lyrics.
+    pub time: Option<f64>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lyrics {
+    pub lines: Vec<Line>,
+}
+
+/// `mm:ss`, `mm:ss.xx` or `hh:mm:ss.xxx` to seconds.
+fn parse_time(time: &str) -> Option<f64> {
+    if !time.contains(':') {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in time.trim().split(':') {
+        let value: f64 = part.parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(seconds)
+}
+
+fn format_time(time: f64) -> String {
+    let centiseconds = (time.max(0.0) * 100.0).round() as u64;
+    format!("{:02}:{:02}.{:02}", centiseconds / 6000, centiseconds / 100 % 60, centiseconds % 100)
+}
+
+/// Text of a caption with the inline timing and styling tags removed.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => {},
+        }
+    }
+    stripped.replace("&amp;", "&").replace("&lt;", "<").replace("&gt;", ">").replace("&nbsp;", " ")
+}
+
+impl Lyrics {
+    /// Synced lyrics have a time on every line.
+    pub fn is_synced(&self) -> bool {
+        !self.lines.is_empty() && self.lines.iter().all(|line| line.time.is_some())
+    }
+
+    /// Reads LRC. Lines may have several timestamps, ID tags other than
+    /// `offset` are ignored and lines without a timestamp are kept as plain
+    /// text, so plain lyrics read as they are.
+    pub fn parse_lrc(text: &str) -> Self {
+        let mut offset = 0.0;
+        let mut lines = Vec::new();
+        for raw in text.lines() {
+            let mut rest = raw.trim();
+            let mut times = Vec::new();
+            let mut tagged = false;
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')) {
+                if let Some(time) = parse_time(tag) {
+                    times.push(time);
+                } else if let Some((key, value)) = tag.split_once(':').filter(|(key, _)| ID_TAGS.contains(&key.trim())) {
+                    if key == "offset" {
+                        // Milliseconds, positive shows lines sooner.
+                        offset = value.trim().parse::<f64>().unwrap_or(0.0) / 1000.0;
+                    }
+                } else {
+                    // Part of the text, like `[Chorus]` or `[Chorus: Artist]`.
+                    break;
+                }
+                tagged = true;
+                rest = after;
+            }
+            let text = rest.trim().to_string();
+            match (times.is_empty(), tagged) {
+                (false, _) => lines.extend(times.into_iter().map(|time| Line { time: Some(time), text: text.clone() })),
+                (true, false) => lines.push(Line { time: None, text }),
+                (true, true) => {},
+            }
+        }
+        let mut lyrics = Self { lines };
+        for line in lyrics.lines.iter_mut() {
+            line.time = line.time.map(|time| (time - offset).max(0.0));
+        }
+        if lyrics.is_synced() {
+            lyrics.lines.sort_by(|a, b| a.time.unwrap_or(0.0).total_cmp(&b.time.unwrap_or(0.0)));
+        }
+        lyrics.trim();
+        lyrics
+    }
+
+    /// Reads WebVTT captions, every caption line starts when its cue does.
+    /// Automatic captions repeat the previous line in every cue, lines equal
+    /// to the one before are dropped.
+    pub fn parse_vtt(text: &str) -> Self {
+        let mut lines: Vec<Line> = Vec::new();
+        let text = text.replace("\r\n", "\n");
+        for block in text.split("\n\n") {
+            let mut block_lines = block.lines().skip_while(|line| !line.contains("-->"));
+            let Some(timing) = block_lines.next() else {
+                continue;
+            };
+            let Some(start) = timing.split("-->").next().and_then(parse_time) else {
+                continue;
+            };
+            for caption in block_lines {
+                let caption = strip_tags(caption).trim().to_string();
+                if caption.is_empty() || lines.last().is_some_and(|last| last.text == caption) {
+                    continue;
+                }
+                lines.push(Line { time: Some(start), text: caption });
+            }
+        }
+        Self { lines }
+    }
+
+    /// Writes LRC, plain lines go without a timestamp.
+    pub fn to_lrc(&self) -> String {
+        self.lines.iter()
+            .map(|line| match line.time {
+                Some(time) => format!("[{}]{}", format_time(time), line.text),
+                None => line.text.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Synced lines between two times of the lyrics, moved to start at zero.
+    /// The line playing at `from` is kept.
+    pub fn slice(&self, from: f64, to: f64) -> Self {
+        let mut lines: Vec<Line> = Vec::new();
+        for line in self.lines.iter() {
+            let Some(time) = line.time else {
+                continue;
+            };
+            if time >= to {
+                break;
+            }
+            if time <= from {
+                lines.clear();
+            }
+            lines.push(Line { time: Some((time - from).max(0.0)), text: line.text.clone() });
+        }
+        Self { lines }
+    }
+
+    /// Drops blank lines at both ends.
+    fn trim(&mut self) {
+        while self.lines.last().is_some_and(|line| line.text.is_empty() && line.time.is_none()) {
+            self.lines.pop();
+        }
+        let leading = self.lines.iter().take_while(|line| line.text.is_empty() && line.time.is_none()).count();
+        self.lines.drain(..leading);
+    }
+}
@@ -0,0 +1,258 @@
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, Mutex};
+
+use crate::decoder::{self, Decoder};
+
+mod sink;
+
+pub use sink::{AudioSink, CpalSink, NullSink};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Status {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    Position {
+        id: u32,
+        position: f64,
+        duration: Option<f64>,
+    },
+    Finished {
+        id: u32,
+    },
+    Error {
+        id: u32,
+        error: decoder::Error,
+    },
+}
+
+enum Command {
+    Load {
+        id: u32,
+        bytes: Vec<u8>,
+        mime: String,
+        start: f64,
+        gain: f32,
+        bounds: Bounds,
+    },
+    Play,
+    Pause,
+    Seek(f64),
+    Stop,
+}
+
+/// Part of the media that is played. Positions going in and out of the
+/// player are relative to `offset`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bounds {
+    pub offset: f64,
+    /// Finishes at this position in the media instead of its end.
+    pub end: Option<f64>,
+    /// Reported instead of the duration of the media.
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerState {
+    pub status: Status,
+    pub current: Option<u32>,
+    pub position: f64,
+    pub duration: Option<f64>,
+}
+
+/// Plays one audio at a time on a dedicated engine thread. Choosing and
+/// loading the media is up to the caller.
+pub struct Player {
+    commands: Sender<Command>,
+    state: Mutex<PlayerState>,
+}
+
+const POSITION_INTERVAL: f64 = 0.25;
+
+fn run(commands: Receiver<Command>, events: UnboundedSender<EngineEvent>, mut sink: Box<dyn AudioSink>) {
+    let mut current: Option<(u32, Decoder)> = None;
+    let mut gain = 1.0;
+    let mut bounds = Bounds::default();
+    let mut playing = false;
+    let mut reported = f64::NEG_INFINITY;
+    loop {
+        let command = if playing {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else {
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            }
+        };
+        match command {
+            Some(Command::Load { id, bytes, mime, start, gain: load_gain, bounds: load_bounds }) => {
+                sink.clear();
+                gain = load_gain;
+                bounds = load_bounds;
+                reported = f64::NEG_INFINITY;
+                match Decoder::open(bytes, &mime) {
+                    Ok(mut decoder) => {
+                        if start + bounds.offset > 0.0 {
+                            let _ = decoder.seek(start + bounds.offset);
+                        }
+                        current = Some((id, decoder));
+                        playing = true;
+                        sink.resume();
+                    },
+                    Err(error) => {
+                        current = None;
+                        playing = false;
+                        let _ = events.send(EngineEvent::Error { id, error });
+                    },
+                }
+            },
+            Some(Command::Play) => {
+                playing = current.is_some();
+                sink.resume();
+            },
+            Some(Command::Pause) => {
+                playing = false;
+                sink.pause();
+            },
+            Some(Command::Seek(position)) => {
+                if let Some((_, decoder)) = current.as_mut() {
+                    sink.clear();
+                    let _ = decoder.seek(position + bounds.offset);
+                    reported = f64::NEG_INFINITY;
+                }
+            },
+            Some(Command::Stop) => {
+                current = None;
+                playing = false;
+                sink.clear();
+            },
+            None => {},
+        }
+        if !playing {
+            continue;
+        }
+        let Some((id, decoder)) = current.as_mut() else {
+            playing = false;
+            continue;
+        };
+        let id = *id;
+        let chunk = match decoder.next_chunk() {
+            Ok(Some(_)) if bounds.end.is_some_and(|end| decoder.position() >= end) => Ok(None),
+            chunk => chunk,
+        };
+        match chunk {
+            Ok(Some(mut samples)) => {
+                if gain != 1.0 {
+                    samples.iter_mut().for_each(|sample| *sample *= gain);
+                }
+                sink.write(&samples, decoder.channels(), decoder.sample_rate());
+                if (decoder.position() - reported).abs() >= POSITION_INTERVAL {
+                    reported = decoder.position();
+                    let position = (reported - bounds.offset).max(0.0);
+                    let _ = events.send(EngineEvent::Position { id, position, duration: bounds.duration.or(decoder.duration()) });
+                }
+            },
+            Ok(None) => {
+                sink.drain();
+                current = None;
+                playing = false;
+                let _ = events.send(EngineEvent::Finished { id });
+            },
+            Err(error) => {
+                current = None;
+                playing = false;
+                let _ = events.send(EngineEvent::Error { id, error });
+            },
+        }
+    }
+}
+
+impl Player {
+    /// The sink is created on the engine thread because audio streams usually
+    /// can't be moved between threads.
+    pub fn new<F>(sink: F) -> (Self, UnboundedReceiver<EngineEvent>)
+    where
+        F: FnOnce() -> Box<dyn AudioSink> + Send + 'static,
+    {
+        let (commands, commands_rx) = mpsc::channel();
+        let (events, events_rx) = unbounded_channel();
+        std::thread::spawn(move || run(commands_rx, events, sink()));
+        (
+            Self {
+                commands,
+                state: Mutex::new(PlayerState {
+                    status: Status::Stopped,
+                    current: None,
+                    position: 0.0,
+                    duration: None,
+                }),
+            },
+            events_rx,
+        )
+    }
+
+    pub async fn get_state(&self) -> PlayerState {
+        self.state.lock().await.clone()
+    }
+
+    pub async fn current(&self) -> Option<u32> {
+        self.state.lock().await.current
+    }
+
+    /// `gain` scales every sample, 1.0 plays the audio as is.
+    pub async fn load(&self, id: u32, bytes: Vec<u8>, mime: String, start: f64, gain: f32, bounds: Bounds) {
+        let mut state = self.state.lock().await;
+        state.status = Status::Playing;
+        state.current = Some(id);
+        state.position = start;
+        state.duration = None;
+        let _ = self.commands.send(Command::Load { id, bytes, mime, start, gain, bounds });
+    }
+
+    pub async fn play(&self) {
+        let mut state = self.state.lock().await;
+        if state.status == Status::Paused {
+            state.status = Status::Playing;
+            let _ = self.commands.send(Command::Play);
+        }
+    }
+
+    pub async fn pause(&self) {
+        let mut state = self.state.lock().await;
+        if state.status == Status::Playing {
+            state.status = Status::Paused;
+            let _ = self.commands.send(Command::Pause);
+        }
+    }
+
+    pub async fn seek(&self, position: f64) {
+        let mut state = self.state.lock().await;
+        state.position = position;
+        let _ = self.commands.send(Command::Seek(position));
+    }
+
+    pub async fn stop(&self) {
+        let mut state = self.state.lock().await;
+        state.status = Status::Stopped;
+        state.current = None;
+        state.position = 0.0;
+        state.duration = None;
+        let _ = self.commands.send(Command::Stop);
+    }
+
+    pub async fn update_position(&self, position: f64, duration: Option<f64>) {
+        let mut state = self.state.lock().await;
+        state.position = position;
+        state.duration = duration;
+    }
+}
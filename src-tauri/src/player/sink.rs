@@ -0,0 +1,193 @@
+use std::{collections::VecDeque, sync::{Arc, Condvar, Mutex}};
+
+use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, FromSample, SampleFormat, SizedSample};
+
+/// Output for decoded audio. Samples are interleaved with the given channel
+/// count and sample rate, `write` may block while the output is busy.
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32], channels: usize, sample_rate: u32);
+    fn pause(&mut self);
+    fn resume(&mut self);
+    /// Drops everything queued and not played yet.
+    fn clear(&mut self);
+    /// Waits until everything queued has been played.
+    fn drain(&mut self);
+}
+
+/// Discards all audio, used in tests and when there is no output device.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _samples: &[f32], _channels: usize, _sample_rate: u32) {}
+
+    fn pause(&mut self) {}
+
+    fn resume(&mut self) {}
+
+    fn clear(&mut self) {}
+
+    fn drain(&mut self) {}
+}
+
+struct Buffer {
+    samples: VecDeque<f32>,
+    paused: bool,
+}
+
+struct Resampler {
+    channels: usize,
+    step: f64,
+    position: f64,
+    previous: Vec<f32>,
+}
+
+impl Resampler {
+    fn new(channels: usize, from: u32, to: u32) -> Self {
+        Self {
+            channels,
+            step: from as f64 / to as f64,
+            position: 0.0,
+            previous: vec![0.0; channels],
+        }
+    }
+
+    /// Linear interpolation between frames, keeping the last frame of the chunk
+    /// so consecutive chunks join without clicks.
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let frames = input.len() / self.channels;
+        if frames == 0 {
+            return;
+        }
+        let frame = |index: usize, channel: usize| -> f32 {
+            if index == 0 {
+                self.previous[channel]
+            } else {
+                input[(index - 1) * self.channels + channel]
+            }
+        };
+        while self.position < frames as f64 {
+            let index = self.position.floor() as usize;
+            let t = (self.position - index as f64) as f32;
+            for channel in 0..self.channels {
+                output.push(frame(index, channel) * (1.0 - t) + frame(index + 1, channel) * t);
+            }
+            self.position += self.step;
+        }
+        self.position -= frames as f64;
+        self.previous = input[(frames - 1) * self.channels..frames * self.channels].to_vec();
+    }
+}
+
+fn remap_channels(samples: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let mut output = Vec::with_capacity(samples.len() / from * to);
+    for frame in samples.chunks(from) {
+        if to == 1 {
+            output.push(frame.iter().sum::<f32>() / from as f32);
+        } else {
+            for channel in 0..to {
+                output.push(frame[channel % from]);
+            }
+        }
+    }
+    output
+}
+
+/// Plays audio through the default output device of the system.
+pub struct CpalSink {
+    _stream: cpal::Stream,
+    shared: Arc<(Mutex<Buffer>, Condvar)>,
+    channels: usize,
+    sample_rate: u32,
+    resampler: Option<(u32, Resampler)>,
+}
+
+/// Output stream taking samples in the format of the device, converted from
+/// the queued `f32` samples.
+fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, shared: Arc<(Mutex<Buffer>, Condvar)>) -> Option<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    device.build_output_stream(config, move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        let (buffer, released) = &*shared;
+        let mut buffer = buffer.lock().unwrap();
+        for sample in data.iter_mut() {
+            let value = if buffer.paused { 0.0 } else { buffer.samples.pop_front().unwrap_or(0.0) };
+            *sample = T::from_sample(value);
+        }
+        released.notify_all();
+    }, |_| {}, None).ok()
+}
+
+impl CpalSink {
+    pub fn new() -> Option<Self> {
+        let device = cpal::default_host().default_output_device()?;
+        let config = device.default_output_config().ok()?;
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0;
+        let shared = Arc::new((Mutex::new(Buffer { samples: VecDeque::new(), paused: false }), Condvar::new()));
+        let format = config.sample_format();
+        let config = config.into();
+        let stream = match format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, shared.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, shared.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, shared.clone()),
+            SampleFormat::I32 => build_stream::<i32>(&device, &config, shared.clone()),
+            _ => None,
+        }?;
+        stream.play().ok()?;
+        Some(Self {
+            _stream: stream,
+            shared,
+            channels,
+            sample_rate,
+            resampler: None,
+        })
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn write(&mut self, samples: &[f32], channels: usize, sample_rate: u32) {
+        let samples = remap_channels(samples, channels, self.channels);
+        let samples = if sample_rate == self.sample_rate {
+            samples
+        } else {
+            if self.resampler.as_ref().map(|(rate, _)| *rate) != Some(sample_rate) {
+                self.resampler = Some((sample_rate, Resampler::new(self.channels, sample_rate, self.sample_rate)));
+            }
+            let mut resampled = Vec::new();
+            self.resampler.as_mut().unwrap().1.process(&samples, &mut resampled);
+            resampled
+        };
+        let limit = self.sample_rate as usize * self.channels / 2;
+        let (buffer, released) = &*self.shared;
+        let mut buffer = buffer.lock().unwrap();
+        while buffer.samples.len() > limit && !buffer.paused {
+            buffer = released.wait(buffer).unwrap();
+        }
+        buffer.samples.extend(samples);
+    }
+
+    fn pause(&mut self) {
+        self.shared.0.lock().unwrap().paused = true;
+    }
+
+    fn resume(&mut self) {
+        self.shared.0.lock().unwrap().paused = false;
+    }
+
+    fn clear(&mut self) {
+        self.shared.0.lock().unwrap().samples.clear();
+        self.resampler = None;
+    }
+
+    fn drain(&mut self) {
+        let (buffer, released) = &*self.shared;
+        let mut buffer = buffer.lock().unwrap();
+        while !buffer.samples.is_empty() && !buffer.paused {
+            buffer = released.wait(buffer).unwrap();
+        }
+    }
+}
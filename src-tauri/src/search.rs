@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::audio::Audio;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Field {
+    Title,
+    Author,
+    Album,
+    Tag(usize),
+}
+
+impl Field {
+    fn weight(&self) -> f64 {
+        match self {
+            Field::Title => 1.0,
+            Field::Author => 0.8,
+            Field::Album => 0.6,
+            Field::Tag(_) => 0.5,
+        }
+    }
+}
+
+/// Matched part of a field, offsets are in UTF-16 code units of the original text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    pub field: Field,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub id: u32,
+    pub score: f64,
+    pub highlights: Vec<Highlight>,
+}
+
+/// Folded word with the original range of every folded character.
+#[derive(Debug, Clone)]
+struct Word {
+    chars: Vec<char>,
+    ranges: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    id: u32,
+    fields: Vec<(Field, Vec<Word>)>,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    entries: Vec<Entry>,
+}
+
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'а' => "a", 'б' => "b", 'в' => "v", 'г' => "g", 'ґ' => "g", 'д' => "d",
+        'е' => "e", 'є' => "ye", 'ж' => "zh", 'з' => "z", 'и' => "i", 'і' => "i",
+        'ї' => "yi", 'й' => "y", 'к' => "k", 'л' => "l", 'м' => "m", 'н' => "n",
+        'о' => "o", 'п' => "p", 'р' => "r", 'с' => "s", 'т' => "t", 'у' => "u",
+        'ф' => "f", 'х' => "kh", 'ц' => "ts", 'ч' => "ch", 'ш' => "sh", 'щ' => "shch",
+        'ъ' => "", 'ы' => "y", 'ь' => "", 'э' => "e", 'ю' => "yu", 'я' => "ya",
+        _ => return None,
+    })
+}
+
+/// Splits the text into lowercase words without diacritics, with Cyrillic
+/// transliterated to Latin so either script finds the other.
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut word = Word { chars: Vec::new(), ranges: Vec::new() };
+    let mut offset = 0;
+    for original in text.chars() {
+        let range = (offset, offset + original.len_utf16());
+        offset = range.1;
+        if !original.is_alphanumeric() {
+            if !word.chars.is_empty() {
+                words.push(std::mem::replace(&mut word, Word { chars: Vec::new(), ranges: Vec::new() }));
+            }
+            continue;
+        }
+        let mut push = |c: char| {
+            match transliterate(c) {
+                Some(latin) => word.chars.extend(latin.chars()),
+                None => word.chars.push(c),
+            }
+            word.ranges.resize(word.chars.len(), range);
+        };
+        for c in original.to_lowercase() {
+            // Letters like `й` transliterate differently from their base letter.
+            if transliterate(c).is_some() {
+                push(c);
+            } else {
+                c.to_string().nfd().filter(|c| !is_combining_mark(*c)).for_each(&mut push);
+            }
+        }
+    }
+    if !word.chars.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == b { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Typos allowed in a query word, short words must match exactly.
+fn max_typos(length: usize) -> usize {
+    match length {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Scores how well the query word matches the word and returns the matched
+/// range of folded characters.
+fn match_word(query: &[char], word: &Word) -> Option<(f64, usize, usize)> {
+    let chars = &word.chars;
+    if chars == query {
+        return Some((1.0, 0, chars.len()));
+    }
+    if chars.starts_with(query) {
+        return Some((0.9, 0, query.len()));
+    }
+    if query.len() >= 2 {
+        if let Some(start) = chars.windows(query.len()).position(|window| window == query) {
+            return Some((0.7, start, start + query.len()));
+        }
+    }
+    let typos = max_typos(query.len());
+    if typos == 0 {
+        return None;
+    }
+    let prefix = &chars[..chars.len().min(query.len())];
+    let distance = levenshtein(query, chars).min(levenshtein(query, prefix));
+    if distance <= typos {
+        Some((0.6 - 0.1 * distance as f64, 0, chars.len()))
+    } else {
+        None
+    }
+}
+
+impl SearchIndex {
+    pub fn new(audios: &[Audio]) -> Self {
+        let mut index = Self::default();
+        for audio in audios {
+            index.upsert(audio);
+        }
+        index
+    }
+
+    pub fn upsert(&mut self, audio: &Audio) {
+        self.remove(audio.id);
+        let mut fields = vec![
+            (Field::Title, words(&audio.title)),
+            (Field::Author, words(&audio.author)),
+        ];
+        if let Some(album) = &audio.album {
+            fields.push((Field::Album, words(album)));
+        }
+        for (index, tag) in audio.tags.iter().enumerate() {
+            fields.push((Field::Tag(index), words(tag)));
+        }
+        self.entries.push(Entry { id: audio.id, fields });
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.entries.retain(|entry| entry.id != id);
+    }
+
+    /// Every query word has to match some field, results are sorted by score.
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        let query: Vec<Vec<char>> = words(query).into_iter().map(|word| word.chars).collect();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut results: Vec<SearchResult> = self.entries.iter().filter_map(|entry| {
+            let mut score = 0.0;
+            let mut highlights = Vec::new();
+            for query_word in query.iter() {
+                let mut best: Option<(f64, Highlight)> = None;
+                for (field, words) in entry.fields.iter() {
+                    for word in words {
+                        let Some((word_score, start, end)) = match_word(query_word, word) else {
+                            continue;
+                        };
+                        let word_score = word_score * field.weight();
+                        if best.as_ref().map(|(best, _)| word_score > *best).unwrap_or(true) {
+                            let highlight = Highlight { field: *field, start: word.ranges[start].0, end: word.ranges[end - 1].1 };
+                            best = Some((word_score, highlight));
+                        }
+                    }
+                }
+                let (word_score, highlight) = best?;
+                score += word_score;
+                if !highlights.contains(&highlight) {
+                    highlights.push(highlight);
+                }
+            }
+            Some(SearchResult { id: entry.id, score: score / query.len() as f64, highlights })
+        }).collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AudioQuality {
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DownloadPolicy {
+    Always,
+    StreamOnly,
+    AfterPlays(u32),
+}
+
+impl DownloadPolicy {
+    pub fn should_download(&self, play_count: u32) -> bool {
+        match self {
+            DownloadPolicy::Always => true,
+            DownloadPolicy::StreamOnly => false,
+            DownloadPolicy::AfterPlays(plays) => play_count >= *plays,
+        }
+    }
+}
+
+/// Which measured loudness playback evens out.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Normalization {
+    Off,
+    Track,
+    /// Keeps the differences between the tracks of an album.
+    Album,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Codec {
+    Mp3,
+    Aac,
+}
+
+/// Format downloads are converted to once they are downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Transcode {
+    pub codec: Codec,
+    /// kbit/s.
+    pub bitrate: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub max_concurrent_downloads: u32,
+    pub download_attempts: u32,
+    pub audio_quality: AudioQuality,
+    /// Directory holding `audios/` and `downloading/`, the app directory when unset.
+    /// Changed only through [`crate::app_state::AppState::move_library`].
+    pub library_dir: Option<String>,
+    /// Maximum size of downloaded audio in bytes, unlimited when unset.
+    pub storage_quota: Option<u64>,
+    /// Used for audios without their own download policy.
+    pub download_policy: DownloadPolicy,
+    pub normalization: Normalization,
+    /// Skip silence detected at the start and end of downloaded audios.
+    pub trim_silence: bool,
+    /// dBFS below which audio counts as silence.
+    pub silence_threshold: f64,
+    /// Seconds, shorter silence is played.
+    pub silence_min_length: f64,
+    /// Downloads stay in the container YouTube served when unset.
+    pub transcode: Option<Transcode>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Unknown,
+    UnsupportedVersion,
+    InvalidConcurrentDownloads,
+    InvalidDownloadAttempts,
+    InvalidStorageQuota,
+    InvalidSilenceThreshold,
+    InvalidSilenceLength,
+    InvalidBitrate,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_concurrent_downloads: 3,
+            download_attempts: 5,
+            audio_quality: AudioQuality::High,
+            library_dir: None,
+            storage_quota: None,
+            download_policy: DownloadPolicy::Always,
+            normalization: Normalization::Off,
+            trim_silence: false,
+            silence_threshold: -50.0,
+            silence_min_length: 1.0,
+            transcode: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_concurrent_downloads == 0 || self.max_concurrent_downloads > 16 {
+            return Err(Error::InvalidConcurrentDownloads);
+        }
+        if self.download_attempts == 0 || self.download_attempts > 20 {
+            return Err(Error::InvalidDownloadAttempts);
+        }
+        if self.storage_quota == Some(0) {
+            return Err(Error::InvalidStorageQuota);
+        }
+        if !(-96.0..=0.0).contains(&self.silence_threshold) {
+            return Err(Error::InvalidSilenceThreshold);
+        }
+        if !(0.0..=60.0).contains(&self.silence_min_length) {
+            return Err(Error::InvalidSilenceLength);
+        }
+        if self.transcode.is_some_and(|transcode| !(32..=320).contains(&transcode.bitrate)) {
+            return Err(Error::InvalidBitrate);
+        }
+        Ok(())
+    }
+}
+
+/// Placed next to the executable to run it in portable mode.
+pub const PORTABLE_MARKER: &str = "portable";
+
+/// Set by the `PORTABLE` environment variable or a [`PORTABLE_MARKER`] file in `exe_dir`.
+pub fn is_portable(exe_dir: &Path) -> bool {
+    std::env::var("PORTABLE").is_ok() || exe_dir.join(PORTABLE_MARKER).is_file()
+}
+
+#[derive(Debug)]
+pub struct SettingsFile(pub String);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SettingsDTO {
+    version: u32,
+    settings: serde_json::Value,
+}
+
+/// Serializes settings in the versioned format of `settings.json`.
+pub fn to_json(settings: &Settings) -> Result<String, Error> {
+    let settings_dto = SettingsDTO {
+        version: VERSION,
+        settings: serde_json::to_value(settings).map_err(|_| Error::Unknown)?,
+    };
+    serde_json::to_string(&settings_dto).map_err(|_| Error::Unknown)
+}
+
+pub fn from_json(serialized: &str) -> Result<Settings, Error> {
+    let settings_dto: SettingsDTO = serde_json::from_str(serialized).map_err(|_| Error::Unknown)?;
+    if settings_dto.version > VERSION {
+        return Err(Error::UnsupportedVersion);
+    }
+    let settings: Settings = serde_json::from_value(settings_dto.settings).map_err(|_| Error::Unknown)?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+impl SettingsFile {
+    pub fn load(&self) -> Result<Settings, Error> {
+        let serialized = std::fs::read_to_string(self.0.clone()).map_err(|_| Error::Unknown)?;
+        from_json(&serialized)
+    }
+
+    pub fn save(&self, settings: &Settings) -> Result<(), Error> {
+        let serialized = to_json(settings)?;
+        if let Some(parent) = Path::new(&self.0).parent() {
+            std::fs::create_dir_all(parent).map_err(|_| Error::Unknown)?;
+        }
+        std::fs::write(self.0.clone(), serialized).map_err(|_| Error::Unknown)?;
+        Ok(())
+    }
+}
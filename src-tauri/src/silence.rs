@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Trim, decoder::Analyzer};
+
+/// Windows per second whose level is compared to the threshold.
+const WINDOWS_PER_SECOND: usize = 100;
+
+/// Silence detected at both ends of an audio with the settings it was detected with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Silence {
+    pub threshold: f64,
+    pub min_length: f64,
+    /// Seconds of silence at the start, 0 when shorter than the minimum length.
+    pub start: f64,
+    /// Where the silence at the end begins, `None` when shorter than the minimum length.
+    pub end: Option<f64>,
+}
+
+impl Silence {
+    pub fn detected_with(&self, threshold: f64, min_length: f64) -> bool {
+        self.threshold == threshold && self.min_length == min_length
+    }
+
+    pub fn trim(&self) -> Trim {
+        Trim { start: self.start, end: self.end }
+    }
+}
+
+/// Finds silence at the start and end of interleaved samples fed in chunks of any size.
+pub struct SilenceDetector {
+    channels: usize,
+    sample_rate: u32,
+    threshold: f64,
+    min_length: f64,
+    /// Mean square below which a window is silent.
+    limit: f64,
+    window: usize,
+    frames: usize,
+    energy: f64,
+    total_frames: u64,
+    /// Frames of silence before the first loud window, `None` while everything was silent.
+    leading: Option<u64>,
+    /// Frames of silence since the last loud window.
+    trailing: u64,
+}
+
+impl SilenceDetector {
+    pub fn new(channels: usize, sample_rate: u32, threshold: f64, min_length: f64) -> Self {
+        Self {
+            channels,
+            sample_rate,
+            threshold,
+            min_length,
+            limit: 10f64.powf(threshold / 10.0),
+            window: (sample_rate as usize / WINDOWS_PER_SECOND).max(1),
+            frames: 0,
+            energy: 0.0,
+            total_frames: 0,
+            leading: None,
+            trailing: 0,
+        }
+    }
+
+    fn end_window(&mut self) {
+        let frames = self.frames as u64;
+        let silent = self.energy / (self.frames as f64) < self.limit;
+        self.total_frames += frames;
+        if silent {
+            self.trailing += frames;
+        } else {
+            if self.leading.is_none() {
+                self.leading = Some(self.total_frames - frames);
+            }
+            self.trailing = 0;
+        }
+        self.frames = 0;
+        self.energy = 0.0;
+    }
+}
+
+impl Analyzer for SilenceDetector {
+    type Output = Silence;
+
+    fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.energy += frame.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / self.channels as f64;
+            self.frames += 1;
+            if self.frames == self.window {
+                self.end_window();
+            }
+        }
+    }
+
+    /// Audio that is silent throughout isn't trimmed at all.
+    fn finish(mut self) -> Silence {
+        if self.frames > 0 {
+            self.end_window();
+        }
+        let seconds = |frames: u64| frames as f64 / self.sample_rate as f64;
+        let (start, end) = match self.leading {
+            Some(leading) => {
+                let start = if seconds(leading) >= self.min_length { seconds(leading) } else { 0.0 };
+                let end = (self.trailing > 0 && seconds(self.trailing) >= self.min_length)
+                    .then(|| seconds(self.total_frames - self.trailing));
+                (start, end)
+            },
+            None => (0.0, None),
+        };
+        Silence { threshold: self.threshold, min_length: self.min_length, start, end }
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+
+mod id3;
+pub(crate) mod mp4;
+mod ogg;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Unknown,
+    Unsupported,
+    /// The file is damaged.
+    Invalid,
+    /// Only Opus in WebM can be cut into chapters.
+    CutUnsupported,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cover {
+    pub bytes: Vec<u8>,
+    pub mime: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tags {
+    pub title: String,
+    pub artist: String,
+    pub album: Option<String>,
+    pub cover: Option<Cover>,
+}
+
+/// A media file rewritten with the tags embedded in its native container.
+#[derive(Debug)]
+pub struct Tagged {
+    pub bytes: Vec<u8>,
+    pub extension: &'static str,
+}
+
+/// WebM with Opus is remuxed to Ogg Opus with Vorbis comments, MP4 gets
+/// iTunes-style atoms and MP3 an ID3v2 tag. Other formats are `Unsupported`.
+pub fn tag(media: &Path, mime: &str, tags: &Tags) -> Result<Tagged, Error> {
+    match mime.split(';').next().unwrap_or("").trim() {
+        "audio/webm" | "video/webm" => Ok(Tagged { bytes: ogg::remux_opus(media, tags, None)?, extension: "opus" }),
+        _ => tag_bytes(&std::fs::read(media).map_err(|_| Error::Unknown)?, mime, tags),
+    }
+}
+
+/// Tags media that is already in memory, MP4 and MP3 only.
+pub fn tag_bytes(bytes: &[u8], mime: &str, tags: &Tags) -> Result<Tagged, Error> {
+    match mime.split(';').next().unwrap_or("").trim() {
+        "audio/mp4" | "video/mp4" | "audio/x-m4a" => Ok(Tagged { bytes: mp4::tag(bytes, tags)?, extension: "m4a" }),
+        "audio/mpeg" | "audio/mp3" => Ok(Tagged { bytes: id3::tag(bytes, tags)?, extension: "mp3" }),
+        _ => Err(Error::Unsupported),
+    }
+}
+
+/// Like [`tag`], keeping only the seconds between `start` and `end`.
+pub fn tag_part(media: &Path, mime: &str, tags: &Tags, start: f64, end: f64) -> Result<Tagged, Error> {
+    match mime.split(';').next().unwrap_or("").trim() {
+        "audio/webm" | "video/webm" => Ok(Tagged { bytes: ogg::remux_opus(media, tags, Some((start, end)))?, extension: "opus" }),
+        _ => Err(Error::CutUnsupported),
+    }
+}
@@ -0,0 +1,57 @@
+use super::{Error, Tags};
+
+/// A synchsafe integer, 7 bits in each byte.
+fn synchsafe(value: usize) -> [u8; 4] {
+    std::array::from_fn(|i| ((value >> (7 * (3 - i))) & 0x7f) as u8)
+}
+
+/// An ID3v2.3 frame, sizes aren't synchsafe in that version.
+fn frame(id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut frame = id.to_vec();
+    frame.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]); // Flags
+    frame.extend_from_slice(content);
+    frame
+}
+
+/// A text frame in UTF-16 with a byte order mark, which every version reads.
+fn text_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+    let mut content = vec![1, 0xff, 0xfe];
+    content.extend(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+    frame(id, &content)
+}
+
+/// Bytes of the ID3v2 tag the audio starts with, if any.
+fn existing_tag(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[..3] != b"ID3" {
+        return 0;
+    }
+    let size = bytes[6..10].iter().fold(0, |size, byte| (size << 7) | (*byte as usize & 0x7f));
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    (10 + size + footer).min(bytes.len())
+}
+
+/// The audio with an ID3v2.3 tag in front, replacing the one it had.
+pub fn tag(bytes: &[u8], tags: &Tags) -> Result<Vec<u8>, Error> {
+    let mut frames = text_frame(b"TIT2", &tags.title);
+    frames.extend(text_frame(b"TPE1", &tags.artist));
+    if let Some(album) = &tags.album {
+        frames.extend(text_frame(b"TALB", album));
+    }
+    if let Some(cover) = &tags.cover {
+        let mut picture = vec![0]; // Latin-1 MIME type and description
+        picture.extend_from_slice(cover.mime.as_bytes());
+        picture.extend_from_slice(&[0, 3, 0]); // Front cover without description
+        picture.extend_from_slice(&cover.bytes);
+        frames.extend(frame(b"APIC", &picture));
+    }
+    if frames.len() >= 1 << 28 {
+        return Err(Error::Unsupported);
+    }
+    let mut tagged = b"ID3".to_vec();
+    tagged.extend_from_slice(&[3, 0, 0]); // Version 2.3 without flags
+    tagged.extend_from_slice(&synchsafe(frames.len()));
+    tagged.extend(frames);
+    tagged.extend_from_slice(&bytes[existing_tag(bytes)..]);
+    Ok(tagged)
+}
@@ -0,0 +1,140 @@
+use super::{Error, Tags};
+
+struct Atom {
+    kind: [u8; 4],
+    start: usize,
+    header: usize,
+    end: usize,
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, Error> {
+    data.get(at..at + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap())).ok_or(Error::Unsupported)
+}
+
+fn read_u64(data: &[u8], at: usize) -> Result<u64, Error> {
+    data.get(at..at + 8).map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap())).ok_or(Error::Unsupported)
+}
+
+fn atoms(data: &[u8], start: usize, end: usize) -> Result<Vec<Atom>, Error> {
+    let mut atoms = Vec::new();
+    let mut at = start;
+    while at + 8 <= end {
+        let kind: [u8; 4] = data[at + 4..at + 8].try_into().unwrap();
+        let (size, header) = match read_u32(data, at)? {
+            0 => (end - at, 8),
+            1 => (read_u64(data, at + 8)? as usize, 16),
+            size => (size as usize, 8),
+        };
+        // An atom running past its parent means the file is damaged.
+        let atom_end = at.checked_add(size).filter(|atom_end| size >= header && *atom_end <= end).ok_or(Error::Invalid)?;
+        atoms.push(Atom { kind, start: at, header, end: atom_end });
+        at = atom_end;
+    }
+    Ok(atoms)
+}
+
+pub(crate) fn atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+    atom.extend_from_slice(kind);
+    atom.extend_from_slice(content);
+    atom
+}
+
+fn data_atom(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = data_type.to_be_bytes().to_vec();
+    data.extend_from_slice(&0u32.to_be_bytes()); // Locale
+    data.extend_from_slice(value);
+    atom(kind, &atom(b"data", &data))
+}
+
+/// `udta` with an iTunes metadata list, as written by most taggers.
+fn udta(tags: &Tags) -> Vec<u8> {
+    let mut ilst = Vec::new();
+    ilst.extend(data_atom(b"\xa9nam", 1, tags.title.as_bytes()));
+    ilst.extend(data_atom(b"\xa9ART", 1, tags.artist.as_bytes()));
+    if let Some(album) = &tags.album {
+        ilst.extend(data_atom(b"\xa9alb", 1, album.as_bytes()));
+    }
+    // MP4 only knows JPEG and PNG covers.
+    let cover_type = tags.cover.as_ref().and_then(|cover| match cover.mime.as_str() {
+        "image/jpeg" => Some(13),
+        "image/png" => Some(14),
+        _ => None,
+    });
+    if let (Some(cover), Some(cover_type)) = (&tags.cover, cover_type) {
+        ilst.extend(data_atom(b"covr", cover_type, &cover.bytes));
+    }
+    let mut hdlr = vec![0; 8]; // Version, flags and pre-defined
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]); // Reserved and an empty name
+    let mut meta = vec![0; 4]; // Version and flags
+    meta.extend(atom(b"hdlr", &hdlr));
+    meta.extend(atom(b"ilst", &ilst));
+    atom(b"udta", &atom(b"meta", &meta))
+}
+
+/// Moves the absolute file offsets that point past `threshold` by `delta`:
+/// chunk offsets of regular files and base offsets of fragments.
+fn shift_offsets(data: &mut [u8], start: usize, end: usize, threshold: u64, delta: i64) -> Result<(), Error> {
+    let shift = |value: u64| if value >= threshold { (value as i64 + delta) as u64 } else { value };
+    for atom in atoms(data, start, end)? {
+        let content = atom.start + atom.header;
+        match &atom.kind {
+            b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"moof" | b"traf" => {
+                shift_offsets(data, content, atom.end, threshold, delta)?;
+            },
+            b"stco" => {
+                let count = read_u32(data, content + 4)? as usize;
+                for i in 0..count {
+                    let at = content + 8 + i * 4;
+                    let value = shift(read_u32(data, at)? as u64);
+                    data[at..at + 4].copy_from_slice(&u32::try_from(value).map_err(|_| Error::Unsupported)?.to_be_bytes());
+                }
+            },
+            b"co64" => {
+                let count = read_u32(data, content + 4)? as usize;
+                for i in 0..count {
+                    let at = content + 8 + i * 8;
+                    let value = shift(read_u64(data, at)?);
+                    data[at..at + 8].copy_from_slice(&value.to_be_bytes());
+                }
+            },
+            b"tfhd" => {
+                let flags = read_u32(data, content)? & 0x00ff_ffff;
+                if flags & 0x01 != 0 {
+                    let at = content + 8;
+                    let value = shift(read_u64(data, at)?);
+                    data[at..at + 8].copy_from_slice(&value.to_be_bytes());
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the user data of `moov` with the tags.
+pub fn tag(bytes: &[u8], tags: &Tags) -> Result<Vec<u8>, Error> {
+    let top = atoms(bytes, 0, bytes.len())?;
+    let moov = top.iter().find(|atom| &atom.kind == b"moov").ok_or(Error::Unsupported)?;
+    let mut content = Vec::new();
+    for child in atoms(bytes, moov.start + moov.header, moov.end)? {
+        if &child.kind != b"udta" {
+            content.extend_from_slice(&bytes[child.start..child.end]);
+        }
+    }
+    content.extend(udta(tags));
+    let mut new_moov = atom(b"moov", &content);
+    let delta = new_moov.len() as i64 - (moov.end - moov.start) as i64;
+    let threshold = moov.end as u64;
+    let length = new_moov.len();
+    shift_offsets(&mut new_moov, 0, length, threshold, delta)?;
+    let mut rest = bytes[moov.end..].to_vec();
+    let rest_length = rest.len();
+    shift_offsets(&mut rest, 0, rest_length, threshold, delta)?;
+
+    let mut tagged = bytes[..moov.start].to_vec();
+    tagged.extend(new_moov);
+    tagged.extend(rest);
+    Ok(tagged)
+}
@@ -0,0 +1,262 @@
+use std::path::Path;
+
+use base64::Engine;
+use symphonia::core::{
+    codecs::CODEC_TYPE_OPUS,
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use super::{Error, Tags};
+
+const VENDOR: &str = "FurPlayer";
+
+/// Audio pages are flushed once they hold this many bytes.
+const PAGE_SIZE: usize = 4096;
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = (i as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+/// Splits packets into Ogg pages of a single logical stream.
+struct OggWriter {
+    table: [u32; 256],
+    serial: u32,
+    sequence: u32,
+    out: Vec<u8>,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    /// Granule of the last packet that ends on the current page.
+    granule: Option<u64>,
+    /// The current page starts with the rest of a packet.
+    continued: bool,
+}
+
+impl OggWriter {
+    fn new(serial: u32) -> Self {
+        Self {
+            table: crc_table(),
+            serial,
+            sequence: 0,
+            out: Vec::new(),
+            segments: Vec::new(),
+            data: Vec::new(),
+            granule: None,
+            continued: false,
+        }
+    }
+
+    fn packet(&mut self, packet: &[u8], granule: u64) {
+        let mut remaining = packet;
+        let mut started = false;
+        loop {
+            if self.segments.len() == 255 {
+                self.flush(false);
+                // Only a packet split across pages continues on the next one.
+                self.continued = started;
+            }
+            started = true;
+            let length = remaining.len().min(255);
+            self.segments.push(length as u8);
+            self.data.extend_from_slice(&remaining[..length]);
+            remaining = &remaining[length..];
+            // A full segment means the packet goes on, even if nothing is left.
+            if length < 255 {
+                break;
+            }
+        }
+        self.granule = Some(granule);
+    }
+
+    fn flush(&mut self, last: bool) {
+        if self.segments.is_empty() && !last {
+            return;
+        }
+        let mut flags = 0;
+        if self.continued {
+            flags |= 0x01;
+        }
+        if self.sequence == 0 {
+            flags |= 0x02;
+        }
+        if last {
+            flags |= 0x04;
+        }
+        let start = self.out.len();
+        self.out.extend_from_slice(b"OggS");
+        self.out.push(0);
+        self.out.push(flags);
+        self.out.extend_from_slice(&self.granule.unwrap_or(u64::MAX).to_le_bytes());
+        self.out.extend_from_slice(&self.serial.to_le_bytes());
+        self.out.extend_from_slice(&self.sequence.to_le_bytes());
+        self.out.extend_from_slice(&[0; 4]);
+        self.out.push(self.segments.len() as u8);
+        self.out.extend_from_slice(&self.segments);
+        self.out.extend_from_slice(&self.data);
+        let crc = self.out[start..].iter()
+            .fold(0u32, |crc, byte| (crc << 8) ^ self.table[((crc >> 24) as u8 ^ byte) as usize]);
+        self.out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+        self.sequence += 1;
+        self.segments.clear();
+        self.data.clear();
+        self.granule = None;
+        self.continued = false;
+    }
+}
+
+/// Number of 48 kHz samples in an Opus packet, from its TOC byte.
+fn opus_samples(packet: &[u8]) -> u64 {
+    let Some(toc) = packet.first() else {
+        return 0;
+    };
+    let config = toc >> 3;
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        12..=15 => [480, 960][(config % 2) as usize],
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map(|count| count & 0x3f).unwrap_or(0) as u64,
+    };
+    frame * frames
+}
+
+fn opus_head(channels: u8) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(channels);
+    head.extend_from_slice(&312u16.to_le_bytes());
+    head.extend_from_slice(&48000u32.to_le_bytes());
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// FLAC picture block as stored in the `METADATA_BLOCK_PICTURE` comment.
+fn picture_block(bytes: &[u8], mime: &str) -> Vec<u8> {
+    let mut block = Vec::new();
+    block.extend_from_slice(&3u32.to_be_bytes()); // Front cover
+    block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+    block.extend_from_slice(mime.as_bytes());
+    block.extend_from_slice(&0u32.to_be_bytes()); // Description
+    block.extend_from_slice(&[0; 16]); // Width, height, depth and colors are optional
+    block.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    block.extend_from_slice(bytes);
+    block
+}
+
+fn opus_tags(tags: &Tags) -> Vec<u8> {
+    let mut comments = vec![format!("TITLE={}", tags.title), format!("ARTIST={}", tags.artist)];
+    if let Some(album) = &tags.album {
+        comments.push(format!("ALBUM={}", album));
+    }
+    if let Some(cover) = &tags.cover {
+        let picture = base64::engine::general_purpose::STANDARD.encode(picture_block(&cover.bytes, &cover.mime));
+        comments.push(format!("METADATA_BLOCK_PICTURE={}", picture));
+    }
+    let mut packet = b"OpusTags".to_vec();
+    packet.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    packet.extend_from_slice(VENDOR.as_bytes());
+    packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        packet.extend_from_slice(comment.as_bytes());
+    }
+    packet
+}
+
+/// Samples of earlier packets decoded before a cut part, so the decoder has
+/// settled at its start, 80 ms.
+const PRE_ROLL: u64 = 3840;
+
+/// Copies the Opus packets of a WebM file into an Ogg Opus stream, no re-encoding.
+/// With `part` only the seconds between its start and end are kept.
+pub fn remux_opus(media: &Path, tags: &Tags, part: Option<(f64, f64)>) -> Result<Vec<u8>, Error> {
+    let file = std::fs::File::open(media).map_err(|_| Error::Unknown)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("webm");
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|_| Error::Unsupported)?
+        .format;
+    let track = format.tracks().iter()
+        .find(|track| track.codec_params.codec == CODEC_TYPE_OPUS)
+        .ok_or(Error::Unsupported)?;
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base;
+    let mut head = match track.codec_params.extra_data.as_deref() {
+        Some(head) if head.starts_with(b"OpusHead") && head.len() >= 19 => head.to_vec(),
+        _ => opus_head(track.codec_params.channels.map(|channels| channels.count() as u8).unwrap_or(2)),
+    };
+
+    // Start in seconds and data of each packet
+    let mut packets = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(_) => return Err(Error::Unknown),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let time = time_base.map(|time_base| time_base.calc_time(packet.ts())).map(|time| time.seconds as f64 + time.frac).unwrap_or(0.0);
+        packets.push((time, packet.data));
+    }
+    // Granule of the end of the stream, when it ends inside the last packet
+    let mut length = None;
+    if let Some((start, end)) = part {
+        let first = packets.iter().rposition(|(time, _)| *time <= start).unwrap_or(0);
+        let mut pre_roll = 0;
+        let mut from = first;
+        while from > 0 && pre_roll < PRE_ROLL {
+            from -= 1;
+            pre_roll += opus_samples(&packets[from].1);
+        }
+        let to = packets.iter().rposition(|(time, _)| *time < end).map(|last| last + 1).unwrap_or(0);
+        if to <= first {
+            return Err(Error::Unknown);
+        }
+        let pre_skip = pre_roll + ((start - packets[first].0).max(0.0) * 48000.0).round() as u64;
+        head[10..12].copy_from_slice(&u16::try_from(pre_skip).map_err(|_| Error::Unknown)?.to_le_bytes());
+        length = Some(pre_skip + ((end - start) * 48000.0).round() as u64);
+        packets.truncate(to);
+        packets.drain(..from);
+    }
+
+    let mut writer = OggWriter::new(rand::random());
+    writer.packet(&head, 0);
+    writer.flush(false);
+    writer.packet(&opus_tags(tags), 0);
+    writer.flush(false);
+    let mut granule = 0;
+    let count = packets.len();
+    for (i, (_, packet)) in packets.iter().enumerate() {
+        // Flushing before the packet keeps the last packet for the end-of-stream page.
+        if writer.data.len() >= PAGE_SIZE {
+            writer.flush(false);
+        }
+        granule += opus_samples(packet);
+        let last = i + 1 == count;
+        writer.packet(packet, match (last, length) {
+            (true, Some(length)) => granule.min(length),
+            _ => granule,
+        });
+    }
+    writer.flush(true);
+    Ok(writer.out)
+}
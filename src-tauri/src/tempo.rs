@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::{decoder::Analyzer, fingerprint::Chromagram, key::{self, Key}};
+
+/// Samples of each analyzed window, about 23 ms at common rates.
+const WINDOW: usize = 1024;
+/// Samples between the starts of two windows.
+const HOP: usize = 512;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+/// Tempo that wins when a slower or faster one fits the beats about as well,
+/// like half or double time.
+const PREFERRED_BPM: f64 = 120.0;
+/// Multiples of the beat period compared when refining the tempo.
+const MULTIPLES: usize = 4;
+/// Seconds of audio below which no tempo is detected.
+const MIN_LENGTH: f64 = 10.0;
+
+/// Collects how strongly notes start in interleaved samples fed in chunks of
+/// any size and finds the tempo of the beats.
+pub struct TempoMeter {
+    channels: usize,
+    /// Windows per second.
+    rate: f64,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Mono samples not yet analyzed.
+    buffer: Vec<f32>,
+    /// Compressed magnitudes of the previous window.
+    previous: Vec<f32>,
+    /// Increase of the magnitudes over the previous window, for every window.
+    onsets: Vec<f32>,
+}
+
+impl TempoMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let window = (0..WINDOW)
+            .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / WINDOW as f32).cos())
+            .collect();
+        Self {
+            channels: channels.max(1),
+            rate: sample_rate as f64 / HOP as f64,
+            fft: FftPlanner::new().plan_fft_forward(WINDOW),
+            window,
+            buffer: Vec::new(),
+            previous: vec![0.0; WINDOW / 2],
+            onsets: Vec::new(),
+        }
+    }
+}
+
+impl Analyzer for TempoMeter {
+    type Output = Option<f64>;
+
+    fn push(&mut self, samples: &[f32]) {
+        self.buffer.extend(samples.chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32));
+        let mut start = 0;
+        while start + WINDOW <= self.buffer.len() {
+            let mut spectrum: Vec<Complex<f32>> = self.buffer[start..start + WINDOW].iter().zip(self.window.iter())
+                .map(|(sample, weight)| Complex::new(sample * weight, 0.0))
+                .collect();
+            self.fft.process(&mut spectrum);
+            let mut onset = 0.0;
+            for (bin, previous) in self.previous.iter_mut().enumerate() {
+                // Compressed so quiet instruments count too.
+                let magnitude = (1.0 + 1000.0 * spectrum[bin].norm()).ln();
+                onset += (magnitude - *previous).max(0.0);
+                *previous = magnitude;
+            }
+            self.onsets.push(onset);
+            start += HOP;
+        }
+        self.buffer.drain(..start);
+    }
+
+    /// Beats per minute, `None` for audio that is too short or has no beat.
+    fn finish(self) -> Option<f64> {
+        if (self.onsets.len() as f64) < MIN_LENGTH * self.rate {
+            return None;
+        }
+        // Only what stands out of the surrounding quarter second counts.
+        let radius = (self.rate / 8.0).round() as usize;
+        let mut sum = 0.0;
+        let mut sums = vec![0.0];
+        for onset in self.onsets.iter() {
+            sum += *onset as f64;
+            sums.push(sum);
+        }
+        let envelope: Vec<f64> = (0..self.onsets.len()).map(|index| {
+            let (from, to) = (index.saturating_sub(radius), (index + radius + 1).min(self.onsets.len()));
+            let mean = (sums[to] - sums[from]) / (to - from) as f64;
+            (self.onsets[index] as f64 - mean).max(0.0)
+        }).collect();
+        let max_lag = (60.0 * self.rate / MIN_BPM).ceil() as usize;
+        let min_lag = (60.0 * self.rate / MAX_BPM).floor().max(1.0) as usize;
+        let correlation: Vec<f64> = (0..=max_lag * MULTIPLES + 1).map(|lag| {
+            let length = envelope.len().saturating_sub(lag);
+            match length {
+                0 => 0.0,
+                _ => envelope[..length].iter().zip(envelope[lag..].iter()).map(|(a, b)| a * b).sum::<f64>() / length as f64,
+            }
+        }).collect();
+        let at = |lag: f64| {
+            let (index, fraction) = (lag.floor() as usize, lag.fract());
+            correlation[index] * (1.0 - fraction) + correlation[index + 1] * fraction
+        };
+        let weight = |lag: f64| (-0.5 * (60.0 * self.rate / lag / PREFERRED_BPM).log2().powi(2)).exp();
+        let (lag, score) = (min_lag..=max_lag)
+            .map(|lag| (lag, correlation[lag] * weight(lag as f64)))
+            .fold((0, 0.0), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+        if score <= 0.0 {
+            return None;
+        }
+        // Beats further apart tell the period more precisely.
+        let comb = |lag: f64| (1..=MULTIPLES).map(|multiple| at(lag * multiple as f64)).sum::<f64>();
+        let refined = (-20..=20)
+            .map(|step| (lag as f64 + step as f64 / 20.0).clamp(min_lag as f64, max_lag as f64))
+            .fold(lag as f64, |best, candidate| if comb(candidate) > comb(best) { candidate } else { best });
+        Some((600.0 * self.rate / refined).round() / 10.0)
+    }
+}
+
+/// Key of everything the chromagram collected.
+pub fn detect_key(chromagram: &Chromagram) -> Option<Key> {
+    let profile = chromagram.profile();
+    // The chromagram starts at A, three semitones below C.
+    key::detect(&std::array::from_fn(|class| profile[(class + 3) % 12]))
+}
+
+/// Tempo and key measured in one pass.
+pub struct BpmKeyMeter {
+    tempo: TempoMeter,
+    chromagram: Chromagram,
+}
+
+impl BpmKeyMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self { tempo: TempoMeter::new(channels, sample_rate), chromagram: Chromagram::new(channels, sample_rate) }
+    }
+}
+
+impl Analyzer for BpmKeyMeter {
+    type Output = (Option<f64>, Option<Key>);
+
+    fn push(&mut self, samples: &[f32]) {
+        self.tempo.push(samples);
+        self.chromagram.push(samples);
+    }
+
+    fn finish(self) -> Self::Output {
+        (self.tempo.finish(), detect_key(&self.chromagram))
+    }
+}
@@ -1,6 +1,6 @@
-use std::{env, sync::atomic::AtomicBool};
+use std::{env, path::Path, sync::{atomic::AtomicBool, Arc}};
 
-use crate::{downloader::{ContentRetriever, DefaultContentRetriever}, ytdlp::{self, YtDlp}};
+use crate::{app_state::{event::{Event, ForwardEvents}, AppState, PlaylistSort}, audio::{self, Audio, Chapter, History, HistoryFile, Listen, PlayQueue, QueueFile, RepeatMode, Rule, RuleContext, RuleMatch, SmartPlaylist, Source, Subtitle}, search::{Field, SearchIndex}, formats::{self, PlaylistEntry, PlaylistFormat, TrackName}, tagging::{self, Tags}, backup::{self, Manifest}, loudness::{self, Loudness, Meter}, waveform::{self, PeakMeter, Waveform}, silence::SilenceDetector, player::{Bounds, EngineEvent, NullSink, Player}, downloader::{ContentRetriever, DefaultContentRetriever, FileDownloader, LYRICS_FILE}, settings::{self, Codec, DownloadPolicy, Settings, SettingsFile, Transcode}, transcode, decoder::{Analyzer, Decoder}, fingerprint::{self, Chromagram, Fingerprint}, lyrics::{Line, Lyrics}, key::{Key, Mode}, tempo::BpmKeyMeter, jobs::{self, JobKind, JobQueue, JobState, JobsFile}, thumbnail, ytdlp::{self, YtDlp}};
 
 
 #[tokio::test]
@@ -22,6 +22,26 @@ async fn ytdlp_fetch() {
     }
 }
 
+#[tokio::test]
+async fn ytdlp_search_remote() {
+    let ytdlp = YtDlp::new(env::current_exe().unwrap().parent().unwrap().parent().unwrap().join("utils").join("yt-dlp.exe").to_str().unwrap().to_string());
+    let entries = ytdlp.search_remote("Rick Astley Never Gonna Give You Up".to_string(), 3).await;
+    assert!(entries.is_ok());
+    let entries = entries.unwrap();
+    assert!(!entries.is_empty() && entries.len() <= 3);
+    assert!(entries.iter().all(|entry| entry.url.ends_with(&entry.id)));
+}
+
+#[test]
+fn video_id_test() {
+    for url in ["https://www.youtube.com/watch?v=dQw4w9WgXcQ", "https://youtu.be/dQw4w9WgXcQ?t=42", "https://m.youtube.com/watch?feature=share&v=dQw4w9WgXcQ&list=1",
+        "youtube.com/shorts/dQw4w9WgXcQ", "https://music.youtube.com/watch?v=dQw4w9WgXcQ#comments"] {
+        assert_eq!(ytdlp::video_id(url), Some("dQw4w9WgXcQ"), "{url}");
+    }
+    assert_eq!(ytdlp::video_id("https://www.youtube.com/watch?list=1"), None);
+    assert_eq!(ytdlp::video_id("https://www.some.com/watch?v=123"), None);
+}
+
 #[tokio::test]
 async fn downloader_test() {
     let downloader = DefaultContentRetriever;
@@ -56,3 +76,1522 @@ async fn downloader_cancel_test() {
     assert!(source.is_err());
     assert!(matches!(source.unwrap_err(), crate::downloader::Error::Canceled));
 }
+
+#[test]
+fn settings_test() {
+    let path = env::temp_dir().join("furplayer_settings_test.json");
+    let file = SettingsFile(path.to_str().unwrap().to_string());
+    let mut settings = Settings { max_concurrent_downloads: 5, ..Default::default() };
+    assert!(file.save(&settings).is_ok());
+    assert_eq!(file.load().unwrap(), settings);
+    settings.max_concurrent_downloads = 0;
+    assert!(matches!(settings.validate().unwrap_err(), settings::Error::InvalidConcurrentDownloads));
+    std::fs::remove_file(path).unwrap();
+
+    // Only the marker file turns portable mode on, not settings next to the executable.
+    let exe_dir = env::temp_dir().join("furplayer_portable_test");
+    let _ = std::fs::remove_dir_all(&exe_dir);
+    std::fs::create_dir_all(&exe_dir).unwrap();
+    std::fs::write(exe_dir.join("settings.json"), "{}").unwrap();
+    if env::var("PORTABLE").is_err() {
+        assert!(!settings::is_portable(&exe_dir));
+    }
+    std::fs::write(exe_dir.join(settings::PORTABLE_MARKER), "").unwrap();
+    assert!(settings::is_portable(&exe_dir));
+    std::fs::remove_dir_all(exe_dir).unwrap();
+}
+
+#[test]
+fn download_policy_test() {
+    assert!(DownloadPolicy::Always.should_download(0));
+    assert!(!DownloadPolicy::StreamOnly.should_download(100));
+    assert!(!DownloadPolicy::AfterPlays(3).should_download(2));
+    assert!(DownloadPolicy::AfterPlays(3).should_download(3));
+    assert!(DownloadPolicy::AfterPlays(0).should_download(0));
+
+    let root = env::temp_dir().join("furplayer_download_policy_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let (state, _) = app_state(&root, &[], Settings { download_policy: DownloadPolicy::AfterPlays(3), ..Default::default() });
+    let audio = |play_count: u32, download_policy: Option<DownloadPolicy>| Audio {
+        play_count,
+        download_policy,
+        ..Audio::create(String::new(), String::new(), Source::YouTube(String::new()))
+    };
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        // The global policy applies without an override.
+        assert!(!state.should_download(&audio(2, None), false).await);
+        assert!(state.should_download(&audio(3, None), false).await);
+        // The listen about to start counts, one before the threshold is enough.
+        assert!(state.should_download(&audio(2, None), true).await);
+        assert!(!state.should_download(&audio(1, None), true).await);
+        // The override of the audio wins in both directions.
+        assert!(state.should_download(&audio(0, Some(DownloadPolicy::Always)), false).await);
+        assert!(!state.should_download(&audio(10, Some(DownloadPolicy::StreamOnly)), true).await);
+        assert!(!state.should_download(&audio(3, Some(DownloadPolicy::AfterPlays(5))), true).await);
+        assert!(state.should_download(&audio(4, Some(DownloadPolicy::AfterPlays(5))), true).await);
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn move_library_test() {
+    let root = env::temp_dir().join("furplayer_move_library_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let old = root.join("old");
+    let new = root.join("new");
+    std::fs::create_dir_all(old.join("audios").join("1")).unwrap();
+    std::fs::write(old.join("audios").join("1").join("index.json"), "{}").unwrap();
+    std::fs::create_dir_all(old.join("downloading").join("1")).unwrap();
+    let downloader = FileDownloader::new(old.join("audios").to_str().unwrap().to_string(), old.join("downloading").to_str().unwrap().to_string());
+    let result = downloader.move_library(new.join("audios").to_str().unwrap().to_string(), new.join("downloading").to_str().unwrap().to_string(), |_, _| async {}).await;
+    assert!(result.is_ok());
+    assert!(new.join("audios").join("1").join("index.json").exists());
+    assert!(!old.join("audios").exists());
+    let result = downloader.move_library(new.join("audios").join("1").to_str().unwrap().to_string(), new.join("downloading").to_str().unwrap().to_string(), |_, _| async {}).await;
+    assert!(matches!(result.unwrap_err(), crate::downloader::Error::InvalidTarget));
+
+    // Merged duplicates hand their files over, lyrics stay with their audio.
+    downloader.save_user_file(1, LYRICS_FILE, b"[00:01.00]Hello").await.unwrap();
+    downloader.move_downloaded(1, 2).await.unwrap();
+    assert!(new.join("audios").join("2").join("index.json").exists());
+    assert!(!new.join("audios").join("1").join("index.json").exists());
+    assert!(downloader.load_user_file(1, LYRICS_FILE).await.is_some());
+    assert!(downloader.move_downloaded(1, 2).await.is_err());
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn verify_library_test() {
+    let root = env::temp_dir().join("furplayer_verify_library_test");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("audios").join("1")).unwrap();
+    std::fs::write(root.join("audios").join("1").join("index.json"), r#"{"media_mime":"audio/webm","thumbnail_mime":"image/jpeg","media_size":10}"#).unwrap();
+    std::fs::write(root.join("audios").join("1").join("media.weba"), "12345").unwrap();
+    std::fs::create_dir_all(root.join("audios").join("2")).unwrap();
+    std::fs::create_dir_all(root.join("downloading").join("2")).unwrap();
+    std::fs::create_dir_all(root.join("audios").join("99")).unwrap();
+    let downloader = FileDownloader::new(root.join("audios").to_str().unwrap().to_string(), root.join("downloading").to_str().unwrap().to_string());
+    let report = downloader.verify(&[1, 2]).await;
+    assert_eq!(report.truncated_media, vec![1]);
+    assert_eq!(report.missing_media, vec![2]);
+    assert_eq!(report.stale_partials, vec![2]);
+    assert_eq!(report.orphaned.len(), 1);
+    assert!(downloader.repair(&report).await.is_ok());
+    assert!(downloader.verify(&[1, 2]).await.broken().is_empty());
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+/// Keeps forwarded events for assertions.
+#[derive(Default)]
+struct Events(std::sync::Mutex<Vec<Event>>);
+
+impl ForwardEvents for Events {
+    fn forward_event(&self, event: Event) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+/// App state in `root` with `audios` in its library, playing into a `NullSink`.
+fn app_state(root: &Path, audios: &[Audio], settings: Settings) -> (Arc<AppState>, Arc<Events>) {
+    std::fs::create_dir_all(root).unwrap();
+    std::fs::write(root.join("playlist.json"), audio::audios_to_json(audios).unwrap()).unwrap();
+    SettingsFile(root.join("settings.json").to_str().unwrap().to_string()).save(&settings).unwrap();
+    let events = Arc::new(Events::default());
+    let state = AppState::open(events.clone(), root.to_str().unwrap(), String::new(), || Box::new(NullSink));
+    (Arc::new(state), events)
+}
+
+/// Stores `media` as the downloaded file of the audio `id` in `root`.
+fn downloaded(root: &Path, id: u32, mime: &str, media: &[u8]) {
+    let dir = root.join("audios").join(id.to_string());
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.json"), format!(r#"{{"media_mime":"{mime}","thumbnail_mime":"image/jpeg"}}"#)).unwrap();
+    std::fs::write(dir.join(format!("media.{}", mime2ext::mime2ext(mime).unwrap())), media).unwrap();
+}
+
+#[test]
+fn storage_test() {
+    let root = env::temp_dir().join("furplayer_storage_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let audio = |id: u32, last_played: Option<u64>, pinned: bool| Audio {
+        id,
+        last_played,
+        pinned,
+        added_at: Some(200),
+        ..Audio::create(String::new(), String::new(), Source::YouTube(String::new()))
+    };
+    let audios = [audio(1, Some(100), false), audio(2, Some(300), false), audio(3, None, false), audio(4, Some(50), true)];
+    for audio in audios.iter() {
+        downloaded(&root, audio.id, "audio/wav", &[0; 1000]);
+    }
+    let (state, events) = app_state(&root, &audios, Settings { storage_quota: Some(2200), ..Settings::default() });
+    let has_file = |id: u32| root.join("audios").join(id.to_string()).join("index.json").exists();
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let usage = serde_json::to_value(state.get_disk_usage().await.unwrap()).unwrap();
+        assert_eq!(usage["audios"].as_array().unwrap().len(), 4);
+        assert!(usage["total"].as_u64().unwrap() > 4000);
+        assert_eq!(usage["quota"].as_u64(), Some(2200));
+
+        // Never played audios count from when they were added, the kept and
+        // pinned audios stay however long ago they were played.
+        state.enforce_quota(Some(2)).await;
+        assert_eq!([1, 2, 3, 4].map(has_file), [false, true, false, true]);
+        let evicted: Vec<u64> = events.0.lock().unwrap().iter().filter_map(|event| match event {
+            Event::Evicted { audio } => serde_json::to_value(audio).unwrap()["id"].as_u64(),
+            _ => None,
+        }).collect();
+        assert_eq!(evicted, vec![1, 3]);
+
+        // Unpinning makes room again, the least recently played first.
+        downloaded(&root, 1, "audio/wav", &[0; 1000]);
+        let unpinned = serde_json::to_value(state.set_pinned(4, false).await.unwrap()).unwrap();
+        assert_eq!(unpinned["pinned"], false);
+        assert_eq!([1, 2, 3, 4].map(has_file), [true, true, false, false]);
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+fn ebml(id: &[u8], content: &[u8]) -> Vec<u8> {
+    let mut element = id.to_vec();
+    element.push(0x01);
+    element.extend_from_slice(&(content.len() as u64).to_be_bytes()[1..]);
+    element.extend_from_slice(content);
+    element
+}
+
+/// WebM of an Opus track with 20 ms `packets`.
+fn webm(channels: u8, pre_skip: u16, packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.extend_from_slice(&[1, channels]);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&[0x80, 0xbb, 0, 0, 0, 0, 0]);
+    let mut tracks = ebml(&[0xd7], &[1]);
+    tracks.extend(ebml(&[0x73, 0xc5], &[1]));
+    tracks.extend(ebml(&[0x83], &[2]));
+    tracks.extend(ebml(&[0x86], b"A_OPUS"));
+    tracks.extend(ebml(&[0x63, 0xa2], &head));
+    let mut audio = ebml(&[0xb5], &48000f64.to_be_bytes());
+    audio.extend(ebml(&[0x9f], &[channels]));
+    tracks.extend(ebml(&[0xe1], &audio));
+    let mut cluster = ebml(&[0xe7], &[0]);
+    for (i, packet) in packets.iter().enumerate() {
+        let mut block = vec![0x81];
+        block.extend_from_slice(&(i as i16 * 20).to_be_bytes());
+        block.push(0x80);
+        block.extend_from_slice(packet);
+        cluster.extend(ebml(&[0xa3], &block));
+    }
+    let mut segment = ebml(&[0x15, 0x49, 0xa9, 0x66], &ebml(&[0x2a, 0xd7, 0xb1], &1_000_000u32.to_be_bytes()));
+    segment.extend(ebml(&[0x16, 0x54, 0xae, 0x6b], &ebml(&[0xae], &tracks)));
+    segment.extend(ebml(&[0x1f, 0x43, 0xb6, 0x75], &cluster));
+    let mut header = ebml(&[0x42, 0x82], b"webm");
+    header.extend(ebml(&[0x42, 0x87], &[2]));
+    header.extend(ebml(&[0x42, 0x85], &[2]));
+    let mut webm = ebml(&[0x1a, 0x45, 0xdf, 0xa3], &header);
+    webm.extend(ebml(&[0x18, 0x53, 0x80, 0x67], &segment));
+    webm
+}
+
+#[test]
+fn opus_test() {
+    // 300 ms of a 440 Hz sine at -12 dBFS, coded as 12 kbit/s CELT.
+    let packets: Vec<Vec<u8>> = [
+        "b8b4efa1bdf36b58ee59bf7558875d4baaa652ce616930735d721a2f9950b16d",
+        "b8b1729a52f6cb0b294b8236febd7f2b23bf76da91210ac73e1b4e3c097dae",
+        "b8ad68e26047129b8dd6c638f67d833a4561fdf78ae0bc92366d39ee7eb671ae",
+        "b8ad7f449a059b919b99f2ec8ecaddcffa4a63984ee66a77094b93d76217ae",
+        "b8aca9fd4dc99986728fc1b08519b31a0d020ae4e2672730c0e9cebf44f1ae",
+        "b8adb428e949830f63949448380e7a15db462c8fc39020271c579649255185ae",
+        "b8acaa103d45053845a49c069b4245d3ccbb7d9262a6077df8b4b8ffaf75ae",
+        "b8ad6602a079828ca9629351a43a0b214b82fe60e2b8f240e39d5c539c71ae",
+        "b8ad7f449a02c4ce6780a1436d605d78d4d1d5ba13b86a77094b93db6217ae",
+        "b8aca9fd4dbcfe17638217e4b7cc933e5af92f0b126798c766c726b544f1ae",
+        "b8adb428e949ca54b8fca9b4fa671f27e045ad987205938e2bcb249f5185ae",
+        "b8acaa103d44ef37ae3767765822b2975c70af0f429c077df8b4b8fb2f75ae",
+        "b8ad6602a06c55d6c3e00ea7bb6ba513413e4013b879974b4c5543bc71ae",
+        "b8ad7f449a0161e673a15b6dfa19a80eda12bed84ee66a77094b93df6217ae",
+        "b8aca9fd4db745fe2d6f5f1f64d7851248b516ac89e631c263935344f1ae",
+    ].iter().map(|hex| (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()).collect();
+    let mut decoder = Decoder::open(webm(1, 312, &packets), "audio/webm").unwrap();
+    let mut samples = Vec::new();
+    while let Some(chunk) = decoder.next_chunk().unwrap() {
+        samples.extend(chunk);
+    }
+    assert_eq!((decoder.channels(), decoder.sample_rate()), (1, 48000));
+    // The encoder delay is skipped
+    assert_eq!(samples.len(), 15 * 960 - 312);
+    let middle = &samples[2400..12000];
+    let rms = (middle.iter().map(|sample| sample * sample).sum::<f32>() / middle.len() as f32).sqrt();
+    assert!((rms - 0.25 / 2f32.sqrt()).abs() < 0.02, "{rms}");
+    let cycles = middle.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+    assert!((87..=89).contains(&cycles), "{cycles}");
+
+    // Without cues WebM only seeks forward.
+    let mut decoder = Decoder::open(webm(1, 312, &packets), "audio/webm").unwrap();
+    decoder.seek(0.1).unwrap();
+    let chunk = decoder.next_chunk().unwrap().unwrap();
+    assert!((decoder.position() - 0.1).abs() < 0.021);
+    assert!(chunk.iter().any(|sample| sample.abs() > 0.1));
+}
+
+#[test]
+fn opus_silk_test() {
+    // SILK frames play as silence of their length.
+    let packets = vec![vec![0x08, 0x55, 0x55]; 3];
+    let mut decoder = Decoder::open(webm(1, 0, &packets), "audio/webm").unwrap();
+    let mut samples = Vec::new();
+    while let Some(chunk) = decoder.next_chunk().unwrap() {
+        samples.extend(chunk);
+    }
+    assert_eq!(samples.len(), 3 * 960);
+    assert!(samples.iter().all(|sample| *sample == 0.0));
+
+    // A hybrid packet in the middle of music doesn't stop the track.
+    let mut packets: Vec<Vec<u8>> = [
+        "b8b4efa1bdf36b58ee59bf7558875d4baaa652ce616930735d721a2f9950b16d",
+        "b8b1729a52f6cb0b294b8236febd7f2b23bf76da91210ac73e1b4e3c097dae",
+        "b8ad68e26047129b8dd6c638f67d833a4561fdf78ae0bc92366d39ee7eb671ae",
+        "b8ad7f449a059b919b99f2ec8ecaddcffa4a63984ee66a77094b93d76217ae",
+        "b8aca9fd4dc99986728fc1b08519b31a0d020ae4e2672730c0e9cebf44f1ae",
+        "b8adb428e949830f63949448380e7a15db462c8fc39020271c579649255185ae",
+    ].iter().map(|hex| (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()).collect();
+    packets.insert(3, vec![0x68, 0x55, 0x55, 0x55]);
+    let mut decoder = Decoder::open(webm(1, 312, &packets), "audio/webm").unwrap();
+    let mut samples = Vec::new();
+    while let Some(chunk) = decoder.next_chunk().unwrap() {
+        samples.extend(chunk);
+    }
+    assert_eq!(samples.len(), 7 * 960 - 312);
+    let hybrid = &samples[3 * 960 - 312..4 * 960 - 312];
+    assert!(hybrid.iter().all(|sample| *sample == 0.0));
+    let last = &samples[6 * 960 - 312..];
+    assert!(last.iter().any(|sample| sample.abs() > 0.1));
+}
+
+fn wav(seconds: f32, sample_rate: u32) -> Vec<u8> {
+    let samples: Vec<i16> = (0..(seconds * sample_rate as f32) as usize)
+        .map(|i| ((i as f32 * 440.0 * 2.0 * std::f32::consts::PI / sample_rate as f32).sin() * 8000.0) as i16)
+        .collect();
+    pcm_wav(&samples, 1, sample_rate)
+}
+
+/// 16-bit WAV file of interleaved samples.
+fn pcm_wav(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2 * channels as u32).to_le_bytes());
+    bytes.extend_from_slice(&(2 * channels).to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples.iter() {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    bytes
+}
+
+#[tokio::test]
+async fn player_test() {
+    let (player, mut events) = Player::new(|| Box::new(NullSink));
+    player.load(1, wav(1.0, 8000), "audio/wav".to_string(), 0.5, 1.0, Bounds::default()).await;
+    loop {
+        match events.recv().await.unwrap() {
+            EngineEvent::Position { id, position, .. } => {
+                assert_eq!(id, 1);
+                assert!(position >= 0.5, "{}", position);
+            },
+            EngineEvent::Finished { id } => {
+                assert_eq!(id, 1);
+                break;
+            },
+            EngineEvent::Error { .. } => panic!("decoding failed"),
+        }
+    }
+    player.load(2, wav(1.0, 8000), "audio/wav".to_string(), 0.0, 1.0, Bounds { offset: 0.25, end: Some(0.75), duration: Some(0.5) }).await;
+    loop {
+        match events.recv().await.unwrap() {
+            EngineEvent::Position { position, duration, .. } => {
+                assert!(position < 0.5, "{}", position);
+                assert_eq!(duration, Some(0.5));
+            },
+            EngineEvent::Finished { id } => {
+                assert_eq!(id, 2);
+                break;
+            },
+            EngineEvent::Error { .. } => panic!("decoding failed"),
+        }
+    }
+    player.load(3, vec![0; 16], "audio/wav".to_string(), 0.0, 1.0, Bounds::default()).await;
+    assert!(matches!(events.recv().await.unwrap(), EngineEvent::Error { id: 3, .. }));
+}
+
+#[test]
+fn play_queue_test() {
+    let mut queue = PlayQueue::default();
+    queue.set(vec![1, 2, 3], 0);
+    assert_eq!(queue.next(false), Some(2));
+    queue.play_next(9);
+    assert_eq!(queue.next(false), Some(9));
+    assert_eq!(queue.previous(), Some(2));
+    queue.set_repeat(RepeatMode::One);
+    assert_eq!(queue.next(true), Some(2));
+    queue.set_repeat(RepeatMode::Off);
+    assert_eq!(queue.next(false), Some(9));
+    assert_eq!(queue.next(false), Some(3));
+    assert_eq!(queue.next(false), None);
+
+    // Removing the current audio goes on with the one that followed it.
+    queue.set(vec![1, 2, 3], 1);
+    queue.remove(2);
+    assert_eq!(queue.current(), Some(3));
+    queue.remove(3);
+    assert_eq!(queue.current(), Some(1));
+    queue.remove(1);
+    assert_eq!(queue.current(), None);
+
+    // An audio queued twice is two entries, each with its own place in the history.
+    queue.set(vec![1, 2, 1, 3], 0);
+    assert_eq!(queue.next(false), Some(2));
+    assert_eq!(queue.next(false), Some(1));
+    assert_eq!(queue.index(), Some(2));
+    assert_eq!(queue.next(false), Some(3));
+    assert_eq!(queue.previous(), Some(1));
+    assert_eq!(queue.index(), Some(2));
+    queue.remove_at(0);
+    assert_eq!((queue.items(), queue.index()), (&[2, 1, 3][..], Some(1)));
+    assert_eq!(queue.previous(), Some(2));
+    queue.move_item(0, 2);
+    assert_eq!((queue.items(), queue.index()), (&[1, 3, 2][..], Some(2)));
+    queue.set(vec![1, 2, 1], 1);
+    queue.remove(1);
+    assert_eq!((queue.items(), queue.current()), (&[2][..], Some(2)));
+
+    let mut first = PlayQueue::default();
+    first.set_shuffle(true, Some(42));
+    first.set((1..=10).collect(), 0);
+    first.set_repeat(RepeatMode::All);
+    let mut second = first.clone();
+    let mut played = vec![1];
+    for _ in 0..9 {
+        let id = first.next(false).unwrap();
+        assert_eq!(second.next(false), Some(id));
+        assert!(!played.contains(&id));
+        played.push(id);
+    }
+    assert!(first.next(false).is_some());
+
+    // A saved queue pointing past its items loads without a current audio.
+    let path = env::temp_dir().join("furplayer_play_queue_test.json");
+    std::fs::write(&path, r#"{"items":[1,2],"current":5,"shuffle":null,"repeat":"Off","history":[],"position":30.0}"#).unwrap();
+    let loaded = QueueFile(path.to_str().unwrap().to_string()).load().unwrap();
+    assert_eq!((loaded.current(), loaded.position), (None, 0.0));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn history_test() {
+    let mut history = History::default();
+    history.begin(1, 100, 0.0);
+    history.progress(1, 0.5);
+    history.progress(1, 30.0);
+    history.progress(1, 30.5);
+    assert_eq!(history.finish(true).map(|listen| listen.listened), Some(1.0));
+    history.begin(1, 200, 0.0);
+    history.progress(2, 0.5);
+    history.finish(false);
+    history.record(Listen { audio_id: 2, started_at: 300, listened: 10.0, completed: true });
+    assert_eq!(history.finish(false), None);
+
+    let stats = history.stats(1);
+    assert_eq!((stats.skip_count, stats.listened), (1, 1.0));
+    let plays: Vec<bool> = history.listens().iter().map(Listen::is_play).collect();
+    assert_eq!(plays, vec![true, false, true]);
+    let range: Vec<u64> = history.range(Some(200), Some(301)).iter().map(|listen| listen.started_at).collect();
+    assert_eq!(range, vec![300, 200]);
+
+    // Listens are appended to what was saved before.
+    let path = env::temp_dir().join("furplayer_history_test.jsonl");
+    let file = HistoryFile(path.to_str().unwrap().to_string());
+    file.save(&History::new(history.listens()[..2].to_vec()));
+    file.append(&history.listens()[2]);
+    assert_eq!(file.load().unwrap().listens(), history.listens());
+    let _ = std::fs::remove_file(path);
+    // Skips count as plays once they've played long enough.
+    let listen = Listen { audio_id: 3, started_at: 400, listened: 45.0, completed: false };
+    assert!(listen.is_play());
+    history.record(listen);
+    assert_eq!(history.stats(3).skip_count, 1);
+}
+
+#[test]
+fn smart_playlist_test() {
+    let mut audio = Audio::create("Never Gonna Give You Up".to_string(), "Rick Astley".to_string(), Source::YouTube("https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string()));
+    audio.duration = Some(213.0);
+    audio.play_count = 12;
+    let now = audio.added_at.unwrap();
+    let context = RuleContext { now, downloaded: false };
+
+    let all = SmartPlaylist::create("Rick".to_string(), vec![Rule::AuthorContains("rick".to_string()), Rule::PlayCountGreater(10), Rule::DurationLess(300.0)], RuleMatch::All);
+    assert!(all.matches(&audio, context));
+    audio.play_count = 3;
+    assert!(!all.matches(&audio, context));
+    assert!(Rule::PlayCountLess(4).matches(&audio, context));
+
+    let recent = SmartPlaylist::create("Recent".to_string(), vec![Rule::AddedWithinDays(30), Rule::Downloaded(false)], RuleMatch::All);
+    assert!(recent.matches(&audio, context));
+    assert!(!recent.matches(&audio, RuleContext { now: now + 60 * 60 * 24 * 31, ..context }));
+    assert!(!recent.matches(&audio, RuleContext { downloaded: true, ..context }));
+
+    let any = SmartPlaylist::create("Any".to_string(), vec![Rule::TitleContains("nothing".to_string()), Rule::Pinned(false)], RuleMatch::Any);
+    assert!(any.matches(&audio, context));
+    audio.duration = None;
+    assert!(!SmartPlaylist::create("Short".to_string(), vec![Rule::DurationLess(300.0)], RuleMatch::All).matches(&audio, context));
+}
+
+#[test]
+fn search_test() {
+    let source = || Source::YouTube("https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string());
+    let mut first = Audio::create("Beyoncé - Halo".to_string(), "Beyoncé".to_string(), source());
+    first.tags = vec!["pop".to_string()];
+    let second = Audio::create("Кино - Группа крови".to_string(), "Виктор Цой".to_string(), source());
+    let third = Audio::create("Halogen".to_string(), "Someone".to_string(), source());
+    let index = SearchIndex::new(&[first.clone(), second.clone(), third.clone()]);
+
+    let results = index.search("beyonce");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id, first.id);
+    assert_eq!((results[0].highlights[0].start, results[0].highlights[0].end), (0, 7));
+
+    let ids: Vec<u32> = index.search("halo").iter().map(|result| result.id).collect();
+    assert_eq!(ids, vec![first.id, third.id]);
+
+    let results = index.search("gruppa krovi");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id, second.id);
+    assert_eq!((results[0].highlights[0].start, results[0].highlights[0].end), (7, 13));
+
+    assert_eq!(index.search("виктор").first().map(|result| result.id), Some(second.id));
+    assert_eq!(index.search("kinno").first().map(|result| result.id), Some(second.id));
+    assert_eq!(index.search("kno").first().map(|result| result.id), None);
+    assert_eq!(index.search("grupa").first().map(|result| result.id), Some(second.id));
+    assert_eq!(index.search("bejonce").first().map(|result| result.id), Some(first.id));
+    assert_eq!(index.search("pop").first().map(|result| result.highlights[0].field), Some(Field::Tag(0)));
+    assert!(index.search("halo nothing").is_empty());
+}
+
+#[test]
+fn playlist_format_test() {
+    let entries = vec![
+        PlaylistEntry {
+            title: "Never Gonna Give You Up".to_string(),
+            author: "Rick Astley".to_string(),
+            album: None,
+            tags: Vec::new(),
+            duration: Some(213.0),
+            source: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            location: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+        },
+        PlaylistEntry {
+            title: "Rock & <Roll>".to_string(),
+            author: "Someone".to_string(),
+            album: Some("Album".to_string()),
+            tags: vec!["rock".to_string()],
+            duration: None,
+            source: "https://www.youtube.com/watch?v=123&list=1".to_string(),
+            location: "/music/Rock & Roll.m4a".to_string(),
+        },
+    ];
+    for format in [PlaylistFormat::M3u8, PlaylistFormat::Xspf, PlaylistFormat::Json] {
+        let content = format.export(&entries).unwrap();
+        assert_eq!(PlaylistFormat::detect(&content), format);
+        let locations = format.import(&content).unwrap();
+        assert_eq!(locations, vec![entries[0].source.clone(), entries[1].source.clone()]);
+    }
+    let xspf = r#"<playlist version="1"><trackList><track id="1"><location>https://example.com/a</location></track><track><image/><location type="url">file:///b.m4a</location></track></trackList></playlist>"#;
+    assert_eq!(PlaylistFormat::Xspf.import(xspf).unwrap(), vec!["https://example.com/a".to_string(), "file:///b.m4a".to_string()]);
+    // Character references and CDATA sections are decoded.
+    let xspf = r#"<playlist><trackList><track><location>https://example.com/?a=1&#38;b=&#x2019;&unknown;</location></track><track><location><![CDATA[https://example.com/?a=1&b=2]]></location></track></trackList></playlist>"#;
+    assert_eq!(PlaylistFormat::Xspf.import(xspf).unwrap(), vec!["https://example.com/?a=1&b=\u{2019}&unknown;".to_string(), "https://example.com/?a=1&b=2".to_string()]);
+    assert_eq!(PlaylistFormat::M3u8.import("#EXTM3U\n/music/a.mp3\n").unwrap(), vec!["/music/a.mp3".to_string()]);
+    assert!(PlaylistFormat::Json.import("{}").is_err());
+    assert!(PlaylistFormat::Xspf.import("<html></html>").is_err());
+
+    let name = TrackName { id: 1, title: "Back In Black?", author: "AC/DC", album: None, ext: "m4a" };
+    assert_eq!(formats::render(formats::DEFAULT_TEMPLATE, &name).unwrap(), std::path::PathBuf::from("AC_DC/Back In Black_.m4a"));
+    assert_eq!(formats::render("{album}/{id}.{ext}", &name).unwrap(), std::path::PathBuf::from("Unknown/1.m4a"));
+    assert!(formats::render("../{title}", &name).is_err());
+    assert!(formats::render("{year}", &name).is_err());
+}
+
+#[test]
+fn import_playlist_test() {
+    let root = env::temp_dir().join("furplayer_import_playlist_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let audio = Audio::create("Title".to_string(), "Author".to_string(), Source::YouTube("https://www.youtube.com/watch?v=1".to_string()));
+    let (state, _) = app_state(&root, &[audio], Settings::default());
+    let playlist = root.join("import.m3u8");
+    std::fs::write(&playlist, "#EXTM3U\n/music/a.mp3\nC:\\Music\\b.mp3\nhttps://www.youtube.com/watch?v=1\nhttps://youtu.be/1?t=42\n").unwrap();
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        // Local files aren't passed on to yt-dlp.
+        let report = serde_json::to_value(state.import_playlist(playlist.to_str().unwrap().to_string()).await.unwrap()).unwrap();
+        assert_eq!(report["skipped"], serde_json::json!(["/music/a.mp3", "C:\\Music\\b.mp3"]));
+        // Short links find the audio added by its watch link.
+        assert_eq!(report["existing"].as_array().unwrap().len(), 2);
+        assert!(report["added"].as_array().unwrap().is_empty() && report["failed"].as_array().unwrap().is_empty());
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn export_cover_test() {
+    let root = env::temp_dir().join("furplayer_export_cover_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::new(160, 90)).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    let thumbnails: [(&str, &[u8]); 2] = [("image/png", &png), ("image/webp", b"not an image")];
+    let mut audios = Vec::new();
+    for (id, (mime, thumbnail)) in thumbnails.iter().enumerate() {
+        let id = id as u32 + 1;
+        downloaded(&root, id, "audio/webm", &webm(2, 312, &vec![vec![0xfc]; 3]));
+        let dir = root.join("audios").join(id.to_string());
+        std::fs::write(dir.join("index.json"), format!(r#"{{"media_mime":"audio/webm","thumbnail_mime":"{mime}"}}"#)).unwrap();
+        std::fs::write(dir.join(format!("thumbnail.{}", mime2ext::mime2ext(mime).unwrap())), thumbnail).unwrap();
+        audios.push(Audio { id, ..Audio::create(format!("Track {id}"), "Author".to_string(), Source::YouTube(String::new())) });
+    }
+    let (state, _) = app_state(&root, &audios, Settings::default());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let export = root.join("export");
+        let report = serde_json::to_value(state.export_tracks(vec![1, 2], export.to_str().unwrap().to_string(), formats::DEFAULT_TEMPLATE.to_string()).await.unwrap()).unwrap();
+        let paths: Vec<&str> = report["exported"].as_array().unwrap().iter().map(|track| track["path"].as_str().unwrap()).collect();
+        assert_eq!(paths.len(), 2);
+        // The front cover picture block starts with its mime type.
+        let picture = |mime: &str| {
+            let mut block = 3u32.to_be_bytes().to_vec();
+            block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+            block.extend_from_slice(mime.as_bytes());
+            format!("METADATA_BLOCK_PICTURE={}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, block))
+        };
+        let contains = |path: &str, text: &str| std::fs::read(path).unwrap().windows(text.len()).any(|window| window == text.as_bytes());
+        // Thumbnails are embedded as JPEG covers, the raw thumbnail only when it can't be decoded.
+        assert!(contains(paths[0], &picture("image/jpeg")));
+        assert!(contains(paths[1], &picture("image/webp")));
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn sync_device_test() {
+    let root = env::temp_dir().join("furplayer_sync_device_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let device = root.join("device");
+    let audio = |id: u32, title: &str, author: &str| Audio {
+        id,
+        ..Audio::create(title.to_string(), author.to_string(), Source::YouTube(String::new()))
+    };
+    let audios = [audio(1, "One", "A"), audio(2, "Two", "A"), audio(3, "Three", "B")];
+    for audio in audios.iter() {
+        downloaded(&root, audio.id, "audio/wav", &[audio.id as u8; 100]);
+    }
+    let (state, _) = app_state(&root, &audios, Settings::default());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        state.create_smart_playlist("Mix: A".to_string(), vec![Rule::AuthorContains("A".to_string())], RuleMatch::All).await;
+        let playlists = serde_json::to_value(state.create_smart_playlist("Mix/ A".to_string(), Vec::new(), RuleMatch::All).await).unwrap();
+        let id = |name: &str| playlists.as_array().unwrap().iter().find(|playlist| playlist["name"] == name).unwrap()["id"].as_u64().unwrap() as u32;
+        let ids = vec![id("Mix: A"), id("Mix/ A")];
+        let sync = |playlists: Vec<u32>| {
+            let state = state.clone();
+            let device = device.to_str().unwrap().to_string();
+            async move {
+                let report = serde_json::to_value(state.sync_device(device, playlists, formats::DEFAULT_TEMPLATE.to_string()).await.unwrap()).unwrap();
+                assert!(report["failed"].as_array().unwrap().is_empty());
+                (report["copied"].as_u64().unwrap(), report["unchanged"].as_u64().unwrap(), report["removed"].as_u64().unwrap())
+            }
+        };
+
+        assert_eq!(sync(ids.clone()).await, (3, 0, 0));
+        assert_eq!(std::fs::read(device.join("B").join("Three.wav")).unwrap(), [3; 100]);
+        // Playlist names sanitized the same way get their own files.
+        let first = std::fs::read_to_string(device.join("Mix_ A.m3u8")).unwrap();
+        let second = std::fs::read_to_string(device.join("Mix_ A (2).m3u8")).unwrap();
+        assert_ne!(first.contains("B/Three.wav"), second.contains("B/Three.wav"));
+
+        assert_eq!(sync(ids.clone()).await, (0, 3, 0));
+
+        state.update_metadata(1, "Uno".to_string(), "A".to_string(), None, Vec::new()).await.unwrap();
+        assert_eq!(sync(ids.clone()).await, (1, 2, 0));
+        assert!(!device.join("A").join("One.wav").exists());
+        assert_eq!(std::fs::read(device.join("A").join("Uno.wav")).unwrap(), [1; 100]);
+
+        // Only the audios and playlists the manifest knows of are removed.
+        std::fs::write(device.join("B").join("Other.wav"), [0; 10]).unwrap();
+        assert_eq!(sync(vec![ids[0]]).await, (0, 2, 1));
+        assert!(!device.join("B").join("Three.wav").exists());
+        assert!(device.join("B").join("Other.wav").exists());
+        assert!(!std::fs::read_to_string(device.join("Mix_ A.m3u8")).unwrap().contains("B/Three.wav"));
+        assert!(!device.join("Mix_ A (2).m3u8").exists());
+
+        // Paths of an edited manifest can't reach outside the device.
+        let manifest_path = device.join(".furplayer-sync.json");
+        let mut manifest: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        manifest["audios"]["2"]["path"] = serde_json::json!("../outside.wav");
+        manifest["playlists"] = serde_json::json!(["/outside.m3u8", "../outside.m3u8"]);
+        std::fs::write(&manifest_path, manifest.to_string()).unwrap();
+        std::fs::write(root.join("outside.wav"), [0; 10]).unwrap();
+        std::fs::write(root.join("outside.m3u8"), "").unwrap();
+        assert_eq!(sync(Vec::new()).await, (0, 0, 1));
+        assert!(!device.join("A").join("Uno.wav").exists());
+        assert!(root.join("outside.wav").exists() && root.join("outside.m3u8").exists());
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+fn mp4_atom(kind: &[u8], content: &[u8]) -> Vec<u8> {
+    let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+    atom.extend_from_slice(kind);
+    atom.extend_from_slice(content);
+    atom
+}
+
+/// Flags, granule and data of the pages of an Ogg stream.
+fn ogg_pages(ogg: &[u8]) -> Vec<(u8, u64, Vec<u8>)> {
+    let mut pages = Vec::new();
+    let mut at = 0;
+    while at < ogg.len() {
+        assert_eq!(&ogg[at..at + 4], b"OggS");
+        let segments = ogg[at + 26] as usize;
+        let size: usize = ogg[at + 27..at + 27 + segments].iter().map(|size| *size as usize).sum();
+        let end = at + 27 + segments + size;
+        pages.push((ogg[at + 5], u64::from_le_bytes(ogg[at + 6..at + 14].try_into().unwrap()), ogg[at + 27 + segments..end].to_vec()));
+        at = end;
+    }
+    pages
+}
+
+#[test]
+fn tagging_test() {
+    let dir = env::temp_dir().join(format!("furplayer-tagging-{}", rand::random::<u32>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let tags = Tags { title: "Title".to_string(), artist: "Artist".to_string(), album: None, cover: None };
+
+    // WebM with three 20 ms Opus packets, the payload isn't decoded.
+    let packets: Vec<Vec<u8>> = [300usize, 600, 20].iter().map(|size| {
+        let mut packet = vec![0xfc];
+        packet.resize(*size, 0x55);
+        packet
+    }).collect();
+    std::fs::write(dir.join("media.webm"), webm(2, 312, &packets)).unwrap();
+
+    let tagged = tagging::tag(&dir.join("media.webm"), "audio/webm", &tags).unwrap();
+    assert_eq!(tagged.extension, "opus");
+    let pages = ogg_pages(&tagged.bytes);
+    assert_eq!(pages.len(), 3);
+    assert_eq!(pages[0].0, 0x02);
+    assert!(pages[0].2.starts_with(b"OpusHead"));
+    assert!(pages[1].2.windows(11).any(|window| window == b"TITLE=Title"));
+    assert_eq!(pages[2].0, 0x04);
+    assert_eq!(pages[2].1, 3 * 960);
+    assert_eq!(pages[2].2.len(), 300 + 600 + 20);
+
+    // 300 packets of a segment each fill a page, the next one starts on a
+    // fresh page instead of continuing.
+    std::fs::write(dir.join("media.webm"), webm(2, 312, &vec![vec![0xfc]; 300])).unwrap();
+    let pages = ogg_pages(&tagging::tag(&dir.join("media.webm"), "audio/webm", &tags).unwrap().bytes);
+    assert_eq!(pages.len(), 4);
+    assert_eq!((pages[2].0, pages[2].1, pages[2].2.len()), (0x00, 255 * 960, 255));
+    assert_eq!((pages[3].0, pages[3].1, pages[3].2.len()), (0x04, 300 * 960, 45));
+
+    // A chapter from 50 to 100 ms starts two packets early to settle the
+    // decoder and skips into the packet at 40 ms.
+    std::fs::write(dir.join("media.webm"), webm(2, 312, &vec![vec![0xfc]; 10])).unwrap();
+    let tagged = tagging::tag_part(&dir.join("media.webm"), "audio/webm", &tags, 0.05, 0.1).unwrap();
+    let pages = ogg_pages(&tagged.bytes);
+    assert_eq!(u16::from_le_bytes([pages[0].2[10], pages[0].2[11]]), 2 * 960 + 480);
+    assert_eq!((pages[2].0, pages[2].1, pages[2].2.len()), (0x04, 5 * 960, 5));
+    assert!(matches!(tagging::tag_part(&dir.join("media.webm"), "audio/mp4", &tags, 0.05, 0.1), Err(tagging::Error::CutUnsupported)));
+
+    // Minimal MP4 with the chunk offset pointing at the `mdat` payload.
+    let payload = b"payload".to_vec();
+    let ftyp = mp4_atom(b"ftyp", b"M4A \0\0\0\0");
+    let moov_size = 5 * 8 + 20;
+    let offset = (ftyp.len() + moov_size + 8) as u32;
+    let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+    stco.extend_from_slice(&offset.to_be_bytes());
+    let stbl = mp4_atom(b"stbl", &mp4_atom(b"stco", &stco));
+    let moov = mp4_atom(b"moov", &mp4_atom(b"trak", &mp4_atom(b"mdia", &mp4_atom(b"minf", &stbl))));
+    assert_eq!(moov.len(), moov_size);
+    let mut mp4 = ftyp.clone();
+    mp4.extend(moov);
+    mp4.extend(mp4_atom(b"mdat", &payload));
+    std::fs::write(dir.join("media.m4a"), mp4).unwrap();
+
+    let tagged = tagging::tag(&dir.join("media.m4a"), "audio/mp4", &tags).unwrap();
+    let m4a = tagged.bytes;
+    assert!(m4a.windows(5).any(|window| window == b"Title"));
+    let stco = m4a.windows(4).position(|window| window == b"stco").unwrap();
+    let offset = u32::from_be_bytes(m4a[stco + 12..stco + 16].try_into().unwrap()) as usize;
+    assert_eq!(&m4a[offset..offset + payload.len()], payload.as_slice());
+
+    // A 64-bit atom size reaching past the end of memory is rejected.
+    let mut damaged = ftyp.clone();
+    damaged.extend_from_slice(&[0, 0, 0, 1]);
+    damaged.extend_from_slice(b"moov");
+    damaged.extend_from_slice(&(u64::MAX - 4).to_be_bytes());
+    std::fs::write(dir.join("media.m4a"), damaged).unwrap();
+    assert!(matches!(tagging::tag(&dir.join("media.m4a"), "audio/mp4", &tags), Err(tagging::Error::Invalid)));
+
+    // MP3 with an old tag that gets replaced.
+    let mut mp3 = b"ID3\x03\x00\x00\x00\x00\x00\x02\x00\x00".to_vec();
+    mp3.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+    std::fs::write(dir.join("media.mp3"), mp3).unwrap();
+
+    let tagged = tagging::tag(&dir.join("media.mp3"), "audio/mpeg", &tags).unwrap();
+    assert_eq!(tagged.extension, "mp3");
+    let mp3 = tagged.bytes;
+    assert_eq!(&mp3[..4], b"ID3\x03");
+    let size = mp3[6..10].iter().fold(0, |size, byte| (size << 7) | *byte as usize);
+    assert!(mp3[10..10 + size].windows(4).any(|window| window == b"TIT2"));
+    assert_eq!(&mp3[10 + size..], &[0xff, 0xfb, 0x90, 0x00]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn backup_test() {
+    let root = env::temp_dir().join("furplayer_backup_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let audio_dir = root.join("audios");
+    std::fs::create_dir_all(audio_dir.join("1")).unwrap();
+    std::fs::write(audio_dir.join("1").join("index.json"), "{}").unwrap();
+    std::fs::write(audio_dir.join("1").join("media.m4a"), vec![7u8; 4096]).unwrap();
+    std::fs::create_dir_all(audio_dir.join("2")).unwrap();
+    std::fs::write(audio_dir.join("2").join("index.json"), "{}").unwrap();
+
+    let audios = vec![Audio::create("Title".to_string(), "Author".to_string(), Source::YouTube("https://www.youtube.com/watch?v=1".to_string()))];
+    let playlist = audio::audios_to_json(&audios).unwrap();
+    let settings = settings::to_json(&Settings::default()).unwrap();
+    let manifest = Manifest { format: backup::FORMAT.to_string(), version: backup::VERSION, created_at: 1, include_media: true };
+    let path = root.join("library.zip");
+    let files = [(backup::PLAYLIST, playlist.clone()), (backup::SETTINGS, settings.clone())];
+    backup::write(&path, &manifest, &files, Some((&audio_dir, &[1]))).unwrap();
+    assert!(!path.with_extension("partial").exists());
+
+    let restored = backup::read(&path).unwrap();
+    assert_eq!(restored.manifest, manifest);
+    assert_eq!(restored.files[backup::PLAYLIST], playlist);
+    assert_eq!(restored.files[backup::SETTINGS], settings);
+    assert_eq!(audio::audios_from_json(&restored.files[backup::PLAYLIST]).unwrap()[0].id, audios[0].id);
+
+    let target = root.join("restored");
+    let extracted = backup::extract_media(&path, &target, &[(1, 5), (2, 2)].into_iter().collect()).unwrap();
+    assert_eq!(extracted, vec![5]);
+    assert_eq!(std::fs::read(target.join("5").join("media.m4a")).unwrap(), vec![7u8; 4096]);
+    assert!(!target.join("1").exists() && !target.join("2").exists());
+
+    // Merged audios keep their id, take the one of the same track in the
+    // library or get a fresh one when another track has theirs.
+    let track = |id: u32, url: &str| Audio { id, ..Audio::create(String::new(), String::new(), Source::YouTube(url.to_string())) };
+    let library = [track(1, "a"), track(2, "b")];
+    let ids = backup::merge_ids(&library, &[track(1, "c"), track(7, "b"), track(3, "d")]);
+    assert_eq!((ids[&7], ids[&3]), (2, 3));
+    assert!(![1, 2, 3].contains(&ids[&1]));
+
+    let newer = Manifest { version: backup::VERSION + 1, ..manifest.clone() };
+    backup::write(&path, &newer, &files, None).unwrap();
+    assert!(matches!(backup::read(&path).unwrap_err(), backup::Error::UnsupportedVersion));
+    backup::write(&path, &manifest, &[], None).unwrap();
+    assert!(matches!(backup::read(&path).unwrap_err(), backup::Error::InvalidArchive));
+    std::fs::write(&path, "not a zip").unwrap();
+    assert!(matches!(backup::read(&path).unwrap_err(), backup::Error::InvalidArchive));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+fn sine(frequency: f64, amplitude: f64, seconds: f64, sample_rate: u32) -> Vec<f32> {
+    (0..(seconds * sample_rate as f64) as usize)
+        .map(|n| (amplitude * (2.0 * std::f64::consts::PI * frequency * n as f64 / sample_rate as f64).sin()) as f32)
+        .collect()
+}
+
+#[test]
+fn loudness_test() {
+    // A 1 kHz sine on one channel reads 3.01 dB below its peak level.
+    let mut meter = Meter::new(1, 48000);
+    meter.push(&sine(1000.0, 0.5, 5.0, 48000));
+    let track = meter.finish();
+    assert!((track.integrated.unwrap() - -9.03).abs() < 0.05);
+    assert!((track.true_peak - 0.5).abs() < 0.01);
+    assert!((track.gain() - (loudness::REFERENCE + 9.03)).abs() < 0.05);
+
+    // Silence is gated out, chunk sizes don't matter.
+    let mut meter = Meter::new(2, 44100);
+    let stereo: Vec<f32> = sine(1000.0, 0.5, 5.0, 44100).iter().flat_map(|sample| [*sample, *sample]).collect();
+    for chunk in stereo.chunks(999) {
+        meter.push(chunk);
+    }
+    meter.push(&vec![0.0; 44100 * 2 * 5]);
+    let stereo = meter.finish();
+    // The blocks overlapping the start of the silence pass the relative gate and pull it down a little.
+    assert!((stereo.integrated.unwrap() - -6.02).abs() < 0.2);
+
+    // A quarter sample rate sine sampled 45 degrees off its peaks, the signal overshoots the samples.
+    let mut meter = Meter::new(1, 48000);
+    meter.push(&(0..48000).map(|n| if n % 4 < 2 { 0.8 } else { -0.8 }).collect::<Vec<f32>>());
+    let clipped = meter.finish();
+    assert!(clipped.true_peak > 0.8 * 1.1);
+    assert!(clipped.gain() <= -20.0 * clipped.true_peak.log10());
+
+    let album = Loudness::combine(&[track.clone(), stereo.clone()]);
+    assert!(album.integrated.unwrap() > -9.03 && album.integrated.unwrap() < -6.02);
+    assert_eq!(album.true_peak, track.true_peak.max(stereo.true_peak));
+    assert!((Loudness::combine(std::slice::from_ref(&track)).integrated.unwrap() - track.integrated.unwrap()).abs() < 0.1);
+
+    let silent = Meter::new(2, 48000).finish();
+    assert_eq!(silent.integrated, None);
+    assert_eq!(silent.gain(), 0.0);
+
+    // Streams without a channel count are read as mono.
+    let mut meter = Meter::new(0, 48000);
+    meter.push(&[0.5; 480]);
+    assert_eq!(meter.finish().integrated, None);
+}
+
+#[test]
+fn waveform_test() {
+    let mut meter = PeakMeter::new(2, 8000);
+    let quiet: Vec<f32> = sine(100.0, 0.25, 30.0, 8000).iter().flat_map(|sample| [*sample, -*sample]).collect();
+    let loud: Vec<f32> = sine(100.0, 1.0, 30.0, 8000).iter().flat_map(|sample| [*sample, 0.0]).collect();
+    for chunk in quiet.chunks(1000).chain(loud.chunks(777)) {
+        meter.push(chunk);
+    }
+    let measured = meter.finish();
+    assert_eq!(measured.peaks.len(), waveform::RESOLUTION);
+
+    let halves = measured.downsample(2);
+    assert!((halves[0].0 - -0.25).abs() < 0.01 && (halves[0].1 - 0.25).abs() < 0.01);
+    assert!((halves[1].0 - -1.0).abs() < 0.01 && (halves[1].1 - 1.0).abs() < 0.01);
+    assert_eq!(measured.downsample(waveform::RESOLUTION * 2).len(), waveform::RESOLUTION);
+    assert!(measured.downsample(0).is_empty());
+
+    let bytes = measured.to_bytes();
+    assert_eq!(bytes.len(), 9 + waveform::RESOLUTION * 2);
+    let stored = Waveform::from_bytes(&bytes).unwrap();
+    assert_eq!(stored.peaks.len(), measured.peaks.len());
+    for (stored, measured) in stored.peaks.iter().zip(measured.peaks.iter()) {
+        assert!(stored.0 <= measured.0 && stored.1 >= measured.1);
+        assert!(measured.0 - stored.0 < 0.01 && stored.1 - measured.1 < 0.01);
+    }
+    assert_eq!(Waveform::from_bytes(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(Waveform::from_bytes(b"FPWF"), None);
+
+    let short = PeakMeter::new(1, 8000);
+    assert!(short.finish().peaks.is_empty());
+}
+
+#[test]
+fn silence_test() {
+    let tone = sine(440.0, 0.5, 10.0, 8000);
+    let hiss = sine(440.0, 0.001, 1.0, 8000);
+    let silent = |seconds: f64| vec![0.0f32; (seconds * 8000.0) as usize];
+
+    let mut detector = SilenceDetector::new(1, 8000, -50.0, 1.0);
+    for chunk in [silent(2.5), hiss.clone(), tone.clone(), silent(3.0)].concat().chunks(333) {
+        detector.push(chunk);
+    }
+    let detected = detector.finish();
+    assert!((detected.start - 3.5).abs() < 0.02, "{}", detected.start);
+    assert!((detected.end.unwrap() - 13.5).abs() < 0.02);
+    assert!(detected.detected_with(-50.0, 1.0));
+    assert!(!detected.detected_with(-40.0, 1.0));
+
+    // Silence shorter than the minimum length is played.
+    let mut detector = SilenceDetector::new(1, 8000, -50.0, 1.0);
+    detector.push(&[silent(0.5), tone.clone(), silent(0.5)].concat());
+    let detected = detector.finish();
+    assert_eq!(detected.start, 0.0);
+    assert_eq!(detected.end, None);
+
+    // A threshold below the hiss, at about -63 dB, counts it as audio.
+    let mut detector = SilenceDetector::new(2, 8000, -80.0, 1.0);
+    let stereo: Vec<f32> = [silent(2.0), hiss, tone].concat().iter().flat_map(|sample| [*sample, *sample]).collect();
+    detector.push(&stereo);
+    assert!((detector.finish().start - 2.0).abs() < 0.02);
+
+    let mut detector = SilenceDetector::new(1, 8000, -50.0, 1.0);
+    detector.push(&silent(5.0));
+    let detected = detector.finish();
+    assert_eq!((detected.start, detected.end), (0.0, None));
+}
+
+#[test]
+fn chapters_test() {
+    let mut mix = Audio::create("Mix".to_string(), "DJ".to_string(), Source::YouTube("https://www.youtube.com/watch?v=1".to_string()));
+    mix.duration = Some(600.0);
+    mix.chapters = vec![
+        Chapter { title: "Intro".to_string(), start: 0.0, end: 200.0 },
+        Chapter { title: "Second".to_string(), start: 200.0, end: 600.0 },
+    ];
+    let track = Audio::from_chapter(&mix, &mix.chapters[1]);
+    assert_ne!(track.id, mix.id);
+    assert_eq!(track.title, "Second");
+    assert_eq!(track.album.as_deref(), Some("Mix"));
+    assert_eq!(track.duration, Some(400.0));
+    assert_eq!(track.media_id(), mix.id);
+    assert_eq!(mix.media_id(), mix.id);
+
+    let restored = audio::audios_from_json(&audio::audios_to_json(&[mix.clone(), track.clone()]).unwrap()).unwrap();
+    assert_eq!(restored[0].chapters, mix.chapters);
+    assert_eq!(restored[1].segment, track.segment);
+
+    let waveform = Waveform { peaks: (0..600).map(|n| (-(n as f32) / 600.0, n as f32 / 600.0)).collect() };
+    let part = waveform.slice(200.0 / 600.0, 1.0);
+    assert_eq!(part.peaks.len(), 400);
+    assert_eq!(part.peaks[0], waveform.peaks[200]);
+    assert_eq!(waveform.slice(0.5, 0.5).peaks.len(), 1);
+    assert!(Waveform::default().slice(0.0, 1.0).peaks.is_empty());
+}
+
+#[test]
+fn transcode_test() {
+    assert_eq!(transcode::mime(Codec::Mp3), "audio/mpeg");
+    assert!(transcode::is_target("audio/mp4", Codec::Aac));
+    assert!(transcode::is_target("audio/mpeg; charset=binary", Codec::Mp3));
+    assert!(!transcode::is_target("audio/webm; codecs=opus", Codec::Mp3));
+    assert!(!transcode::is_target("audio/aac", Codec::Aac));
+
+    let mut settings = Settings { transcode: Some(Transcode { codec: Codec::Mp3, bitrate: 192 }), ..Default::default() };
+    assert!(settings.validate().is_ok());
+    let restored = settings::from_json(&settings::to_json(&settings).unwrap()).unwrap();
+    assert_eq!(restored.transcode, settings.transcode);
+    settings.transcode = Some(Transcode { codec: Codec::Aac, bitrate: 16 });
+    assert!(matches!(settings.validate().unwrap_err(), settings::Error::InvalidBitrate));
+
+    let result = transcode::transcode(b"not audio".to_vec(), "audio/webm", Transcode { codec: Codec::Mp3, bitrate: 192 }, |_, _| true);
+    assert!(matches!(result.unwrap_err(), transcode::Error::Decoder(_)));
+
+    // The sine of `wav` comes back at its level and pitch, 22.05 kHz is
+    // resampled for MP3.
+    for (codec, rate, encoded_rate) in [(Codec::Mp3, 44100, 44100), (Codec::Mp3, 22050, 44100), (Codec::Aac, 48000, 48000)] {
+        let encoded = transcode::transcode(wav(2.0, rate), "audio/wav", Transcode { codec, bitrate: 128 }, |_, _| true).unwrap();
+        let mut decoder = Decoder::open(encoded, transcode::mime(codec)).unwrap();
+        let mut samples = Vec::new();
+        while let Some(chunk) = decoder.next_chunk().unwrap() {
+            samples.extend(chunk);
+        }
+        assert_eq!(decoder.sample_rate(), encoded_rate);
+        let rate = encoded_rate as f32;
+        assert!(samples.len() as f32 >= 2.0 * rate);
+        let middle = &samples[(0.5 * rate) as usize..(1.5 * rate) as usize];
+        let rms = (middle.iter().map(|sample| sample * sample).sum::<f32>() / middle.len() as f32).sqrt();
+        assert!((rms - 8000.0 / 32768.0 / 2f32.sqrt()).abs() < 0.005);
+        let cycles = middle.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+        assert!((439..=441).contains(&cycles));
+    }
+
+    // Chapters are cut to their second of the media.
+    let cut = transcode::cut(wav(2.0, 48000), "audio/wav", 0.5, 1.5, Transcode { codec: Codec::Aac, bitrate: 128 }).unwrap();
+    let mut decoder = Decoder::open(cut, transcode::mime(Codec::Aac)).unwrap();
+    let mut samples = Vec::new();
+    while let Some(chunk) = decoder.next_chunk().unwrap() {
+        samples.extend(chunk);
+    }
+    assert!((samples.len() as i64 - 48000).abs() < 2048, "{}", samples.len());
+}
+
+#[test]
+fn transcode_conformance_test() {
+    // What Symphonia decodes is compared to the signal, a chord on the left
+    // and on the right bursts starting abruptly every half second that the
+    // encoders must not smear far ahead of them.
+    let reference = |sample_rate: u32| -> Vec<f32> {
+        let rate = sample_rate as f64;
+        let sine = |frequency: f64, n: usize| (2.0 * std::f64::consts::PI * frequency * n as f64 / rate).sin();
+        (0..2 * sample_rate as usize).flat_map(|n| {
+            let since = (n as f64 / rate) % 0.5;
+            let left = 0.3 * sine(440.0, n) + 0.15 * sine(1250.0, n);
+            let right = 0.5 * (-since / 0.02).exp() * sine(1500.0, n);
+            [left as f32, right as f32]
+        }).collect()
+    };
+    for (codec, sample_rate, bitrate) in [(Codec::Mp3, 32000, 64), (Codec::Mp3, 44100, 128), (Codec::Mp3, 48000, 320), (Codec::Mp3, 44100, 32),
+        (Codec::Aac, 16000, 32), (Codec::Aac, 22050, 64), (Codec::Aac, 44100, 128), (Codec::Aac, 48000, 256), (Codec::Aac, 48000, 64)] {
+        let reference = reference(sample_rate);
+        let pcm: Vec<i16> = reference.iter().map(|sample| (sample * 32767.0) as i16).collect();
+        let encoded = transcode::transcode(pcm_wav(&pcm, 2, sample_rate), "audio/wav", Transcode { codec, bitrate }, |_, _| true).unwrap();
+        let mut decoder = Decoder::open(encoded, transcode::mime(codec)).unwrap();
+        let mut decoded = Vec::new();
+        while let Some(chunk) = decoder.next_chunk().unwrap() {
+            decoded.extend(chunk);
+        }
+        assert_eq!((decoder.channels(), decoder.sample_rate()), (2, sample_rate));
+        // The decoded audio starts after the delay of the encoder and decoder filters.
+        let frames = reference.len() / 2;
+        let error = |delay: usize, channel: usize, range: std::ops::Range<usize>| -> (f64, f64) {
+            range.map(|n| {
+                let (expected, actual) = (reference[2 * n + channel] as f64, decoded.get(2 * (n + delay) + channel).copied().unwrap_or(0.0) as f64);
+                (expected * expected, (actual - expected) * (actual - expected))
+            }).fold((0.0, 0.0), |(signal, noise), (s, e)| (signal + s, noise + e))
+        };
+        let middle = frames / 10..frames * 9 / 10;
+        let noise = |delay: usize| error(delay, 0, frames / 2..frames * 3 / 5).1 + error(delay, 1, frames / 2..frames * 3 / 5).1;
+        let delay = (0..2048).min_by(|a, b| noise(*a).total_cmp(&noise(*b))).unwrap();
+        let snr = |channel: usize| {
+            let (signal, noise) = error(delay, channel, middle.clone());
+            10.0 * (signal / noise).log10()
+        };
+        let rate = sample_rate as usize;
+        let rms = |range: std::ops::Range<usize>| (range.clone().map(|n| (decoded.get(2 * (n + delay) + 1).copied().unwrap_or(0.0) as f64).powi(2)).sum::<f64>() / range.len() as f64).sqrt();
+        // Level of the 25 ms before each burst relative to its first 20 ms.
+        let pre_echo = (1..4).map(|burst| burst * rate / 2).map(|onset| rms(onset - rate / 40..onset - rate / 400) / rms(onset..onset + rate / 50)).fold(0.0, f64::max);
+        assert!(decoded.len() >= reference.len() + 2 * delay);
+        // Low bitrates leave more noise, on the bursts most.
+        let minimum = if bitrate < 64 { 20.0 } else { 25.0 };
+        assert!(snr(0) > minimum && snr(1) > minimum, "{codec:?} {sample_rate} Hz {bitrate} kbit/s: {} and {} dB", snr(0), snr(1));
+        assert!(pre_echo < 0.1, "{codec:?} {sample_rate} Hz {bitrate} kbit/s: {pre_echo}");
+    }
+}
+
+#[tokio::test]
+async fn lyrics_test() {
+    let lyrics = Lyrics::parse_lrc("[ar:Band]\n[offset:500]\n[00:12.50]First\n[00:05.00][01:00.00]Chorus\n");
+    assert!(lyrics.is_synced());
+    assert_eq!(lyrics.lines, vec![
+        Line { time: Some(4.5), text: "Chorus".to_string() },
+        Line { time: Some(12.0), text: "First".to_string() },
+        Line { time: Some(59.5), text: "Chorus".to_string() },
+    ]);
+    assert_eq!(Lyrics::parse_lrc(&lyrics.to_lrc()), lyrics);
+    let plain = Lyrics::parse_lrc("\n[Chorus]\nLa la\n\n[Verse: Artist]\n");
+    assert!(!plain.is_synced());
+    assert_eq!(plain.lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>(), vec!["[Chorus]", "La la", "", "[Verse: Artist]"]);
+
+    let part = lyrics.slice(10.0, 50.0);
+    assert_eq!(part.lines, vec![
+        Line { time: Some(0.0), text: "Chorus".to_string() },
+        Line { time: Some(2.0), text: "First".to_string() },
+    ]);
+
+    let vtt = "WEBVTT\nKind: captions\n\n00:00:01.000 --> 00:00:03.000 align:start\n \nhello<00:00:01.500><c> world</c>\n\n\
+        00:00:03.000 --> 00:00:03.010\nhello world\n \n\n00:00:03.010 --> 00:00:05.000\nhello world\nrock &amp; roll\n";
+    assert_eq!(Lyrics::parse_vtt(vtt).lines, vec![
+        Line { time: Some(1.0), text: "hello world".to_string() },
+        Line { time: Some(3.01), text: "rock & roll".to_string() },
+    ]);
+
+    // Captions in the language of the video win, uploaded before automatic ones.
+    let subtitle = |language: &str, automatic: bool, original: bool| Subtitle { language: language.to_string(), url: String::new(), automatic, original };
+    let subtitles = vec![subtitle("en", false, false), subtitle("ja-orig", true, true), subtitle("ja", false, true)];
+    assert_eq!(Subtitle::preferred(&subtitles).map(|subtitle| subtitle.language.as_str()), Some("ja"));
+    assert_eq!(Subtitle::preferred(&subtitles[..2]).map(|subtitle| subtitle.language.as_str()), Some("ja-orig"));
+
+    let root = env::temp_dir().join("furplayer_lyrics_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let downloader = FileDownloader::new(root.join("audios").to_str().unwrap().to_string(), root.join("downloading").to_str().unwrap().to_string());
+    assert!(downloader.save_user_file(1, LYRICS_FILE, b"[00:01.00]Hi").await.is_ok());
+    std::fs::write(root.join("audios").join("1").join("media.weba"), "12345").unwrap();
+    let index = r#"{"media_mime":"audio/webm","thumbnail_mime":"image/jpeg","media_size":5}"#;
+    std::fs::write(root.join("audios").join("1").join("index.json"), index).unwrap();
+    assert_eq!(downloader.size(1).await, 5 + index.len() as u64);
+    assert!(downloader.evict(1).await.is_ok());
+    assert_eq!(downloader.size(1).await, 0);
+    assert_eq!(downloader.load_user_file(1, LYRICS_FILE).await.unwrap(), b"[00:01.00]Hi");
+    assert!(downloader.verify(&[1]).await.broken().is_empty());
+    assert!(downloader.remove_user_file(1, LYRICS_FILE).await.is_ok());
+    assert!(!root.join("audios").join("1").exists());
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+/// Chords of three notes with overtones, each lasting half a second, picked
+/// from `seed` so different seeds give different music.
+fn song(seed: u64, seconds: f64, sample_rate: u32) -> Vec<f32> {
+    let mut state = seed;
+    let mut samples = Vec::new();
+    while (samples.len() as f64) < seconds * sample_rate as f64 {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let root = 48 + (state >> 33) % 24;
+        let notes = [root, root + 4 - (state >> 20) % 2, root + 7];
+        let length = sample_rate as usize / 2;
+        for n in 0..length {
+            let t = n as f64 / sample_rate as f64;
+            let sample: f64 = notes.iter().map(|note| {
+                let frequency = 440.0 * 2f64.powf((*note as f64 - 69.0) / 12.0);
+                (1..4).map(|harmonic| (2.0 * std::f64::consts::PI * frequency * harmonic as f64 * t).sin() / harmonic as f64).sum::<f64>()
+            }).sum();
+            samples.push((sample * 0.1) as f32);
+        }
+    }
+    samples
+}
+
+fn fingerprint(samples: &[f32], sample_rate: u32) -> Fingerprint {
+    let mut chromagram = Chromagram::new(1, sample_rate);
+    for chunk in samples.chunks(1000) {
+        chromagram.push(chunk);
+    }
+    chromagram.finish()
+}
+
+#[test]
+fn fingerprint_test() {
+    let original = fingerprint(&song(1, 30.0, 44100), 44100);
+    assert_eq!(original.duration(), 30.0);
+    assert_eq!(original.similarity(&original), 1.0);
+
+    // Another upload: resampled, quieter, with noise and a different intro.
+    let mut noise = 7u64;
+    let mut upload: Vec<f32> = song(9, 7.3, 48000);
+    upload.extend(song(1, 30.0, 48000).iter().map(|sample| {
+        noise = noise.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        sample * 0.5 + ((noise >> 40) as f32 / (1u64 << 24) as f32 - 0.5) * 0.02
+    }));
+    let upload = fingerprint(&upload, 48000);
+    let other = fingerprint(&song(2, 30.0, 44100), 44100);
+    assert!(original.similarity(&upload) >= fingerprint::THRESHOLD);
+    assert!(original.similarity(&other) < fingerprint::THRESHOLD);
+    assert_eq!(original.similarity(&Fingerprint::default()), 0.0);
+}
+
+#[test]
+fn history_reassign_test() {
+    let mut history = History::new(vec![
+        Listen { audio_id: 1, started_at: 100, listened: 5.0, completed: false },
+        Listen { audio_id: 2, started_at: 200, listened: 60.0, completed: true },
+        Listen { audio_id: 3, started_at: 300, listened: 2.0, completed: false },
+    ]);
+    history.begin(2, 400, 0.0);
+    history.progress(2, 0.5);
+    history.reassign(2, 1);
+    assert_eq!(history.listens().iter().map(|listen| listen.audio_id).collect::<Vec<_>>(), vec![1, 1, 3]);
+    let stats = history.stats(1);
+    assert_eq!((stats.skip_count, stats.listened), (1, 65.0));
+    assert_eq!((history.stats(2).skip_count, history.stats(2).listened), (0, 0.0));
+    assert_eq!((history.stats(3).skip_count, history.stats(3).listened), (1, 2.0));
+
+    // The listen still playing counts for the audio it moved to.
+    let finished = history.finish(true).unwrap();
+    assert_eq!((finished.audio_id, finished.started_at), (1, 400));
+    assert_eq!(history.stats(1).listened, 65.5);
+}
+
+#[test]
+fn merge_duplicates_test() {
+    let root = env::temp_dir().join("furplayer_merge_duplicates_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let audio = |id: u32, tags: &[&str], pinned: bool, play_count: u32, last_played: Option<u64>| Audio {
+        id,
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        pinned,
+        play_count,
+        last_played,
+        ..Audio::create(String::new(), String::new(), Source::YouTube(String::new()))
+    };
+    let audios = [audio(1, &["a"], false, 2, Some(100)), audio(2, &["a", "b"], true, 3, Some(50)), audio(3, &["c"], false, 1, None), audio(4, &[], false, 0, None)];
+    downloaded(&root, 2, "audio/wav", &[2; 100]);
+    std::fs::write(root.join("audios").join("2").join("fingerprint.json"), r#"{"codes":[1,2,3]}"#).unwrap();
+    downloaded(&root, 4, "audio/wav", &[4; 100]);
+    std::fs::write(root.join("audios").join("4").join("fingerprint.json"), r#"{"codes":[]}"#).unwrap();
+    let (state, _) = app_state(&root, &audios, Settings::default());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        // Empty fingerprints of short audios aren't compared.
+        let duplicates = serde_json::to_value(state.find_duplicates().await.unwrap()).unwrap();
+        let unchecked: Vec<u64> = duplicates["unchecked"].as_array().unwrap().iter().map(|audio| audio["id"].as_u64().unwrap()).collect();
+        assert_eq!(unchecked, vec![1, 3, 4]);
+
+        state.set_lyrics(2, "[00:01.00]Two".to_string()).await.unwrap();
+        state.set_lyrics(3, "[00:02.00]Three".to_string()).await.unwrap();
+        let merged = serde_json::to_value(state.merge_duplicates(1, vec![2, 3, 1]).await.unwrap()).unwrap();
+        assert_eq!(merged["tags"], serde_json::json!(["a", "b", "c"]));
+        assert_eq!((merged["pinned"].as_bool(), merged["play_count"].as_u64(), merged["last_played"].as_u64()), (Some(true), Some(6), Some(100)));
+
+        // The download and its analyses move to the kept audio, the others are gone.
+        assert_eq!(merged["has_file"].as_bool(), Some(true));
+        assert_eq!(std::fs::read(root.join("audios").join("1").join("media.wav")).unwrap(), [2; 100]);
+        assert!(root.join("audios").join("1").join("fingerprint.json").exists());
+        assert!(!root.join("audios").join("2").exists() && !root.join("audios").join("3").exists());
+        assert!(state.get_lyrics(2).await.is_err() && state.get_lyrics(3).await.is_err());
+
+        // Lyrics come from the first merged audio that has some.
+        let lyrics = serde_json::to_value(state.get_lyrics(1).await.unwrap().unwrap()).unwrap();
+        assert_eq!(lyrics["lines"][0]["text"], "Two");
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+/// Triads of MIDI notes, each held for two seconds.
+fn chords(progression: &[[u32; 3]], sample_rate: u32) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for notes in progression {
+        for n in 0..sample_rate as usize * 2 {
+            let t = n as f64 / sample_rate as f64;
+            let sample: f64 = notes.iter().map(|note| {
+                let frequency = 440.0 * 2f64.powf((*note as f64 - 69.0) / 12.0);
+                (1..4).map(|harmonic| (2.0 * std::f64::consts::PI * frequency * harmonic as f64 * t).sin() / harmonic as f64).sum::<f64>()
+            }).sum();
+            samples.push((sample * 0.05) as f32);
+        }
+    }
+    samples
+}
+
+/// Decaying noise bursts on every beat mixed into the samples.
+fn add_beats(samples: &mut [f32], bpm: f64, sample_rate: u32) {
+    let period = 60.0 / bpm * sample_rate as f64;
+    let mut noise = 3u64;
+    let mut beat = 0.0;
+    while (beat as usize) < samples.len() {
+        for (n, sample) in samples[beat as usize..].iter_mut().take(sample_rate as usize / 20).enumerate() {
+            noise = noise.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let decay = (-(n as f32) / (sample_rate as f32 / 200.0)).exp();
+            *sample += ((noise >> 40) as f32 / (1u64 << 24) as f32 - 0.5) * decay;
+        }
+        beat += period;
+    }
+}
+
+fn detect(samples: &[f32], sample_rate: u32) -> (Option<f64>, Option<Key>) {
+    let mut meter = BpmKeyMeter::new(1, sample_rate);
+    for chunk in samples.chunks(1000) {
+        meter.push(chunk);
+    }
+    meter.finish()
+}
+
+#[test]
+fn bpm_key_test() {
+    // C F G C and Am Dm E Am, played four times.
+    let major = chords(&[[60, 64, 67], [65, 69, 72], [67, 71, 74], [60, 64, 67]].repeat(4), 22050);
+    let minor = chords(&[[57, 60, 64], [62, 65, 69], [64, 68, 71], [57, 60, 64]].repeat(4), 44100);
+    let c_major = Key { tonic: 0, mode: Mode::Major };
+    let a_minor = Key { tonic: 9, mode: Mode::Minor };
+
+    let mut samples = major.clone();
+    add_beats(&mut samples, 128.0, 22050);
+    let (bpm, key) = detect(&samples, 22050);
+    assert!((bpm.unwrap() - 128.0).abs() <= 1.0, "{bpm:?}");
+    assert_eq!(key, Some(c_major));
+    let mut samples = minor.clone();
+    add_beats(&mut samples, 87.0, 44100);
+    let (bpm, key) = detect(&samples, 44100);
+    assert!((bpm.unwrap() - 87.0).abs() <= 1.0, "{bpm:?}");
+    assert_eq!(key, Some(a_minor));
+    assert_eq!(detect(&vec![0.0; 22050 * 20], 22050), (None, None));
+    assert_eq!(detect(&major[..22050 * 5], 22050).0, None);
+
+    assert_eq!((c_major.camelot(), a_minor.camelot()), ((8, 'B'), (8, 'A')));
+    assert_eq!(Key { tonic: 6, mode: Mode::Major }.camelot(), (2, 'B'));
+    assert!(c_major.is_compatible(&a_minor));
+    assert!(c_major.is_compatible(&Key { tonic: 7, mode: Mode::Major }));
+    assert!(Key { tonic: 11, mode: Mode::Major }.is_compatible(&Key { tonic: 6, mode: Mode::Major }));
+    assert!(!c_major.is_compatible(&Key { tonic: 2, mode: Mode::Major }));
+    assert!(!c_major.is_compatible(&Key { tonic: 4, mode: Mode::Minor }));
+
+    let mut audio = Audio::create("Strobe".to_string(), "deadmau5".to_string(), Source::YouTube("https://www.youtube.com/watch?v=tKi9Z-f6qX4".to_string()));
+    let context = RuleContext { now: audio.added_at.unwrap(), downloaded: true };
+    let warmup = SmartPlaylist::create("Warmup".to_string(), vec![Rule::BpmGreater(120.0), Rule::BpmLess(130.0), Rule::KeyCompatible(a_minor)], RuleMatch::All);
+    assert!(!warmup.matches(&audio, context));
+    audio.bpm = Some(128.0);
+    audio.key = Some(Key { tonic: 4, mode: Mode::Minor });
+    assert!(warmup.matches(&audio, context));
+    assert!(!Rule::KeyIs(a_minor).matches(&audio, context));
+    audio.bpm_key_corrected = true;
+    let restored = audio::audios_from_json(&audio::audios_to_json(&[audio.clone()]).unwrap()).unwrap();
+    assert_eq!((restored[0].bpm, restored[0].key, restored[0].bpm_key_corrected), (audio.bpm, audio.key, true));
+}
+
+#[test]
+fn playlist_sort_test() {
+    let root = env::temp_dir().join("furplayer_playlist_sort_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let audio = |id: u32, bpm: Option<f64>, key: Option<Key>| Audio {
+        id,
+        bpm,
+        key,
+        ..Audio::create(String::new(), String::new(), Source::YouTube(String::new()))
+    };
+    let (a_minor, c_major, e_major) = (Key { tonic: 9, mode: Mode::Minor }, Key { tonic: 0, mode: Mode::Major }, Key { tonic: 4, mode: Mode::Major });
+    let audios = [audio(1, None, Some(e_major)), audio(2, Some(128.0), None), audio(3, Some(90.5), Some(c_major)), audio(4, Some(128.0), Some(a_minor)), audio(5, None, None)];
+    let (state, _) = app_state(&root, &audios, Settings::default());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let ids = |sort: Option<PlaylistSort>| {
+            let state = state.clone();
+            async move {
+                state.get_all_audios(sort).await.unwrap().iter()
+                    .map(|audio| serde_json::to_value(audio).unwrap()["id"].as_u64().unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(ids(None).await, vec![1, 2, 3, 4, 5]);
+        // Audios without a value keep their order at the end, so do ties.
+        assert_eq!(ids(Some(PlaylistSort::Bpm)).await, vec![3, 2, 4, 1, 5]);
+        // 8A, 8B and 12B on the Camelot wheel.
+        assert_eq!(ids(Some(PlaylistSort::Key)).await, vec![4, 3, 1, 2, 5]);
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn jobs_test() {
+    let mut queue = JobQueue::default();
+    for kind in JobKind::AFTER_DOWNLOAD {
+        assert!(queue.push(1, kind).is_some());
+    }
+    assert!(queue.push(1, JobKind::Loudness).is_none());
+    assert_eq!(queue.pending(), JobKind::AFTER_DOWNLOAD.len());
+    let ids: Vec<u32> = queue.jobs().iter().map(|job| job.id).collect();
+    assert_eq!(ids, (0..JobKind::AFTER_DOWNLOAD.len() as u32).collect::<Vec<u32>>());
+
+    let loudness = queue.start_next(0, |_| true).unwrap();
+    assert_eq!((loudness.kind, loudness.state), (JobKind::Loudness, JobState::Running));
+    // Running jobs may be queued again, the media could have changed.
+    assert!(queue.push(1, JobKind::Loudness).is_some());
+    assert_eq!(queue.finish(loudness.id).unwrap().state, JobState::Finished);
+
+    // Failing jobs go to the back, waiting longer every time, until they
+    // ran out of attempts.
+    let waveform = queue.start_next(0, |_| true).unwrap();
+    assert_eq!(waveform.kind, JobKind::Waveform);
+    let mut failed = queue.fail(waveform.id, "Unknown error".to_string(), 0).unwrap();
+    assert_eq!((failed.state, failed.attempts, failed.retry_at), (JobState::Queued, 1, Some(jobs::RETRY_DELAY)));
+    assert_eq!(queue.jobs().last().unwrap().id, waveform.id);
+    failed = queue.fail(waveform.id, "Unknown error".to_string(), 100).unwrap();
+    assert_eq!(failed.retry_at, Some(100 + 2 * jobs::RETRY_DELAY));
+    for _ in 2..jobs::MAX_ATTEMPTS {
+        failed = queue.fail(waveform.id, "Unknown error".to_string(), 0).unwrap();
+    }
+    assert_eq!((failed.state, failed.error.as_deref(), failed.retry_at), (JobState::Failed, Some("Unknown error"), None));
+    assert_eq!(queue.pending(), JobKind::AFTER_DOWNLOAD.len() - 1);
+    let retried = queue.retry(waveform.id).unwrap();
+    assert_eq!((retried.attempts, retried.retry_at), (0, None));
+    assert!(queue.retry(waveform.id).is_none());
+
+    // Jobs running when the app quit run again after restart.
+    let silence = queue.start_next(0, |_| true).unwrap();
+    let path = env::temp_dir().join(format!("furplayer-jobs-{}.json", rand::random::<u32>()));
+    let file = JobsFile(path.to_str().unwrap().to_string());
+    file.save(&queue);
+    let mut restored = file.load().unwrap();
+    assert_eq!(restored, queue);
+    restored.resume();
+    assert_eq!(restored.start_next(0, |_| true).unwrap().id, silence.id);
+    // Ids keep counting after a restart.
+    let next = restored.push(3, JobKind::Lyrics).unwrap();
+    assert!(queue.jobs().iter().all(|job| job.id < next.id));
+    let _ = std::fs::remove_file(path);
+
+    queue.push(2, JobKind::Lyrics);
+    queue.remove_audio(1);
+    assert_eq!(queue.jobs().iter().map(|job| job.audio).collect::<Vec<_>>(), vec![2]);
+
+    // Analyses start while network jobs wait for their slots.
+    let mut queue = JobQueue::default();
+    for kind in [JobKind::Lyrics, JobKind::Duration, JobKind::BpmKey] {
+        queue.push(1, kind);
+    }
+    assert_eq!(queue.start_next(0, |kind| !kind.is_network()).unwrap().kind, JobKind::BpmKey);
+    assert!(queue.start_next(0, |kind| !kind.is_network()).is_none());
+    assert_eq!(queue.start_next(0, |_| true).unwrap().kind, JobKind::Lyrics);
+
+    // A failed job waits for its time while the jobs behind it run.
+    let mut queue = JobQueue::default();
+    queue.push(1, JobKind::Lyrics);
+    queue.push(2, JobKind::Lyrics);
+    let offline = queue.start_next(100, |_| true).unwrap();
+    queue.fail(offline.id, "Offline".to_string(), 100);
+    assert_eq!(queue.next_retry(100), Some(100 + jobs::RETRY_DELAY));
+    assert_eq!(queue.start_next(100, |_| true).unwrap().audio, 2);
+    assert!(queue.start_next(100 + jobs::RETRY_DELAY - 1, |_| true).is_none());
+    assert_eq!(queue.next_retry(100 + jobs::RETRY_DELAY), None);
+    assert_eq!(queue.start_next(100 + jobs::RETRY_DELAY, |_| true).unwrap().id, offline.id);
+}
+
+#[test]
+fn run_jobs_test() {
+    let root = env::temp_dir().join("furplayer_run_jobs_test");
+    let _ = std::fs::remove_dir_all(&root);
+    let downloaded_audio = Audio { id: 1, duration: Some(20.0), ..Audio::create(String::new(), String::new(), Source::YouTube(String::new())) };
+    let streamed_audio = Audio { id: 2, ..Audio::create(String::new(), String::new(), Source::YouTube(String::new())) };
+    downloaded(&root, 1, "audio/wav", &wav(20.0, 8000));
+    // The loudness job was running when the app quit, the waveform job
+    // failed and runs again in two seconds.
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let mut queue = JobQueue::default();
+    queue.push(1, JobKind::Loudness);
+    queue.push(1, JobKind::Waveform);
+    let interrupted = queue.start_next(0, |_| true).unwrap();
+    let waiting = queue.start_next(0, |_| true).unwrap();
+    queue.fail(waiting.id, "Unknown error".to_string(), now + 2 - jobs::RETRY_DELAY);
+    JobsFile(root.join("jobs.json").to_str().unwrap().to_string()).save(&queue);
+
+    let (state, events) = app_state(&root, &[downloaded_audio, streamed_audio], Settings::default());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let jobs = state.get_jobs().await;
+        assert_eq!(jobs.iter().map(|job| (job.id, job.state)).collect::<Vec<_>>(), vec![(interrupted.id, JobState::Queued), (waiting.id, JobState::Queued)]);
+
+        state.start();
+        let mut jobs = Vec::new();
+        for _ in 0..600 {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            jobs = state.get_jobs().await;
+            // The duration job is queued last by the backfill.
+            if jobs.len() == 1 && jobs[0].attempts > 0 && jobs[0].state == JobState::Queued {
+                break;
+            }
+        }
+        // The duration of the audio that isn't downloaded is asked of yt-dlp,
+        // which isn't there, and waits before asking again.
+        assert_eq!(jobs.iter().map(|job| (job.audio, job.kind, job.attempts)).collect::<Vec<_>>(), vec![(2, JobKind::Duration, 1)]);
+        assert!(jobs[0].retry_at.unwrap() > now + jobs::RETRY_DELAY / 2);
+
+        // Each missing analysis was queued once, the resumed one too.
+        let finished: Vec<(u32, JobKind)> = events.0.lock().unwrap().iter().filter_map(|event| match event {
+            Event::Job { job, .. } if job.state == JobState::Finished => Some((job.audio, job.kind)),
+            _ => None,
+        }).collect();
+        for kind in JobKind::AFTER_DOWNLOAD {
+            assert_eq!(finished.iter().filter(|finished| **finished == (1, kind)).count(), 1, "{kind:?}");
+        }
+        assert!(finished.iter().all(|(audio, _)| *audio == 1));
+        for file in ["loudness.json", "waveform.bin", "silence.json", "fingerprint.json"] {
+            assert!(root.join("audios").join("1").join(file).exists(), "{file}");
+        }
+    });
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn thumbnail_test() {
+    // A 16:9 frame letterboxed into 4:3, red on the left and blue on the right.
+    let frame = image::RgbImage::from_fn(160, 120, |x, y| match (y, x) {
+        (0..15 | 105.., _) => image::Rgb([(x % 3) as u8, 0, 0]),
+        (_, 0..80) => image::Rgb([200, 30, 30]),
+        _ => image::Rgb([30, 30, 200]),
+    });
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(frame).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+    let covers = thumbnail::covers(&png).unwrap();
+    assert_eq!(covers.iter().map(|(size, _)| *size).collect::<Vec<_>>(), thumbnail::SIZES.to_vec());
+    for (size, bytes) in covers.iter() {
+        let cover = image::load_from_memory(bytes).unwrap().to_rgb8();
+        assert_eq!(cover.dimensions(), (*size, *size));
+        // No bars left, the middle square keeps both halves.
+        let top_left = cover.get_pixel(2, 2).0;
+        let bottom_right = cover.get_pixel(size - 3, size - 3).0;
+        assert!(top_left[0] > 150 && top_left[2] < 80, "{top_left:?}");
+        assert!(bottom_right[2] > 150 && bottom_right[0] < 80, "{bottom_right:?}");
+    }
+    assert!(thumbnail::covers(b"not an image").is_err());
+
+    assert_eq!(thumbnail::closest(&thumbnail::SIZES, 10), Some(64));
+    assert_eq!(thumbnail::closest(&thumbnail::SIZES, 128), Some(256));
+    assert_eq!(thumbnail::closest(&thumbnail::SIZES, 256), Some(256));
+    assert_eq!(thumbnail::closest(&thumbnail::SIZES, 1024), Some(512));
+    assert_eq!(thumbnail::closest(&[], 64), None);
+    assert_eq!(thumbnail::cover_file(256), "cover_256.jpg");
+}
@@ -0,0 +1,73 @@
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, GrayImage};
+
+/// Sides in pixels of the square covers derived from a thumbnail.
+pub const SIZES: [u32; 3] = [64, 256, 512];
+/// Luma below which a pixel belongs to a letterbox bar.
+const BAR_LUMA: u8 = 24;
+/// Fraction of pixels of a row or column that have to be dark for it to be
+/// part of a bar, JPEG artifacts make a few brighter.
+const BAR_FRACTION: f64 = 0.98;
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    InvalidImage,
+    Unknown,
+}
+
+/// File the cover of a size is stored in, next to the thumbnail.
+pub fn cover_file(size: u32) -> String {
+    format!("cover_{size}.jpg")
+}
+
+/// The smallest of the sizes that is at least `size`, or the largest one.
+pub fn closest(sizes: &[u32], size: u32) -> Option<u32> {
+    sizes.iter().copied().filter(|available| *available >= size).min()
+        .or(sizes.iter().copied().max())
+}
+
+fn is_bar(pixels: impl Iterator<Item = u8>) -> bool {
+    let (mut dark, mut total) = (0, 0);
+    for luma in pixels {
+        total += 1;
+        if luma < BAR_LUMA {
+            dark += 1;
+        }
+    }
+    total > 0 && dark as f64 >= total as f64 * BAR_FRACTION
+}
+
+/// Left, top, width and height of the picture inside the black bars around it.
+/// An image that is all dark is kept whole.
+fn content(luma: &GrayImage) -> (u32, u32, u32, u32) {
+    let (width, height) = luma.dimensions();
+    let row = |y: u32| is_bar((0..width).map(|x| luma.get_pixel(x, y).0[0]));
+    let column = |x: u32| is_bar((0..height).map(|y| luma.get_pixel(x, y).0[0]));
+    let Some(top) = (0..height).find(|y| !row(*y)) else {
+        return (0, 0, width, height);
+    };
+    let bottom = (0..height).rev().find(|y| !row(*y)).unwrap_or(top);
+    let left = (0..width).find(|x| !column(*x)).unwrap_or(0);
+    let right = (0..width).rev().find(|x| !column(*x)).unwrap_or(left);
+    (left, top, right - left + 1, bottom - top + 1)
+}
+
+/// Square JPEG covers of the image with letterbox bars trimmed and the
+/// middle cropped, at every size of [`SIZES`].
+pub fn covers(bytes: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let image = image::load_from_memory(bytes).map_err(|_| Error::InvalidImage)?;
+    let (left, top, width, height) = content(&image.to_luma8());
+    let side = width.min(height);
+    let square = image.crop_imm(left + (width - side) / 2, top + (height - side) / 2, side, side);
+    SIZES.into_iter().map(|size| {
+        let cover = match size == side {
+            true => square.clone(),
+            false => square.resize_exact(size, size, FilterType::Lanczos3),
+        };
+        let mut encoded = Vec::new();
+        JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)
+            .encode_image(&DynamicImage::ImageRgb8(cover.to_rgb8()))
+            .map_err(|_| Error::Unknown)?;
+        Ok((size, encoded))
+    }).collect()
+}
@@ -0,0 +1,219 @@
+use crate::{decoder::{self, Decoder}, settings::{Codec, Transcode}};
+
+// The encoders are our own. The maintained ones are C libraries, LAME, FDK
+// AAC and libopus, whose crates build them with cmake or link them from the
+// system, which the offline build of the app can't. They only write what
+// transcodes need, long blocks at a constant or average bitrate, and
+// `transcode_conformance_test` checks what Symphonia decodes from them.
+mod aac;
+mod mp3;
+mod tables;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Decoder(decoder::Error),
+    Encoder,
+    Canceled,
+}
+
+/// MIME type of the files written for the codec.
+pub fn mime(codec: Codec) -> &'static str {
+    match codec {
+        Codec::Mp3 => "audio/mpeg",
+        Codec::Aac => "audio/mp4",
+    }
+}
+
+/// Whether media of the MIME type already is what the transcode writes.
+pub fn is_target(mime: &str, codec: Codec) -> bool {
+    mime.split(';').next().is_some_and(|mime| mime.trim() == self::mime(codec))
+}
+
+/// Packs values most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    /// Bits in `buffer` not yet written to `bytes`.
+    filled: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.buffer = (self.buffer << bits) | (value as u64 & ((1 << bits) - 1));
+        self.filled += bits;
+        while self.filled >= 8 {
+            self.filled -= 8;
+            self.bytes.push((self.buffer >> self.filled) as u8);
+        }
+        self.buffer &= (1 << self.filled) - 1;
+    }
+
+    /// The bytes written, the last one filled up with zeros.
+    fn finish(mut self) -> Vec<u8> {
+        self.put(0, (8 - self.filled % 8) % 8);
+        self.bytes
+    }
+}
+
+/// Linear interpolation of interleaved samples to another sample rate.
+struct Resampler {
+    channels: usize,
+    /// Input frames per output frame.
+    step: f64,
+    /// Input frames kept for the next chunk, the first one is at `position`
+    /// rounded down.
+    buffer: Vec<f32>,
+    position: f64,
+}
+
+impl Resampler {
+    fn new(channels: usize, from: u32, to: u32) -> Self {
+        Self { channels, step: from as f64 / to as f64, buffer: Vec::new(), position: 0.0 }
+    }
+
+    fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.buffer.extend_from_slice(samples);
+        let frames = self.buffer.len() / self.channels;
+        let mut output = Vec::new();
+        while (self.position as usize) + 1 < frames {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            for channel in 0..self.channels {
+                let (from, to) = (self.buffer[index * self.channels + channel], self.buffer[(index + 1) * self.channels + channel]);
+                output.push(from + (to - from) * fraction);
+            }
+            self.position += self.step;
+        }
+        let consumed = (self.position as usize).min(frames);
+        self.buffer.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+        output
+    }
+
+    /// The frames still due at the end of the input, the last input frame
+    /// is held for them.
+    fn finish(mut self) -> Vec<f32> {
+        if self.buffer.is_empty() {
+            return Vec::new();
+        }
+        let last = self.buffer[self.buffer.len() - self.channels..].to_vec();
+        self.process(&last)
+    }
+}
+
+enum Encoder {
+    Mp3(mp3::Encoder),
+    Aac(aac::Encoder),
+}
+
+impl Encoder {
+    fn new(transcode: Transcode, channels: usize, sample_rate: u32) -> Result<Self, Error> {
+        match transcode.codec {
+            Codec::Mp3 => Ok(Encoder::Mp3(mp3::Encoder::new(channels, sample_rate, transcode.bitrate)?)),
+            Codec::Aac => Ok(Encoder::Aac(aac::Encoder::new(channels, sample_rate, transcode.bitrate)?)),
+        }
+    }
+
+    /// The rate closest to `sample_rate` the codec encodes, multiples of
+    /// 8 kHz go to 48 kHz and others to 44.1 kHz when not supported.
+    fn sample_rate(codec: Codec, sample_rate: u32) -> u32 {
+        let supported = match codec {
+            Codec::Mp3 => &mp3::SAMPLE_RATES[..],
+            Codec::Aac => &aac::SAMPLE_RATES[..],
+        };
+        match supported.contains(&sample_rate) {
+            true => sample_rate,
+            false if sample_rate.is_multiple_of(8000) => 48000,
+            false => 44100,
+        }
+    }
+
+    fn encode(&mut self, samples: &[f32]) {
+        match self {
+            Encoder::Mp3(encoder) => encoder.encode(samples),
+            Encoder::Aac(encoder) => encoder.encode(samples),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Mp3(encoder) => encoder.finish(),
+            Encoder::Aac(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Samples for the encoders, channels past the second are dropped.
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f32> {
+    let kept = channels.min(2);
+    samples.chunks_exact(channels)
+        .flat_map(|frame| frame[..kept].iter())
+        .map(|sample| sample.clamp(-1.0, 1.0))
+        .collect()
+}
+
+/// Decodes the media and encodes it with the codec and bitrate, MP3 as is
+/// and AAC in an MP4 container. `progress` gets the decoded seconds and the
+/// duration when known, and stops the transcode by returning `false`.
+pub fn transcode<P>(bytes: Vec<u8>, mime: &str, transcode: Transcode, progress: P) -> Result<Vec<u8>, Error>
+where
+    P: FnMut(f64, Option<f64>) -> bool,
+{
+    encode(Decoder::open(bytes, mime).map_err(Error::Decoder)?, None, transcode, progress)
+}
+
+/// Encodes the part of the media from `start` to `end` seconds like [`transcode`].
+pub fn cut(bytes: Vec<u8>, mime: &str, start: f64, end: f64, transcode: Transcode) -> Result<Vec<u8>, Error> {
+    let mut decoder = Decoder::open(bytes, mime).map_err(Error::Decoder)?;
+    decoder.seek(start).map_err(Error::Decoder)?;
+    encode(decoder, Some(end), transcode, |_, _| true)
+}
+
+fn encode<P>(mut decoder: Decoder, end: Option<f64>, transcode: Transcode, mut progress: P) -> Result<Vec<u8>, Error>
+where
+    P: FnMut(f64, Option<f64>) -> bool,
+{
+    let duration = decoder.duration();
+    let start = decoder.position();
+    let mut encoder: Option<(Encoder, Option<Resampler>, usize)> = None;
+    let mut reported = 0.0;
+    while let Some(samples) = decoder.next_chunk().map_err(Error::Decoder)? {
+        let position = decoder.position();
+        if end.is_some_and(|end| position >= end) {
+            break;
+        }
+        if encoder.is_none() {
+            let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+            let target = Encoder::sample_rate(transcode.codec, sample_rate);
+            let resampler = (target != sample_rate).then(|| Resampler::new(channels.min(2), sample_rate, target));
+            encoder = Some((Encoder::new(transcode, channels.min(2), target)?, resampler, channels));
+        }
+        let Some((encoder, resampler, channels)) = encoder.as_mut() else {
+            continue;
+        };
+        // Frames of the chunk before `start` or from `end` on are left out.
+        let frame = |time: f64| ((time - position) * decoder.sample_rate() as f64).round().max(0.0) as usize * *channels;
+        let first = frame(start).min(samples.len());
+        let last = end.map_or(samples.len(), |end| frame(end).clamp(first, samples.len()));
+        let samples = to_stereo(&samples[first..last], *channels);
+        match resampler {
+            Some(resampler) => encoder.encode(&resampler.process(&samples)),
+            None => encoder.encode(&samples),
+        }
+        if position - reported >= 1.0 {
+            reported = position;
+            if !progress(position, duration) {
+                return Err(Error::Canceled);
+            }
+        }
+    }
+    let (mut encoder, resampler, _) = encoder.ok_or(Error::Decoder(decoder::Error::Unsupported))?;
+    if let Some(resampler) = resampler {
+        encoder.encode(&resampler.finish());
+    }
+    Ok(encoder.finish())
+}
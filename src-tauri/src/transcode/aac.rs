@@ -0,0 +1,447 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::tagging::mp4::atom;
+
+use super::{tables::{AAC_BANDS, AAC_SCALEFACTOR, AAC_SPECTRUM}, BitWriter, Error};
+
+/// Samples per channel in a frame.
+const FRAME: usize = 1024;
+/// Sample rates by their index in the audio specific config.
+pub const SAMPLE_RATES: [u32; 12] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000];
+/// Bits a frame may use per channel, the input buffer of decoders.
+const MAX_CHANNEL_BITS: usize = 6144;
+/// Largest value the escape codebook codes.
+const MAX_VALUE: u32 = 8191;
+/// Largest value of each spectrum codebook, the last one escapes.
+const CAPACITIES: [u32; 11] = [1, 1, 2, 2, 4, 4, 7, 7, 12, 12, MAX_VALUE];
+/// Rounding of the quantizer, fitted to the distribution of the values.
+const ROUNDING: f32 = 0.4054;
+/// Scalefactor the quantizer steps are relative to.
+const SCALEFACTOR_OFFSET: f32 = 100.0;
+/// Gain that brings samples to the range the decoder scales its output from.
+const SAMPLE_SCALE: f32 = 32768.0;
+
+/// Quantized spectrum of a channel and the codebook of each band.
+struct Quantized {
+    values: Vec<i32>,
+    books: Vec<usize>,
+    bits: usize,
+}
+
+/// AAC-LC encoder of long windows with one scalefactor for all bands and an
+/// average bitrate, writes MP4 files.
+pub struct Encoder {
+    channels: usize,
+    sample_rate: u32,
+    rate_index: usize,
+    bitrate: u32,
+    bands: &'static [usize],
+    window: Vec<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    /// Rotations before and after the FFT of the transform.
+    twiddles: Vec<(Complex<f32>, Complex<f32>)>,
+    /// The last frame of each channel, the transform overlaps it.
+    previous: Vec<Vec<f32>>,
+    /// Interleaved samples waiting for a whole frame.
+    pending: Vec<f32>,
+    /// Bits a frame uses on average.
+    target: f64,
+    /// Bits saved by earlier frames that later ones may use.
+    reservoir: f64,
+    frames: Vec<Vec<u8>>,
+}
+
+impl Encoder {
+    pub fn new(channels: usize, sample_rate: u32, kbps: u32) -> Result<Self, Error> {
+        let rate_index = SAMPLE_RATES.iter().position(|rate| *rate == sample_rate).ok_or(Error::Encoder)?;
+        if !(1..=2).contains(&channels) {
+            return Err(Error::Encoder);
+        }
+        let max_kbps = (MAX_CHANNEL_BITS * channels) as u64 * sample_rate as u64 / FRAME as u64 / 1000;
+        let bitrate = kbps.clamp(8, max_kbps as u32) * 1000;
+        let window = (0..2 * FRAME).map(|n| (PI / (2 * FRAME) as f32 * (n as f32 + 0.5)).sin()).collect();
+        Ok(Self {
+            channels,
+            sample_rate,
+            rate_index,
+            bitrate,
+            bands: AAC_BANDS[rate_index],
+            window,
+            fft: FftPlanner::new().plan_fft_forward(FRAME / 2),
+            twiddles: (0..FRAME / 2).map(|n| (
+                Complex::from_polar(1.0, -PI * (n as f32 + 0.25) / FRAME as f32),
+                Complex::from_polar(1.0, -PI * n as f32 / FRAME as f32),
+            )).collect(),
+            previous: vec![vec![0.0; FRAME]; channels],
+            pending: Vec::new(),
+            target: bitrate as f64 * FRAME as f64 / sample_rate as f64,
+            reservoir: 0.0,
+            frames: Vec::new(),
+        })
+    }
+
+    pub fn encode(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+        let length = FRAME * self.channels;
+        let mut start = 0;
+        while start + length <= self.pending.len() {
+            let frame = self.pending[start..start + length].to_vec();
+            self.encode_frame(&frame);
+            start += length;
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Encodes what is left with silence after it, the last frame overlaps
+    /// the one after it, and muxes the frames.
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.pending.is_empty() || !self.frames.is_empty() {
+            let length = FRAME * self.channels;
+            let missing = (length - self.pending.len() % length) % length;
+            self.encode(&vec![0.0; missing + length]);
+        }
+        self.mux()
+    }
+
+    /// MDCT of ISO/IEC 14496-3 4.6.11.3.2 of the previous and the given
+    /// frame, as a DCT-IV of the folded input through a complex FFT.
+    fn transform(&self, previous: &[f32], samples: &[f32]) -> Vec<f32> {
+        let windowed: Vec<f32> = previous.iter().chain(samples.iter()).zip(self.window.iter())
+            .map(|(sample, weight)| sample * weight * SAMPLE_SCALE)
+            .collect();
+        let half = FRAME / 2;
+        let folded: Vec<f32> = (0..FRAME).map(|n| match n < half {
+            true => -windowed[3 * half - 1 - n] - windowed[3 * half + n],
+            false => windowed[n - half] - windowed[3 * half - 1 - n],
+        }).collect();
+        let mut spectrum: Vec<Complex<f32>> = (0..half)
+            .map(|n| Complex::new(folded[2 * n], folded[FRAME - 1 - 2 * n]) * self.twiddles[n].0)
+            .collect();
+        self.fft.process(&mut spectrum);
+        let mut lines = vec![0.0; FRAME];
+        for (k, value) in spectrum.into_iter().enumerate() {
+            let value = value * self.twiddles[k].1;
+            lines[2 * k] = 2.0 * value.re;
+            lines[FRAME - 1 - 2 * k] = -2.0 * value.im;
+        }
+        lines
+    }
+
+    fn encode_frame(&mut self, samples: &[f32]) {
+        let mut lines = Vec::new();
+        for channel in 0..self.channels {
+            let samples: Vec<f32> = samples.iter().skip(channel).step_by(self.channels).copied().collect();
+            lines.push(self.transform(&self.previous[channel], &samples));
+            self.previous[channel] = samples;
+        }
+        let powered: Vec<Vec<f32>> = lines.iter()
+            .map(|lines| lines.iter().map(|line| line.abs().powf(0.75)).collect())
+            .collect();
+        let max_bits = (MAX_CHANNEL_BITS * self.channels) as f64;
+        let budget = (self.target + self.reservoir).min(max_bits) as usize;
+        // Element headers and the end element.
+        let overhead = if self.channels == 1 { 7 } else { 8 } + 3;
+        let quantized = |gain: u32| -> Option<Vec<Quantized>> {
+            powered.iter().zip(lines.iter()).map(|(powered, lines)| self.quantize(powered, lines, gain)).collect()
+        };
+        let fits = |channels: &Option<Vec<Quantized>>| channels.as_ref()
+            .is_some_and(|channels| overhead + channels.iter().map(|channel| channel.bits).sum::<usize>() <= budget);
+        let (mut low, mut high) = (0, 255);
+        while low < high {
+            let middle = (low + high) / 2;
+            match fits(&quantized(middle)) {
+                true => high = middle,
+                false => low = middle + 1,
+            }
+        }
+        let channels = quantized(low).unwrap_or_default();
+
+        let mut writer = BitWriter::default();
+        // A single channel or a channel pair element.
+        writer.put(self.channels as u32 - 1, 3);
+        writer.put(0, 4); // Element instance
+        if self.channels == 2 {
+            writer.put(0, 1); // Each channel has its own window.
+        }
+        for channel in channels.iter() {
+            self.write_channel(&mut writer, channel, low);
+        }
+        writer.put(7, 3); // End element
+        let frame = writer.finish();
+        self.reservoir = (self.reservoir + self.target - (frame.len() * 8) as f64).clamp(0.0, max_bits - self.target.min(max_bits));
+        self.frames.push(frame);
+    }
+
+    /// Quantizes with the step of the scalefactor `gain` and picks the
+    /// codebooks, `None` when a value is too large to code.
+    fn quantize(&self, powered: &[f32], lines: &[f32], gain: u32) -> Option<Quantized> {
+        let step = 2f32.powf(-0.1875 * (gain as f32 - SCALEFACTOR_OFFSET));
+        let mut values: Vec<i32> = Vec::with_capacity(FRAME);
+        for (value, line) in powered.iter().zip(lines.iter()) {
+            let quantized = (value * step + ROUNDING) as u32;
+            if quantized > MAX_VALUE {
+                return None;
+            }
+            values.push(if *line < 0.0 { -(quantized as i32) } else { quantized as i32 });
+        }
+        let mut books = Vec::new();
+        let mut spectral = 0;
+        for band in self.bands.windows(2) {
+            let values = &values[band[0]..band[1]];
+            let max = values.iter().map(|value| value.unsigned_abs()).max().unwrap_or(0);
+            let (book, bits) = match max {
+                0 => (0, 0),
+                _ => (1..=11)
+                    .filter(|book| CAPACITIES[book - 1] >= max)
+                    .map(|book| (book, spectrum_bits(book, values)))
+                    .min_by_key(|(_, bits)| *bits)?,
+            };
+            books.push(book);
+            spectral += bits;
+        }
+        // Bands past the last one with values aren't sent.
+        let max_bands = books.iter().rposition(|book| *book != 0).map_or(0, |band| band + 1);
+        books.truncate(max_bands);
+        let sections = sections(&books).iter().map(|(_, length)| 4 + 5 * (length / 31 + 1)).sum::<usize>();
+        let scalefactors = books.iter().filter(|book| **book != 0).count() * AAC_SCALEFACTOR.length(60) as usize;
+        // Gain, window info, pulse, TNS and gain control flags.
+        let bits = 8 + 11 + 3 + sections + scalefactors + spectral;
+        Some(Quantized { values, books, bits })
+    }
+
+    fn write_channel(&self, writer: &mut BitWriter, channel: &Quantized, gain: u32) {
+        writer.put(gain, 8);
+        writer.put(0, 1); // Reserved
+        writer.put(0, 2); // Only long window
+        writer.put(0, 1); // Sine window
+        writer.put(channel.books.len() as u32, 6);
+        writer.put(0, 1); // No prediction
+        for (book, mut length) in sections(&channel.books) {
+            writer.put(book as u32, 4);
+            while length >= 31 {
+                writer.put(31, 5);
+                length -= 31;
+            }
+            writer.put(length as u32, 5);
+        }
+        // Every band has the scalefactor of the gain.
+        for _ in channel.books.iter().filter(|book| **book != 0) {
+            let (code, length) = AAC_SCALEFACTOR.code(60);
+            writer.put(code, length);
+        }
+        writer.put(0, 1); // No pulses
+        writer.put(0, 1); // No temporal noise shaping
+        writer.put(0, 1); // No gain control
+        for (band, book) in channel.books.iter().enumerate() {
+            if *book != 0 {
+                write_spectrum(writer, *book, &channel.values[self.bands[band]..self.bands[band + 1]]);
+            }
+        }
+    }
+
+    /// ISO/IEC 14496-3 audio specific config of AAC-LC.
+    fn config(&self) -> [u8; 2] {
+        let config = (2 << 11) | ((self.rate_index as u16) << 7) | ((self.channels as u16) << 3);
+        config.to_be_bytes()
+    }
+
+    /// MP4 file with the frames as samples of one chunk, the first frame
+    /// only primes the decoder and is edited out.
+    fn mux(&self) -> Vec<u8> {
+        let frames = self.frames.len() as u32;
+        let duration = frames * FRAME as u32;
+        let played = duration.saturating_sub(FRAME as u32);
+        let movie_duration = (played as u64 * 1000 / self.sample_rate as u64) as u32;
+        let matrix: Vec<u8> = [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000].iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+
+        let mut mvhd = vec![0; 12]; // Version, flags, creation and modification times
+        mvhd.extend(1000u32.to_be_bytes());
+        mvhd.extend(movie_duration.to_be_bytes());
+        mvhd.extend(0x0001_0000u32.to_be_bytes()); // Rate
+        mvhd.extend(0x0100u16.to_be_bytes()); // Volume
+        mvhd.extend([0; 10]);
+        mvhd.extend(&matrix);
+        mvhd.extend([0; 24]);
+        mvhd.extend(2u32.to_be_bytes()); // Next track
+
+        let mut tkhd = vec![0, 0, 0, 3]; // Enabled and in the movie
+        tkhd.extend([0; 8]);
+        tkhd.extend(1u32.to_be_bytes()); // Track
+        tkhd.extend([0; 4]);
+        tkhd.extend(movie_duration.to_be_bytes());
+        tkhd.extend([0; 12]); // Reserved, layer and alternate group
+        tkhd.extend(0x0100u16.to_be_bytes()); // Volume
+        tkhd.extend([0; 2]);
+        tkhd.extend(&matrix);
+        tkhd.extend([0; 8]); // Width and height
+
+        let mut elst = vec![0; 4];
+        elst.extend(1u32.to_be_bytes());
+        elst.extend(movie_duration.to_be_bytes());
+        elst.extend((FRAME as u32).to_be_bytes()); // Media time
+        elst.extend(0x0001_0000u32.to_be_bytes()); // Rate
+
+        let mut mdhd = vec![0; 12];
+        mdhd.extend(self.sample_rate.to_be_bytes());
+        mdhd.extend(duration.to_be_bytes());
+        mdhd.extend(0x55c4u16.to_be_bytes()); // Undetermined language
+        mdhd.extend([0; 2]);
+
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"soun");
+        hdlr.extend([0; 12]);
+        hdlr.extend(b"SoundHandler\0");
+
+        let mut dref = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        dref.extend(atom(b"url ", &[0, 0, 0, 1])); // In this file
+
+        let descriptor = |tag: u8, content: &[u8]| {
+            let mut descriptor = vec![tag, content.len() as u8];
+            descriptor.extend_from_slice(content);
+            descriptor
+        };
+        let mut decoder_config = vec![0x40, 0x15]; // MPEG-4 audio stream
+        decoder_config.extend(&((MAX_CHANNEL_BITS * self.channels / 8) as u32).to_be_bytes()[1..]);
+        decoder_config.extend(self.bitrate.to_be_bytes()); // Maximum
+        decoder_config.extend(self.bitrate.to_be_bytes()); // Average
+        decoder_config.extend(descriptor(0x05, &self.config()));
+        let mut stream = vec![0, 1, 0]; // Stream and flags
+        stream.extend(descriptor(0x04, &decoder_config));
+        stream.extend(descriptor(0x06, &[0x02]));
+        let mut esds = vec![0; 4];
+        esds.extend(descriptor(0x03, &stream));
+
+        let mut mp4a = vec![0; 6];
+        mp4a.extend(1u16.to_be_bytes()); // Data reference
+        mp4a.extend([0; 8]);
+        mp4a.extend((self.channels as u16).to_be_bytes());
+        mp4a.extend(16u16.to_be_bytes()); // Sample size
+        mp4a.extend([0; 4]);
+        // Rates that don't fit are only in the audio specific config.
+        mp4a.extend(if self.sample_rate <= u16::MAX as u32 { self.sample_rate << 16 } else { 0 }.to_be_bytes());
+        mp4a.extend(atom(b"esds", &esds));
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(atom(b"mp4a", &mp4a));
+
+        let mut stts = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stts.extend(frames.to_be_bytes());
+        stts.extend((FRAME as u32).to_be_bytes());
+        let mut stsc = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsc.extend(1u32.to_be_bytes());
+        stsc.extend(frames.to_be_bytes());
+        stsc.extend(1u32.to_be_bytes());
+        let mut stsz = vec![0; 8];
+        stsz.extend(frames.to_be_bytes());
+        for frame in self.frames.iter() {
+            stsz.extend((frame.len() as u32).to_be_bytes());
+        }
+
+        let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A mp42isom");
+        // The chunk offset is only known once the rest of `moov` is.
+        let moov = |offset: u32| {
+            let mut stbl = atom(b"stsd", &stsd);
+            stbl.extend(atom(b"stts", &stts));
+            stbl.extend(atom(b"stsc", &stsc));
+            stbl.extend(atom(b"stsz", &stsz));
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend(offset.to_be_bytes());
+            stbl.extend(atom(b"stco", &stco));
+            let mut minf = atom(b"smhd", &[0; 8]);
+            minf.extend(atom(b"dinf", &atom(b"dref", &dref)));
+            minf.extend(atom(b"stbl", &stbl));
+            let mut mdia = atom(b"mdhd", &mdhd);
+            mdia.extend(atom(b"hdlr", &hdlr));
+            mdia.extend(atom(b"minf", &minf));
+            let mut trak = atom(b"tkhd", &tkhd);
+            trak.extend(atom(b"edts", &atom(b"elst", &elst)));
+            trak.extend(atom(b"mdia", &mdia));
+            let mut moov = atom(b"mvhd", &mvhd);
+            moov.extend(atom(b"trak", &trak));
+            atom(b"moov", &moov)
+        };
+        let offset = ftyp.len() + moov(0).len() + 8;
+        let mut file = ftyp;
+        file.extend(moov(offset as u32));
+        file.extend(atom(b"mdat", &self.frames.concat()));
+        file
+    }
+}
+
+/// Runs of bands with the same codebook and their lengths.
+fn sections(books: &[usize]) -> Vec<(usize, usize)> {
+    let mut sections: Vec<(usize, usize)> = Vec::new();
+    for book in books {
+        match sections.last_mut() {
+            Some((last, length)) if last == book => *length += 1,
+            _ => sections.push((*book, 1)),
+        }
+    }
+    sections
+}
+
+/// Index of the values in a codebook and whether their signs follow.
+fn spectrum_index(book: usize, values: &[i32]) -> (usize, bool) {
+    match book {
+        1 | 2 => (values.iter().fold(0, |index, value| index * 3 + (value + 1) as usize), false),
+        3 | 4 => (values.iter().fold(0, |index, value| index * 3 + value.unsigned_abs() as usize), true),
+        5 | 6 => (values.iter().fold(0, |index, value| index * 9 + (value + 4) as usize), false),
+        7 | 8 => (values.iter().fold(0, |index, value| index * 8 + value.unsigned_abs() as usize), true),
+        9 | 10 => (values.iter().fold(0, |index, value| index * 13 + value.unsigned_abs() as usize), true),
+        _ => (values.iter().fold(0, |index, value| index * 17 + value.unsigned_abs().min(16) as usize), true),
+    }
+}
+
+/// Values coded by one codeword of a codebook.
+fn dimension(book: usize) -> usize {
+    if book <= 4 { 4 } else { 2 }
+}
+
+/// Bits of the escape sequence of a value of the last codebook.
+fn escape_bits(value: u32) -> usize {
+    match value {
+        0..=15 => 0,
+        _ => {
+            let extra = value.ilog2() as usize - 4;
+            2 * extra + 5
+        },
+    }
+}
+
+fn spectrum_bits(book: usize, values: &[i32]) -> usize {
+    values.chunks_exact(dimension(book)).map(|group| {
+        let (index, signed) = spectrum_index(book, group);
+        let mut bits = AAC_SPECTRUM[book - 1].length(index) as usize;
+        if signed {
+            bits += group.iter().filter(|value| **value != 0).count();
+        }
+        if book == 11 {
+            bits += group.iter().map(|value| escape_bits(value.unsigned_abs())).sum::<usize>();
+        }
+        bits
+    }).sum()
+}
+
+fn write_spectrum(writer: &mut BitWriter, book: usize, values: &[i32]) {
+    for group in values.chunks_exact(dimension(book)) {
+        let (index, signed) = spectrum_index(book, group);
+        let (code, length) = AAC_SPECTRUM[book - 1].code(index);
+        writer.put(code, length);
+        if signed {
+            for value in group.iter().filter(|value| **value != 0) {
+                writer.put((*value < 0) as u32, 1);
+            }
+        }
+        if book == 11 {
+            for value in group.iter().map(|value| value.unsigned_abs()).filter(|value| *value >= 16) {
+                let extra = value.ilog2() - 4;
+                writer.put((1 << extra) - 1, extra);
+                writer.put(0, 1);
+                writer.put(value - (1 << (extra + 4)), extra + 4);
+            }
+        }
+    }
+}
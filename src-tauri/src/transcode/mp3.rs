@@ -0,0 +1,445 @@
+use std::f64::consts::PI;
+
+use super::{tables::{MP3_BANDS, MP3_LINBITS, MP3_PAIRS, MP3_QUADS, MP3_WINDOW}, BitWriter, Error};
+
+/// Samples per channel in a granule, a frame has two.
+const GRANULE: usize = 576;
+const FRAME: usize = 2 * GRANULE;
+/// MPEG-1 sample rates by their index in the frame header.
+pub const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+/// Bitrates in kbit/s by their index in the frame header, 0 is free format.
+const BITRATES: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+/// Bands in the first two regions of the big values by the bands the big
+/// values cover, as the ISO reference encoder divides them.
+const SUBDIVISIONS: [(usize, usize); 23] = [
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 1), (1, 1), (1, 1), (1, 2), (2, 2), (2, 3), (2, 3),
+    (3, 4), (3, 4), (3, 4), (4, 5), (4, 5), (4, 6), (5, 6), (5, 6), (5, 7), (6, 7), (6, 7),
+];
+/// Alias reduction coefficients of ISO/IEC 11172-3 Table B.9.
+const ALIAS: [f64; 8] = [-0.6, -0.535, -0.33, -0.185, -0.095, -0.041, -0.0142, -0.0037];
+/// Largest value the Huffman tables code, 15 plus 13 linbits.
+const MAX_VALUE: u32 = 8206;
+/// Bits of `part2_3_length`.
+const MAX_GRANULE_BITS: usize = 4095;
+/// Rounding of the quantizer, fitted to the distribution of the values.
+const ROUNDING: f32 = 0.4054;
+
+/// Subband filter and transform state of a channel.
+struct Channel {
+    /// The last 512 samples, newest first.
+    history: [f64; 512],
+    /// Subband samples of the previous granule, the transform overlaps them.
+    previous: [[f64; 18]; 32],
+}
+
+/// How a quantized granule is split into regions and coded.
+#[derive(Default, Clone)]
+struct Layout {
+    /// Values of the pairs coded with `tables`.
+    big_values: usize,
+    /// End of the quadruples of zeros and ones after the big values.
+    count1_end: usize,
+    tables: [usize; 3],
+    region0_count: usize,
+    region1_count: usize,
+    count1_table: usize,
+    bits: usize,
+}
+
+#[derive(Clone)]
+struct Granule {
+    global_gain: u32,
+    values: Vec<u32>,
+    negative: Vec<bool>,
+    layout: Layout,
+}
+
+/// MPEG-1 Layer III encoder of long blocks with a constant bitrate,
+/// stereo frames use mid/side coding when the channels are alike.
+pub struct Encoder {
+    channels: usize,
+    rate_index: usize,
+    bitrate_index: usize,
+    bands: &'static [usize; 23],
+    /// `cos((2i + 1)(k - 16)π / 64)` of the subband filter.
+    filter: Vec<[f64; 64]>,
+    /// Window times cosine of the 36 point transform.
+    transform: Vec<[f64; 36]>,
+    state: Vec<Channel>,
+    /// Interleaved samples waiting for a whole frame.
+    pending: Vec<f32>,
+    /// Share of a padding byte accumulated over the frames.
+    padding: u32,
+    output: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new(channels: usize, sample_rate: u32, kbps: u32) -> Result<Self, Error> {
+        let rate_index = SAMPLE_RATES.iter().position(|rate| *rate == sample_rate).ok_or(Error::Encoder)?;
+        if !(1..=2).contains(&channels) {
+            return Err(Error::Encoder);
+        }
+        // The highest bitrate that isn't above the requested one.
+        let bitrate_index = BITRATES.iter().rposition(|bitrate| *bitrate <= kbps).unwrap_or(0).max(1);
+        let filter = (0..32)
+            .map(|i| std::array::from_fn(|k| ((2 * i + 1) as f64 * (k as f64 - 16.0) * PI / 64.0).cos()))
+            .collect();
+        // Scaled by 1 / 9 so the inverse transform of the decoder restores the level.
+        let transform = (0..18)
+            .map(|m| std::array::from_fn(|k| {
+                (PI / 36.0 * (k as f64 + 0.5)).sin() * (PI / 72.0 * (2 * k + 19) as f64 * (2 * m + 1) as f64).cos() / 9.0
+            }))
+            .collect();
+        let state = (0..channels).map(|_| Channel { history: [0.0; 512], previous: [[0.0; 18]; 32] }).collect();
+        Ok(Self {
+            channels,
+            rate_index,
+            bitrate_index,
+            bands: &MP3_BANDS[rate_index],
+            filter,
+            transform,
+            state,
+            pending: Vec::new(),
+            padding: 0,
+            output: Vec::new(),
+        })
+    }
+
+    pub fn encode(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+        let length = FRAME * self.channels;
+        let mut start = 0;
+        while start + length <= self.pending.len() {
+            let frame = self.pending[start..start + length].to_vec();
+            self.encode_frame(&frame);
+            start += length;
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Encodes what is left with silence after it, which also pushes out
+    /// the delay of the filters.
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.pending.is_empty() || !self.output.is_empty() {
+            let length = FRAME * self.channels;
+            let missing = (length - self.pending.len() % length) % length;
+            self.encode(&vec![0.0; missing + length]);
+        }
+        self.output
+    }
+
+    /// Subband filter of ISO/IEC 11172-3 C.1.3, 32 samples in and one
+    /// sample of each subband out.
+    fn analyze(&self, channel: &mut Channel, samples: impl Iterator<Item = f64>) -> [f64; 32] {
+        channel.history.copy_within(0..480, 32);
+        for (i, sample) in samples.enumerate() {
+            channel.history[31 - i] = sample;
+        }
+        let mut folded = [0.0; 64];
+        for (i, folded) in folded.iter_mut().enumerate() {
+            for j in 0..8 {
+                *folded += MP3_WINDOW[i + 64 * j] / 32.0 * channel.history[i + 64 * j];
+            }
+        }
+        std::array::from_fn(|i| self.filter[i].iter().zip(folded.iter()).map(|(cos, folded)| cos * folded).sum())
+    }
+
+    /// Frequency lines of the next granule of samples of a channel.
+    fn granule(&self, channel: &mut Channel, samples: &[f32]) -> [f64; GRANULE] {
+        let mut subbands = [[0.0; 18]; 32];
+        for (time, block) in samples.chunks_exact(32).enumerate() {
+            let outputs = self.analyze(channel, block.iter().map(|sample| *sample as f64));
+            for (band, output) in outputs.into_iter().enumerate() {
+                // Odd subbands come out mirrored in frequency.
+                subbands[band][time] = match band % 2 == 1 && time % 2 == 1 {
+                    true => -output,
+                    false => output,
+                };
+            }
+        }
+        let mut lines = [0.0; GRANULE];
+        for band in 0..32 {
+            let input: Vec<f64> = channel.previous[band].iter().chain(subbands[band].iter()).copied().collect();
+            for m in 0..18 {
+                lines[band * 18 + m] = self.transform[m].iter().zip(input.iter()).map(|(weight, sample)| weight * sample).sum();
+            }
+        }
+        channel.previous = subbands;
+        // Undone by the decoder before its inverse transform.
+        for band in 1..32 {
+            for (i, coefficient) in ALIAS.iter().enumerate() {
+                let (cs, ca) = (1.0 / (1.0 + coefficient * coefficient).sqrt(), coefficient / (1.0 + coefficient * coefficient).sqrt());
+                let (lower, upper) = (lines[band * 18 - 1 - i], lines[band * 18 + i]);
+                lines[band * 18 - 1 - i] = lower * cs + upper * ca;
+                lines[band * 18 + i] = upper * cs - lower * ca;
+            }
+        }
+        lines
+    }
+
+    fn encode_frame(&mut self, samples: &[f32]) {
+        let mut lines: Vec<Vec<[f64; GRANULE]>> = vec![Vec::new(); self.channels];
+        let mut state = std::mem::take(&mut self.state);
+        for (index, channel) in state.iter_mut().enumerate() {
+            let samples: Vec<f32> = samples.iter().skip(index).step_by(self.channels).copied().collect();
+            lines[index] = samples.chunks_exact(GRANULE).map(|granule| self.granule(channel, granule)).collect();
+        }
+        self.state = state;
+        let mid_side = self.channels == 2 && {
+            let (mut sum, mut difference) = (0.0, 0.0);
+            for (left, right) in lines[0].iter().zip(lines[1].iter()) {
+                for (left, right) in left.iter().zip(right.iter()) {
+                    sum += (left + right).powi(2);
+                    difference += (left - right).powi(2);
+                }
+            }
+            difference < sum * 0.3
+        };
+        if mid_side {
+            let (left, right) = lines.split_at_mut(1);
+            for (left, right) in left[0].iter_mut().flatten().zip(right[0].iter_mut().flatten()) {
+                (*left, *right) = ((*left + *right) / 2f64.sqrt(), (*left - *right) / 2f64.sqrt());
+            }
+        }
+
+        let numerator = 144_000 * BITRATES[self.bitrate_index];
+        self.padding += numerator % SAMPLE_RATES[self.rate_index];
+        let padded = self.padding >= SAMPLE_RATES[self.rate_index];
+        if padded {
+            self.padding -= SAMPLE_RATES[self.rate_index];
+        }
+        let frame_bytes = (numerator / SAMPLE_RATES[self.rate_index]) as usize + padded as usize;
+        let side_info = if self.channels == 1 { 136 } else { 256 };
+        let mut available = frame_bytes * 8 - 32 - side_info;
+        let mut granules = Vec::new();
+        // Granules in bitstream order, each gets its share of the bits left.
+        let parts = 2 * self.channels;
+        for part in 0..parts {
+            let (granule, channel) = (part / self.channels, part % self.channels);
+            let budget = (available / (parts - part)).min(MAX_GRANULE_BITS);
+            let quantized = self.quantize(&lines[channel][granule], budget);
+            available -= quantized.layout.bits;
+            granules.push(quantized);
+        }
+
+        let mut writer = BitWriter::default();
+        writer.put(0xfff, 12);
+        writer.put(1, 1); // MPEG-1
+        writer.put(1, 2); // Layer III
+        writer.put(1, 1); // No CRC
+        writer.put(self.bitrate_index as u32, 4);
+        writer.put(self.rate_index as u32, 2);
+        writer.put(padded as u32, 1);
+        writer.put(0, 1); // Private
+        // Joint stereo with mid/side and without intensity stereo, or mono.
+        let (mode, extension) = match (self.channels, mid_side) {
+            (1, _) => (3, 0),
+            (_, true) => (1, 2),
+            (_, false) => (1, 0),
+        };
+        writer.put(mode, 2);
+        writer.put(extension, 2);
+        writer.put(0, 1); // Copyright
+        writer.put(1, 1); // Original
+        writer.put(0, 2); // Emphasis
+
+        writer.put(0, 9); // The frame holds all of its main data.
+        writer.put(0, if self.channels == 1 { 5 } else { 3 });
+        writer.put(0, 4 * self.channels as u32); // Scalefactors aren't shared.
+        for granule in granules.iter() {
+            let layout = &granule.layout;
+            writer.put(layout.bits as u32, 12);
+            writer.put(layout.big_values as u32 / 2, 9);
+            writer.put(granule.global_gain, 8);
+            writer.put(0, 4); // No scalefactors
+            writer.put(0, 1); // Long blocks
+            for table in layout.tables {
+                writer.put(table as u32, 5);
+            }
+            writer.put(layout.region0_count as u32, 4);
+            writer.put(layout.region1_count as u32, 3);
+            writer.put(0, 1); // Preflag
+            writer.put(0, 1); // Scalefactor scale
+            writer.put(layout.count1_table as u32, 1);
+        }
+        for granule in granules.iter() {
+            self.write_granule(&mut writer, granule);
+        }
+        let mut frame = writer.finish();
+        frame.resize(frame_bytes, 0);
+        self.output.extend(frame);
+    }
+
+    /// Quantizes with the finest step whose codes fit in `budget` bits.
+    fn quantize(&self, lines: &[f64; GRANULE], budget: usize) -> Granule {
+        let powered: Vec<f32> = lines.iter().map(|line| (line.abs() as f32).powf(0.75)).collect();
+        let quantized = |global_gain: u32| {
+            let step = 2f32.powf(-0.1875 * (global_gain as f32 - 210.0));
+            let values: Vec<u32> = powered.iter().map(|value| (value * step + ROUNDING) as u32).collect();
+            let layout = match values.iter().all(|value| *value <= MAX_VALUE) {
+                true => self.layout(&values),
+                false => None,
+            };
+            (values, layout)
+        };
+        let (mut low, mut high) = (0, 255);
+        while low < high {
+            let middle = (low + high) / 2;
+            match quantized(middle).1 {
+                Some(layout) if layout.bits <= budget => high = middle,
+                _ => low = middle + 1,
+            }
+        }
+        let (values, layout) = quantized(low);
+        Granule {
+            global_gain: low,
+            values,
+            negative: lines.iter().map(|line| *line < 0.0).collect(),
+            layout: layout.unwrap_or_default(),
+        }
+    }
+
+    /// Splits the values into regions and picks the tables that code them
+    /// in the fewest bits.
+    fn layout(&self, values: &[u32]) -> Option<Layout> {
+        let mut end = GRANULE;
+        while end > 1 && values[end - 1] == 0 && values[end - 2] == 0 {
+            end -= 2;
+        }
+        let mut big_values = end;
+        while big_values > 3 && values[big_values - 4..big_values].iter().all(|value| *value <= 1) {
+            big_values -= 4;
+        }
+        let mut layout = Layout { big_values, count1_end: end, ..Default::default() };
+        if big_values > 0 {
+            let covered = self.bands.iter().take_while(|start| **start < big_values).count();
+            let (region0, region1) = SUBDIVISIONS[covered.min(22)];
+            layout.region0_count = (0..=region0).rev()
+                .find(|count| self.bands[count + 1] <= big_values)
+                .unwrap_or(0);
+            let region1_start = self.bands[layout.region0_count + 1];
+            layout.region1_count = (0..=region1).rev()
+                .find(|count| self.bands[layout.region0_count + count + 2] <= big_values)
+                .unwrap_or(0);
+            let region2_start = self.bands[layout.region0_count + layout.region1_count + 2];
+            let regions = [(0, region1_start), (region1_start, region2_start), (region2_start, big_values)];
+            for (index, (start, end)) in regions.into_iter().enumerate() {
+                let (start, end) = (start.min(big_values), end.min(big_values));
+                let (table, bits) = best_table(&values[start..end])?;
+                layout.tables[index] = table;
+                layout.bits += bits;
+            }
+        }
+        let (mut bits_a, mut bits_b) = (0, 0);
+        for quad in values[big_values..end].chunks_exact(4) {
+            let ones = quad.iter().filter(|value| **value != 0).count();
+            bits_a += MP3_QUADS[0].length(quad_index(quad)) as usize + ones;
+            bits_b += MP3_QUADS[1].length(quad_index(quad)) as usize + ones;
+        }
+        layout.count1_table = (bits_b < bits_a) as usize;
+        layout.bits += bits_a.min(bits_b);
+        Some(layout)
+    }
+
+    fn write_granule(&self, writer: &mut BitWriter, granule: &Granule) {
+        let layout = &granule.layout;
+        let region1_start = self.bands[layout.region0_count + 1].min(layout.big_values);
+        let region2_start = self.bands[layout.region0_count + layout.region1_count + 2].min(layout.big_values);
+        for i in (0..layout.big_values).step_by(2) {
+            let table = match i {
+                i if i < region1_start => layout.tables[0],
+                i if i < region2_start => layout.tables[1],
+                _ => layout.tables[2],
+            };
+            let Some(book) = &MP3_PAIRS[table] else {
+                continue;
+            };
+            let linbits = MP3_LINBITS[table];
+            let (x, y) = (granule.values[i], granule.values[i + 1]);
+            let escaped = |value: u32| if linbits > 0 { value.min(15) } else { value };
+            let (code, length) = book.code(escaped(x) as usize * width(table) + escaped(y) as usize);
+            writer.put(code, length);
+            for (value, negative) in [(x, granule.negative[i]), (y, granule.negative[i + 1])] {
+                if linbits > 0 && value >= 15 {
+                    writer.put(value - 15, linbits);
+                }
+                if value != 0 {
+                    writer.put(negative as u32, 1);
+                }
+            }
+        }
+        for start in (layout.big_values..layout.count1_end).step_by(4) {
+            let quad = &granule.values[start..start + 4];
+            let (code, length) = MP3_QUADS[layout.count1_table].code(quad_index(quad));
+            writer.put(code, length);
+            for (value, negative) in quad.iter().zip(granule.negative[start..start + 4].iter()) {
+                if *value != 0 {
+                    writer.put(*negative as u32, 1);
+                }
+            }
+        }
+    }
+}
+
+/// Values per row of a table of pairs.
+fn width(table: usize) -> usize {
+    match table {
+        1 => 2,
+        2 | 3 => 3,
+        5 | 6 => 4,
+        7..=9 => 6,
+        10..=12 => 8,
+        _ => 16,
+    }
+}
+
+/// Largest value a table of pairs codes.
+fn capacity(table: usize) -> u32 {
+    match MP3_LINBITS[table] {
+        0 => width(table) as u32 - 1,
+        linbits => 15 + (1 << linbits) - 1,
+    }
+}
+
+fn quad_index(quad: &[u32]) -> usize {
+    (quad[0] * 8 + quad[1] * 4 + quad[2] * 2 + quad[3]) as usize
+}
+
+fn pair_bits(table: usize, values: &[u32]) -> usize {
+    let Some(book) = &MP3_PAIRS[table] else {
+        return 0;
+    };
+    let linbits = MP3_LINBITS[table];
+    values.chunks_exact(2).map(|pair| {
+        let mut bits = 0;
+        let mut index = 0;
+        for value in pair {
+            let coded = match linbits > 0 && *value >= 15 {
+                true => {
+                    bits += linbits as usize;
+                    15
+                },
+                false => *value,
+            };
+            index = index * width(table) + coded as usize;
+            bits += (*value != 0) as usize;
+        }
+        bits + book.length(index) as usize
+    }).sum()
+}
+
+/// Table coding the values in the fewest bits and its bits. Only the table
+/// with the fewest linbits that suffice is tried of each pair of families.
+fn best_table(values: &[u32]) -> Option<(usize, usize)> {
+    let max = values.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return Some((0, 0));
+    }
+    let first = |tables: std::ops::Range<usize>| tables.into_iter().find(|table| capacity(*table) >= max);
+    (1..16)
+        .filter(|table| MP3_PAIRS[*table].is_some() && capacity(*table) >= max)
+        .chain(first(16..24))
+        .chain(first(24..32))
+        .map(|table| (table, pair_bits(table, values)))
+        .min_by_key(|(_, bits)| *bits)
+}
@@ -0,0 +1,109 @@
+use crate::decoder::Analyzer;
+
+/// Peaks stored per track, enough for a seekbar on any screen.
+pub const RESOLUTION: usize = 4096;
+/// Peaks measured per second before reducing them to [`RESOLUTION`].
+const PEAKS_PER_SECOND: usize = 100;
+
+const MAGIC: &[u8; 4] = b"FPWF";
+const VERSION: u8 = 1;
+
+/// Lowest and highest sample of stretches of equal length, over all channels.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Waveform {
+    pub peaks: Vec<(f32, f32)>,
+}
+
+impl Waveform {
+    /// Merges neighbouring peaks down to `buckets`, never more than are stored.
+    pub fn downsample(&self, buckets: usize) -> Vec<(f32, f32)> {
+        let length = self.peaks.len();
+        let buckets = buckets.min(length);
+        (0..buckets).map(|bucket| {
+            let range = &self.peaks[bucket * length / buckets..(bucket + 1) * length / buckets];
+            range.iter().fold((0.0f32, 0.0f32), |(min, max), peak| (min.min(peak.0), max.max(peak.1)))
+        }).collect()
+    }
+
+    /// Peaks between two fractions of the length, at least one.
+    pub fn slice(&self, from: f64, to: f64) -> Waveform {
+        let length = self.peaks.len();
+        let start = ((from.clamp(0.0, 1.0) * length as f64) as usize).min(length.saturating_sub(1));
+        let end = ((to.clamp(0.0, 1.0) * length as f64).ceil() as usize).clamp(start + 1, length.max(1));
+        Waveform { peaks: self.peaks.get(start..end).unwrap_or_default().to_vec() }
+    }
+
+    /// Magic, version, number of peaks as `u32` and every peak as two `i8`
+    /// scaled so 127 is full scale.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(9 + self.peaks.len() * 2);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.peaks.len() as u32).to_le_bytes());
+        for (min, max) in self.peaks.iter() {
+            bytes.push((min.clamp(-1.0, 1.0) * 127.0).floor() as i8 as u8);
+            bytes.push((max.clamp(-1.0, 1.0) * 127.0).ceil() as i8 as u8);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 9 || &bytes[..4] != MAGIC || bytes[4] != VERSION {
+            return None;
+        }
+        let length = u32::from_le_bytes(bytes[5..9].try_into().ok()?) as usize;
+        let data = bytes.get(9..9 + length * 2)?;
+        let peaks = data.chunks_exact(2)
+            .map(|peak| (peak[0] as i8 as f32 / 127.0, peak[1] as i8 as f32 / 127.0))
+            .collect();
+        Some(Self { peaks })
+    }
+}
+
+/// Collects peaks from interleaved samples fed in chunks of any size.
+pub struct PeakMeter {
+    channels: usize,
+    /// Frames per measured peak.
+    step: usize,
+    frames: usize,
+    current: (f32, f32),
+    peaks: Vec<(f32, f32)>,
+}
+
+impl PeakMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            channels,
+            step: (sample_rate as usize / PEAKS_PER_SECOND).max(1),
+            frames: 0,
+            current: (0.0, 0.0),
+            peaks: Vec::new(),
+        }
+    }
+}
+
+impl Analyzer for PeakMeter {
+    type Output = Waveform;
+
+    fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for sample in frame {
+                self.current = (self.current.0.min(*sample), self.current.1.max(*sample));
+            }
+            self.frames += 1;
+            if self.frames == self.step {
+                self.peaks.push(self.current);
+                self.frames = 0;
+                self.current = (0.0, 0.0);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Waveform {
+        if self.frames > 0 {
+            self.peaks.push(self.current);
+        }
+        let measured = Waveform { peaks: self.peaks };
+        Waveform { peaks: measured.downsample(RESOLUTION) }
+    }
+}
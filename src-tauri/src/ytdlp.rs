@@ -2,9 +2,12 @@ use std::time::{Duration, SystemTime};
 
 use tokio::{process::Command, sync::Mutex};
 
+use crate::{audio::{Chapter, Subtitle}, settings::AudioQuality};
+
 pub struct YtDlp {
     path: String,
     cache: Mutex<Vec<OtherSavedDetails>>,
+    quality: Mutex<AudioQuality>,
 }
 
 
@@ -29,15 +32,43 @@ pub struct Details {
     pub author: String,
     pub thumbnail: String,
     pub media: String,
+    /// Seconds.
+    pub duration: Option<f64>,
+    pub chapters: Vec<Chapter>,
+    /// WebVTT captions, the best match first.
+    pub subtitles: Vec<Subtitle>,
+}
+
+/// Search result, formats aren't resolved until the audio is added.
+#[derive(Debug, Clone)]
+pub struct RemoteEntry {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    pub channel: String,
+    /// Seconds.
+    pub duration: Option<f64>,
+    pub thumbnail: Option<String>,
 }
 
 mod youtube;
 
+pub use youtube::video_id;
+
 impl YtDlp {
     pub fn new(path: String) -> Self {
         Self {
             path,
             cache: Mutex::new(Vec::new()),
+            quality: Mutex::new(AudioQuality::High),
+        }
+    }
+
+    pub async fn set_quality(&self, quality: AudioQuality) {
+        let mut current = self.quality.lock().await;
+        if *current != quality {
+            *current = quality;
+            self.cache.lock().await.clear();
         }
     }
 
@@ -58,18 +89,30 @@ impl YtDlp {
             .next()
     }
     
-    fn get_command(&self, url: String) -> Command {
+    fn new_command(&self, args: Vec<String>) -> Command {
         let mut cmd = Command::new(&self.path);
         // Костыльный костыль
         #[cfg(target_os = "windows")]
         {
             cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
         }
-        cmd.args(vec![
+        cmd.args(args);
+        cmd
+    }
+
+    fn get_command(&self, url: String) -> Command {
+        self.new_command(vec![
             "--dump-json".to_string(),
             url.to_string(),
-        ]);
-        cmd
+        ])
+    }
+
+    fn get_search_command(&self, query: String, limit: u32) -> Command {
+        self.new_command(vec![
+            "--flat-playlist".to_string(),
+            "--dump-single-json".to_string(),
+            format!("ytsearch{}:{}", limit, query),
+        ])
     }
 
     pub async fn fetch(&self, url: String) -> Result<Details, FetchError> {
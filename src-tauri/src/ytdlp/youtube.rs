@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use serde::Deserialize;
 
-use super::{FetchError, Details, YtDlp};
+use crate::{audio::{Chapter, Subtitle}, settings::AudioQuality};
+
+use super::{FetchError, Details, RemoteEntry, YtDlp};
 
 #[derive(Debug, Deserialize, Clone)]
 struct YouTubeVideo {
@@ -9,6 +13,30 @@ struct YouTubeVideo {
     pub thumbnail: String,
     pub formats: Vec<YouTubeFormat>,
     pub channel: String,
+    pub duration: Option<f64>,
+    /// Null for videos without chapters.
+    #[serde(default)]
+    pub chapters: Option<Vec<YouTubeChapter>>,
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Formats of the captions by language.
+    #[serde(default)]
+    pub subtitles: Option<HashMap<String, Vec<YouTubeSubtitle>>>,
+    #[serde(default)]
+    pub automatic_captions: Option<HashMap<String, Vec<YouTubeSubtitle>>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct YouTubeSubtitle {
+    pub ext: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct YouTubeChapter {
+    pub title: String,
+    pub start_time: f64,
+    pub end_time: f64,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -16,6 +44,46 @@ struct YouTubeFormat {
     pub url: String,
     pub ext: String,
     pub resolution: String,
+    pub abr: Option<f64>,
+    #[serde(default)]
+    pub acodec: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct YouTubeThumbnail {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct YouTubeSearchEntry {
+    pub id: String,
+    pub title: String,
+    pub channel: Option<String>,
+    pub uploader: Option<String>,
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub thumbnails: Vec<YouTubeThumbnail>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct YouTubeSearch {
+    pub entries: Vec<YouTubeSearchEntry>,
+}
+
+/// The id of the video a YouTube link points to, however the link is written.
+pub fn video_id(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let host = host.trim_start_matches("www.").trim_start_matches("m.").trim_start_matches("music.");
+    let id = match (host, path.split('/').collect::<Vec<_>>().as_slice()) {
+        ("youtu.be", [id, ..]) => *id,
+        ("youtube.com", ["watch"]) => query.split('&').find_map(|pair| pair.strip_prefix("v="))?,
+        ("youtube.com", ["shorts" | "embed" | "live" | "v", id, ..]) => *id,
+        _ => return None,
+    };
+    let id = id.split('#').next().unwrap_or(id);
+    (!id.is_empty()).then_some(id)
 }
 
 impl YtDlp {
@@ -38,17 +106,88 @@ impl YtDlp {
         }
         let stdout = String::from_utf8(output.stdout).map_err(|_| FetchError::Unknown)?;
         let metadata = serde_json::from_str::<YouTubeVideo>(&stdout).map_err(|_| FetchError::Unknown)?;
+        let quality = *self.quality.lock().await;
+        let subtitles = Self::select_subtitles(&metadata);
         Ok(Details {
             url: format!("https://www.youtube.com/watch?v={}", metadata.id),
             title: metadata.title,
             thumbnail: metadata.thumbnail,
-            media: metadata.formats.iter()
-                .filter(|x| x.resolution == "audio only")
-                .filter(|x| x.ext == "webm")
-                .next()
+            media: Self::select_format(&metadata.formats, quality)
                 .map(|x| x.url.clone())
                 .ok_or(FetchError::NotFound)?,
             author: metadata.channel,
+            duration: metadata.duration,
+            chapters: metadata.chapters.unwrap_or_default().into_iter()
+                .filter(|chapter| chapter.end_time > chapter.start_time)
+                .map(|chapter| Chapter { title: chapter.title, start: chapter.start_time, end: chapter.end_time })
+                .collect(),
+            subtitles,
         })
     }
+
+    pub async fn search_remote(&self, query: String, limit: u32) -> Result<Vec<RemoteEntry>, FetchError> {
+        let mut cmd = self.get_search_command(query, limit);
+        let output = cmd.output().await.map_err(|_| FetchError::Unknown)?;
+        if !output.status.success() {
+            return Err(FetchError::Unknown);
+        }
+        let stdout = String::from_utf8(output.stdout).map_err(|_| FetchError::Unknown)?;
+        let search = serde_json::from_str::<YouTubeSearch>(&stdout).map_err(|_| FetchError::Unknown)?;
+        Ok(search.entries.into_iter().map(|entry| RemoteEntry {
+            url: format!("https://www.youtube.com/watch?v={}", entry.id),
+            id: entry.id,
+            title: entry.title,
+            channel: entry.channel.or(entry.uploader).unwrap_or_default(),
+            duration: entry.duration,
+            // The last thumbnail is the largest one.
+            thumbnail: entry.thumbnails.last().map(|thumbnail| thumbnail.url.clone()),
+        }).collect())
+    }
+
+    /// WebVTT captions in the language of the video first, then English, then
+    /// the rest. Automatic captions come after the uploaded ones and are only
+    /// taken in the original language, the others are machine translations.
+    fn select_subtitles(video: &YouTubeVideo) -> Vec<Subtitle> {
+        let rank = |key: &str| -> u8 {
+            let key = key.trim_end_matches("-orig");
+            match video.language.as_deref() {
+                Some(language) if key == language => 0,
+                Some(language) if key.split('-').next() == Some(language) => 1,
+                _ if key == "en" => 2,
+                _ if key.starts_with("en-") => 3,
+                _ => 4,
+            }
+        };
+        let collect = |tracks: &Option<HashMap<String, Vec<YouTubeSubtitle>>>, automatic: bool| {
+            let mut found: Vec<(u8, Subtitle)> = tracks.iter().flatten()
+                .filter(|(language, _)| *language != "live_chat")
+                .filter(|(language, _)| !automatic || language.ends_with("-orig") || rank(language) < 2)
+                .filter_map(|(language, formats)| {
+                    let vtt = formats.iter().find(|format| format.ext == "vtt")?;
+                    Some((rank(language), Subtitle { language: language.clone(), url: vtt.url.clone(), automatic, original: rank(language) < 2 }))
+                })
+                .collect();
+            found.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.language.cmp(&b.1.language)));
+            found.into_iter().map(|(_, subtitle)| subtitle)
+        };
+        collect(&video.subtitles, false).chain(collect(&video.automatic_captions, true)).collect()
+    }
+
+    /// The best Opus stream for high quality. Low quality takes the smallest
+    /// AAC-LC stream instead, low bitrate Opus codes speech in SILK frames
+    /// the native decoder plays as silence.
+    fn select_format(formats: &[YouTubeFormat], quality: AudioQuality) -> Option<&YouTubeFormat> {
+        let audio_formats = formats.iter().filter(|x| x.resolution == "audio only");
+        let bitrate = |x: &&YouTubeFormat| x.abr.unwrap_or(0.0);
+        let best = || audio_formats.clone()
+            .filter(|x| x.ext == "webm")
+            .max_by(|a, b| bitrate(a).total_cmp(&bitrate(b)));
+        match quality {
+            AudioQuality::High => best(),
+            AudioQuality::Low => audio_formats.clone()
+                .filter(|x| x.ext == "m4a" && x.acodec.as_deref().is_some_and(|codec| codec.starts_with("mp4a.40.2")))
+                .min_by(|a, b| bitrate(a).total_cmp(&bitrate(b)))
+                .or_else(best),
+        }
+    }
 }
\ No newline at end of file
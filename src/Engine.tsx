@@ -14,11 +14,50 @@ export type IndexedAudioDTO = {
     id: number,
     title: string,
     author: string,
+    album: string | null,
+    tags: string[],
     source: {
         YouTube?: string,
     },
+    pinned: boolean,
+    last_played: number | null,
+    play_count: number,
+    download_policy: DownloadPolicy | null,
+    added_at: number | null,
+    duration: number | null,
+    has_file: boolean,
+    skip_count: number,
+    listened: number,
+    chapters: Chapter[],
+    segment: Segment | null,
+    bpm: number | null,
+    key: Key | null,
+    camelot: string | null,
+    bpm_key_corrected: boolean,
 }
 
+export type Key = {
+    tonic: number,
+    mode: 'Major' | 'Minor',
+}
+
+export type Chapter = {
+    title: string,
+    start: number,
+    end: number,
+}
+
+export type Segment = {
+    parent: number,
+    start: number,
+    end: number,
+}
+
+// Audios without the value go last, keys go around the Camelot wheel.
+export type PlaylistSort = 'Bpm' | 'Key'
+
+export type DownloadPolicy = 'Always' | 'StreamOnly' | { AfterPlays: number }
+
 export type ThumbnailEvent = {
     id: number,
     url: string,
@@ -50,11 +89,16 @@ type DownloadEventDTO = {
         audio: IndexedAudioDTO,
         downloaded: number,
         total: number,
+    },
+    Transcode?: {
+        audio: IndexedAudioDTO,
+        transcoded: number,
+        total: number,
     }
 }
 
 export type Download = {
-    state: 'downloading' | 'finished' | 'error',
+    state: 'downloading' | 'transcoding' | 'finished' | 'error',
     error: string | null,
     progress: {
         total: number,
@@ -129,6 +173,19 @@ export default class Engine {
                 for (const listener of this.listeners['download']) {
                     listener(payload.Download);
                 }
+            } else if (payload.Transcode) {
+                this._downloads[payload.Transcode.audio.id] = {
+                    state: 'transcoding',
+                    error: null,
+                    progress: {
+                        downloaded: payload.Transcode.transcoded,
+                        total: payload.Transcode.total,
+                    },
+                    audio: payload.Transcode.audio,
+                }
+                for (const listener of this.listeners['download']) {
+                    listener(payload.Transcode);
+                }
             }
         });
     }
@@ -148,7 +205,9 @@ export default class Engine {
     }
 
     private async loadThumbnail(id: number) {
-        let content: ContentDTO = await invoke("get_thumbnail", { id });
+        // Rows and the player show covers of 64 CSS pixels.
+        let size = Math.round(64 * window.devicePixelRatio);
+        let content: ContentDTO = await invoke("get_thumbnail", { id, size });
         this.thumbnails[id] = content;
         for (const thumbnail of this.listeners['thumbnail_load']) {
             let url = this.contentToURL(content);
@@ -174,8 +233,12 @@ export default class Engine {
         }
     }
 
-    async getPlaylist(): Promise<IndexedAudioDTO[]> {
-        this._playlist = await invoke("get_playlist");
+    async reportListen(id: number, startedAt: number, listened: number, completed: boolean) {
+        await invoke("report_listen", { id, startedAt, listened, completed });
+    }
+
+    async getPlaylist(sort: PlaylistSort | null = null): Promise<IndexedAudioDTO[]> {
+        this._playlist = await invoke("get_playlist", { sort });
         this.thumbnails = {};
         for (const audio of this._playlist) {
             this.loadThumbnail(audio.id);
@@ -225,6 +288,8 @@ type ContextType = {
     addAudio: (url: string) => void,
     removeAudio: (id: number) => void,
     selectAudio: (id: number) => void,
+    sort: PlaylistSort | null,
+    sortPlaylist: (sort: PlaylistSort | null) => void,
     state: 'idle' | 'fetching_audio' | 'loading_audio',
     selectedAudio: [IndexedAudioDTO, string] | null,
 }
@@ -252,6 +317,7 @@ export function EngineContext({children}: {children: ReactNode}) {
     let [selectedAudio, setSelectedAudio] = useState<[IndexedAudioDTO, string] | null>(null);
     let [state, setState] = useState<State>('idle');
     let [downloads, setDownloads] = useState<{[id: number]: Download}>({});
+    let [sort, setSort] = useState<PlaylistSort | null>(null);
     
     useEffect(() => {
         engine.init();
@@ -297,6 +363,11 @@ export function EngineContext({children}: {children: ReactNode}) {
             await engine.removeAudio(id);
             setPlaylist(engine.playlist);
         },
+        sort,
+        sortPlaylist: async (sort: PlaylistSort | null) => {
+            setSort(sort);
+            setPlaylist(await engine.getPlaylist(sort));
+        },
         state,
         selectedAudio,
         selectAudio: async (id: number) => {
@@ -1,10 +1,17 @@
-import { useEngine } from "./Engine";
+import { PlaylistSort, useEngine } from "./Engine";
 import { Thumbnail } from "./components/Thumbnail";
 
 export function Playlist() {
-    let { thumbnails, playlist, state, removeAudio, selectAudio, selectedAudio } = useEngine();
+    let { thumbnails, playlist, state, removeAudio, selectAudio, selectedAudio, sort, sortPlaylist } = useEngine();
     return <div className="flex flex-col min-h-0 playlist">
-            <h2 className="text-lg mb-2">Playlist</h2>
+            <div className="flex items-center justify-between mb-2">
+                <h2 className="text-lg">Playlist</h2>
+                <select className="bg-gray-800 rounded p-1" value={sort ?? ''} onChange={(e) => sortPlaylist((e.target.value || null) as PlaylistSort | null)}>
+                    <option value="">Added</option>
+                    <option value="Bpm">BPM</option>
+                    <option value="Key">Key</option>
+                </select>
+            </div>
             <ul className="flex flex-col space-y-2 bg-gray-800 p-2 rounded-xl overflow-y-auto min-h-0 flex-grow">
             {playlist.map((audio, index) => (<li key={index} className={"flex last:border-b-0 border-b border-gray-700 p-2 hover:bg-gray-700 rounded transition-all " + ((selectedAudio && selectedAudio[0].id == audio.id) && "bg-gray-700")}>
                 <button className="flex items-center space-x-2 flex-grow text-left" onClick={() => selectAudio(audio.id)}>
@@ -13,6 +20,9 @@ export function Playlist() {
                     <div className="flex flex-col">
                         <span>{audio.title}</span>
                         <span className="text-gray-400">{audio.author}</span>
+                        { (audio.bpm != null || audio.camelot != null) && <span className="text-gray-500 text-sm">
+                            {[audio.bpm != null && `${Math.round(audio.bpm)} BPM`, audio.camelot].filter(Boolean).join(' · ')}
+                        </span> }
                     </div>
                 </button>
                 <button className="ml-4 px-2 py-2 self-center bg-blue-500 text-white rounded hover:bg-blue-600 transition" onClick={() => removeAudio(audio.id)}>
@@ -13,7 +13,10 @@ export function DownloadList() {
                         <li key={id} className="flex flex-col space-y-1">
                             <span className="font-semibold">{audio?.title}</span>
                             <span className="text-sm text-gray-400">{audio?.author}</span>
-                            {download.state === "downloading" && download.progress && (
+                            {download.state === "transcoding" && (
+                                <span className="text-sm text-gray-400">Transcoding</span>
+                            )}
+                            {(download.state === "downloading" || download.state === "transcoding") && download.progress && (
                                 <div className="w-full bg-gray-700 rounded-full h-2.5">
                                     <div
                                         className="bg-blue-500 h-2.5 rounded-full"
@@ -2,17 +2,62 @@ import { useEffect, useRef } from 'react';
 import { useEngine } from '../Engine';
 import { Thumbnail } from './Thumbnail';
 
+type Listen = {
+    id: number,
+    startedAt: number,
+    listened: number,
+    position: number,
+};
+
+// Jumps between two positions longer than this are seeks and don't count as listened.
+const MAX_PROGRESS = 1.0;
+
 export function Player() {
     const { selectedAudio, state, engine, thumbnails } = useEngine();
     const audioRef = useRef<HTMLAudioElement>(null);
+    const listenRef = useRef<Listen | null>(null);
+
+    const finishListen = (completed: boolean) => {
+        const listen = listenRef.current;
+        listenRef.current = null;
+        if (listen) {
+            engine.reportListen(listen.id, listen.startedAt, listen.listened, completed);
+        }
+    };
+
+    const beginListen = () => {
+        if (selectedAudio && !listenRef.current) {
+            listenRef.current = {
+                id: selectedAudio[0].id,
+                startedAt: Math.floor(Date.now() / 1000),
+                listened: 0,
+                position: audioRef.current?.currentTime ?? 0,
+            };
+        }
+    };
+
+    const progressListen = () => {
+        const listen = listenRef.current;
+        if (listen && audioRef.current) {
+            const position = audioRef.current.currentTime;
+            const delta = position - listen.position;
+            if (delta > 0 && delta <= MAX_PROGRESS) {
+                listen.listened += delta;
+            }
+            listen.position = position;
+        }
+    };
 
     useEffect(() => {
         if (selectedAudio && audioRef.current) {
+            finishListen(false);
             audioRef.current.src = selectedAudio[1];
             audioRef.current.play();
         }
     }, [selectedAudio]);
 
+    useEffect(() => () => finishListen(false), []);
+
     return (
         <div className="bg-gray-800 p-4 rounded-xl flex items-center space-x-4 player">
             {selectedAudio ? (
@@ -26,7 +71,8 @@ export function Player() {
                         <h2 className="text-lg font-bold">{selectedAudio[0].title}</h2>
                         <p className="text-gray-400">{selectedAudio[0].author}</p>
                     </div>
-                    <audio ref={audioRef} controls className="ml-auto flex-grow">
+                    <audio ref={audioRef} controls className="ml-auto flex-grow"
+                        onPlay={beginListen} onTimeUpdate={progressListen} onEnded={() => finishListen(true)}>
                         Your browser does not support the audio element.
                     </audio>
                 </>
@@ -36,4 +82,4 @@ export function Player() {
             {state === 'loading_audio' && <p className="text-gray-400">Loading...</p>}
         </div>
     );
-}
\ No newline at end of file
+}